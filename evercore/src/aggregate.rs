@@ -1,24 +1,37 @@
 use serde::Serialize;
 use serde::de::DeserializeOwned;
+use std::future::Future;
+use std::pin::Pin;
 use std::sync::Arc;
+use std::task::{Context, Poll};
+use uuid::Uuid;
 use crate::SharedEventContext;
-use crate::event::Event;
-use crate::snapshot::Snapshot;
-use crate::EventStoreError;
-use crate::EventContext;
+use crate::cursor::ViewToken;
+use crate::contexts::RecordedCommand;
+use crate::event::{redact_json, Event};
+use crate::snapshot::{Snapshot, SnapshotPolicy};
+use crate::{AggregateId, CreateOutcome, DuplicateKeyPolicy, EventStoreError};
+use crate::contexts::EventContext;
+use crate::registry::{EventStoreRegistry, DEFAULT_STORE};
 
 /// Aggregate is a trait that must be implemented by any aggregate that is to be stored in the event store.
 pub trait Aggregate<'a> {
 
     /// returns the id of the aggregate.
-    fn id(&self) -> i64;
+    fn id(&self) -> AggregateId;
 
     /// sets the id of the aggregate.
-    fn id_mut(&mut self, id: i64);
+    fn id_mut(&mut self, id: AggregateId);
 
     /// returns frequency of snapshots for this aggregate. 0 means no snapshots.
     fn snapshot_frequency(&self) -> i32;
 
+    /// returns the snapshot policy of this aggregate, overriding the store default and
+    /// `snapshot_frequency`; None leaves the choice to the store.
+    fn snapshot_policy(&self) -> Option<SnapshotPolicy> {
+        None
+    }
+
     /// returns the type of the aggregate.
     fn aggregate_type(&self) -> &str;
 
@@ -33,6 +46,21 @@ pub trait Aggregate<'a> {
 
     /// returns a snapshot of the aggregate.
     fn take_snapshot(&self) -> Result<Snapshot, EventStoreError>;
+
+    /// returns the id of the context the aggregate was created or loaded with, if it tracks one.
+    fn context_id(&self) -> Option<Uuid> {
+        None
+    }
+
+    /// returns the name of the registered event store the aggregate belongs to.
+    fn store_name(&self) -> &str {
+        DEFAULT_STORE
+    }
+
+    /// returns the event types the aggregate applies; empty means any type is accepted.
+    fn registered_event_types(&self) -> &'static [&'static str] {
+        &[]
+    }
 }
 
 /// A trait that must be implemented by any struct that is to be used as a xxxBackedAggregate.
@@ -43,6 +71,38 @@ pub trait Composable
     fn snapshot_frequency(&self) -> i32 {
         10
     }
+    /// Snapshot policy overriding the store default and `snapshot_frequency`, see
+    /// `SnapshotPolicy`.
+    fn snapshot_policy(&self) -> Option<SnapshotPolicy> {
+        None
+    }
+    /// Name of the registered event store this aggregate lives in.
+    fn store_name(&self) -> &str {
+        DEFAULT_STORE
+    }
+    /// Command fields masked before commands are recorded, when the store records them.
+    fn redact_command_fields(&self) -> &[&str] {
+        &[]
+    }
+    /// Event types this aggregate understands. When non-empty, publishing any other
+    /// type fails with `EventStoreError::UnknownEventType`.
+    fn registered_event_types() -> &'static [&'static str] {
+        &[]
+    }
+    /// Called after each event is applied; invalidate every `Derived` field here.
+    fn invalidate_derived(&mut self) {}
+    /// Checked after `ComposedAggregate::request` applies its event. An error, typically
+    /// `EventStoreError::ValidationError`, withdraws the event and restores the prior state.
+    fn validate_state(&self) -> Result<(), EventStoreError> {
+        Ok(())
+    }
+}
+
+/// Validates a state with the `validator` crate, for `Composable::validate_state`
+/// implementations of states deriving `validator::Validate`.
+#[cfg(feature = "validation")]
+pub fn validate<T: validator::Validate>(state: &T) -> Result<(), EventStoreError> {
+    state.validate().map_err(|errors| EventStoreError::ValidationError(errors.to_string()))
 }
 
 /// A trait that must be implemented by any struct that is to be used as a ComposedAggregate. 
@@ -55,6 +115,33 @@ where
     fn request(&self, request: TCommand) -> Result<(String, TEvent), EventStoreError>;
 }
 
+/// Typed alternative to matching raw events in `Composable::apply_event`: the payload is
+/// deserialized into `Events` and handed to `apply`. Forward `Composable::apply_event`
+/// to `apply_typed` to use it.
+pub trait AppliesEvents {
+    type Events: DeserializeOwned;
+
+    fn apply(&mut self, event: Self::Events) -> Result<(), EventStoreError>;
+
+    /// Payloads that do not deserialize into `Events` fail with `UnknownEventType`.
+    fn apply_typed(&mut self, event: &Event) -> Result<(), EventStoreError> {
+        let typed = serde_json::from_str(event.data.json()?)
+            .map_err(|_| EventStoreError::UnknownEventType(event.event_type.clone()))?;
+        self.apply(typed)
+    }
+}
+
+
+/// Whether a `ComposedAggregate` reflects its stored history.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Hydration {
+    /// Created in this context; there is no stored history yet.
+    New,
+    /// Rebuilt from its snapshot and events.
+    Loaded,
+    /// Not (fully) rebuilt from storage. Publishing is refused.
+    Unhydrated,
+}
 
 /// Generic implementation of an aggregate that is backed by a struct.
 /// This saves having to implement the boilerplate code for each aggregate.
@@ -62,21 +149,29 @@ pub struct ComposedAggregate<T>
 where 
     T: DeserializeOwned + Default + Serialize + Composable
 {
-    id: i64,
+    id: AggregateId,
     version: i64,
     context: Option<Arc<EventContext>>,
+    hydration: Hydration,
     state: T,
+    /// What `clone_at_version` replays from: the initial state or the last applied snapshot.
+    /// None stands for `T::default()` at version 0.
+    base: Option<Snapshot>,
+    /// Events applied on top of `base`.
+    history: Vec<Event>,
+    /// How the instance was obtained, for aggregates made by `new`.
+    create_outcome: Option<CreateOutcome>,
 }
 
 impl<'a, T> Aggregate<'a> for ComposedAggregate<T>
     where T: DeserializeOwned + Default + Serialize + Composable + Clone
 {
 
-    fn id(&self) -> i64 {
+    fn id(&self) -> AggregateId {
         self.id
     }
 
-    fn id_mut(&mut self, id: i64) {
+    fn id_mut(&mut self, id: AggregateId) {
         self.id = id;
     }
 
@@ -92,20 +187,32 @@ impl<'a, T> Aggregate<'a> for ComposedAggregate<T>
         self.state.snapshot_frequency()
     }
 
+    fn snapshot_policy(&self) -> Option<SnapshotPolicy> {
+        self.state.snapshot_policy()
+    }
+
     fn apply_snapshot(&mut self, snapshot: &Snapshot) -> Result<(), EventStoreError> {
         self.version = snapshot.version;
         let state: T = snapshot.to_state()?;
         self.state = state;
         self.version = snapshot.version;
+        self.base = Some(snapshot.clone());
+        self.history.clear();
         Ok(())
     }
 
     fn apply_event(&mut self, event: &Event) -> Result<(), EventStoreError> {
-        self.version = event.version;
         self.state.apply_event(event)?;
+        self.state.invalidate_derived();
+        self.version = event.version;
+        self.history.push(event.clone());
         Ok(())
     }
 
+    fn registered_event_types(&self) -> &'static [&'static str] {
+        T::registered_event_types()
+    }
+
     fn take_snapshot(&self) -> Result<Snapshot, EventStoreError> {
         let snapshot = Snapshot::new(
             self.id, 
@@ -116,6 +223,14 @@ impl<'a, T> Aggregate<'a> for ComposedAggregate<T>
         Ok(snapshot)
     }
 
+    fn store_name(&self) -> &str {
+        self.state.store_name()
+    }
+
+    fn context_id(&self) -> Option<Uuid> {
+        self.context.as_ref().map(|ctx| ctx.context_id())
+    }
+
 }
 
 impl<'a, T> ComposedAggregate<T> 
@@ -127,18 +242,68 @@ impl<'a, T> ComposedAggregate<T>
 {
     pub async fn new(ctx: &SharedEventContext, natural_key: Option<&str>) -> Result<ComposedAggregate<T>, EventStoreError> 
     {
-        let state = T::default();
+        ComposedAggregate::new_with_state(ctx, natural_key, T::default()).await
+    }
+
+    /// Creates a new aggregate starting from `initial_state` instead of `T::default()`.
+    /// The aggregate still gets a fresh id and starts at version 0.
+    ///
+    /// When the natural key is taken and the engine's `DuplicateKeyPolicy` returns the existing
+    /// instance, that instance is loaded instead and `initial_state` is ignored.
+    pub async fn new_with_state(ctx: &SharedEventContext, natural_key: Option<&str>, initial_state: T) -> Result<ComposedAggregate<T>, EventStoreError>
+    {
+        ComposedAggregate::create(ctx, natural_key, initial_state, None).await
+    }
+
+    /// Loads the aggregate registered under `natural_key`, creating it if there is none.
+    /// Returns true along with the aggregate if it was created.
+    ///
+    /// The key is resolved and claimed in one step by the storage engine, so concurrent
+    /// callers with the same key all end up with the same instance.
+    pub async fn new_or_load(ctx: &SharedEventContext, natural_key: &str) -> Result<(ComposedAggregate<T>, bool), EventStoreError>
+    {
+        let aggregate = ComposedAggregate::create(ctx, Some(natural_key), T::default(), Some(DuplicateKeyPolicy::ReturnExisting)).await?;
+        let created = aggregate.create_outcome.as_ref().is_some_and(|outcome| outcome.created);
+        Ok((aggregate, created))
+    }
+
+    async fn create(ctx: &SharedEventContext, natural_key: Option<&str>, initial_state: T, policy: Option<DuplicateKeyPolicy>) -> Result<ComposedAggregate<T>, EventStoreError>
+    {
+        let state = initial_state;
         let aggregate_type = state.get_type();
+        let outcome = ctx.create_aggregate_instance(aggregate_type, natural_key, policy).await?;
+        let id = outcome.id;
 
+        if !outcome.created {
+            match ComposedAggregate::<T>::load(ctx, id).await {
+                Ok(mut existing) => {
+                    existing.create_outcome = Some(outcome);
+                    return Ok(existing);
+                },
+                // The instance exists but nothing was committed to it yet.
+                Err(EventStoreError::AggregateNotFound(_)) => {},
+                Err(error) => return Err(error),
+            }
+        }
 
         Ok(ComposedAggregate {
-            id: ctx.next_aggregate_id(aggregate_type, natural_key).await?,
+            id,
             version: 0,
             context: Some(ctx.clone()),
+            hydration: Hydration::New,
+            base: Some(Snapshot::new(id, aggregate_type, 0, &state)?),
+            history: Vec::new(),
+            create_outcome: Some(outcome),
             state
         })
     }
 
+    /// How `new` obtained the instance: whether it was created and under which natural key.
+    /// None for aggregates that were loaded.
+    pub fn create_outcome(&self) -> Option<&CreateOutcome> {
+        self.create_outcome.as_ref()
+    }
+
     pub fn request<TCommand, TEvent>(&mut self, request: TCommand) -> Result<(), EventStoreError>
     where 
         TCommand: 'a + Serialize + DeserializeOwned,
@@ -149,25 +314,207 @@ impl<'a, T> ComposedAggregate<T>
             Some(ctx) => ctx.clone(),
             None => return Err(EventStoreError::NoContext),
         };
+        self.ensure_hydrated()?;
         
+        let command = self.record_command(&ctx, &request)?;
         let (event_type, event) = CanRequest::<TCommand, TEvent>::request(&self.state, request)?;
-        ctx.publish(self, &event_type, &event)?;
+        ctx.publish_recorded(self, &event_type, &event, command)?;
+        self.check_state(&ctx, None)
+    }
 
-        Ok(())
+    /// Like `request`, but the event is skipped if `dedup_key` was already ingested.
+    /// Returns whether the event was published.
+    pub async fn request_dedup<TCommand, TEvent>(&mut self, request: TCommand, dedup_key: &str) -> Result<bool, EventStoreError>
+    where 
+        TCommand: 'a + Serialize + DeserializeOwned,
+        TEvent: 'a + Serialize + DeserializeOwned,
+        T: CanRequest<TCommand, TEvent>
+    {
+        let ctx = match &self.context {
+            Some(ctx) => ctx.clone(),
+            None => return Err(EventStoreError::NoContext),
+        };
+        self.ensure_hydrated()?;
+
+        let command = self.record_command(&ctx, &request)?;
+        let (event_type, event) = CanRequest::<TCommand, TEvent>::request(&self.state, request)?;
+        if !ctx.publish_dedup_recorded(self, &event_type, &event, dedup_key, command).await? {
+            return Ok(false);
+        }
+        self.check_state(&ctx, Some(dedup_key))?;
+        Ok(true)
+    }
+
+    /// Runs `Composable::validate_state` on the state the last published event led to. A
+    /// refused state takes the event (and its dedup key) back out of the context and is
+    /// rebuilt as it was before the event.
+    fn check_state(&mut self, ctx: &EventContext, dedup_key: Option<&str>) -> Result<(), EventStoreError> {
+        let Err(error) = self.state.validate_state() else {
+            return Ok(());
+        };
+        ctx.withdraw_event(self, dedup_key)?;
+        self.history.pop();
+        self.version -= 1;
+        self.state = self.clone_at_version(self.version)?;
+        Err(error)
     }
 
-    pub async fn load(ctx: &SharedEventContext, id: i64) -> Result<ComposedAggregate<T>, EventStoreError>     {
+    /// Serializes the command for its event's metadata, if the store records commands.
+    fn record_command<TCommand: Serialize>(&self, ctx: &EventContext, request: &TCommand) -> Result<Option<RecordedCommand>, EventStoreError> {
+        if !ctx.records_commands() {
+            return Ok(None);
+        }
+        let mut payload = serde_json::to_value(request).map_err(EventStoreError::EventMetaDataSerializationError)?;
+        redact_json(&mut payload, self.state.redact_command_fields());
+        Ok(Some(RecordedCommand {
+            command_type: std::any::type_name::<TCommand>(),
+            payload: payload.to_string(),
+        }))
+    }
+
+    pub async fn load(ctx: &SharedEventContext, id: AggregateId) -> Result<ComposedAggregate<T>, EventStoreError>     {
         let mut state_aggregate = ComposedAggregate{
             id,
             version: 0,
             context: Some(ctx.clone()),
+            hydration: Hydration::Unhydrated,
             state: T::default(),
+            base: None,
+            history: Vec::new(),
+            create_outcome: None,
         };
 
         ctx.load(&mut state_aggregate).await?; 
+        state_aggregate.hydration = Hydration::Loaded;
         Ok(state_aggregate)
     }
 
+    /// Loads the aggregate created with `natural_key`. Fails with `AggregateInstanceNotFound`
+    /// when no instance of the type has that key.
+    pub async fn load_by_key(ctx: &SharedEventContext, natural_key: &str) -> Result<ComposedAggregate<T>, EventStoreError> {
+        let aggregate_type = T::default().get_type().to_string();
+        let id = ctx.get_aggregate_instance_id(&aggregate_type, natural_key).await?
+            .ok_or(EventStoreError::AggregateInstanceNotFound)?;
+        ComposedAggregate::load(ctx, id).await
+    }
+
+    /// Loads the aggregate as it was at `version`, for inspecting past state.
+    pub async fn load_at_version(ctx: &SharedEventContext, id: AggregateId, version: i64) -> Result<ComposedAggregate<T>, EventStoreError> {
+        let mut state_aggregate = ComposedAggregate{
+            id,
+            version: 0,
+            context: Some(ctx.clone()),
+            hydration: Hydration::Unhydrated,
+            state: T::default(),
+            base: None,
+            history: Vec::new(),
+            create_outcome: None,
+        };
+
+        ctx.load_at_version(&mut state_aggregate, version).await?;
+        state_aggregate.hydration = Hydration::Loaded;
+        Ok(state_aggregate)
+    }
+
+    /// Loads the aggregate as it was when `view` was taken by `EventStore::consistent_view`.
+    pub async fn load_at_view(ctx: &SharedEventContext, id: AggregateId, view: &ViewToken) -> Result<ComposedAggregate<T>, EventStoreError> {
+        let mut state_aggregate = ComposedAggregate{
+            id,
+            version: 0,
+            context: Some(ctx.clone()),
+            hydration: Hydration::Unhydrated,
+            state: T::default(),
+            base: None,
+            history: Vec::new(),
+            create_outcome: None,
+        };
+
+        ctx.load_at_view(&mut state_aggregate, view).await?;
+        state_aggregate.hydration = Hydration::Loaded;
+        Ok(state_aggregate)
+    }
+
+    /// Rebuilds the state at `target_version` from the events this aggregate has applied,
+    /// without touching storage. Events before the snapshot it was loaded from are not kept,
+    /// so earlier versions fail with `HistoryUnavailable`. Versions past the current one
+    /// return the current state.
+    pub fn clone_at_version(&self, target_version: i64) -> Result<T, EventStoreError> {
+        let (base_version, mut state) = match &self.base {
+            Some(snapshot) => (snapshot.version, snapshot.to_state()?),
+            None => (0, T::default()),
+        };
+        if target_version < base_version {
+            return Err(EventStoreError::HistoryUnavailable { earliest: base_version });
+        }
+        for event in self.history.iter().take_while(|event| event.version <= target_version) {
+            state.apply_event(event)?;
+        }
+        Ok(state)
+    }
+
+    /// Whether the aggregate was created, loaded, or never rebuilt from storage.
+    pub fn hydration(&self) -> Hydration {
+        self.hydration
+    }
+
+    fn ensure_hydrated(&self) -> Result<(), EventStoreError> {
+        match self.hydration {
+            Hydration::Unhydrated => Err(EventStoreError::AggregateNotHydrated(self.id)),
+            Hydration::New | Hydration::Loaded => Ok(()),
+        }
+    }
+
+    /// Creates a new aggregate in the store it declares through `Composable::store_name`,
+    /// using a fresh context from that store. See `context` to commit it.
+    pub async fn new_in(registry: &EventStoreRegistry, natural_key: Option<&str>) -> Result<ComposedAggregate<T>, EventStoreError> {
+        let ctx = registry.context(T::default().store_name())?;
+        ComposedAggregate::new(&ctx, natural_key).await
+    }
+
+    /// Loads an aggregate from the store it declares through `Composable::store_name`.
+    pub async fn load_in(registry: &EventStoreRegistry, id: AggregateId) -> Result<ComposedAggregate<T>, EventStoreError> {
+        let ctx = registry.context(T::default().store_name())?;
+        ComposedAggregate::load(&ctx, id).await
+    }
+
+    /// The context the aggregate publishes through.
+    pub fn context(&self) -> Option<SharedEventContext> {
+        self.context.clone()
+    }
+
+    /// Queues a snapshot at the current version for the next commit, regardless of the
+    /// snapshot policy, see `EventContext::capture_snapshot`.
+    pub fn snapshot_now(&self) -> Result<(), EventStoreError> {
+        let ctx = self.context.as_ref().ok_or(EventStoreError::NoContext)?;
+        ctx.capture_snapshot(self)
+    }
+
+    /// Takes a snapshot and flushes it to storage immediately, bypassing the context's
+    /// pending commit so a crashed saga can resume without replaying the full history.
+    pub async fn checkpoint(&self) -> Result<(), EventStoreError> {
+        let ctx = self.context.as_ref().ok_or(EventStoreError::NoContext)?;
+        let snapshot = self.take_snapshot()?;
+        ctx.write_snapshot(snapshot).await
+    }
+
+    /// Adds a non-unique lookup key to this aggregate when the context is committed.
+    pub fn add_lookup_key(&self, key_name: &str, key_value: &str) -> Result<(), EventStoreError> {
+        let ctx = self.context.as_ref().ok_or(EventStoreError::NoContext)?;
+        ctx.add_lookup_key(self, key_name, key_value)
+    }
+
+    /// Removes a lookup key from this aggregate when the context is committed.
+    pub fn remove_lookup_key(&self, key_name: &str, key_value: &str) -> Result<(), EventStoreError> {
+        let ctx = self.context.as_ref().ok_or(EventStoreError::NoContext)?;
+        ctx.remove_lookup_key(self, key_name, key_value)
+    }
+
+    /// Returns the ids of all aggregates of this type carrying the lookup key.
+    pub async fn find_by_lookup_key(ctx: &SharedEventContext, key_name: &str, key_value: &str) -> Result<Vec<AggregateId>, EventStoreError> {
+        let state = T::default();
+        ctx.find_by_lookup_key(state.get_type(), key_name, key_value).await
+    }
+
     pub fn state(&self) -> &T {
         &self.state
     }
@@ -176,4 +523,554 @@ impl<'a, T> ComposedAggregate<T>
     pub fn owned_state(&self) -> T {
         self.state.clone()
     }
+
+    /// Consumes the aggregate, moving its state out without cloning.
+    pub fn into_state(self) -> T {
+        self.state
+    }
+}
+
+type BoxedLoad<T> = Pin<Box<dyn Future<Output = Result<ComposedAggregate<T>, EventStoreError>>>>;
+
+/// Loads a `ComposedAggregate` when awaited. Created by `EventContext::load_aggregate`.
+///
+/// Nothing is read until the future is first polled.
+pub struct LoadFuture<T>
+where
+    T: DeserializeOwned + Default + Serialize + Composable
+{
+    ctx: SharedEventContext,
+    id: AggregateId,
+    inner: Option<BoxedLoad<T>>,
+}
+
+impl<T> LoadFuture<T>
+where
+    T: DeserializeOwned + Default + Serialize + Composable
+{
+    pub(crate) fn new(ctx: SharedEventContext, id: AggregateId) -> LoadFuture<T> {
+        LoadFuture { ctx, id, inner: None }
+    }
+}
+
+impl<T> Future for LoadFuture<T>
+where
+    T: 'static + DeserializeOwned + Default + Serialize + Composable + Clone
+{
+    type Output = Result<ComposedAggregate<T>, EventStoreError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        let inner = this.inner.get_or_insert_with(|| {
+            let ctx = this.ctx.clone();
+            let id = this.id;
+            Box::pin(async move { ComposedAggregate::load(&ctx, id).await })
+        });
+        inner.as_mut().poll(cx)
+    }
+}
+
+#[cfg(all(test, feature = "memory"))]
+mod tests {
+    use serde::{Serialize, Deserialize};
+    use crate::{EventStore, memory::MemoryStorageEngine};
+    use super::*;
+    use crate::event::numbered_id;
+
+    #[derive(Default, Clone, Serialize, Deserialize)]
+    struct Counter {
+        count: i64,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    struct Increment;
+
+    impl Composable for Counter {
+        fn get_type(&self) -> &str {
+            "counter"
+        }
+
+        fn apply_event(&mut self, _event: &Event) -> Result<(), EventStoreError> {
+            self.count += 1;
+            Ok(())
+        }
+    }
+
+    impl CanRequest<Increment, Increment> for Counter {
+        fn request(&self, request: Increment) -> Result<(String, Increment), EventStoreError> {
+            Ok(("incremented".to_string(), request))
+        }
+    }
+
+    #[derive(Default, Clone, Serialize, Deserialize)]
+    struct Thermostat {
+        target: i64,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    struct Reset;
+
+    impl Composable for Thermostat {
+        fn get_type(&self) -> &str {
+            "thermostat"
+        }
+
+        fn apply_event(&mut self, _event: &Event) -> Result<(), EventStoreError> {
+            self.target += 1;
+            Ok(())
+        }
+
+        fn registered_event_types() -> &'static [&'static str] {
+            &["incremented"]
+        }
+    }
+
+    impl CanRequest<Increment, Increment> for Thermostat {
+        fn request(&self, request: Increment) -> Result<(String, Increment), EventStoreError> {
+            Ok(("incremented".to_string(), request))
+        }
+    }
+
+    impl CanRequest<Reset, Reset> for Thermostat {
+        fn request(&self, request: Reset) -> Result<(String, Reset), EventStoreError> {
+            Ok(("reset".to_string(), request))
+        }
+    }
+
+    #[tokio::test]
+    async fn ensure_new_and_loaded_aggregates_accept_requests() {
+        let event_store = EventStore::new(MemoryStorageEngine::new());
+        let ctx = event_store.get_context();
+        let mut counter = ComposedAggregate::<Counter>::new(&ctx, None).await.unwrap();
+        assert_eq!(counter.hydration(), Hydration::New);
+        counter.request(Increment).unwrap();
+        ctx.commit().await.unwrap();
+
+        let ctx = event_store.get_context();
+        let mut counter = ComposedAggregate::<Counter>::load(&ctx, counter.id()).await.unwrap();
+        assert_eq!(counter.hydration(), Hydration::Loaded);
+        counter.request(Increment).unwrap();
+        assert_eq!(counter.version(), 2);
+    }
+
+    #[tokio::test]
+    async fn ensure_unhydrated_aggregate_refuses_requests() {
+        let ctx = EventStore::new(MemoryStorageEngine::new()).get_context();
+        let mut shell = ComposedAggregate {
+            id: numbered_id(42),
+            version: 0,
+            context: Some(ctx.clone()),
+            hydration: Hydration::Unhydrated,
+            state: Counter::default(),
+            base: None,
+            history: Vec::new(),
+            create_outcome: None,
+        };
+
+        assert!(matches!(shell.request(Increment), Err(EventStoreError::AggregateNotHydrated(id)) if id == numbered_id(42)));
+        assert!(matches!(shell.request_dedup(Increment, "msg-1").await, Err(EventStoreError::AggregateNotHydrated(id)) if id == numbered_id(42)));
+        ctx.assert_nothing_published();
+    }
+
+    #[tokio::test]
+    async fn ensure_load_aggregate_future_loads_on_await() {
+        let event_store = EventStore::new(MemoryStorageEngine::new());
+        let ctx = event_store.get_context();
+        let mut counter = ComposedAggregate::<Counter>::new(&ctx, None).await.unwrap();
+        counter.request(Increment).unwrap();
+        counter.request(Increment).unwrap();
+        ctx.commit().await.unwrap();
+
+        let ctx = event_store.get_context();
+        let counter: ComposedAggregate<Counter> = ctx.load_aggregate(counter.id()).await.unwrap();
+        assert_eq!(counter.state().count, 2);
+        assert_eq!(counter.hydration(), Hydration::Loaded);
+
+        let missing = ctx.load_aggregate::<Counter>(numbered_id(99)).await;
+        assert!(matches!(missing, Err(EventStoreError::AggregateNotFound(_))));
+    }
+
+    #[tokio::test]
+    async fn ensure_load_at_version_replays_intact_history() {
+        let event_store = EventStore::new(MemoryStorageEngine::new());
+        let ctx = event_store.get_context();
+        let mut counter = ComposedAggregate::<Counter>::new(&ctx, None).await.unwrap();
+        for _ in 0..12 {
+            counter.request(Increment).unwrap();
+        }
+        ctx.commit().await.unwrap();
+
+        let ctx = event_store.get_context();
+        for version in [0, 4, 11, 12] {
+            let past = ComposedAggregate::<Counter>::load_at_version(&ctx, counter.id(), version).await.unwrap();
+            assert_eq!(past.version(), version);
+            assert_eq!(past.state().count, version);
+        }
+    }
+
+    #[tokio::test]
+    async fn ensure_load_at_version_refuses_pruned_history() {
+        let event_store = EventStore::new(MemoryStorageEngine::new());
+        // What retention leaves behind: a snapshot at version 3 and the events after it.
+        let snapshot = Snapshot::new(numbered_id(1), "counter", 3, &Counter { count: 3 }).unwrap();
+        let events = vec![
+            Event::new(numbered_id(1), "counter", 4, "incremented", &Increment).unwrap(),
+            Event::new(numbered_id(1), "counter", 5, "incremented", &Increment).unwrap(),
+        ];
+        event_store.write_updates(&events, &[snapshot]).await.unwrap();
+
+        let ctx = event_store.get_context();
+        for version in [0, 2] {
+            let result = ComposedAggregate::<Counter>::load_at_version(&ctx, numbered_id(1), version).await;
+            assert!(matches!(result, Err(EventStoreError::HistoryUnavailable { earliest: 3 })));
+        }
+        let past = ComposedAggregate::<Counter>::load_at_version(&ctx, numbered_id(1), 4).await.unwrap();
+        assert_eq!((past.version(), past.state().count), (4, 4));
+
+        let missing = ComposedAggregate::<Counter>::load_at_version(&ctx, numbered_id(2), 0).await;
+        assert!(matches!(missing, Err(EventStoreError::AggregateNotFound(_))));
+    }
+
+    #[tokio::test]
+    async fn ensure_load_at_version_starts_from_historical_snapshots() {
+        let event_store = EventStore::new(MemoryStorageEngine::new());
+        // Events before version 4 are pruned; snapshots were kept at 3 and 6. The one at 6
+        // carries a marker count so it is recognizable.
+        let snapshots = [
+            Snapshot::new(numbered_id(1), "counter", 3, &Counter { count: 3 }).unwrap(),
+            Snapshot::new(numbered_id(1), "counter", 6, &Counter { count: 60 }).unwrap(),
+        ];
+        let events: Vec<Event> = (4..=8)
+            .map(|version| Event::new(numbered_id(1), "counter", version, "incremented", &Increment).unwrap())
+            .collect();
+        event_store.write_updates(&events, &snapshots).await.unwrap();
+
+        let history = event_store.read_snapshots(numbered_id(1), "counter", 10).await.unwrap();
+        assert_eq!(history.iter().map(|info| info.version).collect::<Vec<_>>(), vec![6, 3]);
+        assert_eq!(event_store.read_snapshots(numbered_id(1), "counter", 1).await.unwrap().len(), 1);
+        assert_eq!(event_store.read_snapshot_at(numbered_id(1), "counter", 5).await.unwrap().unwrap().version, 3);
+
+        let ctx = event_store.get_context();
+        let past = ComposedAggregate::<Counter>::load_at_version(&ctx, numbered_id(1), 5).await.unwrap();
+        assert_eq!((past.version(), past.state().count), (5, 5));
+        let past = ComposedAggregate::<Counter>::load_at_version(&ctx, numbered_id(1), 7).await.unwrap();
+        assert_eq!((past.version(), past.state().count), (7, 61));
+
+        let result = ComposedAggregate::<Counter>::load_at_version(&ctx, numbered_id(1), 2).await;
+        assert!(matches!(result, Err(EventStoreError::HistoryUnavailable { earliest: 6 })));
+    }
+
+    #[tokio::test]
+    async fn ensure_clone_at_version_replays_applied_events() {
+        let event_store = EventStore::new(MemoryStorageEngine::new());
+        let ctx = event_store.get_context();
+        let mut counter = ComposedAggregate::<Counter>::new_with_state(&ctx, None, Counter { count: 100 }).await.unwrap();
+        for _ in 0..4 {
+            counter.request(Increment).unwrap();
+        }
+        assert_eq!(counter.clone_at_version(0).unwrap().count, 100);
+        assert_eq!(counter.clone_at_version(2).unwrap().count, 102);
+        assert_eq!(counter.clone_at_version(9).unwrap().count, 104);
+
+        // Counter snapshots every 10 events, so a reload starts from the snapshot at version 9.
+        for _ in 0..8 {
+            counter.request(Increment).unwrap();
+        }
+        ctx.commit().await.unwrap();
+        let ctx = event_store.get_context();
+        let loaded = ComposedAggregate::<Counter>::load(&ctx, counter.id()).await.unwrap();
+        assert_eq!(loaded.clone_at_version(11).unwrap().count, 111);
+        assert!(matches!(loaded.clone_at_version(5), Err(EventStoreError::HistoryUnavailable { earliest: 9 })));
+    }
+
+    #[tokio::test]
+    async fn ensure_invalid_states_withdraw_their_event() {
+        #[derive(Default, Clone, Serialize, Deserialize)]
+        struct Gauge {
+            level: i64,
+        }
+
+        impl Composable for Gauge {
+            fn get_type(&self) -> &str {
+                "gauge"
+            }
+
+            fn apply_event(&mut self, _event: &Event) -> Result<(), EventStoreError> {
+                self.level += 1;
+                Ok(())
+            }
+
+            fn validate_state(&self) -> Result<(), EventStoreError> {
+                match self.level > 2 {
+                    true => Err(EventStoreError::ValidationError("level above 2".to_string())),
+                    false => Ok(()),
+                }
+            }
+        }
+
+        impl CanRequest<Increment, Increment> for Gauge {
+            fn request(&self, request: Increment) -> Result<(String, Increment), EventStoreError> {
+                Ok(("raised".to_string(), request))
+            }
+        }
+
+        let event_store = EventStore::new(MemoryStorageEngine::new());
+        let ctx = event_store.get_context();
+        let mut gauge = ComposedAggregate::<Gauge>::new(&ctx, None).await.unwrap();
+        gauge.request(Increment).unwrap();
+        gauge.request(Increment).unwrap();
+        let result = gauge.request(Increment);
+        assert!(matches!(result, Err(EventStoreError::ValidationError(_))));
+        assert_eq!(gauge.version(), 2);
+        assert_eq!(gauge.state().level, 2);
+        ctx.assert_published("raised", 2);
+
+        let result = gauge.request_dedup(Increment, "raise-3").await;
+        assert!(matches!(result, Err(EventStoreError::ValidationError(_))));
+        ctx.commit().await.unwrap();
+
+        let ctx = event_store.get_context();
+        let mut gauge = ComposedAggregate::<Gauge>::load(&ctx, gauge.id()).await.unwrap();
+        assert_eq!(gauge.version(), 2);
+        assert!(matches!(gauge.request_dedup(Increment, "raise-3").await, Err(EventStoreError::ValidationError(_))));
+    }
+
+    #[cfg(feature = "validation")]
+    #[tokio::test]
+    async fn ensure_validator_states_are_checked() {
+        #[derive(Default, Clone, Serialize, Deserialize, validator::Validate)]
+        struct Budget {
+            #[validate(range(max = 1))]
+            spent: i64,
+        }
+
+        impl Composable for Budget {
+            fn get_type(&self) -> &str {
+                "budget"
+            }
+
+            fn apply_event(&mut self, _event: &Event) -> Result<(), EventStoreError> {
+                self.spent += 1;
+                Ok(())
+            }
+
+            fn validate_state(&self) -> Result<(), EventStoreError> {
+                validate(self)
+            }
+        }
+
+        impl CanRequest<Increment, Increment> for Budget {
+            fn request(&self, request: Increment) -> Result<(String, Increment), EventStoreError> {
+                Ok(("spent".to_string(), request))
+            }
+        }
+
+        let ctx = EventStore::new(MemoryStorageEngine::new()).get_context();
+        let mut budget = ComposedAggregate::<Budget>::new(&ctx, None).await.unwrap();
+        budget.request(Increment).unwrap();
+        let result = budget.request(Increment);
+        assert!(matches!(result, Err(EventStoreError::ValidationError(_))));
+        ctx.assert_published("spent", 1);
+    }
+
+    #[tokio::test]
+    async fn ensure_failed_load_returns_no_aggregate() {
+        let ctx = EventStore::new(MemoryStorageEngine::new()).get_context();
+        let result = ComposedAggregate::<Counter>::load(&ctx, numbered_id(42)).await;
+        assert!(matches!(result, Err(EventStoreError::AggregateNotFound(_))));
+    }
+
+    #[tokio::test]
+    async fn ensure_new_returns_existing_instance_under_policy() {
+        let event_store = EventStore::new(MemoryStorageEngine::with_duplicate_key_policy(crate::DuplicateKeyPolicy::ReturnExisting));
+        let ctx = event_store.get_context();
+        let mut counter = ComposedAggregate::<Counter>::new(&ctx, Some("main")).await.unwrap();
+        assert!(counter.create_outcome().unwrap().created);
+        counter.request(Increment).unwrap();
+        ctx.commit().await.unwrap();
+
+        let ctx = event_store.get_context();
+        let existing = ComposedAggregate::<Counter>::new(&ctx, Some("main")).await.unwrap();
+        let outcome = existing.create_outcome().unwrap();
+        assert!(!outcome.created);
+        assert_eq!(outcome.id, counter.id());
+        assert_eq!(existing.hydration(), Hydration::Loaded);
+        assert_eq!(existing.state().count, 1);
+    }
+
+    #[tokio::test]
+    async fn ensure_new_or_load_creates_one_instance_per_key() {
+        let event_store = EventStore::new(MemoryStorageEngine::new());
+        let ctx = event_store.get_context();
+        let (mut counter, created) = ComposedAggregate::<Counter>::new_or_load(&ctx, "main").await.unwrap();
+        assert!(created);
+        // A claimed key is not created twice, even before anything is committed to it.
+        let (again, created) = ComposedAggregate::<Counter>::new_or_load(&ctx, "main").await.unwrap();
+        assert!(!created);
+        assert_eq!(again.id(), counter.id());
+        counter.request(Increment).unwrap();
+        ctx.commit().await.unwrap();
+
+        let racers: Vec<_> = (0..4).map(|_| {
+            let event_store = event_store.clone();
+            tokio::spawn(async move {
+                let ctx = event_store.get_context();
+                ComposedAggregate::<Counter>::new_or_load(&ctx, "other").await
+                    .map(|(counter, created)| (counter.id(), created))
+                    .map_err(|error| error.to_string())
+            })
+        }).collect();
+        let mut outcomes = Vec::new();
+        for racer in racers {
+            outcomes.push(racer.await.unwrap().unwrap());
+        }
+        assert_eq!(outcomes.iter().filter(|(_, created)| *created).count(), 1);
+        assert!(outcomes.iter().all(|(id, _)| *id == outcomes[0].0));
+
+        let ctx = event_store.get_context();
+        let (loaded, created) = ComposedAggregate::<Counter>::new_or_load(&ctx, "main").await.unwrap();
+        assert!(!created);
+        assert_eq!(loaded.hydration(), Hydration::Loaded);
+        assert_eq!(loaded.state().count, 1);
+    }
+
+    #[tokio::test]
+    async fn ensure_loads_record_replay_stats() {
+        let event_store = EventStore::new(MemoryStorageEngine::new());
+        let ctx = event_store.get_context();
+        let mut counter = ComposedAggregate::<Counter>::new(&ctx, None).await.unwrap();
+        for _ in 0..3 {
+            counter.request(Increment).unwrap();
+        }
+        ctx.commit().await.unwrap();
+
+        let ctx = event_store.get_context();
+        let mut counter = ComposedAggregate::<Counter>::load(&ctx, counter.id()).await.unwrap();
+        let stats = ctx.last_load_stats().unwrap().unwrap();
+        assert!(!stats.snapshot_used);
+        assert_eq!(stats.snapshot_version, None);
+        assert_eq!(stats.events_replayed, 3);
+        assert_eq!(stats.bytes, 3 * "null".len());
+
+        for _ in 0..9 {
+            counter.request(Increment).unwrap();
+        }
+        ctx.commit().await.unwrap();
+
+        let ctx = event_store.get_context();
+        assert_eq!(ctx.last_load_stats().unwrap(), None);
+        ComposedAggregate::<Counter>::load(&ctx, counter.id()).await.unwrap();
+        let stats = ctx.last_load_stats().unwrap().unwrap();
+        assert!(stats.snapshot_used);
+        assert_eq!(stats.snapshot_version, Some(9));
+        assert_eq!(stats.events_replayed, 3);
+
+        let metrics = event_store.load_metrics().unwrap();
+        let counters = &metrics["counter"];
+        assert_eq!(counters.loads, 2);
+        assert_eq!(counters.snapshot_loads, 1);
+        assert_eq!(counters.events_replayed.sum(), 6);
+        assert_eq!(counters.events_replayed.max(), 3);
+    }
+
+    #[tokio::test]
+    async fn ensure_unregistered_event_types_are_rejected() {
+        let event_store = EventStore::new(MemoryStorageEngine::new());
+        let ctx = event_store.get_context();
+        let mut thermostat = ComposedAggregate::<Thermostat>::new(&ctx, None).await.unwrap();
+        thermostat.request(Increment).unwrap();
+
+        let result = thermostat.request(Reset);
+        assert!(matches!(result, Err(EventStoreError::UnknownEventType(ref event_type)) if event_type == "reset"));
+        assert_eq!(thermostat.version(), 1);
+
+        // Aggregates without a registry accept any event type.
+        let mut counter = ComposedAggregate::<Counter>::new(&ctx, None).await.unwrap();
+        assert_eq!(counter.registered_event_types(), &[] as &[&str]);
+        counter.request(Increment).unwrap();
+        ctx.commit().await.unwrap();
+    }
+}
+
+#[cfg(all(test, feature = "proptest", feature = "memory"))]
+mod proptests {
+    use proptest::prelude::*;
+    use serde::{Serialize, Deserialize};
+    use crate::{EventStore, memory::MemoryStorageEngine};
+    use super::*;
+
+    /// Keeps every applied amount so ordering mistakes in replay show up, not just totals.
+    #[derive(Default, Clone, Debug, PartialEq, Serialize, Deserialize)]
+    struct Ledger {
+        balance: i64,
+        history: Vec<i64>,
+    }
+
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    struct Adjust {
+        amount: i64,
+    }
+
+    impl Composable for Ledger {
+        fn get_type(&self) -> &str {
+            "ledger"
+        }
+
+        fn apply_event(&mut self, event: &Event) -> Result<(), EventStoreError> {
+            let adjust: Adjust = event.deserialize()?;
+            self.balance += adjust.amount;
+            self.history.push(adjust.amount);
+            Ok(())
+        }
+
+        fn snapshot_frequency(&self) -> i32 {
+            3
+        }
+    }
+
+    impl CanRequest<Adjust, Adjust> for Ledger {
+        fn request(&self, request: Adjust) -> Result<(String, Adjust), EventStoreError> {
+            Ok(("adjusted".to_string(), request))
+        }
+    }
+
+    /// Commands split into commits, so snapshots land in the middle of a commit as well as at its end.
+    fn commits() -> impl Strategy<Value = Vec<Vec<Adjust>>> {
+        let command = (-1000i64..1000).prop_map(|amount| Adjust { amount });
+        prop::collection::vec(prop::collection::vec(command, 1..8), 1..6)
+    }
+
+    async fn roundtrip(commits: Vec<Vec<Adjust>>) -> (Ledger, Ledger) {
+        let event_store = EventStore::new(MemoryStorageEngine::new());
+        let mut expected = Ledger::default();
+        let mut id = None;
+        for commands in commits {
+            let ctx = event_store.get_context();
+            let mut ledger = match id {
+                Some(id) => ComposedAggregate::<Ledger>::load(&ctx, id).await.unwrap(),
+                None => ComposedAggregate::<Ledger>::new(&ctx, None).await.unwrap(),
+            };
+            for command in commands {
+                expected.balance += command.amount;
+                expected.history.push(command.amount);
+                ledger.request(command).unwrap();
+            }
+            assert_eq!(ledger.state(), &expected);
+            ctx.commit().await.unwrap();
+            id = Some(ledger.id());
+        }
+
+        let ctx = event_store.get_context();
+        let reloaded = ComposedAggregate::<Ledger>::load(&ctx, id.unwrap()).await.unwrap();
+        (expected, reloaded.into_state())
+    }
+
+    proptest! {
+        #[test]
+        fn reloaded_state_matches_applied_state(commits in commits()) {
+            let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+            let (expected, reloaded) = runtime.block_on(roundtrip(commits));
+            prop_assert_eq!(reloaded, expected);
+        }
+    }
 }
@@ -0,0 +1,666 @@
+use std::{collections::{HashMap, HashSet}, path::{Path, PathBuf}, sync::Arc};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use crate::{event::Event, integrity::verify_chain, payload::Payload, AggregateId, AggregateInstance, EventStoreError, EventStoreStorageEngine, LookupKey, SharedEventStore};
+
+/// Name of the manifest file in an archive directory.
+pub const MANIFEST_FILE: &str = "manifest.json";
+
+/// Version of the archive layout written by `Archiver`. Archives of version 1, which hold
+/// no state file, can still be restored.
+pub const ARCHIVE_FORMAT_VERSION: u32 = 2;
+
+/// Instances and lookup keys read per page when capturing the state of a store.
+const STATE_PAGE_SIZE: usize = 1000;
+
+fn archive_error(error: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> EventStoreError {
+    EventStoreError::ArchiveError(error.into())
+}
+
+/// One line of a segment file.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ArchivedEvent {
+    /// Position in the global feed of the archived store.
+    pub position: i64,
+    pub aggregate_id: AggregateId,
+    pub aggregate_type: String,
+    pub version: i64,
+    pub event_type: String,
+    /// The payload as stored, so hashes over it still match. JSON is archived as a string,
+    /// binary payloads as `{"binary": "<base64>"}`.
+    #[serde(with = "archived_payload")]
+    pub data: Payload,
+    pub metadata: Option<String>,
+    /// Microseconds since the Unix epoch.
+    pub created_at: Option<i64>,
+    pub hash: Option<String>,
+}
+
+impl ArchivedEvent {
+    fn from_event(event: &Event) -> Result<ArchivedEvent, EventStoreError> {
+        let position = event.position.ok_or_else(|| archive_error("the engine returned a feed event without a position"))?;
+        Ok(ArchivedEvent {
+            position,
+            aggregate_id: event.aggregate_id,
+            aggregate_type: event.aggregate_type.clone(),
+            version: event.version,
+            event_type: event.event_type.clone(),
+            data: event.data.clone(),
+            metadata: event.metadata.clone(),
+            created_at: event.created_at.map(|created_at| created_at.timestamp_micros()),
+            hash: event.hash.clone(),
+        })
+    }
+
+    /// The event to write back, without a position; the target engine assigns a new one.
+    pub fn to_event(&self) -> Event {
+        let mut event = Event::from_payload(self.aggregate_id, &self.aggregate_type, self.version, &self.event_type, self.data.clone());
+        event.metadata = self.metadata.clone();
+        event.created_at = self.created_at.and_then(DateTime::<Utc>::from_timestamp_micros);
+        event.hash = self.hash.clone();
+        event
+    }
+}
+
+mod archived_payload {
+    use base64::{engine::general_purpose::STANDARD, Engine};
+    use serde::{de::Error, Deserialize, Deserializer, Serialize, Serializer};
+    use crate::payload::Payload;
+
+    #[derive(Serialize, Deserialize)]
+    #[serde(untagged)]
+    enum Archived {
+        Json(String),
+        Binary { binary: String },
+    }
+
+    pub fn serialize<S: Serializer>(payload: &Payload, serializer: S) -> Result<S::Ok, S::Error> {
+        match payload {
+            Payload::Json(json) => serializer.serialize_str(json),
+            Payload::Binary(bytes) => Archived::Binary { binary: STANDARD.encode(bytes) }.serialize(serializer),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Payload, D::Error> {
+        match Archived::deserialize(deserializer)? {
+            Archived::Json(json) => Ok(Payload::Json(json)),
+            Archived::Binary { binary } => STANDARD.decode(binary).map(Payload::Binary).map_err(D::Error::custom),
+        }
+    }
+}
+
+/// An aggregate instance as archived, with what its events do not record.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ArchivedInstance {
+    pub aggregate_type: String,
+    pub id: AggregateId,
+    pub natural_key: Option<String>,
+    /// Microseconds since the Unix epoch, for soft deleted instances.
+    pub deleted_at: Option<i64>,
+}
+
+impl ArchivedInstance {
+    fn from_instance(instance: &AggregateInstance) -> ArchivedInstance {
+        ArchivedInstance {
+            aggregate_type: instance.aggregate_type.clone(),
+            id: instance.id,
+            natural_key: instance.natural_key.clone(),
+            deleted_at: instance.deleted_at.map(|deleted_at| deleted_at.timestamp_micros()),
+        }
+    }
+}
+
+/// A lookup key as archived.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ArchivedLookupKey {
+    pub aggregate_type: String,
+    pub aggregate_id: AggregateId,
+    pub key_name: String,
+    pub key_value: String,
+}
+
+impl ArchivedLookupKey {
+    fn from_key(key: &LookupKey) -> ArchivedLookupKey {
+        ArchivedLookupKey {
+            aggregate_type: key.aggregate_type.clone(),
+            aggregate_id: key.aggregate_id,
+            key_name: key.key_name.clone(),
+            key_value: key.key_value.clone(),
+        }
+    }
+
+    pub fn to_lookup_key(&self) -> LookupKey {
+        LookupKey {
+            aggregate_id: self.aggregate_id,
+            aggregate_type: self.aggregate_type.clone(),
+            key_name: self.key_name.clone(),
+            key_value: self.key_value.clone(),
+        }
+    }
+}
+
+/// Contents of the state file: the instances and lookup keys of the store as of the last
+/// run of the archiver.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ArchivedState {
+    pub instances: Vec<ArchivedInstance>,
+    pub lookup_keys: Vec<ArchivedLookupKey>,
+}
+
+/// The state file of an archive.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StateInfo {
+    /// File name, relative to the archive directory.
+    pub file: String,
+    pub instances: usize,
+    pub lookup_keys: usize,
+    /// SHA-256 of the file contents, as lowercase hex.
+    pub sha256: String,
+}
+
+/// A segment file and the feed positions it covers.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SegmentInfo {
+    /// File name, relative to the archive directory.
+    pub file: String,
+    pub first_position: i64,
+    pub last_position: i64,
+    pub events: usize,
+    /// SHA-256 of the file contents, as lowercase hex.
+    pub sha256: String,
+}
+
+/// Lists the segments of an archive in feed order, and its state file. The archiver
+/// resumes after the last position it lists.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ArchiveManifest {
+    pub format_version: u32,
+    pub segments: Vec<SegmentInfo>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub state: Option<StateInfo>,
+}
+
+impl Default for ArchiveManifest {
+    fn default() -> ArchiveManifest {
+        ArchiveManifest {
+            format_version: ARCHIVE_FORMAT_VERSION,
+            segments: Vec::new(),
+            state: None,
+        }
+    }
+}
+
+impl ArchiveManifest {
+    /// Feed position of the last archived event, or 0 for an empty archive.
+    pub fn last_position(&self) -> i64 {
+        self.segments.last().map_or(0, |segment| segment.last_position)
+    }
+
+    /// Reads the manifest of the archive in `dir`; a directory without one is an empty archive.
+    pub async fn read(dir: &Path) -> Result<ArchiveManifest, EventStoreError> {
+        let bytes = match tokio::fs::read(dir.join(MANIFEST_FILE)).await {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(ArchiveManifest::default()),
+            Err(e) => return Err(archive_error(e)),
+        };
+        let manifest: ArchiveManifest = serde_json::from_slice(&bytes).map_err(archive_error)?;
+        if !(1..=ARCHIVE_FORMAT_VERSION).contains(&manifest.format_version) {
+            return Err(archive_error(format!("unsupported archive format version {}", manifest.format_version)));
+        }
+        Ok(manifest)
+    }
+}
+
+/// What a run of the archiver wrote.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ArchiveReport {
+    pub segments_written: usize,
+    pub events_archived: usize,
+    /// Feed position the archive reaches after the run.
+    pub position: i64,
+}
+
+/// Exports the global feed of a store to a directory, for disaster recovery. Created with
+/// `EventStore::archiver`; run it periodically with `MaintenanceScheduler::archive`.
+///
+/// Each run writes the events committed since the previous one as NDJSON segment files of
+/// up to `segment_size` events, then records them in the manifest. It also replaces the
+/// state file, which holds the natural keys, soft deletes and lookup keys the feed does not
+/// record; the engine has to list instances and lookup keys. Files are written aside and
+/// renamed, so an interrupted run leaves the archive as the previous run left it. Restore
+/// it with `restore_from_archive`.
+#[derive(Clone)]
+pub struct Archiver {
+    event_store: SharedEventStore,
+    sink_dir: PathBuf,
+    segment_size: usize,
+}
+
+impl Archiver {
+    pub(crate) fn new(event_store: SharedEventStore, sink_dir: PathBuf, segment_size: usize) -> Archiver {
+        Archiver {
+            event_store,
+            sink_dir,
+            segment_size,
+        }
+    }
+
+    pub fn sink_dir(&self) -> &Path {
+        &self.sink_dir
+    }
+
+    pub async fn manifest(&self) -> Result<ArchiveManifest, EventStoreError> {
+        ArchiveManifest::read(&self.sink_dir).await
+    }
+
+    /// Archives every event committed since the last run. The last segment written may
+    /// hold fewer than `segment_size` events; the next run starts a new one.
+    pub async fn run_once(&self) -> Result<ArchiveReport, EventStoreError> {
+        if self.segment_size == 0 {
+            return Err(EventStoreError::InvalidArgument {
+                parameter: "segment_size".to_string(),
+                value: "0".to_string(),
+                reason: "must be at least 1".to_string(),
+            });
+        }
+        tokio::fs::create_dir_all(&self.sink_dir).await.map_err(archive_error)?;
+        let mut manifest = self.manifest().await?;
+        let mut report = ArchiveReport {
+            position: manifest.last_position(),
+            ..ArchiveReport::default()
+        };
+
+        loop {
+            let events = self.event_store.storage_engine.read_all_events(report.position, self.segment_size).await?;
+            if events.is_empty() {
+                break;
+            }
+            let archived = events.iter().map(ArchivedEvent::from_event).collect::<Result<Vec<_>, _>>()?;
+            let mut contents = String::new();
+            for event in &archived {
+                contents.push_str(&serde_json::to_string(event).map_err(archive_error)?);
+                contents.push('\n');
+            }
+
+            let segment = SegmentInfo {
+                file: format!("segment-{:08}.ndjson", manifest.segments.len() + 1),
+                first_position: archived[0].position,
+                last_position: archived[archived.len() - 1].position,
+                events: archived.len(),
+                sha256: sha256_hex(contents.as_bytes()),
+            };
+            write_aside(&self.sink_dir.join(&segment.file), contents.as_bytes()).await?;
+            report.position = segment.last_position;
+            manifest.segments.push(segment);
+            let manifest_json = serde_json::to_vec_pretty(&manifest).map_err(archive_error)?;
+            write_aside(&self.sink_dir.join(MANIFEST_FILE), &manifest_json).await?;
+
+            report.segments_written += 1;
+            report.events_archived += archived.len();
+            if events.len() < self.segment_size {
+                break;
+            }
+        }
+
+        let state = self.capture_state().await?;
+        let contents = serde_json::to_vec(&state).map_err(archive_error)?;
+        let sha256 = sha256_hex(&contents);
+        if manifest.state.as_ref().map(|info| &info.sha256) != Some(&sha256) {
+            let info = StateInfo {
+                file: format!("state-{}.json", &sha256[..16]),
+                instances: state.instances.len(),
+                lookup_keys: state.lookup_keys.len(),
+                sha256,
+            };
+            write_aside(&self.sink_dir.join(&info.file), &contents).await?;
+            let previous = manifest.state.replace(info);
+            manifest.format_version = ARCHIVE_FORMAT_VERSION;
+            let manifest_json = serde_json::to_vec_pretty(&manifest).map_err(archive_error)?;
+            write_aside(&self.sink_dir.join(MANIFEST_FILE), &manifest_json).await?;
+            if let Some(previous) = previous {
+                // A leftover file is harmless, the manifest no longer lists it.
+                let _ = tokio::fs::remove_file(self.sink_dir.join(previous.file)).await;
+            }
+        }
+        Ok(report)
+    }
+
+    /// Reads the instances and lookup keys of every aggregate type.
+    async fn capture_state(&self) -> Result<ArchivedState, EventStoreError> {
+        let engine = &self.event_store.storage_engine;
+        let mut state = ArchivedState::default();
+        for aggregate_type in engine.list_aggregate_types(false).await? {
+            let mut offset = 0;
+            loop {
+                let instances = engine.list_aggregate_instances(&aggregate_type.name, offset, STATE_PAGE_SIZE).await?;
+                offset += instances.len();
+                state.instances.extend(instances.iter().map(ArchivedInstance::from_instance));
+                if instances.len() < STATE_PAGE_SIZE {
+                    break;
+                }
+            }
+            let mut offset = 0;
+            loop {
+                let keys = engine.list_lookup_keys(&aggregate_type.name, offset, STATE_PAGE_SIZE).await?;
+                offset += keys.len();
+                state.lookup_keys.extend(keys.iter().map(ArchivedLookupKey::from_key));
+                if keys.len() < STATE_PAGE_SIZE {
+                    break;
+                }
+            }
+        }
+        Ok(state)
+    }
+}
+
+/// What `restore_from_archive` wrote.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RestoreReport {
+    pub segments: usize,
+    pub events: usize,
+    pub aggregates: usize,
+    pub lookup_keys: usize,
+}
+
+/// Rebuilds an empty store from the archive in `dir`, one segment per write.
+///
+/// The state file and every segment are checked against the hashes in the manifest, and
+/// hashed events against the chain of their stream, before anything is written, so a
+/// damaged archive leaves the store empty. Aggregate instances keep their ids, natural keys
+/// and soft deletes, and lookup keys are restored; snapshots are not archived. Archives of
+/// format version 1 hold no state file, their instances are restored without natural keys.
+/// The engine needs a global feed, to tell that it is empty.
+pub async fn restore_from_archive(dir: impl AsRef<Path>, engine: Arc<dyn EventStoreStorageEngine + Send + Sync>) -> Result<RestoreReport, EventStoreError> {
+    let dir = dir.as_ref();
+    if engine.head_position().await? != 0 {
+        return Err(archive_error("the store to restore into is not empty"));
+    }
+    let manifest_path = dir.join(MANIFEST_FILE);
+    if !tokio::fs::try_exists(&manifest_path).await.map_err(archive_error)? {
+        return Err(archive_error(format!("{} not found", manifest_path.display())));
+    }
+    let manifest = ArchiveManifest::read(dir).await?;
+
+    let state = read_state(dir, &manifest).await?;
+    let mut verifier = SegmentReader::default();
+    for segment in &manifest.segments {
+        verifier.read(dir, segment).await?;
+    }
+
+    let mut report = RestoreReport::default();
+    let mut restored: HashSet<(String, AggregateId)> = HashSet::new();
+    for instance in &state.instances {
+        engine.restore_aggregate_instance(&instance.aggregate_type, instance.id, instance.natural_key.as_deref()).await?;
+        restored.insert((instance.aggregate_type.clone(), instance.id));
+    }
+    let mut reader = SegmentReader::default();
+    for segment in &manifest.segments {
+        let events = reader.read(dir, segment).await?;
+        for event in &events {
+            if restored.insert((event.aggregate_type.clone(), event.aggregate_id)) {
+                engine.restore_aggregate_instance(&event.aggregate_type, event.aggregate_id, None).await?;
+            }
+        }
+        engine.write_updates(&events, &[]).await?;
+        report.segments += 1;
+        report.events += events.len();
+    }
+    for key in &state.lookup_keys {
+        engine.add_lookup_key(&key.to_lookup_key()).await?;
+    }
+    // Deleted last, as the events of deleted instances are written like any other.
+    for instance in &state.instances {
+        if let Some(deleted_at) = instance.deleted_at.and_then(DateTime::<Utc>::from_timestamp_micros) {
+            engine.soft_delete_aggregate(&instance.aggregate_type, instance.id, deleted_at).await?;
+        }
+    }
+    report.aggregates = restored.len();
+    report.lookup_keys = state.lookup_keys.len();
+    Ok(report)
+}
+
+/// Reads and checks the state file listed in the manifest; archives without one have no
+/// state to restore.
+async fn read_state(dir: &Path, manifest: &ArchiveManifest) -> Result<ArchivedState, EventStoreError> {
+    let Some(info) = &manifest.state else {
+        return Ok(ArchivedState::default());
+    };
+    let contents = tokio::fs::read(dir.join(&info.file)).await.map_err(archive_error)?;
+    if sha256_hex(&contents) != info.sha256 {
+        return Err(archive_error(format!("state file {} does not match its hash", info.file)));
+    }
+    let state: ArchivedState = serde_json::from_slice(&contents).map_err(archive_error)?;
+    if state.instances.len() != info.instances || state.lookup_keys.len() != info.lookup_keys {
+        return Err(archive_error(format!("state file {} does not hold the entries listed in the manifest", info.file)));
+    }
+    for instance in &state.instances {
+        if instance.deleted_at.is_some_and(|micros| DateTime::<Utc>::from_timestamp_micros(micros).is_none()) {
+            return Err(archive_error(format!("instance {} has a deletion time out of range", instance.id)));
+        }
+    }
+    Ok(state)
+}
+
+/// Reads the segments of an archive in order, checking them against the manifest, the feed
+/// order and the hash chains of their streams.
+#[derive(Default)]
+struct SegmentReader {
+    position: i64,
+    chains: HashMap<(String, AggregateId), Option<String>>,
+}
+
+impl SegmentReader {
+    async fn read(&mut self, dir: &Path, segment: &SegmentInfo) -> Result<Vec<Event>, EventStoreError> {
+        let contents = tokio::fs::read(dir.join(&segment.file)).await.map_err(archive_error)?;
+        if sha256_hex(&contents) != segment.sha256 {
+            return Err(archive_error(format!("segment {} does not match its hash", segment.file)));
+        }
+
+        let mut events = Vec::with_capacity(segment.events);
+        for line in contents.split(|byte| *byte == b'\n').filter(|line| !line.is_empty()) {
+            let archived: ArchivedEvent = serde_json::from_slice(line).map_err(archive_error)?;
+            if archived.position <= self.position {
+                return Err(archive_error(format!("segment {} is out of feed order at position {}", segment.file, archived.position)));
+            }
+            self.position = archived.position;
+
+            let event = archived.to_event();
+            let previous = self.chains.entry((event.aggregate_type.clone(), event.aggregate_id)).or_default();
+            verify_chain(previous.as_deref(), std::slice::from_ref(&event))?;
+            previous.clone_from(&event.hash);
+            events.push(event);
+        }
+        if events.len() != segment.events || self.position != segment.last_position {
+            return Err(archive_error(format!("segment {} does not hold the events listed in the manifest", segment.file)));
+        }
+        Ok(events)
+    }
+}
+
+fn sha256_hex(bytes: &[u8]) -> String {
+    Sha256::digest(bytes).iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Writes the file under a temporary name first and renames it into place.
+async fn write_aside(path: &Path, bytes: &[u8]) -> Result<(), EventStoreError> {
+    let partial = path.with_file_name(format!("{}.partial", path.file_name().unwrap_or_default().to_string_lossy()));
+    tokio::fs::write(&partial, bytes).await.map_err(archive_error)?;
+    tokio::fs::rename(&partial, path).await.map_err(archive_error)
+}
+
+#[cfg(all(test, feature = "memory"))]
+mod tests {
+    use serde::{Deserialize, Serialize};
+    use super::*;
+    use crate::aggregate::{Aggregate, Composable, ComposedAggregate};
+    use crate::{memory::MemoryStorageEngine, EventStore};
+
+    #[derive(Clone, Default, Serialize, Deserialize)]
+    struct Tally {
+        total: i64,
+    }
+
+    impl Composable for Tally {
+        fn get_type(&self) -> &str {
+            "tally"
+        }
+
+        fn apply_event(&mut self, event: &Event) -> Result<(), EventStoreError> {
+            self.total += event.deserialize::<i64>()?;
+            Ok(())
+        }
+
+        fn snapshot_frequency(&self) -> i32 {
+            0
+        }
+    }
+
+    fn feed(events: &[Event]) -> Vec<(AggregateId, i64, String, Payload, Option<String>)> {
+        events.iter()
+            .map(|event| (event.aggregate_id, event.version, event.event_type.clone(), event.data.clone(), event.hash.clone()))
+            .collect()
+    }
+
+    fn archive_dir() -> PathBuf {
+        std::env::temp_dir().join(format!("evercore-archive-{}", uuid::Uuid::new_v4()))
+    }
+
+    #[tokio::test]
+    async fn ensure_archives_resume_and_restore() {
+        let source = MemoryStorageEngine::new();
+        let event_store = EventStore::builder(source.clone()).hash_events(true).build().unwrap();
+        let dir = archive_dir();
+        let archiver = event_store.archiver(&dir, 2);
+
+        let ctx = event_store.get_context();
+        let mut first = ComposedAggregate::<Tally>::new(&ctx, None).await.unwrap();
+        let mut second = ComposedAggregate::<Tally>::new(&ctx, None).await.unwrap();
+        for amount in 1..=5i64 {
+            let tally = if amount % 2 == 0 { &mut second } else { &mut first };
+            ctx.publish(tally, "added", &amount).unwrap();
+        }
+        ctx.commit().await.unwrap();
+
+        let report = archiver.run_once().await.unwrap();
+        assert_eq!((report.segments_written, report.events_archived, report.position), (3, 5, 5));
+
+        let ctx = event_store.get_context();
+        let mut third = ComposedAggregate::<Tally>::new(&ctx, None).await.unwrap();
+        ctx.publish(&mut third, "added", &10i64).unwrap();
+        ctx.commit().await.unwrap();
+        let report = archiver.run_once().await.unwrap();
+        assert_eq!((report.segments_written, report.events_archived, report.position), (1, 1, 6));
+        assert_eq!(archiver.run_once().await.unwrap().segments_written, 0);
+        let manifest = archiver.manifest().await.unwrap();
+        assert_eq!(manifest.segments.iter().map(|segment| segment.events).collect::<Vec<_>>(), vec![2, 2, 1, 1]);
+
+        let target = MemoryStorageEngine::new();
+        let restored = restore_from_archive(&dir, target.clone()).await.unwrap();
+        assert_eq!((restored.segments, restored.events, restored.aggregates), (4, 6, 3));
+        assert_eq!(feed(&target.read_all_events(0, usize::MAX).await.unwrap()), feed(&source.read_all_events(0, usize::MAX).await.unwrap()));
+
+        let restored_store = EventStore::builder(target.clone()).verify_hashes(true).build().unwrap();
+        let ctx = restored_store.get_context();
+        let tally = ComposedAggregate::<Tally>::load(&ctx, second.id()).await.unwrap();
+        assert_eq!(tally.state().total, 6);
+        let fourth = ComposedAggregate::<Tally>::new(&ctx, None).await.unwrap();
+        assert!(fourth.id() > third.id());
+
+        let result = restore_from_archive(&dir, target).await;
+        assert!(matches!(result, Err(EventStoreError::ArchiveError(_))));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn ensure_tampered_archives_are_not_restored() {
+        let event_store = EventStore::builder(MemoryStorageEngine::new()).hash_events(true).build().unwrap();
+        let dir = archive_dir();
+        let ctx = event_store.get_context();
+        let mut tally = ComposedAggregate::<Tally>::new(&ctx, None).await.unwrap();
+        for amount in 1..=3i64 {
+            ctx.publish(&mut tally, "added", &amount).unwrap();
+        }
+        ctx.commit().await.unwrap();
+        event_store.archiver(&dir, 10).run_once().await.unwrap();
+
+        let segment = dir.join("segment-00000001.ndjson");
+        let original = std::fs::read_to_string(&segment).unwrap();
+        let altered = original.replacen(r#""data":"2""#, r#""data":"20""#, 1);
+        std::fs::write(&segment, &altered).unwrap();
+        let result = restore_from_archive(&dir, MemoryStorageEngine::new()).await;
+        assert!(matches!(result, Err(EventStoreError::ArchiveError(_))));
+
+        // A manifest updated along with the segment still leaves the hash chain broken.
+        let mut manifest = ArchiveManifest::read(&dir).await.unwrap();
+        manifest.segments[0].sha256 = sha256_hex(altered.as_bytes());
+        std::fs::write(dir.join(MANIFEST_FILE), serde_json::to_vec(&manifest).unwrap()).unwrap();
+        let result = restore_from_archive(&dir, MemoryStorageEngine::new()).await;
+        assert!(matches!(result, Err(EventStoreError::IntegrityViolation { version: 2 })));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn ensure_damaged_archives_leave_the_store_empty() {
+        let event_store = EventStore::builder(MemoryStorageEngine::new()).hash_events(true).build().unwrap();
+        let dir = archive_dir();
+        let ctx = event_store.get_context();
+        let mut tally = ComposedAggregate::<Tally>::new(&ctx, None).await.unwrap();
+        for amount in 1..=3i64 {
+            ctx.publish(&mut tally, "added", &amount).unwrap();
+        }
+        ctx.commit().await.unwrap();
+        event_store.archiver(&dir, 1).run_once().await.unwrap();
+
+        let segment = dir.join("segment-00000003.ndjson");
+        let original = std::fs::read(&segment).unwrap();
+        std::fs::write(&segment, b"damaged").unwrap();
+        let target = MemoryStorageEngine::new();
+        let result = restore_from_archive(&dir, target.clone()).await;
+        assert!(matches!(result, Err(EventStoreError::ArchiveError(_))));
+        assert_eq!(target.head_position().await.unwrap(), 0);
+        assert!(target.list_aggregate_instances("tally", 0, usize::MAX).await.unwrap().is_empty());
+
+        std::fs::write(&segment, original).unwrap();
+        assert_eq!(restore_from_archive(&dir, target).await.unwrap().events, 3);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn ensure_natural_keys_lookup_keys_and_deletions_are_restored() {
+        let event_store = EventStore::new(MemoryStorageEngine::new());
+        let dir = archive_dir();
+        let archiver = event_store.archiver(&dir, 10);
+        let ctx = event_store.get_context();
+        let mut alice = ComposedAggregate::<Tally>::new(&ctx, Some("alice")).await.unwrap();
+        let mut bob = ComposedAggregate::<Tally>::new(&ctx, Some("bob")).await.unwrap();
+        ctx.publish(&mut alice, "added", &1i64).unwrap();
+        ctx.publish(&mut bob, "added", &2i64).unwrap();
+        alice.add_lookup_key("team", "red").unwrap();
+        bob.add_lookup_key("team", "red").unwrap();
+        ctx.commit().await.unwrap();
+        archiver.run_once().await.unwrap();
+        let first_state = archiver.manifest().await.unwrap().state.unwrap();
+
+        // State changes without new events still reach the archive.
+        bob.remove_lookup_key("team", "red").unwrap();
+        ctx.commit().await.unwrap();
+        event_store.soft_delete_aggregate("tally", bob.id()).await.unwrap();
+        assert_eq!(archiver.run_once().await.unwrap().segments_written, 0);
+        let state = archiver.manifest().await.unwrap().state.unwrap();
+        assert_eq!((state.instances, state.lookup_keys), (2, 1));
+        assert!(!dir.join(&first_state.file).exists());
+
+        let target = MemoryStorageEngine::new();
+        let report = restore_from_archive(&dir, target.clone()).await.unwrap();
+        assert_eq!((report.events, report.aggregates, report.lookup_keys), (2, 2, 1));
+        let restored_store = EventStore::new(target.clone());
+        let ctx = restored_store.get_context();
+        let restored = ComposedAggregate::<Tally>::load_by_key(&ctx, "alice").await.unwrap();
+        assert_eq!((restored.id(), restored.state().total), (alice.id(), 1));
+        assert_eq!(target.find_by_lookup_key("tally", "team", "red").await.unwrap(), vec![alice.id()]);
+        assert!(target.aggregate_deleted_at("tally", bob.id()).await.unwrap().is_some());
+        assert!(target.aggregate_deleted_at("tally", alice.id()).await.unwrap().is_none());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,116 @@
+use std::fmt::Display;
+use crate::{AggregateId, EventStoreError};
+
+// Checks for arguments crossing the public API, run before any I/O. Storage engines call
+// them as well, so callers going straight to an engine are protected the same way.
+
+fn invalid(parameter: &str, value: impl Display, reason: &str) -> EventStoreError {
+    EventStoreError::InvalidArgument {
+        parameter: parameter.to_string(),
+        value: value.to_string(),
+        reason: reason.to_string(),
+    }
+}
+
+/// Versions and positions to read from or up to start at 0, meaning before the first event.
+pub fn non_negative(parameter: &str, value: i64) -> Result<i64, EventStoreError> {
+    match value {
+        0.. => Ok(value),
+        _ => Err(invalid(parameter, value, "must not be negative")),
+    }
+}
+
+/// Event versions start at 1.
+pub fn event_version(value: i64) -> Result<i64, EventStoreError> {
+    match value {
+        1.. => Ok(value),
+        _ => Err(invalid("version", value, "must be at least 1")),
+    }
+}
+
+/// Engines hand out aggregate ids from 1.
+#[cfg(not(feature = "uuid-ids"))]
+pub fn aggregate_id(value: AggregateId) -> Result<AggregateId, EventStoreError> {
+    match value {
+        1.. => Ok(value),
+        _ => Err(invalid("aggregate_id", value, "must be positive")),
+    }
+}
+
+/// Engines never hand out the nil uuid.
+#[cfg(feature = "uuid-ids")]
+pub fn aggregate_id(value: AggregateId) -> Result<AggregateId, EventStoreError> {
+    match value.is_nil() {
+        false => Ok(value),
+        true => Err(invalid("aggregate_id", value, "must not be nil")),
+    }
+}
+
+/// Longest tenant id accepted, so ids fit the tenant columns of every engine.
+pub const MAX_TENANT_ID_LEN: usize = 64;
+
+/// Tenant ids are non-empty, at most `MAX_TENANT_ID_LEN` long and made of ASCII letters,
+/// digits, '-', '_' and '.', so they are safe to use in keys, paths and log lines.
+pub fn tenant_id(value: &str) -> Result<&str, EventStoreError> {
+    if value.is_empty() {
+        return Err(invalid("tenant_id", value, "must not be empty"));
+    }
+    if value.len() > MAX_TENANT_ID_LEN {
+        return Err(invalid("tenant_id", value, &format!("must be at most {} characters", MAX_TENANT_ID_LEN)));
+    }
+    match value.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.')) {
+        true => Ok(value),
+        false => Err(invalid("tenant_id", value, "may only contain ASCII letters, digits, '-', '_' and '.'")),
+    }
+}
+
+/// Page sizes are capped at the store's `max_page_size`.
+pub fn limit(value: usize, max: usize) -> Result<usize, EventStoreError> {
+    match value <= max {
+        true => Ok(value),
+        false => Err(invalid("limit", value, &format!("must be at most {}", max))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rejected(result: Result<impl Sized, EventStoreError>) -> (String, String) {
+        match result {
+            Err(EventStoreError::InvalidArgument { parameter, value, .. }) => (parameter, value),
+            Err(other) => panic!("unexpected error {:?}", other),
+            Ok(_) => panic!("argument was accepted"),
+        }
+    }
+
+    #[test]
+    fn ensure_out_of_range_arguments_are_rejected() {
+        for value in [-1, -5, i64::MIN] {
+            assert_eq!(rejected(non_negative("max_version", value)), ("max_version".to_string(), value.to_string()));
+        }
+        for value in [0, -1, i64::MIN] {
+            assert_eq!(rejected(event_version(value)), ("version".to_string(), value.to_string()));
+        }
+        #[cfg(not(feature = "uuid-ids"))]
+        for value in [0, -1, i64::MIN] {
+            assert_eq!(rejected(aggregate_id(value)), ("aggregate_id".to_string(), value.to_string()));
+        }
+        #[cfg(feature = "uuid-ids")]
+        rejected(aggregate_id(uuid::Uuid::nil()));
+        for value in [11, usize::MAX] {
+            assert_eq!(rejected(limit(value, 10)), ("limit".to_string(), value.to_string()));
+        }
+        let too_long = "t".repeat(MAX_TENANT_ID_LEN + 1);
+        for value in ["", "acme corp", "acme/north", "ücme", too_long.as_str()] {
+            assert_eq!(rejected(tenant_id(value)), ("tenant_id".to_string(), value.to_string()));
+        }
+
+        assert_eq!(non_negative("version", 0).unwrap(), 0);
+        assert_eq!(event_version(1).unwrap(), 1);
+        #[cfg(not(feature = "uuid-ids"))]
+        assert_eq!(aggregate_id(1).unwrap(), 1);
+        assert_eq!(limit(10, 10).unwrap(), 10);
+        assert_eq!(tenant_id("acme-north_1.eu").unwrap(), "acme-north_1.eu");
+    }
+}
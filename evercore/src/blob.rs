@@ -0,0 +1,244 @@
+use std::borrow::Cow;
+use std::sync::Arc;
+use serde::{Deserialize, Serialize};
+use crate::EventStoreError;
+
+/// Name of the field marking a stored payload as a pointer into a blob store.
+pub const POINTER_FIELD: &str = "__blob";
+
+/// External storage for payloads too large to keep in the database.
+#[async_trait::async_trait]
+pub trait BlobStore: Send + Sync {
+    /// Stores `bytes` under `key`, returning the uri recorded in the pointer document.
+    async fn put(&self, key: &str, bytes: &[u8]) -> Result<String, EventStoreError>;
+
+    /// Reads the bytes stored at `uri`.
+    async fn get(&self, uri: &str) -> Result<Vec<u8>, EventStoreError>;
+
+    /// Deletes the bytes stored at `uri`. Deleting a missing blob is not an error.
+    async fn delete(&self, uri: &str) -> Result<(), EventStoreError>;
+}
+
+/// The document persisted in place of an offloaded payload.
+///
+/// The format is stable: `{"__blob":"<uri>","size":<bytes>}`, compact, with the fields in that
+/// order. A payload that is an object with exactly these two fields is read as a pointer, so
+/// aggregates must not use that shape for their own data.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BlobPointer {
+    #[serde(rename = "__blob")]
+    pub uri: String,
+    pub size: u64,
+}
+
+impl BlobPointer {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("a pointer always serializes")
+    }
+
+    /// Reads a stored payload as a pointer, or None if it holds data of its own.
+    pub fn parse(data: &str) -> Option<BlobPointer> {
+        if !data.trim_start().starts_with('{') || !data.contains(POINTER_FIELD) {
+            return None;
+        }
+        serde_json::from_str(data).ok()
+    }
+}
+
+/// Stored payloads that may be moved to a blob store.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum BlobColumn {
+    SnapshotData,
+    EventData,
+}
+
+impl BlobColumn {
+    fn key_prefix(self) -> &'static str {
+        match self {
+            BlobColumn::SnapshotData => "snapshots",
+            BlobColumn::EventData => "events",
+        }
+    }
+}
+
+/// Moves payloads over a size threshold to a blob store, leaving a [`BlobPointer`] behind.
+/// Offloads snapshot data unless configured otherwise.
+#[derive(Clone)]
+pub struct BlobOffload {
+    store: Arc<dyn BlobStore>,
+    threshold: usize,
+    columns: Vec<BlobColumn>,
+}
+
+impl BlobOffload {
+    /// Offloads snapshot payloads longer than `threshold` bytes.
+    pub fn new(store: Arc<dyn BlobStore>, threshold: usize) -> BlobOffload {
+        BlobOffload {
+            store,
+            threshold,
+            columns: vec![BlobColumn::SnapshotData],
+        }
+    }
+
+    /// Also offloads payloads stored in `column`.
+    pub fn column(mut self, column: BlobColumn) -> BlobOffload {
+        if !self.columns.contains(&column) {
+            self.columns.push(column);
+        }
+        self
+    }
+
+    pub fn threshold(&self) -> usize {
+        self.threshold
+    }
+
+    pub fn offloads(&self, column: BlobColumn) -> bool {
+        self.columns.contains(&column)
+    }
+
+    /// Returns what to persist for a payload: the payload itself, or a pointer once it has
+    /// been written to the blob store. `name` identifies the payload within its column; a
+    /// unique suffix is added so rewrites never replace a blob another row points to.
+    pub async fn offload<'a>(&self, column: BlobColumn, name: &str, data: &'a str) -> Result<Cow<'a, str>, EventStoreError> {
+        if !self.offloads(column) || data.len() <= self.threshold {
+            return Ok(Cow::Borrowed(data));
+        }
+        let key = format!("{}/{}-{}", column.key_prefix(), name, crate::integrity::unique_token());
+        let uri = self.store.put(&key, data.as_bytes()).await?;
+        let pointer = BlobPointer {
+            uri,
+            size: data.len() as u64,
+        };
+        Ok(Cow::Owned(pointer.to_json()))
+    }
+
+    /// Replaces a pointer with the payload it points to. Other payloads are returned as is,
+    /// whichever columns are configured, so turning offloading off keeps old rows readable.
+    pub async fn resolve(&self, data: String) -> Result<String, EventStoreError> {
+        let Some(pointer) = BlobPointer::parse(&data) else {
+            return Ok(data);
+        };
+        let bytes = self.store.get(&pointer.uri).await?;
+        if bytes.len() as u64 != pointer.size {
+            return Err(EventStoreError::BlobStoreError(
+                format!("blob {} holds {} bytes, expected {}", pointer.uri, bytes.len(), pointer.size).into()));
+        }
+        String::from_utf8(bytes).map_err(|e| EventStoreError::BlobStoreError(Box::new(e)))
+    }
+
+    /// Deletes the blob a stored payload points to, returning whether it was a pointer.
+    pub async fn release(&self, data: &str) -> Result<bool, EventStoreError> {
+        match BlobPointer::parse(data) {
+            Some(pointer) => {
+                self.store.delete(&pointer.uri).await?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+}
+
+/// A blob store keeping each blob as a file below a root directory, addressed by
+/// `file://` uris.
+#[cfg(feature = "blobs")]
+pub struct FsBlobStore {
+    root: std::path::PathBuf,
+}
+
+#[cfg(feature = "blobs")]
+impl FsBlobStore {
+    pub fn new(root: impl Into<std::path::PathBuf>) -> FsBlobStore {
+        FsBlobStore { root: root.into() }
+    }
+
+    /// Keys are relative paths; anything that could step outside the root is rejected.
+    fn path_for_key(&self, key: &str) -> Result<std::path::PathBuf, EventStoreError> {
+        let relative = std::path::Path::new(key);
+        let plain = relative.components().all(|component| matches!(component, std::path::Component::Normal(_)));
+        if key.is_empty() || !plain {
+            return Err(EventStoreError::BlobStoreError(format!("invalid blob key '{key}'").into()));
+        }
+        Ok(self.root.join(relative))
+    }
+
+    fn path_for_uri(&self, uri: &str) -> Result<std::path::PathBuf, EventStoreError> {
+        let path = uri.strip_prefix("file://").map(std::path::Path::new);
+        match path.and_then(|path| path.strip_prefix(&self.root).ok()) {
+            Some(relative) => self.path_for_key(&relative.to_string_lossy()),
+            None => Err(EventStoreError::BlobStoreError(format!("blob uri '{uri}' is outside {}", self.root.display()).into())),
+        }
+    }
+}
+
+#[cfg(feature = "blobs")]
+#[async_trait::async_trait]
+impl BlobStore for FsBlobStore {
+    async fn put(&self, key: &str, bytes: &[u8]) -> Result<String, EventStoreError> {
+        let path = self.path_for_key(key)?;
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await.map_err(|e| EventStoreError::BlobStoreError(Box::new(e)))?;
+        }
+        // Written aside and renamed so a reader never sees a partial blob.
+        let partial = path.with_file_name(format!("{}.partial", path.file_name().unwrap_or_default().to_string_lossy()));
+        tokio::fs::write(&partial, bytes).await.map_err(|e| EventStoreError::BlobStoreError(Box::new(e)))?;
+        tokio::fs::rename(&partial, &path).await.map_err(|e| EventStoreError::BlobStoreError(Box::new(e)))?;
+        Ok(format!("file://{}", path.display()))
+    }
+
+    async fn get(&self, uri: &str) -> Result<Vec<u8>, EventStoreError> {
+        let path = self.path_for_uri(uri)?;
+        tokio::fs::read(&path).await.map_err(|e| EventStoreError::BlobStoreError(Box::new(e)))
+    }
+
+    async fn delete(&self, uri: &str) -> Result<(), EventStoreError> {
+        let path = self.path_for_uri(uri)?;
+        match tokio::fs::remove_file(&path).await {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(EventStoreError::BlobStoreError(Box::new(e))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pointers_use_the_documented_format() {
+        let pointer = BlobPointer {
+            uri: "file:///blobs/snapshots/1".to_string(),
+            size: 42,
+        };
+        assert_eq!(pointer.to_json(), r#"{"__blob":"file:///blobs/snapshots/1","size":42}"#);
+        assert_eq!(BlobPointer::parse(&pointer.to_json()), Some(pointer));
+        assert_eq!(BlobPointer::parse(r#"{"__blob":"x","size":1,"other":true}"#), None);
+        assert_eq!(BlobPointer::parse(r#"{"name":"__blob"}"#), None);
+        assert_eq!(BlobPointer::parse("[1,2,3]"), None);
+    }
+
+    #[cfg(feature = "blobs")]
+    #[tokio::test]
+    async fn fs_blob_store_offloads_and_releases_payloads() {
+        let root = std::env::temp_dir().join(format!("evercore-blobs-{}", crate::integrity::unique_token()));
+        let store = Arc::new(FsBlobStore::new(&root));
+        let offload = BlobOffload::new(store.clone(), 8);
+
+        let small = offload.offload(BlobColumn::SnapshotData, "1-1", "{}").await.unwrap();
+        assert_eq!(small, "{}");
+        let events = offload.offload(BlobColumn::EventData, "1-1", r#"{"large":true}"#).await.unwrap();
+        assert_eq!(events, r#"{"large":true}"#);
+
+        let stored = offload.offload(BlobColumn::SnapshotData, "1-2", r#"{"large":true}"#).await.unwrap().into_owned();
+        let pointer = BlobPointer::parse(&stored).unwrap();
+        assert!(pointer.uri.starts_with("file://"));
+        assert_eq!(offload.resolve(stored.clone()).await.unwrap(), r#"{"large":true}"#);
+
+        assert!(offload.release(&stored).await.unwrap());
+        assert!(offload.resolve(stored).await.is_err());
+        assert!(store.put("../escape", b"x").await.is_err());
+        assert!(store.get("file:///etc/hostname").await.is_err());
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
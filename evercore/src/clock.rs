@@ -0,0 +1,162 @@
+use std::{collections::HashMap, sync::Mutex};
+use chrono::{DateTime, Duration, Utc};
+use crate::{event::Event, AggregateId, EventStoreError};
+
+/// Source of the current time for event stamps, snapshot policies and retention.
+pub trait Clock: Send + Sync {
+    fn now(&self) -> DateTime<Utc>;
+}
+
+/// The system wall clock, used unless the store is built with another clock.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+/// A clock that only moves when told to, for tests and simulations.
+#[derive(Debug)]
+pub struct ManualClock {
+    now: Mutex<DateTime<Utc>>,
+}
+
+impl ManualClock {
+    pub fn new(start: DateTime<Utc>) -> ManualClock {
+        ManualClock {
+            now: Mutex::new(start),
+        }
+    }
+
+    pub fn set(&self, now: DateTime<Utc>) {
+        *self.now.lock().unwrap() = now;
+    }
+
+    pub fn advance(&self, by: Duration) {
+        *self.now.lock().unwrap() += by;
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> DateTime<Utc> {
+        *self.now.lock().unwrap()
+    }
+}
+
+/// Identifies an aggregate stream as (aggregate_type, aggregate_id).
+pub type StreamKey = (String, AggregateId);
+
+/// What a storage engine does when an event is stamped earlier than the event before it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ClockSkewPolicy {
+    /// Move created_at forward to the previous timestamp and mark the event `clock_adjusted`.
+    #[default]
+    Clamp,
+    /// Reject the write with `EventStoreError::ClockSkew`.
+    Fail,
+}
+
+/// Ensures created_at never goes backwards within an aggregate stream.
+///
+/// `heads` holds the stored head timestamp of each stream and is advanced as the events are
+/// checked, so later events in the same batch are compared against earlier ones.
+/// Events without a created_at are left alone.
+pub fn enforce_monotonic_created_at(
+    events: &mut [Event],
+    heads: &mut HashMap<StreamKey, DateTime<Utc>>,
+    policy: ClockSkewPolicy,
+) -> Result<(), EventStoreError> {
+    for event in events.iter_mut() {
+        let Some(created_at) = event.created_at else {
+            continue;
+        };
+        let key = (event.aggregate_type.clone(), event.aggregate_id);
+
+        match heads.get(&key) {
+            Some(head) if created_at < *head => match policy {
+                ClockSkewPolicy::Clamp => {
+                    event.created_at = Some(*head);
+                    event.mark_clock_adjusted()?;
+                }
+                ClockSkewPolicy::Fail => {
+                    return Err(EventStoreError::ClockSkew((key.0, key.1, event.version)));
+                }
+            },
+            _ => {
+                heads.insert(key, created_at);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Streams in the batch that carry timestamps and therefore need their stored head checked.
+pub fn stamped_streams(events: &[Event]) -> Vec<StreamKey> {
+    let mut streams: Vec<StreamKey> = Vec::new();
+    for event in events.iter().filter(|event| event.created_at.is_some()) {
+        let key = (event.aggregate_type.clone(), event.aggregate_id);
+        if !streams.contains(&key) {
+            streams.push(key);
+        }
+    }
+    streams
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use super::*;
+    use crate::event::numbered_id;
+
+    fn stamped(version: i64, offset_ms: i64) -> Event {
+        let mut event = Event::new(numbered_id(1), "test", version, "updated", &"data".to_string()).unwrap();
+        event.created_at = Some(Utc::now() + Duration::milliseconds(offset_ms));
+        event
+    }
+
+    #[test]
+    fn ensure_manual_clock_only_moves_when_told() {
+        let start = Utc::now();
+        let clock = ManualClock::new(start);
+        assert_eq!(clock.now(), start);
+        clock.advance(Duration::minutes(5));
+        assert_eq!(clock.now(), start + Duration::minutes(5));
+        clock.set(start);
+        assert_eq!(clock.now(), start);
+    }
+
+    #[test]
+    fn ensure_in_order_events_are_untouched() {
+        let mut events = vec![stamped(1, 0), stamped(2, 10)];
+        let expected: Vec<_> = events.iter().map(|event| event.created_at).collect();
+
+        enforce_monotonic_created_at(&mut events, &mut HashMap::new(), ClockSkewPolicy::Clamp).unwrap();
+
+        let actual: Vec<_> = events.iter().map(|event| event.created_at).collect();
+        assert_eq!(actual, expected);
+        assert!(events.iter().all(|event| event.metadata.is_none()));
+    }
+
+    #[test]
+    fn ensure_stored_head_is_respected() {
+        let mut events = vec![stamped(3, -5_000)];
+        let head = Utc::now();
+        let mut heads = HashMap::from([(("test".to_string(), numbered_id(1)), head)]);
+
+        enforce_monotonic_created_at(&mut events, &mut heads, ClockSkewPolicy::Clamp).unwrap();
+
+        assert_eq!(events[0].created_at, Some(head));
+        assert_eq!(events[0].metadata_keys(), vec!["clock_adjusted".to_string()]);
+    }
+
+    #[test]
+    fn ensure_other_streams_do_not_interfere() {
+        let mut events = vec![stamped(1, 0), stamped(1, -5_000)];
+        events[1].aggregate_id = numbered_id(2);
+
+        enforce_monotonic_created_at(&mut events, &mut HashMap::new(), ClockSkewPolicy::Fail).unwrap();
+        assert!(events[1].metadata.is_none());
+    }
+}
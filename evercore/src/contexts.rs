@@ -1,55 +1,578 @@
-use std::{sync::Arc, collections::HashMap};
-use serde::de::DeserializeOwned;
+use std::{sync::Arc, collections::HashMap, future::Future, ops::Deref, pin::Pin, time::Duration};
+use tokio::time::Instant;
+use chrono::{DateTime, Utc};
+use crossbeam_queue::ArrayQueue;
+use uuid::Uuid;
+use serde::{Serialize, de::DeserializeOwned};
 use std::sync::Mutex;
-use crate::{EventStore, event::Event, EventStoreError, aggregate::Aggregate, snapshot::Snapshot};
+use std::sync::atomic::{AtomicBool, Ordering};
+use crate::{AggregateId, EventStore, event::Event, EventStoreError, aggregate::{Aggregate, Composable, LoadFuture}, snapshot::Snapshot, SharedEventContext, SharedEventStore};
+use crate::{AggregateInstance, CreateOutcome, DedupKey, ErrorContext, DuplicateKeyPolicy, DuplicatePolicy, LookupKey, LookupKeyChange, WriteBatch};
+use crate::storage_engine::{enriched, WrittenEvent};
+use crate::{clock::StreamKey, snapshot::SnapshotCheck};
+use crate::event::{COMMAND_PAYLOAD_KEY, COMMAND_TYPE_KEY};
+use crate::integrity::canonical_json;
+use crate::cursor::ViewToken;
+use crate::arguments;
+use crate::metrics::LoadStats;
+use crate::deadline;
+use crate::metadata::missing_keys;
 
+/// Metadata key under which `EventContext::link_to_saga` records the saga of each event.
+pub const SAGA_ID_KEY: &str = "_saga_id";
+
+/// A command recorded in the metadata of the event it produced.
+pub(crate) struct RecordedCommand {
+    pub command_type: &'static str,
+    /// The command as JSON, with redacted fields masked.
+    pub payload: String,
+}
+
+/// Owned counterpart of `WriteBatch`, gathered from one or more contexts.
+#[derive(Default)]
+pub(crate) struct CapturedWrites {
+    pub events: Vec<Event>,
+    pub snapshots: Vec<Snapshot>,
+    pub lookup_keys: Vec<LookupKeyChange>,
+    pub dedup_keys: Vec<DedupKey>,
+}
+
+impl CapturedWrites {
+    pub fn batch(&self) -> WriteBatch<'_> {
+        WriteBatch {
+            events: &self.events,
+            snapshots: &self.snapshots,
+            lookup_keys: &self.lookup_keys,
+            dedup_keys: &self.dedup_keys,
+        }
+    }
+
+    pub fn counts(&self) -> CaptureCounts {
+        CaptureCounts {
+            events: self.events.len(),
+            snapshots: self.snapshots.len(),
+            lookup_keys: self.lookup_keys.len(),
+            dedup_keys: self.dedup_keys.len(),
+        }
+    }
+
+    pub fn extend(&mut self, other: CapturedWrites) {
+        self.events.extend(other.events);
+        self.snapshots.extend(other.snapshots);
+        self.lookup_keys.extend(other.lookup_keys);
+        self.dedup_keys.extend(other.dedup_keys);
+    }
+}
+
+/// How much of each capture buffer a batch took, to drain once it is written.
+#[derive(Clone, Copy)]
+pub(crate) struct CaptureCounts {
+    pub events: usize,
+    pub snapshots: usize,
+    pub lookup_keys: usize,
+    pub dedup_keys: usize,
+}
+
+/// Future returned by a hook registered with `EventContext::on_commit_async`.
+pub type CommitHookFuture = Pin<Box<dyn Future<Output = Result<(), EventStoreError>> + Send>>;
+
+type SyncCommitHook = Box<dyn FnOnce(&[Event]) -> Result<(), EventStoreError> + Send>;
+type AsyncCommitHook = Box<dyn FnOnce(Vec<Event>) -> CommitHookFuture + Send>;
+
+/// A hook registered with `EventContext::on_commit` or `on_commit_async`.
+pub(crate) enum CommitHook {
+    Sync(SyncCommitHook),
+    Async(AsyncCommitHook),
+}
+
+/// Runs hooks in registration order with the events their context committed, stopping at
+/// the first that fails.
+pub(crate) async fn run_commit_hooks(hooks: Vec<CommitHook>, events: &[Event]) -> Result<(), EventStoreError> {
+    for hook in hooks {
+        let result = match hook {
+            CommitHook::Sync(hook) => hook(events),
+            CommitHook::Async(hook) => hook(events.to_vec()).await,
+        };
+        if let Err(e) = result {
+            return Err(EventStoreError::PostCommitHookError(Box::new(e)));
+        }
+    }
+    Ok(())
+}
+
+/// What a successful commit wrote.
+#[derive(Clone, Debug, Default)]
+pub struct CommitReceipt {
+    /// The committed events in publish order, with the position and created_at the
+    /// engine assigned.
+    pub events: Vec<Event>,
+    /// Number of snapshots written with the events.
+    pub snapshot_count: usize,
+}
+
+impl CommitReceipt {
+    pub(crate) fn new(captured: &CapturedWrites, written: &[WrittenEvent]) -> CommitReceipt {
+        CommitReceipt {
+            events: enriched(&captured.events, written),
+            snapshot_count: captured.snapshots.len(),
+        }
+    }
+
+    pub fn event_count(&self) -> usize {
+        self.events.len()
+    }
+
+    /// Lowest feed position written, for engines keeping a global feed.
+    pub fn first_position(&self) -> Option<i64> {
+        self.events.iter().filter_map(|event| event.position).min()
+    }
+
+    /// Highest feed position written, which subscribers can catch up to.
+    pub fn last_position(&self) -> Option<i64> {
+        self.events.iter().filter_map(|event| event.position).max()
+    }
+
+    /// The aggregates that received events, in the order they first did.
+    pub fn aggregate_ids(&self) -> Vec<StreamKey> {
+        let mut ids: Vec<StreamKey> = Vec::new();
+        for event in &self.events {
+            if !ids.iter().any(|(aggregate_type, id)| *id == event.aggregate_id && *aggregate_type == event.aggregate_type) {
+                ids.push((event.aggregate_type.clone(), event.aggregate_id));
+            }
+        }
+        ids
+    }
+}
+
+/// What the context knows of an aggregate's snapshots, for its snapshot policy.
+#[derive(Clone, Copy, Debug, Default)]
+struct SnapshotProgress {
+    /// Version of the last snapshot loaded or captured, 0 for none.
+    version: i64,
+    /// When that snapshot was taken, if known.
+    taken_at: Option<DateTime<Utc>>,
+    /// created_at of the oldest event not covered by a snapshot, if known.
+    pending_since: Option<DateTime<Utc>>,
+}
 
 /// A struct that is passed to the aggregate when it is loaded or created.
 pub struct EventContext {
+    /// Replaced by `reset`, so aggregates remembering an earlier id are refused.
+    context_id: Mutex<Uuid>,
     event_store: Arc<EventStore>,
     captured_snapshots: Arc<Mutex<Vec<Snapshot>>>,
     captured_events: Arc<Mutex<Vec<Event>>>,
-    context: Arc<Mutex<HashMap<String, String>>>
+    captured_lookup_keys: Arc<Mutex<Vec<LookupKeyChange>>>,
+    captured_dedup_keys: Arc<Mutex<Vec<DedupKey>>>,
+    snapshot_progress: Arc<Mutex<HashMap<StreamKey, SnapshotProgress>>>,
+    last_load_stats: Arc<Mutex<Option<LoadStats>>>,
+    committed: Arc<AtomicBool>,
+    rolled_back: Arc<AtomicBool>,
+    context: Arc<Mutex<HashMap<String, String>>>,
+    /// When metadata added with an expiry stops being stamped onto events.
+    metadata_valid_until: Arc<Mutex<HashMap<String, DateTime<Utc>>>>,
+    commit_hooks: Arc<Mutex<Vec<CommitHook>>>,
+    /// Budget for the storage calls made through the context, see `set_deadline`.
+    deadline: Arc<Mutex<Option<Instant>>>,
+    /// Held by `commit`, so concurrent commits of a shared context write its events once.
+    commit_lock: Arc<tokio::sync::Mutex<()>>,
 }
 
 impl EventContext {
     pub fn new(event_store: Arc<EventStore>) -> EventContext {
         EventContext {
+            context_id: Mutex::new(Uuid::new_v4()),
             event_store,
             captured_snapshots: Arc::new(Mutex::new(Vec::new())),
             captured_events: Arc::new(Mutex::new(Vec::new())),
-            context: Arc::new(Mutex::new(HashMap::new()))
+            captured_lookup_keys: Arc::new(Mutex::new(Vec::new())),
+            captured_dedup_keys: Arc::new(Mutex::new(Vec::new())),
+            snapshot_progress: Arc::new(Mutex::new(HashMap::new())),
+            last_load_stats: Arc::new(Mutex::new(None)),
+            committed: Arc::new(AtomicBool::new(false)),
+            rolled_back: Arc::new(AtomicBool::new(false)),
+            context: Arc::new(Mutex::new(HashMap::new())),
+            metadata_valid_until: Arc::new(Mutex::new(HashMap::new())),
+            commit_hooks: Arc::new(Mutex::new(Vec::new())),
+            deadline: Arc::new(Mutex::new(None)),
+            commit_lock: Arc::new(tokio::sync::Mutex::new(())),
+        }
+    }
+
+    /// Identifies this context; aggregates remember it so they cannot be published elsewhere.
+    pub fn context_id(&self) -> Uuid {
+        *self.context_id.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Discards everything captured along with the commit hooks, and makes later commits
+    /// fail with `ContextRolledBack`. Aggregates loaded through the context already applied
+    /// the discarded events, so they must be reloaded in a new context.
+    pub fn rollback(&self) -> Result<(), EventStoreError> {
+        self.rolled_back.store(true, Ordering::SeqCst);
+        self.commit_hooks.lock()?.clear();
+        self.captured_events.lock()?.clear();
+        self.captured_snapshots.lock()?.clear();
+        self.captured_lookup_keys.lock()?.clear();
+        self.captured_dedup_keys.lock()?.clear();
+        self.snapshot_progress.lock()?.clear();
+        Ok(())
+    }
+
+    /// Clears captured events, snapshots and metadata so the context can be reused, under a
+    /// new `context_id`.
+    pub(crate) fn reset(&self) -> Result<(), EventStoreError> {
+        self.rollback()?;
+        *self.context_id.lock()? = Uuid::new_v4();
+        self.context.lock()?.clear();
+        self.metadata_valid_until.lock()?.clear();
+        *self.last_load_stats.lock()? = None;
+        *self.deadline.lock()? = None;
+        self.committed.store(false, Ordering::SeqCst);
+        self.rolled_back.store(false, Ordering::SeqCst);
+        Ok(())
+    }
+
+    /// Bounds the storage calls made through this context, including the commit's write,
+    /// by `deadline`: a call is not started once it has passed and is cancelled when it
+    /// passes mid-call, failing with `StorageTimeout`. A deadline set around the calls
+    /// with `deadline::with_deadline` applies as well, the earlier one winning.
+    pub fn set_deadline(&self, deadline: Instant) -> Result<(), EventStoreError> {
+        *self.deadline.lock()? = Some(deadline);
+        Ok(())
+    }
+
+    /// The deadline set with `set_deadline`, if any.
+    pub fn deadline(&self) -> Result<Option<Instant>, EventStoreError> {
+        Ok(*self.deadline.lock()?)
+    }
+
+    /// Awaits a storage call within the context's deadline, which engines see as the
+    /// current one.
+    async fn bounded<T>(&self, future: impl Future<Output = Result<T, EventStoreError>>) -> Result<T, EventStoreError> {
+        let deadline = *self.deadline.lock()?;
+        match deadline {
+            Some(deadline) => deadline::with_deadline(deadline, future).await,
+            None => deadline::bounded(future).await,
         }
     }
 
+    /// What the most recent `load` through this context cost.
+    pub fn last_load_stats(&self) -> Result<Option<LoadStats>, EventStoreError> {
+        Ok(self.last_load_stats.lock()?.clone())
+    }
+
+    /// A copy of the events captured by this context.
+    pub fn captured_events(&self) -> Result<Vec<Event>, EventStoreError> {
+        Ok(self.captured_events.lock()?.clone())
+    }
+
+    /// The types of the captured events, in publish order.
+    pub fn captured_event_types(&self) -> Result<Vec<String>, EventStoreError> {
+        Ok(self.captured_events.lock()?.iter().map(|event| event.event_type.clone()).collect())
+    }
+
+    /// How many events a commit would write.
+    pub fn pending_event_count(&self) -> Result<usize, EventStoreError> {
+        Ok(self.captured_events.lock()?.len())
+    }
+
+    /// Whether a commit would write anything: events, snapshots, lookup keys or dedup keys.
+    pub fn has_pending_changes(&self) -> Result<bool, EventStoreError> {
+        Ok(!self.captured_events.lock()?.is_empty()
+            || !self.captured_snapshots.lock()?.is_empty()
+            || !self.captured_lookup_keys.lock()?.is_empty()
+            || !self.captured_dedup_keys.lock()?.is_empty())
+    }
+
+    /// A copy of the snapshots captured by this context.
+    pub fn captured_snapshots(&self) -> Result<Vec<Snapshot>, EventStoreError> {
+        Ok(self.captured_snapshots.lock()?.clone())
+    }
+
+    /// Removes and returns the published events without committing them.
+    pub fn take_captured(&self) -> Result<Vec<Event>, EventStoreError> {
+        Ok(std::mem::take(&mut *self.captured_events.lock()?))
+    }
+
+    /// Panics unless exactly `times` captured events have type `event_type`.
+    #[cfg(any(test, feature = "test-util"))]
+    pub fn assert_published(&self, event_type: &str, times: usize) {
+        let types = self.captured_event_types().expect("captured events are readable");
+        let count = types.iter().filter(|published| *published == event_type).count();
+        assert_eq!(count, times, "expected {} '{}' event(s), published: {:?}", times, event_type, types);
+    }
+
+    /// Panics if the context has captured any events.
+    #[cfg(any(test, feature = "test-util"))]
+    pub fn assert_nothing_published(&self) {
+        let types = self.captured_event_types().expect("captured events are readable");
+        assert!(types.is_empty(), "expected no events, published: {:?}", types);
+    }
+
+    /// Adds metadata stamped onto every event published afterwards. Fails with
+    /// `MetadataLimitExceeded` when the context already holds the most entries allowed.
     pub fn add_metadata(&self, key: &str, value: &str) -> Result<(), EventStoreError> {
-        self.context.lock()?.insert(key.to_string(), value.to_string());
+        self.insert_metadata(key, value)?;
+        self.metadata_valid_until.lock()?.remove(key);
         Ok(())
     }
 
-    pub async fn next_aggregate_id(&self, aggregate_type: &str, natural_key: Option<&str>) -> Result<i64, EventStoreError> {
-        self.event_store.next_aggregate_id(aggregate_type, natural_key).await
+    /// Marks this context as a step of a saga: the events it publishes carry `saga_id` in
+    /// their metadata under `_saga_id`, so the steps of a saga can be found across services.
+    pub fn link_to_saga(&self, saga_id: Uuid) -> Result<(), EventStoreError> {
+        self.add_metadata(SAGA_ID_KEY, &saga_id.to_string())
+    }
+
+    /// The saga this context was linked to with `link_to_saga`.
+    pub fn saga_id(&self) -> Option<Uuid> {
+        self.context.lock().ok()?
+            .get(SAGA_ID_KEY)
+            .and_then(|saga_id| Uuid::parse_str(saga_id).ok())
+    }
+
+    /// Adds metadata that is only stamped onto events published before `valid_until`, by
+    /// the store's clock. Later events are published without it.
+    pub fn add_metadata_with_expiry(&self, key: &str, value: &str, valid_until: DateTime<Utc>) -> Result<(), EventStoreError> {
+        self.insert_metadata(key, value)?;
+        self.metadata_valid_until.lock()?.insert(key.to_string(), valid_until);
+        Ok(())
+    }
+
+    fn insert_metadata(&self, key: &str, value: &str) -> Result<(), EventStoreError> {
+        let mut context = self.context.lock()?;
+        let limit = self.event_store.max_metadata_entries();
+        if !context.contains_key(key) && context.len() >= limit {
+            return Err(EventStoreError::MetadataLimitExceeded { limit });
+        }
+        context.insert(key.to_string(), value.to_string());
+        Ok(())
+    }
+
+    /// The metadata to stamp onto an event published at `now`, without expired entries.
+    fn live_metadata(&self, now: DateTime<Utc>) -> Result<HashMap<String, String>, EventStoreError> {
+        let mut metadata = self.context.lock()?.clone();
+        for (key, valid_until) in self.metadata_valid_until.lock()?.iter() {
+            if *valid_until <= now && metadata.remove(key).is_some() {
+                tracing::warn!(key = key.as_str(), %valid_until, "expired metadata left off the event");
+            }
+        }
+        Ok(metadata)
+    }
+
+    /// The metadata to stamp onto an event published at `now`: what the providers supply,
+    /// overridden by the live metadata of the context. Fails with `MissingMetadata` when
+    /// required keys are absent.
+    fn event_metadata(&self, now: DateTime<Utc>) -> Result<HashMap<String, String>, EventStoreError> {
+        let mut metadata = self.event_store.provided_metadata();
+        metadata.extend(self.live_metadata(now)?);
+        let missing = missing_keys(&metadata, self.event_store.required_metadata());
+        if !missing.is_empty() {
+            return Err(EventStoreError::MissingMetadata { keys: missing });
+        }
+        Ok(metadata)
+    }
+
+    /// Fails with `MetadataExpired` listing the required keys whose value, added to the
+    /// context with an expiry, has expired since events were published with it.
+    fn check_required_metadata(&self) -> Result<(), EventStoreError> {
+        let required = self.event_store.required_metadata();
+        if required.is_empty() {
+            return Ok(());
+        }
+        let now = self.event_store.now();
+        let provided = self.event_store.provided_metadata();
+        let valid_until = self.metadata_valid_until.lock()?;
+        let expired: Vec<String> = required.iter()
+            .filter(|key| !provided.contains_key(*key))
+            .filter(|key| valid_until.get(*key).is_some_and(|valid_until| *valid_until <= now))
+            .cloned()
+            .collect();
+        match expired.is_empty() {
+            true => Ok(()),
+            false => Err(EventStoreError::MetadataExpired { keys: expired }),
+        }
+    }
+
+    pub async fn next_aggregate_id(&self, aggregate_type: &str, natural_key: Option<&str>) -> Result<AggregateId, EventStoreError> {
+        self.bounded(self.event_store.next_aggregate_id(aggregate_type, natural_key)).await
+    }
+
+    /// Resolves the id of the aggregate instance created with `natural_key`, if any.
+    pub async fn get_aggregate_instance_id(&self, aggregate_type: &str, natural_key: &str) -> Result<Option<AggregateId>, EventStoreError> {
+        self.bounded(self.event_store.get_aggregate_instance_id(aggregate_type, natural_key)).await
+    }
+
+    /// The instance holding `natural_key`, including whether it is soft deleted.
+    pub async fn find_aggregate_instance(&self, aggregate_type: &str, natural_key: &str) -> Result<Option<AggregateInstance>, EventStoreError> {
+        self.bounded(self.event_store.find_aggregate_instance(aggregate_type, natural_key)).await
+    }
+
+    pub async fn create_aggregate_instance(&self, aggregate_type: &str, natural_key: Option<&str>, policy: Option<DuplicateKeyPolicy>) -> Result<CreateOutcome, EventStoreError> {
+        self.bounded(self.event_store.create_aggregate_instance(aggregate_type, natural_key, policy)).await
+    }
+
+    /// Fails if the aggregate is bound to a different registered store than this context.
+    fn check_store<'a, A: Aggregate<'a> + ?Sized>(&self, aggregate: &A) -> Result<(), EventStoreError> {
+        match self.event_store.registered_name() {
+            Some(name) if name != aggregate.store_name() => {
+                Err(EventStoreError::WrongStore((aggregate.store_name().to_string(), name.to_string())))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Returns a future that loads a `ComposedAggregate` through this context.
+    pub fn load_aggregate<T>(self: &SharedEventContext, id: AggregateId) -> LoadFuture<T>
+    where
+        T: DeserializeOwned + Default + Serialize + Composable
+    {
+        LoadFuture::new(self.clone(), id)
     }
 
-    pub async fn load(&self, aggregate: &mut dyn Aggregate<'_>) -> Result<(), EventStoreError> {
-        let snapshot = self.event_store.get_snapshot(aggregate.id(), aggregate.aggregate_type()).await?;
+    pub async fn load<'a, A: Aggregate<'a> + ?Sized>(&self, aggregate: &mut A) -> Result<(), EventStoreError> {
+        self.check_store(aggregate)?;
+        arguments::aggregate_id(aggregate.id())?;
+        self.bounded(self.event_store.check_not_deleted(aggregate.aggregate_type(), aggregate.id())).await?;
+        let snapshot = self.bounded(self.event_store.get_snapshot(aggregate.id(), aggregate.aggregate_type())).await
+            .map_err(|e| e.with_context(ErrorContext::new("load").aggregate(aggregate.aggregate_type(), aggregate.id())))?;
 
+        let mut elapsed = Duration::ZERO;
         let snapshot_found = snapshot.is_some();
+        let snapshot_version = snapshot.as_ref().map(|snapshot| snapshot.version);
+        let snapshot_taken_at = snapshot.as_ref().and_then(|snapshot| snapshot.created_at);
+        let mut bytes = snapshot.as_ref().map_or(0, |snapshot| snapshot.data.len());
         if let Some(snapshot) = snapshot {
-            aggregate.apply_snapshot(&snapshot)?;
+            let started = Instant::now();
+            aggregate.apply_snapshot(&snapshot)
+                .map_err(|e| e.with_context(ErrorContext::new("load").aggregate(&snapshot.aggregate_type, snapshot.aggregate_id).version(snapshot.version)))?;
+            elapsed += started.elapsed();
         }
 
-        let events = self
-            .event_store
-            .get_events(aggregate.id(), aggregate.aggregate_type(), aggregate.version())
-            .await?;
+        let events = self.read_stream(aggregate.id(), aggregate.aggregate_type(), aggregate.version()).await
+            .map_err(|e| e.with_context(ErrorContext::new("load").aggregate(aggregate.aggregate_type(), aggregate.id())))?;
 
         if !snapshot_found && events.is_empty() {
             return Err(EventStoreError::AggregateNotFound((aggregate.aggregate_type().to_string(), aggregate.id())));
         }
 
+        // The replayed events are exactly those not covered by the snapshot, so the first
+        // one tells time based policies how long snapshotting has been pending.
+        self.snapshot_progress.lock()?.insert((aggregate.aggregate_type().to_string(), aggregate.id()), SnapshotProgress {
+            version: snapshot_version.unwrap_or(0),
+            taken_at: snapshot_taken_at,
+            pending_since: events.first().and_then(|event| event.created_at),
+        });
+
+        let events_replayed = events.len();
+        bytes += events.iter().map(|event| event.data.len()).sum::<usize>();
+        let started = Instant::now();
+        // Stored history that cannot be replayed is always down to one event, so whatever
+        // the aggregate reports is wrapped with that event's context.
         for event in events {
-            aggregate.apply_event(&event)?;
+            aggregate.apply_event(&event).map_err(|e| EventStoreError::WithContext {
+                context: ErrorContext::event("load", &event),
+                source: Box::new(e),
+            })?;
+        }
+        elapsed += started.elapsed();
+
+        let stats = LoadStats { snapshot_used: snapshot_found, snapshot_version, events_replayed, elapsed, bytes };
+        tracing::debug!(
+            aggregate_type = aggregate.aggregate_type(),
+            aggregate_id = %aggregate.id(),
+            snapshot_used = stats.snapshot_used,
+            events_replayed = stats.events_replayed,
+            elapsed_us = stats.elapsed.as_micros() as u64,
+            bytes = stats.bytes,
+            "aggregate loaded"
+        );
+        self.event_store.record_load(aggregate.aggregate_type(), &stats)?;
+        *self.last_load_stats.lock()? = Some(stats);
+
+        Ok(())
+    }
+
+    /// Loads the aggregate as it was when `view` was taken, leaving out events committed
+    /// since. The retained history is replayed from its start; a snapshot is only used as
+    /// the base when earlier events were pruned.
+    pub async fn load_at_view<'a, A: Aggregate<'a> + ?Sized>(&self, aggregate: &mut A, view: &ViewToken) -> Result<(), EventStoreError> {
+        self.check_store(aggregate)?;
+        let position = view.position_in(self.store_id())?;
+        let id = aggregate.id();
+        let aggregate_type = aggregate.aggregate_type().to_string();
+        let events = self.bounded(self.event_store.get_events_until_position(id, &aggregate_type, position)).await
+            .map_err(|e| e.with_context(ErrorContext::new("load").aggregate(&aggregate_type, id)))?;
+        let (Some(first), Some(last)) = (events.first(), events.last()) else {
+            return Err(EventStoreError::AggregateNotFound((aggregate_type, id)));
+        };
+
+        if first.version > 1 {
+            let snapshot = self.bounded(self.event_store.read_snapshot_at(id, &aggregate_type, last.version)).await?
+                .filter(|snapshot| snapshot.version >= first.version - 1)
+                .ok_or(EventStoreError::HistoryUnavailable { earliest: first.version })?;
+            aggregate.apply_snapshot(&snapshot)?;
+        }
+        let base_version = aggregate.version();
+        for event in events.iter().filter(|event| event.version > base_version) {
+            aggregate.apply_event(event).map_err(|e| EventStoreError::WithContext {
+                context: ErrorContext::event("load", event),
+                source: Box::new(e),
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Reads the events to replay. Engines return them in version order; debug builds sort
+    /// them again so a misbehaving engine cannot replay history out of order.
+    async fn read_stream(&self, aggregate_id: AggregateId, aggregate_type: &str, version: i64) -> Result<Vec<Event>, EventStoreError> {
+        let mut events = self.bounded(self.event_store.get_events(aggregate_id, aggregate_type, version)).await?;
+        if cfg!(debug_assertions) {
+            events.sort_by_key(|event| event.version);
+        }
+        Ok(events)
+    }
+
+    /// Rebuilds the aggregate as it was at `version`. Fails with `HistoryUnavailable`
+    /// when the events needed to get there have been pruned, rather than returning a
+    /// state the aggregate never had.
+    pub async fn load_at_version(&self, aggregate: &mut dyn Aggregate<'_>, version: i64) -> Result<(), EventStoreError> {
+        self.check_store(aggregate)?;
+        arguments::aggregate_id(aggregate.id())?;
+        arguments::non_negative("version", version)?;
+        let id = aggregate.id();
+        let aggregate_type = aggregate.aggregate_type().to_string();
+        let latest = self.bounded(self.event_store.get_snapshot(id, &aggregate_type)).await?;
+        let earliest_event = self.bounded(self.event_store.earliest_event_version(id, &aggregate_type)).await?;
+
+        // With the full history every version can be replayed; once events are pruned the
+        // latest snapshot and what follows it are known to remain.
+        let earliest = match (earliest_event, &latest) {
+            (None, None) => return Err(EventStoreError::AggregateNotFound((aggregate_type, id))),
+            (Some(1), _) => 0,
+            (_, Some(snapshot)) => snapshot.version,
+            (Some(earliest_event), None) => earliest_event,
+        };
+
+        // Older snapshots can still reach further back, as long as the events after them
+        // were kept; gaps are caught while replaying.
+        let snapshot = match latest.filter(|snapshot| snapshot.version <= version) {
+            Some(snapshot) => Some(snapshot),
+            None => self.bounded(self.event_store.read_snapshot_at(id, &aggregate_type, version)).await?,
+        };
+        if version < earliest && snapshot.is_none() {
+            return Err(EventStoreError::HistoryUnavailable { earliest });
+        }
+
+        if let Some(snapshot) = snapshot {
+            aggregate.apply_snapshot(&snapshot)?;
+        }
+
+        let events = self.read_stream(id, &aggregate_type, aggregate.version()).await?;
+        for event in events.iter().take_while(|event| event.version <= version) {
+            if event.version != aggregate.version() + 1 {
+                return Err(EventStoreError::HistoryUnavailable { earliest });
+            }
+            aggregate.apply_event(event)?;
         }
 
         Ok(())
@@ -64,38 +587,465 @@ impl EventContext {
     where
         T: serde::Serialize + DeserializeOwned
     {
+        self.publish_recorded(source, event_type, data, None)
+    }
+
+    /// Whether the store records the commands behind requested events.
+    pub(crate) fn records_commands(&self) -> bool {
+        self.event_store.records_commands()
+    }
+
+    /// Publishes an event, adding `command` to its metadata if given.
+    pub(crate) fn publish_recorded<T>(
+        &self,
+        source: &mut dyn Aggregate,
+        event_type: &str,
+        data: &T,
+        command: Option<RecordedCommand>,
+    ) -> Result<(), EventStoreError>
+    where
+        T: serde::Serialize + DeserializeOwned
+    {
+        let data = serde_json::to_string(data)
+            .map_err(|e| EventStoreError::EventSerializationError(e).with_context(publish_context(source, event_type)))?;
+        self.publish_serialized(source, event_type, data, command)
+    }
+
+    /// Publishes a payload that is already JSON, storing it verbatim instead of round
+    /// tripping it through a typed struct. The string is only checked for being valid JSON;
+    /// the aggregate still applies the event, so a payload it cannot read is refused.
+    /// Stores hashing events canonicalize the payload like any other.
+    pub fn publish_json(
+        &self,
+        source: &mut dyn Aggregate,
+        event_type: &str,
+        json: &str,
+    ) -> Result<(), EventStoreError> {
+        serde_json::from_str::<&serde_json::value::RawValue>(json)
+            .map_err(|e| EventStoreError::EventSerializationError(e).with_context(publish_context(source, event_type)))?;
+        self.publish_serialized(source, event_type, json.to_string(), None)
+    }
+
+    /// Publishes an untyped JSON value as the event payload.
+    pub fn publish_raw_value(
+        &self,
+        source: &mut dyn Aggregate,
+        event_type: &str,
+        value: serde_json::Value,
+    ) -> Result<(), EventStoreError> {
+        self.publish_serialized(source, event_type, value.to_string(), None)
+    }
+
+    /// Common path of every publish once the payload is serialized.
+    fn publish_serialized(
+        &self,
+        source: &mut dyn Aggregate,
+        event_type: &str,
+        data: String,
+        command: Option<RecordedCommand>,
+    ) -> Result<(), EventStoreError> {
+        self.publish_event(source, event_type, data, command)
+            .map_err(|e| e.with_context(publish_context(source, event_type)))
+    }
+
+    fn publish_event(
+        &self,
+        source: &mut dyn Aggregate,
+        event_type: &str,
+        data: String,
+        command: Option<RecordedCommand>,
+    ) -> Result<(), EventStoreError> {
+        self.check_store(source)?;
+        if source.context_id().is_some_and(|context_id| context_id != self.context_id()) {
+            return Err(EventStoreError::WrongContext { aggregate_id: source.id() });
+        }
+        let registered = source.registered_event_types();
+        if !registered.is_empty() && !registered.contains(&event_type) {
+            return Err(EventStoreError::UnknownEventType(event_type.to_string()));
+        }
+        let now = self.event_store.now();
+        let mut metadata = self.event_metadata(now)?;
         let new_version = source.version() + 1;
+        // Held until the event is captured, so copies of an aggregate publishing from
+        // several tasks cannot both take the same version.
+        let mut captured_events = self.captured_events.lock()?;
+        let published = captured_events.iter().any(|event| {
+            event.version == new_version && event.aggregate_id == source.id() && event.aggregate_type == source.aggregate_type()
+        });
+        if published {
+            return Err(EventStoreError::DuplicateVersion {
+                aggregate_type: source.aggregate_type().to_string(),
+                aggregate_id: source.id(),
+                version: new_version,
+            });
+        }
+        let data = match self.event_store.hashes_events() {
+            true => canonical_json(&data)?,
+            false => data,
+        };
 
-        let mut event = Event::new(
+        let mut event = Event::from_payload(
             source.id(),
             source.aggregate_type(),
             new_version,
             event_type,
             data,
-        )?;
+        );
+        event.created_at = Some(now);
 
-        let context = self.context.lock()?;
-        if !context.is_empty() {
-            event.add_metadata(&*context)?;
+        if let Some(command) = command {
+            metadata.insert(COMMAND_TYPE_KEY.to_string(), command.command_type.to_string());
+            metadata.insert(COMMAND_PAYLOAD_KEY.to_string(), command.payload);
+        }
+        if !metadata.is_empty() {
+            event.add_metadata(&metadata)?;
+        }
+        if self.event_store.hashes_events() {
+            event.metadata = event.metadata.as_deref().map(canonical_json).transpose()?;
         }
 
-        let snapshot_frequency: i64 = source.snapshot_frequency().into();
-        if snapshot_frequency > 0 && new_version % snapshot_frequency == 0 {
-            let snapshot = source.take_snapshot()?;
-            self.captured_snapshots.lock()?.push(snapshot);
+        if self.should_snapshot(source, new_version, now)? {
+            let mut snapshot = source.take_snapshot()?;
+            snapshot.created_at = Some(now);
+            self.queue_snapshot(snapshot)?;
         }
 
         source.apply_event(&event)?;
 
-        self.captured_events.lock()?.push(event);
+        tracing::trace!(event = %event, "event published");
+        captured_events.push(event);
+        Ok(())
+    }
+
+    /// Snapshots the aggregate at its current version for the next commit, whatever its
+    /// snapshot policy, e.g. after an expensive migration. Capturing again at the same
+    /// version keeps the first snapshot, and the commit skips snapshots that are not newer
+    /// than the one stored.
+    pub fn capture_snapshot(&self, source: &dyn Aggregate) -> Result<(), EventStoreError> {
+        self.check_store(source)?;
+        if source.context_id().is_some_and(|context_id| context_id != self.context_id()) {
+            return Err(EventStoreError::WrongContext { aggregate_id: source.id() });
+        }
+        let mut snapshot = source.take_snapshot()?;
+        snapshot.created_at = Some(self.event_store.now());
+        self.queue_snapshot(snapshot)?;
+        // Every event applied so far is covered now.
+        if let Some(progress) = self.snapshot_progress.lock()?.get_mut(&(source.aggregate_type().to_string(), source.id())) {
+            progress.pending_since = None;
+        }
+        Ok(())
+    }
+
+    /// Keeps only the newest captured snapshot of each aggregate, as older ones would never
+    /// be read.
+    fn queue_snapshot(&self, snapshot: Snapshot) -> Result<(), EventStoreError> {
+        let key = (snapshot.aggregate_type.clone(), snapshot.aggregate_id);
+        let (version, taken_at) = (snapshot.version, snapshot.created_at);
+        {
+            let mut captured_snapshots = self.captured_snapshots.lock()?;
+            let captured = captured_snapshots.iter_mut()
+                .find(|captured| captured.aggregate_id == snapshot.aggregate_id && captured.aggregate_type == snapshot.aggregate_type);
+            match captured {
+                Some(captured) if captured.version >= snapshot.version => return Ok(()),
+                Some(captured) => *captured = snapshot,
+                None => captured_snapshots.push(snapshot),
+            }
+        }
+        let mut snapshot_progress = self.snapshot_progress.lock()?;
+        let progress = snapshot_progress.entry(key).or_default();
+        progress.version = version;
+        progress.taken_at = taken_at;
+        Ok(())
+    }
+
+    /// Asks the aggregate's snapshot policy whether to snapshot before publishing `version`.
+    /// Aggregates the context has not loaded count as never snapshotted.
+    fn should_snapshot(&self, source: &dyn Aggregate, version: i64, now: DateTime<Utc>) -> Result<bool, EventStoreError> {
+        let policy = self.event_store.snapshot_policy(source);
+        let mut snapshot_progress = self.snapshot_progress.lock()?;
+        let progress = snapshot_progress.entry((source.aggregate_type().to_string(), source.id())).or_default();
+        let check = SnapshotCheck {
+            version,
+            events_since_snapshot: version - progress.version,
+            last_snapshot_at: progress.taken_at,
+            pending_since: progress.pending_since,
+            now,
+        };
+        let snapshot = policy.should_snapshot(&check);
+        // The snapshot is taken before this event is applied, so this event is the oldest
+        // one left uncovered, just as it is for an aggregate with nothing pending.
+        if snapshot || check.pending_since.is_none() {
+            progress.pending_since = Some(now);
+        }
+        Ok(snapshot)
+    }
+
+    /// Publishes an event unless `dedup_key` was already ingested, either by an earlier commit
+    /// or earlier in this context. Duplicates are skipped silently, or rejected with
+    /// `DuplicateEvent` when the store is configured with `DuplicatePolicy::Error`.
+    /// Returns whether the event was published.
+    pub async fn publish_dedup<T>(
+        &self,
+        source: &mut dyn Aggregate<'_>,
+        event_type: &str,
+        data: &T,
+        dedup_key: &str,
+    ) -> Result<bool, EventStoreError>
+    where
+        T: serde::Serialize + DeserializeOwned
+    {
+        self.publish_dedup_recorded(source, event_type, data, dedup_key, None).await
+    }
+
+    pub(crate) async fn publish_dedup_recorded<T>(
+        &self,
+        source: &mut dyn Aggregate<'_>,
+        event_type: &str,
+        data: &T,
+        dedup_key: &str,
+        command: Option<RecordedCommand>,
+    ) -> Result<bool, EventStoreError>
+    where
+        T: serde::Serialize + DeserializeOwned
+    {
+        let pending = self.captured_dedup_keys.lock()?.iter().any(|captured| captured.key == dedup_key);
+        if pending || self.bounded(self.event_store.has_dedup_key(dedup_key)).await? {
+            return match self.event_store.duplicate_policy() {
+                DuplicatePolicy::Ignore => Ok(false),
+                DuplicatePolicy::Error => Err(EventStoreError::DuplicateEvent(dedup_key.to_string())),
+            };
+        }
+
+        self.publish_recorded(source, event_type, data, command)?;
+        self.captured_dedup_keys.lock()?.push(DedupKey {
+            key: dedup_key.to_string(),
+            aggregate_id: source.id(),
+            created_at: self.event_store.now(),
+        });
+        Ok(true)
+    }
+
+    /// Takes back the event `source` published last, along with `dedup_key` if given, for
+    /// publishes whose outcome the aggregate refused.
+    pub(crate) fn withdraw_event(&self, source: &dyn Aggregate, dedup_key: Option<&str>) -> Result<(), EventStoreError> {
+        self.captured_events.lock()?.retain(|event| {
+            event.version != source.version() || event.aggregate_id != source.id() || event.aggregate_type != source.aggregate_type()
+        });
+        if let Some(dedup_key) = dedup_key {
+            self.captured_dedup_keys.lock()?.retain(|captured| captured.key != dedup_key);
+        }
+        Ok(())
+    }
+
+    /// Queues a lookup key for the aggregate, written atomically with the commit.
+    pub fn add_lookup_key(&self, source: &dyn Aggregate, key_name: &str, key_value: &str) -> Result<(), EventStoreError> {
+        let key = lookup_key(source, key_name, key_value);
+        self.captured_lookup_keys.lock()?.push(LookupKeyChange::Add(key));
+        Ok(())
+    }
+
+    /// Queues removal of a lookup key from the aggregate, written atomically with the commit.
+    pub fn remove_lookup_key(&self, source: &dyn Aggregate, key_name: &str, key_value: &str) -> Result<(), EventStoreError> {
+        let key = lookup_key(source, key_name, key_value);
+        self.captured_lookup_keys.lock()?.push(LookupKeyChange::Remove(key));
+        Ok(())
+    }
+
+    pub async fn find_by_lookup_key(&self, aggregate_type: &str, key_name: &str, key_value: &str) -> Result<Vec<AggregateId>, EventStoreError> {
+        self.bounded(self.event_store.find_by_lookup_key(aggregate_type, key_name, key_value)).await
+    }
+
+    /// Writes a snapshot straight to storage, outside of the pending commit, stamping it
+    /// with the store clock unless it carries a timestamp.
+    pub async fn write_snapshot(&self, mut snapshot: Snapshot) -> Result<(), EventStoreError> {
+        snapshot.created_at = snapshot.created_at.or_else(|| Some(self.event_store.now()));
+        self.bounded(self.event_store.write_updates(&[], &[snapshot])).await?;
+        Ok(())
+    }
+
+    /// Writes everything captured in one batch and removes it from the context, then runs
+    /// the commit hooks. Committing again writes nothing, unless more was published since,
+    /// and returns an empty receipt. A failed commit keeps what was captured, so it can be
+    /// retried.
+    pub async fn commit(&self) -> Result<CommitReceipt, EventStoreError> {
+        let _commit = self.lock_commit().await;
+        let captured = self.captured_writes()?;
+        let written = self.bounded(self.event_store.write_batch(&captured.batch())).await?;
+        self.drain_committed(captured.counts())?;
+        self.mark_committed();
+        let receipt = CommitReceipt::new(&captured, &written);
+        self.event_store.notify_commit(vec![self.context_id()], &receipt);
+        let hooks = self.take_commit_hooks()?;
+        run_commit_hooks(hooks, &receipt.events).await?;
+        Ok(receipt)
+    }
+
+    /// Held while the context commits, so its captures are written once.
+    pub(crate) async fn lock_commit(&self) -> tokio::sync::MutexGuard<'_, ()> {
+        self.commit_lock.lock().await
+    }
+
+    /// Registers a hook to run with the committed events once a commit of this context
+    /// succeeds, e.g. to send mail only for durable changes. Hooks run in registration order
+    /// and never when the commit fails, so a retried commit still runs them. A failing hook
+    /// makes the commit return `PostCommitHookError`, the events staying committed, and
+    /// skips the hooks after it.
+    pub fn on_commit<F>(&self, hook: F) -> Result<(), EventStoreError>
+    where
+        F: FnOnce(&[Event]) -> Result<(), EventStoreError> + Send + 'static,
+    {
+        self.commit_hooks.lock()?.push(CommitHook::Sync(Box::new(hook)));
         Ok(())
     }
 
-    pub async fn commit(&self) -> Result<(), EventStoreError> {
-        let events = self.captured_events.lock()?.clone();   
-        let snapshots = self.captured_snapshots.lock()?.clone();
-        self.event_store.write_updates(&events, &snapshots).await?;
+    /// Like `on_commit`, for hooks that await, such as enqueueing a job.
+    pub fn on_commit_async<F, Fut>(&self, hook: F) -> Result<(), EventStoreError>
+    where
+        F: FnOnce(Vec<Event>) -> Fut + Send + 'static,
+        Fut: Future<Output = Result<(), EventStoreError>> + Send + 'static,
+    {
+        self.commit_hooks.lock()?.push(CommitHook::Async(Box::new(move |events| Box::pin(hook(events)))));
         Ok(())
     }
 
+    pub(crate) fn take_commit_hooks(&self) -> Result<Vec<CommitHook>, EventStoreError> {
+        Ok(std::mem::take(&mut *self.commit_hooks.lock()?))
+    }
+
+    /// Whether a commit of this context, alone or through `EventStore::commit_all`, succeeded.
+    pub fn is_committed(&self) -> bool {
+        self.committed.load(Ordering::SeqCst)
+    }
+
+    pub(crate) fn mark_committed(&self) {
+        self.committed.store(true, Ordering::SeqCst);
+    }
+
+    /// Removes what a written batch took from the captured buffers. Captures are only ever
+    /// appended, so that is a prefix of each; anything published while the write ran stays
+    /// for the next commit.
+    pub(crate) fn drain_committed(&self, counts: CaptureCounts) -> Result<(), EventStoreError> {
+        fn drain<T>(captured: &Mutex<Vec<T>>, count: usize) -> Result<(), EventStoreError> {
+            let mut captured = captured.lock()?;
+            let count = count.min(captured.len());
+            captured.drain(..count);
+            Ok(())
+        }
+        drain(&self.captured_events, counts.events)?;
+        drain(&self.captured_snapshots, counts.snapshots)?;
+        drain(&self.captured_lookup_keys, counts.lookup_keys)?;
+        drain(&self.captured_dedup_keys, counts.dedup_keys)
+    }
+
+    /// Whether `rollback` was called, which rules out committing the context.
+    pub fn is_rolled_back(&self) -> bool {
+        self.rolled_back.load(Ordering::SeqCst)
+    }
+
+    /// Identifies the store the context writes to.
+    pub(crate) fn store_id(&self) -> Uuid {
+        self.event_store.store_id
+    }
+
+    /// A copy of everything a commit would write. Fails if the context was rolled back, or
+    /// if there are events to write but the metadata the store requires has expired.
+    pub(crate) fn captured_writes(&self) -> Result<CapturedWrites, EventStoreError> {
+        if self.is_rolled_back() {
+            return Err(EventStoreError::ContextRolledBack);
+        }
+        let events = self.captured_events.lock()?.clone();
+        if !events.is_empty() {
+            self.check_required_metadata()?;
+        }
+        Ok(CapturedWrites {
+            events,
+            snapshots: self.captured_snapshots.lock()?.clone(),
+            lookup_keys: self.captured_lookup_keys.lock()?.clone(),
+            dedup_keys: self.captured_dedup_keys.lock()?.clone(),
+        })
+    }
+
+}
+
+/// Context for a failure publishing the next event of `source`.
+fn publish_context(source: &dyn Aggregate, event_type: &str) -> ErrorContext {
+    ErrorContext::new("publish")
+        .aggregate(source.aggregate_type(), source.id())
+        .version(source.version() + 1)
+        .event_type(event_type)
+}
+
+fn lookup_key(source: &dyn Aggregate, key_name: &str, key_value: &str) -> LookupKey {
+    LookupKey {
+        aggregate_id: source.id(),
+        aggregate_type: source.aggregate_type().to_string(),
+        key_name: key_name.to_string(),
+        key_value: key_value.to_string(),
+    }
+}
+
+
+/// A fixed-size pool of reusable EventContexts.
+///
+/// Intended for very high throughput services where allocating a context per request
+/// is measurable. Contexts are handed out as `PooledContext` guards which reset the
+/// context and return it to the pool when dropped.
+pub struct EventContextPool {
+    event_store: SharedEventStore,
+    contexts: Arc<ArrayQueue<SharedEventContext>>,
+}
+
+impl EventContextPool {
+    pub fn new(event_store: SharedEventStore, size: usize) -> EventContextPool {
+        let contexts = Arc::new(ArrayQueue::new(size.max(1)));
+        for _ in 0..size {
+            let _ = contexts.push(Arc::new(EventContext::new(event_store.clone())));
+        }
+        EventContextPool {
+            event_store,
+            contexts,
+        }
+    }
+
+    /// Takes a context from the pool, allocating a new one if the pool is empty.
+    pub fn get(&self) -> PooledContext {
+        let context = self.contexts.pop()
+            .unwrap_or_else(|| Arc::new(EventContext::new(self.event_store.clone())));
+        PooledContext {
+            context: Some(context),
+            contexts: self.contexts.clone(),
+        }
+    }
+
+    /// Number of idle contexts currently in the pool.
+    pub fn available(&self) -> usize {
+        self.contexts.len()
+    }
+}
+
+/// Guard for a context borrowed from an `EventContextPool`.
+///
+/// Uncommitted events are discarded when the guard is dropped. A context is only
+/// returned to the pool if no other clones of it are still alive.
+pub struct PooledContext {
+    context: Option<SharedEventContext>,
+    contexts: Arc<ArrayQueue<SharedEventContext>>,
+}
+
+impl Deref for PooledContext {
+    type Target = SharedEventContext;
+
+    fn deref(&self) -> &SharedEventContext {
+        self.context.as_ref().expect("pooled context is present until drop")
+    }
+}
+
+impl Drop for PooledContext {
+    fn drop(&mut self) {
+        if let Some(context) = self.context.take() {
+            if Arc::strong_count(&context) == 1 && context.reset().is_ok() {
+                let _ = self.contexts.push(context);
+            }
+        }
+    }
 }
@@ -0,0 +1,559 @@
+use std::{fmt, sync::Arc};
+use crate::{event::Event, payload::Payload, snapshot::Snapshot, AggregateId, DuplicateKeyPolicy, EngineCapabilities, EventStoreError, EventStoreStorageEngine};
+use crate::{LookupKey, LookupKeyChange, PurgeReport, RewriteReport, WriteBatch};
+
+/// Whether an engine must pass a check, or only when it supports the behavior probed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Requirement {
+    Required,
+    Optional,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    Passed,
+    Failed(String),
+    /// The engine reported the optional behavior as not supported.
+    Unsupported,
+}
+
+/// The outcome of probing one guarantee of the storage engine trait.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ContractCheck {
+    pub name: &'static str,
+    pub requirement: Requirement,
+    /// The guarantee, as engine authors should read it.
+    pub contract: &'static str,
+    pub outcome: Outcome,
+}
+
+/// What `check_storage_contract` found out about an engine.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ComplianceReport {
+    pub engine: String,
+    pub advertised: EngineCapabilities,
+    pub checks: Vec<ContractCheck>,
+}
+
+impl ComplianceReport {
+    /// Checks the engine failed, including optional behaviors it implements incorrectly
+    /// or advertises without implementing.
+    pub fn failures(&self) -> Vec<&ContractCheck> {
+        self.checks.iter().filter(|check| matches!(check.outcome, Outcome::Failed(_))).collect()
+    }
+
+    pub fn is_compliant(&self) -> bool {
+        self.failures().is_empty()
+    }
+
+    /// Names of the optional behaviors the engine supports.
+    pub fn supported(&self) -> Vec<&'static str> {
+        self.checks.iter()
+            .filter(|check| check.requirement == Requirement::Optional && check.outcome == Outcome::Passed)
+            .map(|check| check.name)
+            .collect()
+    }
+}
+
+/// Renders the report as a markdown table.
+impl fmt::Display for ComplianceReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "# Storage contract: {}", self.engine)?;
+        writeln!(f)?;
+        writeln!(f, "Advertised capabilities: {}", self.advertised.names().join(", "))?;
+        writeln!(f)?;
+        writeln!(f, "| Check | Requirement | Outcome | Contract |")?;
+        writeln!(f, "|---|---|---|---|")?;
+        for check in &self.checks {
+            let outcome = match &check.outcome {
+                Outcome::Passed => "passed".to_string(),
+                Outcome::Failed(reason) => format!("FAILED: {}", reason.replace('|', "\\|")),
+                Outcome::Unsupported => "unsupported".to_string(),
+            };
+            writeln!(f, "| {} | {:?} | {} | {} |", check.name, check.requirement, outcome, check.contract)?;
+        }
+        Ok(())
+    }
+}
+
+type Probe = Result<(), String>;
+
+fn ensure(condition: bool, failure: impl FnOnce() -> String) -> Probe {
+    if condition { Ok(()) } else { Err(failure()) }
+}
+
+fn describe(error: EventStoreError) -> String {
+    format!("{:?}", error)
+}
+
+/// Whether an optional method reported that the engine does not implement it, as the
+/// trait's defaults do.
+fn is_unsupported(error: &EventStoreError) -> bool {
+    matches!(error.root_cause(), EventStoreError::StorageEngineErrorOther(message) if message.contains("does not support"))
+}
+
+#[cfg(not(feature = "uuid-ids"))]
+fn unused_id(known: AggregateId) -> AggregateId {
+    known + 1_000_000
+}
+
+#[cfg(feature = "uuid-ids")]
+fn unused_id(_known: AggregateId) -> AggregateId {
+    uuid::Uuid::new_v4()
+}
+
+/// Probes `engine` for the guarantees the event store relies on and for the optional
+/// behaviors it supports. Engine authors can run it from their test suites and assert
+/// `is_compliant`.
+///
+/// The checks write to a fresh aggregate type named `contract_<random suffix>`, which is
+/// left in place, so the engine may hold other data. They do not prune, migrate or
+/// otherwise touch data outside that type.
+pub async fn check_storage_contract(engine: Arc<dyn EventStoreStorageEngine + Send + Sync>) -> ComplianceReport {
+    let suffix = crate::integrity::unique_token();
+    let probe = ContractProbe {
+        engine: engine.clone(),
+        aggregate_type: format!("contract_{}", &suffix[..8]),
+        suffix,
+    };
+    let advertised = engine.capabilities();
+    let mut checks = Vec::new();
+
+    let mut required = |name, contract, result: Probe| checks.push(ContractCheck {
+        name,
+        requirement: Requirement::Required,
+        contract,
+        outcome: result.map_or_else(Outcome::Failed, |_| Outcome::Passed),
+    });
+    required("missing_aggregate_reads_empty",
+        "Reading the events of an aggregate without any returns an empty list and version 0, not an error.",
+        probe.missing_aggregate_reads_empty().await);
+    required("missing_snapshot_reads_none",
+        "Reading the snapshot of an aggregate without one returns None.",
+        probe.missing_snapshot_reads_none().await);
+    required("empty_write_is_noop",
+        "Writing no events and no snapshots succeeds and describes no events.",
+        probe.empty_write_is_noop().await);
+    required("unkeyed_instances_never_conflict",
+        "Instances created without a natural key always get distinct ids.",
+        probe.unkeyed_instances_never_conflict().await);
+    required("duplicate_natural_key_follows_policy",
+        "A taken natural key fails with DuplicateNaturalKey under DuplicateKeyPolicy::Error and returns the existing instance under ReturnExisting.",
+        probe.duplicate_natural_key_follows_policy().await);
+    required("events_read_in_version_order",
+        "Events are described in input order when written and read back in ascending version order, after the version asked for.",
+        probe.events_read_in_version_order().await);
+    required("rewritten_versions_conflict",
+        "Writing a version an aggregate already has fails with VersionConflict and stores nothing.",
+        probe.rewritten_versions_conflict().await);
+    required("snapshots_round_trip",
+        "The latest snapshot written is read back as written, with the same payload bytes.",
+        probe.snapshots_round_trip().await);
+
+    let mut optional = |name, contract, capability: Option<EngineCapabilities>, result: Result<Probe, EventStoreError>| {
+        let advertises = capability.is_some_and(|capability| advertised.contains(capability));
+        let outcome = match result {
+            Ok(Ok(())) => Outcome::Passed,
+            Ok(Err(reason)) => Outcome::Failed(reason),
+            Err(error) if is_unsupported(&error) && advertises => Outcome::Failed(format!("advertised but not supported: {}", describe(error))),
+            Err(error) if is_unsupported(&error) => Outcome::Unsupported,
+            Err(error) => Outcome::Failed(describe(error)),
+        };
+        checks.push(ContractCheck { name, requirement: Requirement::Optional, contract, outcome });
+    };
+    optional("global_feed",
+        "read_all_events returns events across aggregates in commit order with ascending positions, read_events sets them too, and head_position is the latest.",
+        Some(EngineCapabilities::GLOBAL_FEED),
+        probe.global_feed().await);
+    optional("soft_delete",
+        "Soft deleted instances report when they were deleted until they are resurrected.",
+        Some(EngineCapabilities::SOFT_DELETE),
+        probe.soft_delete().await);
+    optional("instance_listing",
+        "list_aggregate_instances pages through the instances of a type in creation order.",
+        None,
+        probe.instance_listing().await);
+    optional("type_listing",
+        "list_aggregate_types includes every type written to.",
+        None,
+        probe.type_listing().await);
+    optional("snapshot_history",
+        "read_snapshots lists snapshots newest first and read_snapshot_at finds the newest at or below a version.",
+        None,
+        probe.snapshot_history().await);
+    optional("atomic_lookup_keys",
+        "Lookup keys written in a batch are found by find_by_lookup_key.",
+        None,
+        probe.atomic_lookup_keys().await);
+    optional("purge",
+        "purge_aggregate removes the instance with its events, snapshots and lookup keys, frees its natural key and leaves other aggregates alone.",
+        None,
+        probe.purge().await);
+    optional("replace_events",
+        "replace_events swaps the events of an aggregate and drops its snapshots, refusing when the aggregate moved past the expected version.",
+        None,
+        probe.replace_events().await);
+    optional("tenants",
+        "Handles from for_tenant see only the instances, events, snapshots, natural keys, lookup keys and checkpoints of their tenant, and the unscoped engine sees none of them.",
+        Some(EngineCapabilities::TENANT_COLUMN),
+        probe.tenants().await);
+    optional("binary_payloads",
+        "Binary event and snapshot payloads are read back byte for byte.",
+        Some(EngineCapabilities::BINARY_PAYLOADS),
+        probe.binary_payloads().await);
+
+    ComplianceReport {
+        engine: engine.engine_name().to_string(),
+        advertised,
+        checks,
+    }
+}
+
+struct ContractProbe {
+    engine: Arc<dyn EventStoreStorageEngine + Send + Sync>,
+    aggregate_type: String,
+    suffix: String,
+}
+
+impl ContractProbe {
+    async fn instance(&self) -> Result<AggregateId, String> {
+        self.engine.create_aggregate_instance(&self.aggregate_type, None).await.map_err(describe)
+    }
+
+    fn event(&self, id: AggregateId, version: i64) -> Result<Event, String> {
+        Event::new(id, &self.aggregate_type, version, "contract_checked", &version).map_err(describe)
+    }
+
+    /// An aggregate with events at versions 1 to `count`.
+    async fn stream(&self, count: i64) -> Result<(AggregateId, Vec<Event>), String> {
+        let id = self.instance().await?;
+        let events = (1..=count).map(|version| self.event(id, version)).collect::<Result<Vec<_>, _>>()?;
+        self.engine.write_updates(&events, &[]).await.map_err(describe)?;
+        Ok((id, events))
+    }
+
+    async fn missing_aggregate_reads_empty(&self) -> Probe {
+        let missing = unused_id(self.instance().await?);
+        let events = self.engine.read_events(missing, &self.aggregate_type, 0).await.map_err(describe)?;
+        ensure(events.is_empty(), || format!("read {} events", events.len()))?;
+        let version = self.engine.get_aggregate_version(missing, &self.aggregate_type).await.map_err(describe)?;
+        ensure(version == 0, || format!("version {}", version))
+    }
+
+    async fn missing_snapshot_reads_none(&self) -> Probe {
+        let id = self.instance().await?;
+        let snapshot = self.engine.read_snapshot(id, &self.aggregate_type).await.map_err(describe)?;
+        ensure(snapshot.is_none(), || "read a snapshot".to_string())
+    }
+
+    async fn empty_write_is_noop(&self) -> Probe {
+        let written = self.engine.write_updates(&[], &[]).await.map_err(describe)?;
+        ensure(written.is_empty(), || format!("described {} events", written.len()))
+    }
+
+    async fn unkeyed_instances_never_conflict(&self) -> Probe {
+        let mut ids = Vec::new();
+        for _ in 0..3 {
+            ids.push(self.instance().await?);
+        }
+        ids.sort();
+        ids.dedup();
+        ensure(ids.len() == 3, || format!("got {} distinct ids for 3 instances", ids.len()))
+    }
+
+    async fn duplicate_natural_key_follows_policy(&self) -> Probe {
+        let key = format!("contract-key-{}", self.suffix);
+        let created = self.engine.create_aggregate_instance_with_policy(&self.aggregate_type, Some(&key), Some(DuplicateKeyPolicy::Error)).await
+            .map_err(describe)?;
+        match self.engine.create_aggregate_instance_with_policy(&self.aggregate_type, Some(&key), Some(DuplicateKeyPolicy::Error)).await {
+            Err(error) if matches!(error.root_cause(), EventStoreError::DuplicateNaturalKey(_)) => {}
+            Err(error) => return Err(format!("expected DuplicateNaturalKey, got {}", describe(error))),
+            Ok(outcome) => return Err(format!("created a second instance {:?}", outcome.id)),
+        }
+        let existing = self.engine.create_aggregate_instance_with_policy(&self.aggregate_type, Some(&key), Some(DuplicateKeyPolicy::ReturnExisting)).await
+            .map_err(describe)?;
+        ensure(existing.id == created.id && !existing.created, || format!("ReturnExisting gave {:?}", existing))?;
+        let found = self.engine.get_aggregate_instance_id(&self.aggregate_type, &key).await.map_err(describe)?;
+        ensure(found == Some(created.id), || format!("natural key resolves to {:?}", found))
+    }
+
+    async fn events_read_in_version_order(&self) -> Probe {
+        let id = self.instance().await?;
+        let events = [1, 3, 2].into_iter().map(|version| self.event(id, version)).collect::<Result<Vec<_>, _>>()?;
+        let written = self.engine.write_updates(&events, &[]).await.map_err(describe)?;
+        let described: Vec<i64> = written.iter().map(|event| event.version).collect();
+        ensure(described == [1, 3, 2], || format!("described versions {:?}", described))?;
+
+        let read: Vec<i64> = self.engine.read_events(id, &self.aggregate_type, 0).await.map_err(describe)?
+            .iter().map(|event| event.version).collect();
+        ensure(read == [1, 2, 3], || format!("read versions {:?}", read))?;
+        let after: Vec<i64> = self.engine.read_events(id, &self.aggregate_type, 1).await.map_err(describe)?
+            .iter().map(|event| event.version).collect();
+        ensure(after == [2, 3], || format!("read versions {:?} after version 1", after))?;
+        let version = self.engine.get_aggregate_version(id, &self.aggregate_type).await.map_err(describe)?;
+        ensure(version == 3, || format!("aggregate version {}", version))
+    }
+
+    async fn rewritten_versions_conflict(&self) -> Probe {
+        let (id, events) = self.stream(2).await?;
+        match self.engine.write_updates(&events, &[]).await {
+            Err(error) if matches!(error.root_cause(), EventStoreError::VersionConflict { .. }) => {}
+            Err(error) => return Err(format!("expected VersionConflict, got {}", describe(error))),
+            Ok(_) => return Err("the same batch was written twice".to_string()),
+        }
+        let stored = self.engine.read_events(id, &self.aggregate_type, 0).await.map_err(describe)?;
+        ensure(stored.len() == 2, || format!("{} events stored", stored.len()))
+    }
+
+    async fn snapshots_round_trip(&self) -> Probe {
+        let (id, _) = self.stream(2).await?;
+        let snapshot = Snapshot::new(id, &self.aggregate_type, 2, &"state".to_string()).map_err(describe)?;
+        self.engine.write_updates(&[], std::slice::from_ref(&snapshot)).await.map_err(describe)?;
+        let read = self.engine.read_snapshot(id, &self.aggregate_type).await.map_err(describe)?;
+        ensure(read.as_ref().is_some_and(|read| read.version == 2 && read.data.as_bytes() == snapshot.data.as_bytes()), || format!("read {:?}", read))
+    }
+
+    async fn global_feed(&self) -> Result<Probe, EventStoreError> {
+        let head = self.engine.head_position().await?;
+        let first = self.engine.create_aggregate_instance(&self.aggregate_type, None).await?;
+        let second = self.engine.create_aggregate_instance(&self.aggregate_type, None).await?;
+        let events = vec![
+            Event::new(first, &self.aggregate_type, 1, "contract_checked", &1)?,
+            Event::new(second, &self.aggregate_type, 1, "contract_checked", &1)?,
+            Event::new(first, &self.aggregate_type, 2, "contract_checked", &2)?,
+        ];
+        self.engine.write_updates(&events, &[]).await?;
+
+        let feed: Vec<Event> = self.engine.read_all_events(head, usize::MAX).await?.into_iter()
+            .filter(|event| event.aggregate_type == self.aggregate_type && (event.aggregate_id == first || event.aggregate_id == second))
+            .collect();
+        let order: Vec<(AggregateId, i64)> = feed.iter().map(|event| (event.aggregate_id, event.version)).collect();
+        if order != [(first, 1), (second, 1), (first, 2)] {
+            return Ok(Err(format!("feed order {:?}", order)));
+        }
+        let positions: Vec<Option<i64>> = feed.iter().map(|event| event.position).collect();
+        if !positions.windows(2).all(|pair| pair[0].is_some() && pair[0] < pair[1]) {
+            return Ok(Err(format!("feed positions {:?}", positions)));
+        }
+        let stream: Vec<Option<i64>> = self.engine.read_events(first, &self.aggregate_type, 0).await?.iter().map(|event| event.position).collect();
+        if stream != [positions[0], positions[2]] {
+            return Ok(Err(format!("read_events positions {:?}", stream)));
+        }
+        let new_head = self.engine.head_position().await?;
+        Ok(ensure(Some(new_head) >= positions[2], || format!("head position {} behind the feed", new_head)))
+    }
+
+    async fn soft_delete(&self) -> Result<Probe, EventStoreError> {
+        let id = self.engine.create_aggregate_instance(&self.aggregate_type, None).await?;
+        let deleted_at = chrono::DateTime::<chrono::Utc>::from_timestamp_micros(chrono::Utc::now().timestamp_micros()).unwrap_or_default();
+        self.engine.soft_delete_aggregate(&self.aggregate_type, id, deleted_at).await?;
+        let reported = self.engine.aggregate_deleted_at(&self.aggregate_type, id).await?;
+        if reported != Some(deleted_at) {
+            return Ok(Err(format!("deleted_at {:?} after deleting", reported)));
+        }
+        self.engine.resurrect_aggregate(&self.aggregate_type, id).await?;
+        let reported = self.engine.aggregate_deleted_at(&self.aggregate_type, id).await?;
+        Ok(ensure(reported.is_none(), || format!("deleted_at {:?} after resurrecting", reported)))
+    }
+
+    async fn instance_listing(&self) -> Result<Probe, EventStoreError> {
+        let aggregate_type = format!("{}_listed", self.aggregate_type);
+        let mut created = Vec::new();
+        for _ in 0..3 {
+            created.push(self.engine.create_aggregate_instance(&aggregate_type, None).await?);
+        }
+        let mut listed = self.engine.list_aggregate_instances(&aggregate_type, 0, 2).await?;
+        listed.extend(self.engine.list_aggregate_instances(&aggregate_type, 2, 2).await?);
+        let ids: Vec<AggregateId> = listed.iter().map(|instance| instance.id).collect();
+        Ok(ensure(ids == created, || format!("listed {:?}, created {:?}", ids, created)))
+    }
+
+    async fn type_listing(&self) -> Result<Probe, EventStoreError> {
+        self.engine.write_updates(&[Event::new(self.engine.create_aggregate_instance(&self.aggregate_type, None).await?, &self.aggregate_type, 1, "contract_checked", &1)?], &[]).await?;
+        let types = self.engine.list_aggregate_types(false).await?;
+        Ok(ensure(types.iter().any(|info| info.name == self.aggregate_type), || "the type written to is not listed".to_string()))
+    }
+
+    async fn snapshot_history(&self) -> Result<Probe, EventStoreError> {
+        let id = self.engine.create_aggregate_instance(&self.aggregate_type, None).await?;
+        let events = (1..=4).map(|version| Event::new(id, &self.aggregate_type, version, "contract_checked", &version)).collect::<Result<Vec<_>, _>>()?;
+        let snapshots = [2, 4].map(|version| Snapshot::new(id, &self.aggregate_type, version, &version));
+        let snapshots = snapshots.into_iter().collect::<Result<Vec<_>, _>>()?;
+        self.engine.write_updates(&events, &snapshots).await?;
+
+        let history: Vec<i64> = self.engine.read_snapshots(id, &self.aggregate_type, 10).await?.iter().map(|info| info.version).collect();
+        if history != [4, 2] {
+            return Ok(Err(format!("snapshot history {:?}", history)));
+        }
+        let at = self.engine.read_snapshot_at(id, &self.aggregate_type, 3).await?.map(|snapshot| snapshot.version);
+        Ok(ensure(at == Some(2), || format!("snapshot at version 3 is {:?}", at)))
+    }
+
+    async fn atomic_lookup_keys(&self) -> Result<Probe, EventStoreError> {
+        let id = self.engine.create_aggregate_instance(&self.aggregate_type, None).await?;
+        let key = LookupKey {
+            aggregate_id: id,
+            aggregate_type: self.aggregate_type.clone(),
+            key_name: "contract".to_string(),
+            key_value: self.suffix.clone(),
+        };
+        let events = [Event::new(id, &self.aggregate_type, 1, "contract_checked", &1)?];
+        self.engine.write_batch(&WriteBatch {
+            events: &events,
+            snapshots: &[],
+            lookup_keys: &[LookupKeyChange::Add(key)],
+            dedup_keys: &[],
+        }).await?;
+        let found = self.engine.find_by_lookup_key(&self.aggregate_type, "contract", &self.suffix).await?;
+        Ok(ensure(found == [id], || format!("lookup key finds {:?}", found)))
+    }
+
+    async fn purge(&self) -> Result<Probe, EventStoreError> {
+        let natural_key = format!("contract-purged-{}", self.suffix);
+        let id = self.engine.create_aggregate_instance(&self.aggregate_type, Some(&natural_key)).await?;
+        let kept = self.engine.create_aggregate_instance(&self.aggregate_type, None).await?;
+        let events = [
+            Event::new(id, &self.aggregate_type, 1, "contract_checked", &1)?,
+            Event::new(id, &self.aggregate_type, 2, "contract_checked", &2)?,
+            Event::new(kept, &self.aggregate_type, 1, "contract_checked", &1)?,
+        ];
+        self.engine.write_updates(&events, &[Snapshot::new(id, &self.aggregate_type, 2, &2)?]).await?;
+        for aggregate_id in [id, kept] {
+            self.engine.add_lookup_key(&LookupKey {
+                aggregate_id,
+                aggregate_type: self.aggregate_type.clone(),
+                key_name: "purged".to_string(),
+                key_value: self.suffix.clone(),
+            }).await?;
+        }
+
+        let report = self.engine.purge_aggregate(id, &self.aggregate_type).await?;
+        if report != (PurgeReport { events: 2, snapshots: 1, lookup_keys: 1, dedup_keys: 0 }) {
+            return Ok(Err(format!("purge report {:?}", report)));
+        }
+        let events = self.engine.read_events(id, &self.aggregate_type, 0).await?;
+        let snapshot = self.engine.read_snapshot(id, &self.aggregate_type).await?;
+        if !events.is_empty() || snapshot.is_some() {
+            return Ok(Err(format!("{} events and snapshot {:?} left", events.len(), snapshot)));
+        }
+        let found = self.engine.find_by_lookup_key(&self.aggregate_type, "purged", &self.suffix).await?;
+        let kept_events = self.engine.read_events(kept, &self.aggregate_type, 0).await?;
+        if found != [kept] || kept_events.len() != 1 {
+            return Ok(Err(format!("lookup key finds {:?} and the other aggregate has {} events", found, kept_events.len())));
+        }
+        match self.engine.purge_aggregate(id, &self.aggregate_type).await {
+            Err(error) if matches!(error.root_cause(), EventStoreError::AggregateInstanceNotFound) => {}
+            other => return Ok(Err(format!("purging again returned {:?}", other))),
+        }
+        match self.engine.create_aggregate_instance_with_policy(&self.aggregate_type, Some(&natural_key), Some(DuplicateKeyPolicy::Error)).await {
+            Ok(_) => Ok(Ok(())),
+            Err(error) if matches!(error.root_cause(), EventStoreError::DuplicateNaturalKey(_)) => Ok(Err("the natural key is still taken".to_string())),
+            Err(error) => Err(error),
+        }
+    }
+
+    async fn replace_events(&self) -> Result<Probe, EventStoreError> {
+        let id = self.engine.create_aggregate_instance(&self.aggregate_type, None).await?;
+        let events = [
+            Event::new(id, &self.aggregate_type, 1, "contract_checked", &1)?,
+            Event::new(id, &self.aggregate_type, 2, "contract_checked", &2)?,
+            Event::new(id, &self.aggregate_type, 3, "contract_checked", &3)?,
+        ];
+        self.engine.write_updates(&events, &[Snapshot::new(id, &self.aggregate_type, 3, &3)?]).await?;
+
+        let replacement = [
+            Event::new(id, &self.aggregate_type, 1, "contract_checked", &10)?,
+            Event::new(id, &self.aggregate_type, 2, "contract_checked", &30)?,
+        ];
+        let report = self.engine.replace_events(id, &self.aggregate_type, 3, &replacement).await?;
+        if report != (RewriteReport { events_removed: 3, events_written: 2, snapshots_removed: 1 }) {
+            return Ok(Err(format!("rewrite report {:?}", report)));
+        }
+        // Compares bytes, as engines with binary payloads read JSON back as bytes.
+        let events = self.engine.read_events(id, &self.aggregate_type, 0).await?;
+        let data: Vec<&[u8]> = events.iter().map(|event| event.data.as_bytes()).collect();
+        if data != [&b"10"[..], b"30"] || events.iter().map(|event| event.version).ne([1, 2]) {
+            return Ok(Err(format!("events read back as {:?}", events)));
+        }
+        if let Some(snapshot) = self.engine.read_snapshot(id, &self.aggregate_type).await? {
+            return Ok(Err(format!("snapshot {:?} left", snapshot)));
+        }
+        match self.engine.replace_events(id, &self.aggregate_type, 3, &replacement[..1]).await {
+            Err(error) if matches!(error.root_cause(), EventStoreError::VersionConflict { .. }) => {}
+            other => return Ok(Err(format!("replacing at a stale version returned {:?}", other))),
+        }
+        let version = self.engine.get_aggregate_version(id, &self.aggregate_type).await?;
+        match version {
+            2 => Ok(Ok(())),
+            version => Ok(Err(format!("the refused replacement left the aggregate at version {}", version))),
+        }
+    }
+
+    async fn tenants(&self) -> Result<Probe, EventStoreError> {
+        let tenant = self.engine.for_tenant(&format!("contract-a-{}", &self.suffix[..8]))?;
+        let other = self.engine.for_tenant(&format!("contract-b-{}", &self.suffix[..8]))?;
+        let natural_key = format!("contract-tenant-{}", self.suffix);
+        let id = tenant.create_aggregate_instance(&self.aggregate_type, Some(&natural_key)).await?;
+        let events = [Event::new(id, &self.aggregate_type, 1, "contract_checked", &1)?];
+        tenant.write_updates(&events, &[Snapshot::new(id, &self.aggregate_type, 1, &1)?]).await?;
+        tenant.add_lookup_key(&LookupKey {
+            aggregate_id: id,
+            aggregate_type: self.aggregate_type.clone(),
+            key_name: "tenant".to_string(),
+            key_value: self.suffix.clone(),
+        }).await?;
+        let checkpoint = format!("contract-tenant-{}", self.suffix);
+        if self.engine.capabilities().contains(EngineCapabilities::GLOBAL_FEED) {
+            tenant.write_checkpoint(&checkpoint, 1).await?;
+        }
+
+        for (name, engine) in [("another tenant", &other), ("the unscoped engine", &self.engine)] {
+            let events = engine.read_events(id, &self.aggregate_type, 0).await?;
+            let snapshot = engine.read_snapshot(id, &self.aggregate_type).await?;
+            let version = engine.get_aggregate_version(id, &self.aggregate_type).await?;
+            if !events.is_empty() || snapshot.is_some() || version != 0 {
+                return Ok(Err(format!("{} reads {} events, snapshot {:?} and version {}", name, events.len(), snapshot, version)));
+            }
+            let found = engine.get_aggregate_instance_id(&self.aggregate_type, &natural_key).await?;
+            let keyed = engine.find_by_lookup_key(&self.aggregate_type, "tenant", &self.suffix).await?;
+            if found.is_some() || !keyed.is_empty() {
+                return Ok(Err(format!("{} finds {:?} by natural key and {:?} by lookup key", name, found, keyed)));
+            }
+            if self.engine.capabilities().contains(EngineCapabilities::GLOBAL_FEED) {
+                let feed = engine.read_all_events(0, usize::MAX).await?;
+                if feed.iter().any(|event| event.aggregate_type == self.aggregate_type && event.aggregate_id == id) {
+                    return Ok(Err(format!("the feed of {} holds the tenant's events", name)));
+                }
+                if let Some(position) = engine.read_checkpoint(&checkpoint).await? {
+                    return Ok(Err(format!("{} reads the tenant's checkpoint at {}", name, position)));
+                }
+            }
+        }
+
+        let created = other.create_aggregate_instance_with_policy(&self.aggregate_type, Some(&natural_key), Some(DuplicateKeyPolicy::Error)).await?;
+        if created.id == id {
+            return Ok(Err("another tenant got the same aggregate id".to_string()));
+        }
+        let again = self.engine.for_tenant(&format!("contract-a-{}", &self.suffix[..8]))?;
+        let events = again.read_events(id, &self.aggregate_type, 0).await?;
+        Ok(ensure(events.len() == 1 && again.tenant_id() == tenant.tenant_id(), || format!("a new handle on the tenant reads {} events", events.len())))
+    }
+
+    async fn binary_payloads(&self) -> Result<Probe, EventStoreError> {
+        let id = self.engine.create_aggregate_instance(&self.aggregate_type, None).await?;
+        // Not valid UTF-8, and holding a NUL byte.
+        let data = Payload::Binary(vec![0x92, 0x00, 0xff, 0xc0]);
+        let mut event = Event::new(id, &self.aggregate_type, 1, "contract_checked", &1)?;
+        event.data = data.clone();
+        let mut snapshot = Snapshot::new(id, &self.aggregate_type, 1, &1)?;
+        snapshot.data = data.clone();
+        self.engine.write_updates(&[event], &[snapshot]).await?;
+
+        let events = self.engine.read_events(id, &self.aggregate_type, 0).await?;
+        if events.len() != 1 || events[0].data != data {
+            return Ok(Err(format!("read events {:?}", events)));
+        }
+        let snapshot = self.engine.read_snapshot(id, &self.aggregate_type).await?;
+        Ok(ensure(snapshot.as_ref().is_some_and(|snapshot| snapshot.data == data), || format!("read snapshot {:?}", snapshot)))
+    }
+}
@@ -0,0 +1,174 @@
+use std::{collections::HashMap, sync::{Arc, Mutex}};
+use tokio::sync::{Mutex as CommitQueue, OwnedMutexGuard, OwnedSemaphorePermit, Semaphore};
+use crate::{clock::StreamKey, EventStoreError, LookupKeyChange, WriteBatch};
+
+/// Routes commits through per-aggregate queues inside this process.
+///
+/// Commits touching the same aggregate wait for each other instead of racing into the
+/// storage engine, while commits on disjoint aggregates run in parallel up to
+/// `max_concurrency`. Queues for a multi-aggregate batch are always taken in sorted
+/// order, so two batches can never hold each other's queues.
+///
+/// This only coordinates commits made through one `EventStore`; other processes
+/// writing to the same storage still rely on the engine to detect conflicts.
+pub struct CommitCoordinator {
+    queues: Mutex<HashMap<StreamKey, Arc<CommitQueue<()>>>>,
+    permits: Arc<Semaphore>,
+    max_concurrency: usize,
+}
+
+/// Held while a commit is written. Releases the aggregate queues when dropped.
+pub struct CommitPermit {
+    _queues: Vec<OwnedMutexGuard<()>>,
+    _permit: OwnedSemaphorePermit,
+}
+
+impl CommitCoordinator {
+    pub fn new(max_concurrency: usize) -> CommitCoordinator {
+        CommitCoordinator {
+            queues: Mutex::new(HashMap::new()),
+            permits: Arc::new(Semaphore::new(max_concurrency)),
+            max_concurrency,
+        }
+    }
+
+    pub fn max_concurrency(&self) -> usize {
+        self.max_concurrency
+    }
+
+    /// Waits until every stream's queue is free and a concurrency slot is available.
+    pub async fn acquire(&self, mut streams: Vec<StreamKey>) -> Result<CommitPermit, EventStoreError> {
+        streams.sort();
+        streams.dedup();
+
+        let queues: Vec<_> = {
+            let mut all_queues = self.queues.lock()?;
+            // Queues nobody holds or waits on are dropped so the map does not grow forever.
+            all_queues.retain(|_, queue| Arc::strong_count(queue) > 1);
+            streams.into_iter()
+                .map(|stream| all_queues.entry(stream).or_default().clone())
+                .collect()
+        };
+
+        let mut guards = Vec::with_capacity(queues.len());
+        for queue in queues {
+            guards.push(queue.lock_owned().await);
+        }
+        let permit = self.permits.clone().acquire_owned().await
+            .expect("the commit semaphore is never closed");
+
+        Ok(CommitPermit {
+            _queues: guards,
+            _permit: permit,
+        })
+    }
+}
+
+/// The aggregate streams a batch writes to.
+pub(crate) fn batch_streams(batch: &WriteBatch<'_>) -> Vec<StreamKey> {
+    let events = batch.events.iter().map(|event| (event.aggregate_type.clone(), event.aggregate_id));
+    let snapshots = batch.snapshots.iter().map(|snapshot| (snapshot.aggregate_type.clone(), snapshot.aggregate_id));
+    let lookup_keys = batch.lookup_keys.iter().map(|change| match change {
+        LookupKeyChange::Add(key) | LookupKeyChange::Remove(key) => (key.aggregate_type.clone(), key.aggregate_id),
+    });
+    events.chain(snapshots).chain(lookup_keys).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+    use super::*;
+    use crate::event::numbered_id;
+
+    #[derive(Default)]
+    struct Overlap {
+        in_flight: AtomicUsize,
+        max_in_flight: AtomicUsize,
+    }
+
+    impl Overlap {
+        async fn run(&self) {
+            let now = self.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+            self.max_in_flight.fetch_max(now, Ordering::SeqCst);
+            for _ in 0..3 {
+                tokio::task::yield_now().await;
+            }
+            self.in_flight.fetch_sub(1, Ordering::SeqCst);
+        }
+
+        fn max(&self) -> usize {
+            self.max_in_flight.load(Ordering::SeqCst)
+        }
+    }
+
+    fn stream(id: i64) -> StreamKey {
+        ("account".to_string(), numbered_id(id))
+    }
+
+    async fn commit_same_aggregate(coordinator: Option<Arc<CommitCoordinator>>) -> usize {
+        let overlap = Arc::new(Overlap::default());
+        let mut tasks = Vec::new();
+        for _ in 0..20 {
+            let overlap = overlap.clone();
+            let coordinator = coordinator.clone();
+            tasks.push(tokio::spawn(async move {
+                let _permit = match &coordinator {
+                    Some(coordinator) => Some(coordinator.acquire(vec![stream(1)]).await.unwrap()),
+                    None => None,
+                };
+                overlap.run().await;
+            }));
+        }
+        for task in tasks {
+            task.await.unwrap();
+        }
+        overlap.max()
+    }
+
+    #[tokio::test]
+    async fn ensure_same_aggregate_commits_are_serialized() {
+        assert!(commit_same_aggregate(None).await > 1);
+        assert_eq!(commit_same_aggregate(Some(Arc::new(CommitCoordinator::new(8)))).await, 1);
+    }
+
+    #[tokio::test]
+    async fn ensure_disjoint_commits_run_up_to_the_limit() {
+        let coordinator = Arc::new(CommitCoordinator::new(3));
+        let overlap = Arc::new(Overlap::default());
+        let mut tasks = Vec::new();
+        for id in 0..10 {
+            let overlap = overlap.clone();
+            let coordinator = coordinator.clone();
+            tasks.push(tokio::spawn(async move {
+                let _permit = coordinator.acquire(vec![stream(id)]).await.unwrap();
+                overlap.run().await;
+            }));
+        }
+        for task in tasks {
+            task.await.unwrap();
+        }
+        assert_eq!(overlap.max(), 3);
+    }
+
+    #[tokio::test]
+    async fn ensure_overlapping_batches_do_not_deadlock() {
+        let coordinator = Arc::new(CommitCoordinator::new(4));
+        let mut tasks = Vec::new();
+        for round in 0..20 {
+            let coordinator = coordinator.clone();
+            let streams = if round % 2 == 0 { vec![stream(1), stream(2)] } else { vec![stream(2), stream(1)] };
+            tasks.push(tokio::spawn(async move {
+                let _permit = coordinator.acquire(streams).await.unwrap();
+                tokio::task::yield_now().await;
+            }));
+        }
+        let all = async {
+            for task in tasks {
+                task.await.unwrap();
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(5), all).await.unwrap();
+        assert!(coordinator.queues.lock().unwrap().values().all(|queue| Arc::strong_count(queue) == 1));
+    }
+}
@@ -0,0 +1,130 @@
+use std::{fmt, str::FromStr};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use crate::EventStoreError;
+
+/// Which listing API a cursor belongs to.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) enum CursorKind {
+    AggregateInstances(String),
+    GlobalFeed,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CursorState {
+    store: Uuid,
+    kind: CursorKind,
+    position: i64,
+}
+
+/// Opaque, URL-safe position in a paged listing.
+///
+/// A cursor is only valid for the listing and the `EventStore` instance that produced it;
+/// handing it to anything else fails with `EventStoreError::InvalidCursor`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Cursor(String);
+
+impl Cursor {
+    pub(crate) fn new(store: Uuid, kind: CursorKind, position: i64) -> Cursor {
+        let state = CursorState { store, kind, position };
+        let json = serde_json::to_vec(&state).expect("cursor state always serializes");
+        Cursor(URL_SAFE_NO_PAD.encode(json))
+    }
+
+    /// Returns the position if the cursor was issued by `store` for `kind`.
+    pub(crate) fn position(&self, store: Uuid, kind: &CursorKind) -> Result<i64, EventStoreError> {
+        let invalid = |reason: &str| EventStoreError::InvalidCursor(reason.to_string());
+        let json = URL_SAFE_NO_PAD.decode(&self.0).map_err(|_| invalid("not a cursor"))?;
+        let state: CursorState = serde_json::from_slice(&json).map_err(|_| invalid("not a cursor"))?;
+        if state.store != store {
+            return Err(invalid("issued by a different event store"));
+        }
+        if state.kind != *kind {
+            return Err(invalid("issued for a different listing"));
+        }
+        Ok(state.position)
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for Cursor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// A point in the global feed taken by `EventStore::consistent_view`. Aggregates loaded
+/// with `EventContext::load_at_view` and the same token reflect that one point in time.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ViewToken {
+    store: Uuid,
+    position: i64,
+}
+
+impl ViewToken {
+    pub(crate) fn new(store: Uuid, position: i64) -> ViewToken {
+        ViewToken { store, position }
+    }
+
+    /// Position of the latest event included in the view.
+    pub fn position(&self) -> i64 {
+        self.position
+    }
+
+    /// Returns the position if the token was taken from `store`.
+    pub(crate) fn position_in(&self, store: Uuid) -> Result<i64, EventStoreError> {
+        match self.store == store {
+            true => Ok(self.position),
+            false => Err(EventStoreError::InvalidCursor("view taken from a different event store".to_string())),
+        }
+    }
+}
+
+/// Cursors are checked when they are used, so parsing never fails.
+impl FromStr for Cursor {
+    type Err = std::convert::Infallible;
+
+    fn from_str(s: &str) -> Result<Cursor, Self::Err> {
+        Ok(Cursor(s.to_string()))
+    }
+}
+
+/// One page of a listing, with the cursor for the next page if there may be one.
+#[derive(Clone, Debug)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub next: Option<Cursor>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ensure_cursor_round_trips_through_a_string() {
+        let store = Uuid::new_v4();
+        let cursor = Cursor::new(store, CursorKind::GlobalFeed, 42);
+        let parsed: Cursor = cursor.to_string().parse().unwrap();
+        assert_eq!(parsed.position(store, &CursorKind::GlobalFeed).unwrap(), 42);
+        assert!(cursor.as_str().chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_'));
+    }
+
+    #[test]
+    fn ensure_mismatched_cursors_are_rejected() {
+        let store = Uuid::new_v4();
+        let cursor = Cursor::new(store, CursorKind::AggregateInstances("order".to_string()), 10);
+
+        let other_type = CursorKind::AggregateInstances("user".to_string());
+        assert!(matches!(cursor.position(store, &other_type), Err(EventStoreError::InvalidCursor(_))));
+        assert!(matches!(cursor.position(store, &CursorKind::GlobalFeed), Err(EventStoreError::InvalidCursor(_))));
+        assert!(matches!(cursor.position(Uuid::new_v4(), &CursorKind::AggregateInstances("order".to_string())),
+            Err(EventStoreError::InvalidCursor(_))));
+
+        let garbage: Cursor = "not-a-cursor".parse().unwrap();
+        assert!(matches!(garbage.position(store, &CursorKind::GlobalFeed), Err(EventStoreError::InvalidCursor(_))));
+    }
+}
@@ -0,0 +1,83 @@
+use std::{future::Future, time::Duration};
+use tokio::time::Instant;
+use crate::EventStoreError;
+
+tokio::task_local! {
+    static DEADLINE: Instant;
+}
+
+/// Runs `future` with `deadline` as the budget of every storage call made within it, such
+/// as those of a request handler. Nested deadlines keep the earlier one. Fails with
+/// `StorageTimeout` once the deadline passes, dropping the future.
+pub async fn with_deadline<T, F>(deadline: Instant, future: F) -> Result<T, EventStoreError>
+where
+    F: Future<Output = Result<T, EventStoreError>>
+{
+    let deadline = current().map_or(deadline, |outer| outer.min(deadline));
+    DEADLINE.scope(deadline, bounded(future)).await
+}
+
+/// The deadline set by the innermost `with_deadline`, if any.
+pub fn current() -> Option<Instant> {
+    DEADLINE.try_with(|deadline| *deadline).ok()
+}
+
+/// Time left before the current deadline; zero once it has passed.
+pub fn remaining() -> Option<Duration> {
+    current().map(|deadline| deadline.saturating_duration_since(Instant::now()))
+}
+
+/// Fails with `StorageTimeout` when the current deadline has passed, for engines to call
+/// before starting an operation and loops before another attempt.
+pub fn check() -> Result<(), EventStoreError> {
+    match remaining() {
+        Some(remaining) if remaining.is_zero() => Err(EventStoreError::StorageTimeout),
+        _ => Ok(()),
+    }
+}
+
+/// Awaits `future` within the current deadline: it is not started when the deadline has
+/// passed and is dropped when the deadline passes while it runs.
+pub async fn bounded<T, F>(future: F) -> Result<T, EventStoreError>
+where
+    F: Future<Output = Result<T, EventStoreError>>
+{
+    let Some(deadline) = current() else {
+        return future.await;
+    };
+    check()?;
+    tokio::time::timeout_at(deadline, future).await
+        .map_err(|_| EventStoreError::StorageTimeout)?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn ensure_nested_deadlines_keep_the_earlier() {
+        assert!(current().is_none());
+        let outer = Instant::now() + Duration::from_secs(60);
+        let inner = with_deadline(outer, async {
+            with_deadline(outer + Duration::from_secs(60), async { Ok(current()) }).await
+        }).await.unwrap();
+        assert_eq!(inner, Some(outer));
+    }
+
+    #[tokio::test]
+    async fn ensure_expired_deadlines_skip_and_cancel_futures() {
+        let started = std::sync::atomic::AtomicBool::new(false);
+        let result = with_deadline(Instant::now(), async {
+            started.store(true, std::sync::atomic::Ordering::SeqCst);
+            Ok(())
+        }).await;
+        assert!(matches!(result, Err(EventStoreError::StorageTimeout)));
+        assert!(!started.load(std::sync::atomic::Ordering::SeqCst));
+
+        let result = with_deadline(Instant::now() + Duration::from_millis(20), async {
+            tokio::time::sleep(Duration::from_secs(5)).await;
+            Ok(())
+        }).await;
+        assert!(matches!(result, Err(EventStoreError::StorageTimeout)));
+    }
+}
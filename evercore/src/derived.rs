@@ -0,0 +1,156 @@
+use std::{fmt, sync::{atomic::{AtomicU64, Ordering}, OnceLock}};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// A value computed from the rest of an aggregate's state (e.g. a sorted index or a total),
+/// cached until the next event is applied.
+///
+/// `ComposedAggregate` calls `Composable::invalidate_derived` after every event, which
+/// should `invalidate` each derived field. The value is then recomputed on first access,
+/// so replaying many events computes it once instead of once per event.
+///
+/// The cached value is not serialized; snapshots store `null` in its place and aggregates
+/// loaded from them recompute it when it is first read.
+pub struct Derived<D> {
+    value: OnceLock<D>,
+    recomputes: AtomicU64,
+}
+
+impl<D> Derived<D> {
+    /// The cached value, computed with `recompute` if there is none.
+    pub fn get_or_recompute(&self, recompute: impl FnOnce() -> D) -> &D {
+        self.value.get_or_init(|| {
+            self.recomputes.fetch_add(1, Ordering::Relaxed);
+            recompute()
+        })
+    }
+
+    /// The cached value, if it is current.
+    pub fn get(&self) -> Option<&D> {
+        self.value.get()
+    }
+
+    /// Drops the cached value.
+    pub fn invalidate(&mut self) {
+        self.value.take();
+    }
+
+    /// How many times the value was computed since this field was created.
+    pub fn recomputes(&self) -> u64 {
+        self.recomputes.load(Ordering::Relaxed)
+    }
+}
+
+impl<D> Default for Derived<D> {
+    fn default() -> Self {
+        Derived {
+            value: OnceLock::new(),
+            recomputes: AtomicU64::new(0),
+        }
+    }
+}
+
+impl<D: Clone> Clone for Derived<D> {
+    fn clone(&self) -> Self {
+        Derived {
+            value: self.value.clone(),
+            recomputes: AtomicU64::new(self.recomputes()),
+        }
+    }
+}
+
+impl<D: fmt::Debug> fmt::Debug for Derived<D> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Derived").field("value", &self.value.get()).finish()
+    }
+}
+
+impl<D> Serialize for Derived<D> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_unit()
+    }
+}
+
+impl<'de, D> Deserialize<'de> for Derived<D> {
+    fn deserialize<De: Deserializer<'de>>(deserializer: De) -> Result<Self, De::Error> {
+        serde::de::IgnoredAny::deserialize(deserializer)?;
+        Ok(Derived::default())
+    }
+}
+
+#[cfg(all(test, feature = "memory"))]
+mod tests {
+    use serde::{Deserialize, Serialize};
+    use super::*;
+    use crate::aggregate::{Aggregate, Composable, ComposedAggregate};
+    use crate::{event::Event, memory::MemoryStorageEngine, EventStore, EventStoreError};
+
+    #[derive(Clone, Default, Serialize, Deserialize)]
+    struct Scores {
+        scores: Vec<i64>,
+        ranked: Derived<Vec<i64>>,
+    }
+
+    impl Scores {
+        fn ranked(&self) -> &[i64] {
+            self.ranked.get_or_recompute(|| {
+                let mut ranked = self.scores.clone();
+                ranked.sort_unstable_by(|a, b| b.cmp(a));
+                ranked
+            })
+        }
+    }
+
+    impl Composable for Scores {
+        fn get_type(&self) -> &str {
+            "scores"
+        }
+
+        fn apply_event(&mut self, event: &Event) -> Result<(), EventStoreError> {
+            self.scores.push(event.deserialize()?);
+            Ok(())
+        }
+
+        fn snapshot_frequency(&self) -> i32 {
+            0
+        }
+
+        fn invalidate_derived(&mut self) {
+            self.ranked.invalidate();
+        }
+    }
+
+    #[tokio::test]
+    async fn ensure_derived_state_is_computed_once_per_load() {
+        let event_store = EventStore::new(MemoryStorageEngine::new());
+        let ctx = event_store.get_context();
+        let mut scores = ComposedAggregate::<Scores>::new(&ctx, None).await.unwrap();
+        for score in 0..10_000i64 {
+            ctx.publish(&mut scores, "scored", &((score * 7919) % 10_007)).unwrap();
+        }
+        ctx.commit().await.unwrap();
+
+        let ctx = event_store.get_context();
+        let mut loaded = ComposedAggregate::<Scores>::load(&ctx, scores.id()).await.unwrap();
+        assert_eq!(loaded.state().ranked.recomputes(), 0);
+        assert_eq!(loaded.state().ranked()[0], 10_006);
+        assert_eq!(loaded.state().ranked().len(), 10_000);
+        assert_eq!(loaded.state().ranked.recomputes(), 1);
+
+        ctx.publish(&mut loaded, "scored", &20_000i64).unwrap();
+        assert!(loaded.state().ranked.get().is_none());
+        assert_eq!(loaded.state().ranked()[0], 20_000);
+        assert_eq!(loaded.state().ranked.recomputes(), 2);
+    }
+
+    #[test]
+    fn ensure_derived_values_are_left_out_of_snapshots() {
+        let state = Scores { scores: vec![1, 3, 2], ..Scores::default() };
+        assert_eq!(state.ranked(), [3, 2, 1]);
+
+        let json = serde_json::to_string(&state).unwrap();
+        assert_eq!(json, r#"{"scores":[1,3,2],"ranked":null}"#);
+        let restored: Scores = serde_json::from_str(&json).unwrap();
+        assert!(restored.ranked.get().is_none());
+        assert_eq!(restored.ranked(), [3, 2, 1]);
+    }
+}
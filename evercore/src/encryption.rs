@@ -0,0 +1,513 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use chacha20poly1305::consts::U12;
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use serde_json::Value;
+use crate::{event::Event, snapshot::Snapshot, AggregateId, EventStoreError};
+
+/// Name of the field marking an encrypted value.
+pub const MARKER_FIELD: &str = "__enc";
+
+/// A 256-bit key, as used by every `EventEncryption`.
+pub type EncryptionKey = [u8; 32];
+
+/// Length of the nonce stored in front of each sealed value, the same for every cipher.
+const NONCE_LEN: usize = 12;
+
+/// The authenticated cipher sealing encrypted fields with their aggregate's key, set with
+/// `EventStoreBuilder::event_encryption`. ChaCha20-Poly1305 unless configured otherwise.
+///
+/// Sealed values do not record their cipher: a store reads back the values it sealed, so
+/// changing the cipher of a store with data needs the data migrated.
+pub trait EventEncryption: Send + Sync {
+    /// Short name of the cipher, e.g. "chacha20poly1305".
+    fn name(&self) -> &'static str;
+
+    /// Seals `plaintext` under a fresh nonce, binding it to `associated_data`.
+    fn encrypt(&self, key: &EncryptionKey, plaintext: &[u8], associated_data: &[u8]) -> Result<Vec<u8>, EventStoreError>;
+
+    /// Opens a value sealed by `encrypt` with the same key and associated data.
+    fn decrypt(&self, key: &EncryptionKey, sealed: &[u8], associated_data: &[u8]) -> Result<Vec<u8>, EventStoreError>;
+}
+
+/// Seals `plaintext` with an AEAD cipher, prefixing the random nonce.
+fn seal<C: Aead + AeadCore<NonceSize = U12>>(cipher: &C, plaintext: &[u8], associated_data: &[u8]) -> Result<Vec<u8>, EventStoreError> {
+    let nonce = C::generate_nonce(&mut OsRng);
+    let ciphertext = cipher.encrypt(&nonce, Payload { msg: plaintext, aad: associated_data })
+        .map_err(|_| EventStoreError::EncryptionError("could not encrypt".to_string()))?;
+    let mut sealed = nonce.to_vec();
+    sealed.extend(ciphertext);
+    Ok(sealed)
+}
+
+fn open<C: Aead + AeadCore<NonceSize = U12>>(cipher: &C, sealed: &[u8], associated_data: &[u8]) -> Result<Vec<u8>, EventStoreError> {
+    if sealed.len() < NONCE_LEN {
+        return Err(EventStoreError::EncryptionError("too short to hold a nonce".to_string()));
+    }
+    let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+    cipher.decrypt(Nonce::from_slice(nonce), Payload { msg: ciphertext, aad: associated_data })
+        .map_err(|_| EventStoreError::EncryptionError("could not decrypt".to_string()))
+}
+
+/// ChaCha20-Poly1305, the default cipher.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ChaCha20Poly1305Encryption;
+
+impl EventEncryption for ChaCha20Poly1305Encryption {
+    fn name(&self) -> &'static str {
+        "chacha20poly1305"
+    }
+
+    fn encrypt(&self, key: &EncryptionKey, plaintext: &[u8], associated_data: &[u8]) -> Result<Vec<u8>, EventStoreError> {
+        seal(&ChaCha20Poly1305::new(Key::from_slice(key)), plaintext, associated_data)
+    }
+
+    fn decrypt(&self, key: &EncryptionKey, sealed: &[u8], associated_data: &[u8]) -> Result<Vec<u8>, EventStoreError> {
+        open(&ChaCha20Poly1305::new(Key::from_slice(key)), sealed, associated_data)
+    }
+}
+
+/// AES-256-GCM, for deployments that need a NIST approved cipher or have AES hardware
+/// support.
+#[cfg(feature = "aes-gcm")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Aes256GcmEncryption;
+
+#[cfg(feature = "aes-gcm")]
+impl EventEncryption for Aes256GcmEncryption {
+    fn name(&self) -> &'static str {
+        "aes256gcm"
+    }
+
+    fn encrypt(&self, key: &EncryptionKey, plaintext: &[u8], associated_data: &[u8]) -> Result<Vec<u8>, EventStoreError> {
+        seal(&aes_gcm::Aes256Gcm::new(aes_gcm::Key::<aes_gcm::Aes256Gcm>::from_slice(key)), plaintext, associated_data)
+    }
+
+    fn decrypt(&self, key: &EncryptionKey, sealed: &[u8], associated_data: &[u8]) -> Result<Vec<u8>, EventStoreError> {
+        open(&aes_gcm::Aes256Gcm::new(aes_gcm::Key::<aes_gcm::Aes256Gcm>::from_slice(key)), sealed, associated_data)
+    }
+}
+
+/// Holds a key per aggregate instance. Deleting an instance's key (crypto-shredding)
+/// leaves its encrypted fields unreadable while the rest of its events stay intact.
+#[async_trait::async_trait]
+pub trait KeyStore: Send + Sync {
+    /// The key of an aggregate instance, or None if it has none or it was shredded.
+    async fn key(&self, aggregate_type: &str, aggregate_id: AggregateId) -> Result<Option<EncryptionKey>, EventStoreError>;
+
+    /// The key of an aggregate instance, generating one if it has none. An instance written
+    /// to after shredding gets a new key; its earlier fields stay unreadable.
+    async fn get_or_create_key(&self, aggregate_type: &str, aggregate_id: AggregateId) -> Result<EncryptionKey, EventStoreError>;
+
+    /// Deletes the key of an aggregate instance. Shredding a missing key is not an error.
+    async fn shred(&self, aggregate_type: &str, aggregate_id: AggregateId) -> Result<(), EventStoreError>;
+}
+
+/// Keeps keys in memory, for tests and single process deployments that rebuild their
+/// keys elsewhere.
+#[derive(Default)]
+pub struct MemoryKeyStore {
+    keys: Mutex<HashMap<(String, AggregateId), EncryptionKey>>,
+}
+
+impl MemoryKeyStore {
+    pub fn new() -> Arc<MemoryKeyStore> {
+        Arc::new(MemoryKeyStore::default())
+    }
+}
+
+#[async_trait::async_trait]
+impl KeyStore for MemoryKeyStore {
+    async fn key(&self, aggregate_type: &str, aggregate_id: AggregateId) -> Result<Option<EncryptionKey>, EventStoreError> {
+        Ok(self.keys.lock()?.get(&(aggregate_type.to_string(), aggregate_id)).copied())
+    }
+
+    async fn get_or_create_key(&self, aggregate_type: &str, aggregate_id: AggregateId) -> Result<EncryptionKey, EventStoreError> {
+        let mut keys = self.keys.lock()?;
+        let key = keys.entry((aggregate_type.to_string(), aggregate_id))
+            .or_insert_with(|| ChaCha20Poly1305::generate_key(&mut OsRng).into());
+        Ok(*key)
+    }
+
+    async fn shred(&self, aggregate_type: &str, aggregate_id: AggregateId) -> Result<(), EventStoreError> {
+        self.keys.lock()?.remove(&(aggregate_type.to_string(), aggregate_id));
+        Ok(())
+    }
+}
+
+/// The sensitive fields of an aggregate type, as JSON pointers (`/card/number`) into its
+/// event data, its snapshots and its event metadata. Everything else is stored in
+/// plaintext and stays queryable.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct EncryptedFields {
+    event_fields: Vec<String>,
+    snapshot_fields: Vec<String>,
+    metadata_fields: Vec<String>,
+}
+
+impl EncryptedFields {
+    pub fn new() -> EncryptedFields {
+        EncryptedFields::default()
+    }
+
+    /// Encrypts the value at `pointer` in the data of every event of the type that has one.
+    pub fn event_field(mut self, pointer: &str) -> EncryptedFields {
+        self.event_fields.push(pointer.to_string());
+        self
+    }
+
+    /// Encrypts the value at `pointer` in the snapshots of the type. Snapshots hold the
+    /// aggregate state, which usually repeats the sensitive event fields.
+    pub fn snapshot_field(mut self, pointer: &str) -> EncryptedFields {
+        self.snapshot_fields.push(pointer.to_string());
+        self
+    }
+
+    /// Encrypts the metadata entry at `pointer`, e.g. `/ip_address`, of every event of the
+    /// type that has one. The entry's key stays readable, so required metadata checks still
+    /// see it.
+    pub fn metadata_field(mut self, pointer: &str) -> EncryptedFields {
+        self.metadata_fields.push(pointer.to_string());
+        self
+    }
+
+    pub(crate) fn pointers(&self) -> impl Iterator<Item = &String> {
+        self.event_fields.iter().chain(&self.snapshot_fields).chain(&self.metadata_fields)
+    }
+}
+
+/// Encrypts the configured fields of events and snapshots as they are written and
+/// decrypts them as they are read, so aggregates only ever see plaintext.
+///
+/// Each value is replaced by `{"__enc":"<base64 nonce and ciphertext>"}`, bound to its
+/// aggregate instance and pointer. Values that are not markers are read as they are, so
+/// data written before a field was marked stays readable. The fields of a shredded
+/// instance read as `null`.
+#[derive(Clone)]
+pub(crate) struct FieldEncryption {
+    key_store: Arc<dyn KeyStore>,
+    cipher: Arc<dyn EventEncryption>,
+    fields: HashMap<String, EncryptedFields>,
+}
+
+type KeyCache = HashMap<(String, AggregateId), Option<EncryptionKey>>;
+
+/// Where an encrypted field lives. Part of the associated data, so a value sealed in the
+/// metadata cannot be moved to the data at the same pointer.
+#[derive(Clone, Copy)]
+enum Section {
+    /// Event data and snapshots.
+    Data,
+    Metadata,
+}
+
+impl FieldEncryption {
+    pub(crate) fn new(key_store: Arc<dyn KeyStore>, cipher: Arc<dyn EventEncryption>, fields: HashMap<String, EncryptedFields>) -> FieldEncryption {
+        FieldEncryption { key_store, cipher, fields }
+    }
+
+    pub(crate) async fn encrypt_events(&self, events: &[Event]) -> Result<Vec<Event>, EventStoreError> {
+        let mut encrypted = Vec::with_capacity(events.len());
+        for event in events {
+            let mut event = event.clone();
+            if let Some(fields) = self.fields.get(&event.aggregate_type) {
+                let data = event.data.json()?;
+                event.data = self.encrypt(&event.aggregate_type, event.aggregate_id, Section::Data, &fields.event_fields, data).await?.into();
+                if let Some(metadata) = &event.metadata {
+                    event.metadata = Some(self.encrypt(&event.aggregate_type, event.aggregate_id, Section::Metadata, &fields.metadata_fields, metadata).await?);
+                }
+            }
+            encrypted.push(event);
+        }
+        Ok(encrypted)
+    }
+
+    pub(crate) async fn encrypt_snapshots(&self, snapshots: &[Snapshot]) -> Result<Vec<Snapshot>, EventStoreError> {
+        let mut encrypted = Vec::with_capacity(snapshots.len());
+        for snapshot in snapshots {
+            let mut snapshot = snapshot.clone();
+            if let Some(fields) = self.fields.get(&snapshot.aggregate_type) {
+                let data = snapshot.data.json()?;
+                snapshot.data = self.encrypt(&snapshot.aggregate_type, snapshot.aggregate_id, Section::Data, &fields.snapshot_fields, data).await?.into();
+            }
+            encrypted.push(snapshot);
+        }
+        Ok(encrypted)
+    }
+
+    pub(crate) async fn decrypt_events(&self, mut events: Vec<Event>) -> Result<Vec<Event>, EventStoreError> {
+        let mut keys = KeyCache::new();
+        for event in &mut events {
+            if let Some(fields) = self.fields.get(&event.aggregate_type) {
+                let data = event.data.json()?;
+                event.data = self.decrypt(&mut keys, &event.aggregate_type, event.aggregate_id, Section::Data, &fields.event_fields, data).await?.into();
+                if let Some(metadata) = &event.metadata {
+                    event.metadata = Some(self.decrypt(&mut keys, &event.aggregate_type, event.aggregate_id, Section::Metadata, &fields.metadata_fields, metadata).await?);
+                }
+            }
+        }
+        Ok(events)
+    }
+
+    pub(crate) async fn decrypt_snapshot(&self, mut snapshot: Snapshot) -> Result<Snapshot, EventStoreError> {
+        if let Some(fields) = self.fields.get(&snapshot.aggregate_type) {
+            let mut keys = KeyCache::new();
+            let data = snapshot.data.json()?;
+            snapshot.data = self.decrypt(&mut keys, &snapshot.aggregate_type, snapshot.aggregate_id, Section::Data, &fields.snapshot_fields, data).await?.into();
+        }
+        Ok(snapshot)
+    }
+
+    async fn encrypt(&self, aggregate_type: &str, aggregate_id: AggregateId, section: Section, pointers: &[String], data: &str) -> Result<String, EventStoreError> {
+        if pointers.is_empty() {
+            return Ok(data.to_string());
+        }
+        let mut document: Value = serde_json::from_str(data).map_err(EventStoreError::EventDeserializationError)?;
+        let mut key = None;
+        for pointer in pointers {
+            let Some(value) = document.pointer_mut(pointer) else {
+                continue;
+            };
+            if marker(value).is_some() {
+                continue;
+            }
+            let key = match &key {
+                Some(key) => key,
+                None => key.insert(self.key_store.get_or_create_key(aggregate_type, aggregate_id).await?),
+            };
+            let plaintext = serde_json::to_vec(value).map_err(EventStoreError::EventSerializationError)?;
+            let aad = associated_data(aggregate_type, aggregate_id, section, pointer);
+            let sealed = self.cipher.encrypt(key, &plaintext, aad.as_bytes())
+                .map_err(|_| EventStoreError::EncryptionError(format!("could not encrypt {}", pointer)))?;
+            *value = serde_json::json!({ MARKER_FIELD: STANDARD.encode(sealed) });
+        }
+        if key.is_none() {
+            return Ok(data.to_string());
+        }
+        serde_json::to_string(&document).map_err(EventStoreError::EventSerializationError)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn decrypt(&self, keys: &mut KeyCache, aggregate_type: &str, aggregate_id: AggregateId, section: Section, pointers: &[String], data: &str) -> Result<String, EventStoreError> {
+        if pointers.is_empty() || !data.contains(MARKER_FIELD) {
+            return Ok(data.to_string());
+        }
+        let mut document: Value = serde_json::from_str(data).map_err(EventStoreError::EventDeserializationError)?;
+        for pointer in pointers {
+            let Some(value) = document.pointer_mut(pointer) else {
+                continue;
+            };
+            let Some(sealed) = marker(value) else {
+                continue;
+            };
+            let cache_key = (aggregate_type.to_string(), aggregate_id);
+            let key = match keys.get(&cache_key) {
+                Some(key) => *key,
+                None => {
+                    let key = self.key_store.key(aggregate_type, aggregate_id).await?;
+                    *keys.entry(cache_key).or_insert(key)
+                }
+            };
+            let Some(key) = key else {
+                *value = Value::Null;
+                continue;
+            };
+            let sealed = STANDARD.decode(sealed)
+                .map_err(|_| EventStoreError::EncryptionError(format!("{} is not valid base64", pointer)))?;
+            if sealed.len() < NONCE_LEN {
+                return Err(EventStoreError::EncryptionError(format!("{} is too short to hold a nonce", pointer)));
+            }
+            let aad = associated_data(aggregate_type, aggregate_id, section, pointer);
+            let plaintext = self.cipher.decrypt(&key, &sealed, aad.as_bytes())
+                .map_err(|_| EventStoreError::EncryptionError(format!("could not decrypt {}", pointer)))?;
+            *value = serde_json::from_slice(&plaintext).map_err(EventStoreError::EventDeserializationError)?;
+        }
+        serde_json::to_string(&document).map_err(EventStoreError::EventSerializationError)
+    }
+}
+
+/// The sealed value of an encrypted field: an object holding only the marker field.
+fn marker(value: &Value) -> Option<String> {
+    match value.as_object() {
+        Some(object) if object.len() == 1 => object.get(MARKER_FIELD)?.as_str().map(str::to_string),
+        _ => None,
+    }
+}
+
+/// Ties a sealed value to where it was written, so it cannot be moved to another instance
+/// or field.
+fn associated_data(aggregate_type: &str, aggregate_id: AggregateId, section: Section, pointer: &str) -> String {
+    match section {
+        Section::Data => format!("{}/{}{}", aggregate_type, aggregate_id, pointer),
+        Section::Metadata => format!("{}/{}#metadata{}", aggregate_type, aggregate_id, pointer),
+    }
+}
+
+#[cfg(all(test, feature = "memory"))]
+mod tests {
+    use serde::{Deserialize, Serialize};
+    use super::*;
+    use crate::aggregate::{Aggregate, Composable, ComposedAggregate};
+    use crate::{memory::MemoryStorageEngine, EventStore, EventStoreStorageEngine, SharedEventStore};
+
+    #[derive(Clone, Default, Serialize, Deserialize)]
+    struct User {
+        name: String,
+        email: Option<String>,
+    }
+
+    impl Composable for User {
+        fn get_type(&self) -> &str {
+            "user"
+        }
+
+        fn apply_event(&mut self, event: &Event) -> Result<(), EventStoreError> {
+            *self = event.deserialize()?;
+            Ok(())
+        }
+
+        fn snapshot_frequency(&self) -> i32 {
+            2
+        }
+    }
+
+    fn encrypting_store(storage_engine: Arc<MemoryStorageEngine>, key_store: Arc<MemoryKeyStore>) -> SharedEventStore {
+        EventStore::builder(storage_engine)
+            .key_store(key_store)
+            .encrypted_fields("user", EncryptedFields::new().event_field("/email").snapshot_field("/email"))
+            .build()
+            .unwrap()
+    }
+
+    async fn register(event_store: &SharedEventStore, name: &str, email: &str) -> AggregateId {
+        let ctx = event_store.get_context();
+        let mut user = ComposedAggregate::<User>::new(&ctx, None).await.unwrap();
+        let registered = User { name: name.to_string(), email: Some(email.to_string()) };
+        ctx.publish(&mut user, "registered", &registered).unwrap();
+        // The snapshot taken with the second event holds the state after the first.
+        ctx.publish(&mut user, "verified", &registered).unwrap();
+        ctx.commit().await.unwrap();
+        user.id()
+    }
+
+    #[tokio::test]
+    async fn ensure_only_marked_fields_are_stored_encrypted() {
+        let storage_engine = MemoryStorageEngine::new();
+        let event_store = encrypting_store(storage_engine.clone(), MemoryKeyStore::new());
+        let id = register(&event_store, "Ada", "ada@example.com").await;
+
+        let stored = storage_engine.read_events(id, "user", 0).await.unwrap();
+        let data: Value = serde_json::from_str(stored[0].data.json().unwrap()).unwrap();
+        assert_eq!(data["name"], "Ada");
+        assert!(data["email"][MARKER_FIELD].is_string());
+        assert!(!stored[0].data.to_string().contains("ada@example.com"));
+        let snapshot = storage_engine.read_snapshot(id, "user").await.unwrap().unwrap();
+        assert!(snapshot.data.to_string().contains("Ada"));
+        assert!(!snapshot.data.to_string().contains("ada@example.com"));
+
+        let events = event_store.get_events(id, "user", 0).await.unwrap();
+        assert_eq!(events[0].deserialize::<User>().unwrap().email.as_deref(), Some("ada@example.com"));
+        let ctx = event_store.get_context();
+        let user = ComposedAggregate::<User>::load(&ctx, id).await.unwrap();
+        assert_eq!(user.state().email.as_deref(), Some("ada@example.com"));
+    }
+
+    #[tokio::test]
+    async fn ensure_shredding_a_key_only_breaks_marked_fields() {
+        let key_store = MemoryKeyStore::new();
+        let event_store = encrypting_store(MemoryStorageEngine::new(), key_store.clone());
+        let shredded = register(&event_store, "Ada", "ada@example.com").await;
+        let kept = register(&event_store, "Grace", "grace@example.com").await;
+
+        key_store.shred("user", shredded).await.unwrap();
+        let events = event_store.get_events(shredded, "user", 0).await.unwrap();
+        let data: Value = serde_json::from_str(events[0].data.json().unwrap()).unwrap();
+        assert_eq!(data, serde_json::json!({ "name": "Ada", "email": null }));
+        let ctx = event_store.get_context();
+        let user = ComposedAggregate::<User>::load(&ctx, shredded).await.unwrap();
+        assert_eq!((user.state().name.as_str(), user.state().email.as_deref()), ("Ada", None));
+
+        let feed = event_store.read_all_events(0, 10).await.unwrap();
+        let emails: Vec<Option<String>> = feed.iter().map(|event| event.deserialize::<User>().unwrap().email).collect();
+        assert_eq!(emails, [None, None, Some("grace@example.com".to_string()), Some("grace@example.com".to_string())]);
+        let user = ComposedAggregate::<User>::load(&ctx, kept).await.unwrap();
+        assert_eq!(user.state().email.as_deref(), Some("grace@example.com"));
+    }
+
+    #[tokio::test]
+    async fn ensure_marked_metadata_is_stored_encrypted() {
+        let storage_engine = MemoryStorageEngine::new();
+        let event_store = EventStore::builder(storage_engine.clone())
+            .key_store(MemoryKeyStore::new())
+            .encrypted_fields("user", EncryptedFields::new().metadata_field("/ip_address"))
+            .build()
+            .unwrap();
+        let ctx = event_store.get_context();
+        ctx.add_metadata("ip_address", "192.0.2.7").unwrap();
+        ctx.add_metadata("request_id", "r-1").unwrap();
+        let mut user = ComposedAggregate::<User>::new(&ctx, None).await.unwrap();
+        ctx.publish(&mut user, "registered", &User { name: "Ada".to_string(), email: None }).unwrap();
+        ctx.commit().await.unwrap();
+
+        let stored = storage_engine.read_events(user.id(), "user", 0).await.unwrap();
+        let metadata: Value = serde_json::from_str(stored[0].metadata.as_deref().unwrap()).unwrap();
+        assert!(metadata["ip_address"][MARKER_FIELD].is_string());
+        assert_eq!(metadata["request_id"], "r-1");
+        assert!(!stored[0].metadata.as_deref().unwrap().contains("192.0.2.7"));
+
+        let events = event_store.get_events(user.id(), "user", 0).await.unwrap();
+        let metadata: HashMap<String, String> = events[0].deserialize_metadata().unwrap().unwrap();
+        assert_eq!(metadata["ip_address"], "192.0.2.7");
+        assert_eq!(metadata["request_id"], "r-1");
+    }
+
+    #[test]
+    fn ensure_sealed_values_are_bound_to_their_associated_data() {
+        let key = [7u8; 32];
+        let sealed = ChaCha20Poly1305Encryption.encrypt(&key, b"secret", b"user/1/email").unwrap();
+        assert_eq!(ChaCha20Poly1305Encryption.decrypt(&key, &sealed, b"user/1/email").unwrap(), b"secret");
+        assert!(ChaCha20Poly1305Encryption.decrypt(&key, &sealed, b"user/2/email").is_err());
+        assert!(ChaCha20Poly1305Encryption.decrypt(&[8u8; 32], &sealed, b"user/1/email").is_err());
+    }
+
+    #[cfg(feature = "aes-gcm")]
+    #[tokio::test]
+    async fn ensure_aes_gcm_stores_ciphertext_and_round_trips() {
+        let storage_engine = MemoryStorageEngine::new();
+        let key_store = MemoryKeyStore::new();
+        let event_store = EventStore::builder(storage_engine.clone())
+            .key_store(key_store.clone())
+            .event_encryption(Arc::new(Aes256GcmEncryption))
+            .encrypted_fields("user", EncryptedFields::new().event_field("/email").snapshot_field("/email"))
+            .build()
+            .unwrap();
+        let id = register(&event_store, "Ada", "ada@example.com").await;
+
+        let stored = storage_engine.read_events(id, "user", 0).await.unwrap();
+        assert!(!stored[0].data.to_string().contains("ada@example.com"));
+        let ctx = event_store.get_context();
+        let user = ComposedAggregate::<User>::load(&ctx, id).await.unwrap();
+        assert_eq!(user.state().email.as_deref(), Some("ada@example.com"));
+
+        // Sealed values do not record their cipher.
+        let chacha_store = encrypting_store(storage_engine, key_store);
+        let read = chacha_store.get_events(id, "user", 0).await;
+        assert!(matches!(read, Err(EventStoreError::EncryptionError(_))));
+    }
+
+    #[test]
+    fn ensure_encrypted_fields_need_pointers_and_a_key_store() {
+        let result = EventStore::builder(MemoryStorageEngine::new())
+            .encrypted_fields("user", EncryptedFields::new().event_field("email"))
+            .build();
+        let Err(EventStoreError::ConfigurationError(problems)) = result else {
+            panic!("the configuration was accepted");
+        };
+        assert_eq!(problems, [
+            "encrypted fields of 'user' need a key store".to_string(),
+            "encrypted field 'email' of 'user' is not a JSON pointer".to_string(),
+        ]);
+    }
+}
@@ -0,0 +1,272 @@
+use std::fmt::Debug;
+use std::ops::Deref;
+use serde::{Deserialize, Serialize};
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use crate::{event::Event, EventStoreError};
+
+/// What happens to an event addressed to an entity id that is not in the collection.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UnknownEntityPolicy {
+    /// Build the entity with `Entity::create`; events it cannot create from are errors.
+    Create,
+    /// Fail with `EventStoreError::UnknownEntity`.
+    Error,
+}
+
+/// What applying an event did to an entity.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EntityChange {
+    Updated,
+    /// The entity is dropped from its collection.
+    Removed,
+}
+
+/// A child entity of an aggregate (e.g. the line items of an order) that applies the events
+/// addressed to it, so the aggregate does not have to.
+///
+/// Events are addressed by the `ID_FIELD` of their payload, found at the top level or inside
+/// the variant of an externally tagged enum, e.g. `{"LineItemAdded":{"line_item_id":3}}`.
+/// Override `route_event` to address events some other way.
+pub trait Entity: Serialize + DeserializeOwned {
+    type Id: PartialEq + Debug + DeserializeOwned;
+
+    /// Payload field holding the id of the entity an event is addressed to.
+    const ID_FIELD: &'static str = "id";
+
+    /// Event types routed to entities; empty routes every event carrying `ID_FIELD`.
+    const EVENT_TYPES: &'static [&'static str] = &[];
+
+    /// What to do with events for ids not in the collection.
+    const UNKNOWN_ID: UnknownEntityPolicy = UnknownEntityPolicy::Create;
+
+    fn id(&self) -> &Self::Id;
+
+    /// Builds an entity from the first event addressed to its id. The event is not applied
+    /// again afterwards. Returns None for events that cannot create an entity.
+    fn create(id: &Self::Id, event: &Event) -> Result<Option<Self>, EventStoreError> {
+        let _ = (id, event);
+        Ok(None)
+    }
+
+    fn apply_event(&mut self, event: &Event) -> Result<EntityChange, EventStoreError>;
+
+    /// The id of the entity an event is addressed to, or None if it is not addressed to one.
+    fn route_event(event: &Event) -> Result<Option<Self::Id>, EventStoreError> {
+        let payload: Value = serde_json::from_str(event.data.json()?).map_err(EventStoreError::EventDeserializationError)?;
+        let id = match payload.get(Self::ID_FIELD) {
+            Some(id) => Some(id),
+            None => match &payload {
+                Value::Object(fields) if fields.len() == 1 => fields.values().next().and_then(|variant| variant.get(Self::ID_FIELD)),
+                _ => None,
+            },
+        };
+        id.map(|id| serde_json::from_value(id.clone()).map_err(EventStoreError::EventDeserializationError))
+            .transpose()
+    }
+}
+
+/// A collection of child entities inside an aggregate's state. It serializes as a plain
+/// list, so snapshots of the aggregate carry its children as they are.
+///
+/// Forward events to `route` from `Composable::apply_event` before handling the
+/// aggregate's own events.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Entities<E> {
+    items: Vec<E>,
+}
+
+impl<E> Default for Entities<E> {
+    fn default() -> Self {
+        Entities { items: Vec::new() }
+    }
+}
+
+impl<E: Entity> Entities<E> {
+    pub fn get(&self, id: &E::Id) -> Option<&E> {
+        self.items.iter().find(|entity| entity.id() == id)
+    }
+
+    /// Applies an event to the entity it is addressed to, creating or removing entities as
+    /// needed. Returns false, leaving the collection untouched, for events not addressed
+    /// to an entity.
+    pub fn route(&mut self, event: &Event) -> Result<bool, EventStoreError> {
+        if !E::EVENT_TYPES.is_empty() && !E::EVENT_TYPES.contains(&event.event_type.as_str()) {
+            return Ok(false);
+        }
+        let Some(id) = E::route_event(event)? else {
+            return Ok(false);
+        };
+
+        let Some(index) = self.items.iter().position(|entity| entity.id() == &id) else {
+            let created = match E::UNKNOWN_ID {
+                UnknownEntityPolicy::Create => E::create(&id, event)?,
+                UnknownEntityPolicy::Error => None,
+            };
+            let entity = created.ok_or_else(|| EventStoreError::UnknownEntity {
+                event_type: event.event_type.clone(),
+                entity_id: format!("{:?}", id),
+            })?;
+            self.items.push(entity);
+            return Ok(true);
+        };
+
+        if self.items[index].apply_event(event)? == EntityChange::Removed {
+            self.items.remove(index);
+        }
+        Ok(true)
+    }
+}
+
+impl<E> Deref for Entities<E> {
+    type Target = [E];
+
+    fn deref(&self) -> &[E] {
+        &self.items
+    }
+}
+
+#[cfg(all(test, feature = "memory"))]
+mod tests {
+    use serde::{Deserialize, Serialize};
+    use super::*;
+    use crate::aggregate::{Aggregate, AppliesEvents, CanRequest, Composable, ComposedAggregate};
+    use crate::{memory::MemoryStorageEngine, EventStore};
+
+    #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+    struct LineItem {
+        line_item_id: u32,
+        sku: String,
+        quantity: u32,
+    }
+
+    #[derive(Clone, Default, Serialize, Deserialize)]
+    struct Order {
+        placed: bool,
+        line_items: Entities<LineItem>,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    enum OrderEvents {
+        LineItemAdded { line_item_id: u32, sku: String, quantity: u32 },
+        QuantityChanged { line_item_id: u32, quantity: u32 },
+        LineItemRemoved { line_item_id: u32 },
+        OrderPlaced,
+    }
+
+    impl Entity for LineItem {
+        type Id = u32;
+        const ID_FIELD: &'static str = "line_item_id";
+
+        fn id(&self) -> &u32 {
+            &self.line_item_id
+        }
+
+        fn create(_id: &u32, event: &Event) -> Result<Option<LineItem>, EventStoreError> {
+            match event.deserialize()? {
+                OrderEvents::LineItemAdded { line_item_id, sku, quantity } => Ok(Some(LineItem { line_item_id, sku, quantity })),
+                _ => Ok(None),
+            }
+        }
+
+        fn apply_event(&mut self, event: &Event) -> Result<EntityChange, EventStoreError> {
+            match event.deserialize()? {
+                OrderEvents::QuantityChanged { quantity, .. } => self.quantity = quantity,
+                OrderEvents::LineItemRemoved { .. } => return Ok(EntityChange::Removed),
+                _ => return Err(EventStoreError::ApplyEventError(format!("line item {} exists", self.line_item_id))),
+            }
+            Ok(EntityChange::Updated)
+        }
+    }
+
+    impl Composable for Order {
+        fn get_type(&self) -> &str {
+            "order"
+        }
+
+        fn apply_event(&mut self, event: &Event) -> Result<(), EventStoreError> {
+            if self.line_items.route(event)? {
+                return Ok(());
+            }
+            self.apply_typed(event)
+        }
+
+        fn snapshot_frequency(&self) -> i32 {
+            3
+        }
+    }
+
+    impl AppliesEvents for Order {
+        type Events = OrderEvents;
+
+        fn apply(&mut self, event: OrderEvents) -> Result<(), EventStoreError> {
+            match event {
+                OrderEvents::OrderPlaced => self.placed = true,
+                _ => return Err(EventStoreError::ApplyEventError("line item event was not routed".to_string())),
+            }
+            Ok(())
+        }
+    }
+
+    impl CanRequest<OrderEvents, OrderEvents> for Order {
+        fn request(&self, request: OrderEvents) -> Result<(String, OrderEvents), EventStoreError> {
+            let event_type = match &request {
+                OrderEvents::LineItemAdded { .. } => "line_item_added",
+                OrderEvents::QuantityChanged { .. } => "quantity_changed",
+                OrderEvents::LineItemRemoved { .. } => "line_item_removed",
+                OrderEvents::OrderPlaced => "order_placed",
+            };
+            Ok((event_type.to_string(), request))
+        }
+    }
+
+    fn added(line_item_id: u32, sku: &str) -> OrderEvents {
+        OrderEvents::LineItemAdded { line_item_id, sku: sku.to_string(), quantity: 1 }
+    }
+
+    #[tokio::test]
+    async fn ensure_events_are_routed_to_line_items() {
+        let event_store = EventStore::new(MemoryStorageEngine::new());
+        let ctx = event_store.get_context();
+        let mut order = ComposedAggregate::<Order>::new(&ctx, None).await.unwrap();
+        order.request(added(1, "apple")).unwrap();
+        order.request(added(2, "pear")).unwrap();
+        order.request(added(3, "plum")).unwrap();
+        order.request(OrderEvents::QuantityChanged { line_item_id: 2, quantity: 5 }).unwrap();
+        order.request(OrderEvents::LineItemRemoved { line_item_id: 1 }).unwrap();
+        order.request(OrderEvents::OrderPlaced).unwrap();
+
+        let skus: Vec<&str> = order.state().line_items.iter().map(|item| item.sku.as_str()).collect();
+        assert_eq!(skus, vec!["pear", "plum"]);
+        assert_eq!(order.state().line_items.get(&2).unwrap().quantity, 5);
+        assert!(order.state().placed);
+        ctx.commit().await.unwrap();
+
+        // Loads start from the snapshot taken at version 6, which carries the children.
+        let ctx = event_store.get_context();
+        let mut loaded = ComposedAggregate::<Order>::load(&ctx, order.id()).await.unwrap();
+        assert_eq!(loaded.state().line_items, order.state().line_items);
+        loaded.request(OrderEvents::LineItemRemoved { line_item_id: 3 }).unwrap();
+        loaded.request(OrderEvents::QuantityChanged { line_item_id: 2, quantity: 7 }).unwrap();
+        ctx.commit().await.unwrap();
+
+        let ctx = event_store.get_context();
+        let loaded = ComposedAggregate::<Order>::load(&ctx, order.id()).await.unwrap();
+        assert_eq!(loaded.state().line_items.len(), 1);
+        assert_eq!(loaded.state().line_items.get(&2).unwrap().quantity, 7);
+    }
+
+    #[tokio::test]
+    async fn ensure_unknown_line_items_are_rejected() {
+        let ctx = EventStore::new(MemoryStorageEngine::new()).get_context();
+        let mut order = ComposedAggregate::<Order>::new(&ctx, None).await.unwrap();
+        order.request(added(1, "apple")).unwrap();
+
+        let error = order.request(OrderEvents::QuantityChanged { line_item_id: 9, quantity: 2 }).unwrap_err();
+        assert!(matches!(error.root_cause(), EventStoreError::UnknownEntity { entity_id, .. } if entity_id == "9"));
+        let error = order.request(added(1, "apple")).unwrap_err();
+        assert!(matches!(error.root_cause(), EventStoreError::ApplyEventError(_)));
+        assert_eq!(order.state().line_items.len(), 1);
+    }
+}
@@ -1,13 +1,15 @@
+use std::fmt;
 use std::sync::PoisonError;
 
 use thiserror::Error;
+use crate::{event::Event, AggregateId};
 
 /// EventStoreError is the error type for the event store.
 #[derive(Error, Debug)]
 pub enum EventStoreError {
 
     #[error("Aggregate not found: {0:?}")]
-    AggregateNotFound((String, i64)),
+    AggregateNotFound((String, AggregateId)),
 
     #[error("Error serializaing event.")]
     EventSerializationError(serde_json::Error),
@@ -25,19 +27,19 @@ pub enum EventStoreError {
     SnapshotDeserializationError(serde_json::Error),
 
     #[error("Error saving events.")]
-    SaveEventsError(Box<dyn std::error::Error>),
+    SaveEventsError(Box<dyn std::error::Error + Send + Sync>),
 
     #[error("Error saving snapshot.")]
-    SaveSnapshotError(Box<dyn std::error::Error>),
+    SaveSnapshotError(Box<dyn std::error::Error + Send + Sync>),
 
     #[error("Error getting events.")]
-    GetEventsError(Box<dyn std::error::Error>),
+    GetEventsError(Box<dyn std::error::Error + Send + Sync>),
 
     #[error("Error getting snapshot.")]
-    GetSnapshotError(Box<dyn std::error::Error>),
+    GetSnapshotError(Box<dyn std::error::Error + Send + Sync>),
 
     #[error("Error getting next aggregate id.")]
-    GetNextAggregateIdError(Box<dyn std::error::Error>),
+    GetNextAggregateIdError(Box<dyn std::error::Error + Send + Sync>),
 
     #[error("Error applying snapshot.")]
     ApplySnapshotError(String),
@@ -49,7 +51,7 @@ pub enum EventStoreError {
     ApplyEventError(String),
 
     #[error("Error during context callback.")]
-    ContextError(Box<dyn std::error::Error>),
+    ContextError(Box<dyn std::error::Error + Send + Sync>),
 
     /*
     #[error("Error acquiring lock in context.")]
@@ -64,8 +66,17 @@ pub enum EventStoreError {
     #[error("Attempt to publish an event before context is set.")]
     NoContext,
 
+    #[error("Aggregate {0} was not loaded from storage and cannot publish events.")]
+    AggregateNotHydrated(AggregateId),
+
+    #[error("State failed validation: {0}")]
+    ValidationError(String),
+
+    #[error("Invalid cursor: {0}")]
+    InvalidCursor(String),
+
     #[error("Error in storage engine.")]
-    StorageEngineError(Box<dyn std::error::Error>),
+    StorageEngineError(Box<dyn std::error::Error + Send + Sync>),
    
     #[error("Error in storage engine.")]
     StorageEngineErrorOther(String),
@@ -76,8 +87,230 @@ pub enum EventStoreError {
     #[error("Aggregate instance not found.")]
     AggregateInstanceNotFound,
 
+    #[error("{aggregate_type} {aggregate_id} is deleted.")]
+    AggregateDeleted { aggregate_type: String, aggregate_id: AggregateId },
+
+    #[error("Database schema does not match expectations: {0:?}")]
+    SchemaMismatch(Vec<String>),
+
+    #[error("Database schema is at version {found}, newer than the supported version {expected}.")]
+    SchemaVersionMismatch { found: i64, expected: i64 },
+
+    #[error("Commit spans multiple shards: {0:?}")]
+    CrossShardCommit(Vec<usize>),
+
+    #[error("Invalid event store configuration: {0:?}")]
+    ConfigurationError(Vec<String>),
+
+    #[error("No event store registered as '{0}'.")]
+    UnknownStore(String),
+
+    #[error("Aggregate belongs to event store '{}' but the context is from '{}'.", .0.0, .0.1)]
+    WrongStore((String, String)),
+
+    #[error("Version {version} of {aggregate_type} {aggregate_id} was already published in this context.")]
+    DuplicateVersion { aggregate_type: String, aggregate_id: AggregateId, version: i64 },
+
+    #[error("Expected {aggregate_type} {aggregate_id} at version {expected}, but it is at version {actual}.")]
+    VersionConflict { aggregate_type: String, aggregate_id: AggregateId, expected: i64, actual: i64 },
+
+    #[error("Stored data of aggregate type '{stored}' was returned for '{requested}'.")]
+    AggregateTypeMismatch { requested: String, stored: String },
+
+    #[error("Aggregate {aggregate_id} belongs to a different context.")]
+    WrongContext { aggregate_id: AggregateId },
+
+    #[error("History before version {earliest} is no longer available.")]
+    HistoryUnavailable { earliest: i64 },
+
+    #[error("Event with dedup key '{0}' was already ingested.")]
+    DuplicateEvent(String),
+
+    #[error("Commit did not complete before its timeout.")]
+    CommitTimeout,
+
+    #[error("Context metadata is limited to {limit} entries.")]
+    MetadataLimitExceeded { limit: usize },
+
+    #[error("Required metadata keys {keys:?} expired before the commit.")]
+    MetadataExpired { keys: Vec<String> },
+
+    #[error("Required metadata keys {keys:?} are missing.")]
+    MissingMetadata { keys: Vec<String> },
+
+    #[error("Writes are paused while the store is quiesced.")]
+    Quiesced,
+
+    #[error("Writes in flight did not finish before the quiesce timeout.")]
+    QuiesceTimeout,
+
+    #[error("Event created_at is earlier than the previous event in its stream: {0:?}")]
+    ClockSkew((String, AggregateId, i64)),
+
+    #[error("Contexts committed together published events for the same aggregates: {0:?}")]
+    OverlappingContexts(Vec<(String, AggregateId)>),
+
+    #[error("Natural key '{0}' is already in use.")]
+    DuplicateNaturalKey(String),
+
+    #[error("Event type '{0}' is not registered for the aggregate.")]
+    UnknownEventType(String),
+
+    #[error("Event '{event_type}' is addressed to unknown entity {entity_id}.")]
+    UnknownEntity { event_type: String, entity_id: String },
+
+    #[error("Event at version {version} does not match its stored hash.")]
+    IntegrityViolation { version: i64 },
+
+    #[error("Error in blob store: {0}")]
+    BlobStoreError(Box<dyn std::error::Error + Send + Sync>),
+
+    #[error("{context}: {source}")]
+    WithContext { context: ErrorContext, source: Box<EventStoreError> },
+
+    #[error("Column '{column}' could not be decoded as {expected} in: {statement}")]
+    StorageDecodeError { column: String, expected: String, statement: String },
+
+    #[error("Error in archive: {0}")]
+    ArchiveError(Box<dyn std::error::Error + Send + Sync>),
+
+    #[error("Invalid {parameter} {value}: {reason}.")]
+    InvalidArgument { parameter: String, value: String, reason: String },
+
+    #[error("Error in field encryption: {0}")]
+    EncryptionError(String),
+
+    #[error("Events were committed, but a post-commit hook failed: {0}")]
+    PostCommitHookError(Box<EventStoreError>),
+
+    #[error("The context was rolled back and can no longer be committed.")]
+    ContextRolledBack,
+
+    #[error("The storage operation did not complete before its deadline.")]
+    StorageTimeout,
+
+    #[error("Type ids are assigned differently in the destination: {0:?}")]
+    TypeIdConflict(Vec<String>),
+
+    #[error("Schema sync is disabled in the '{0}' environment.")]
+    SchemaSyncDisabled(String),
+
+    #[error("Error in payload serializer: {0}")]
+    PayloadSerializerError(String),
+
+}
+
+
+impl EventStoreError {
+    /// Attaches the operation and aggregate that produced a storage or serialization failure.
+    /// Other errors already say what went wrong and are returned unchanged, as is an error
+    /// that carries context already.
+    pub fn with_context(self, context: ErrorContext) -> EventStoreError {
+        match self {
+            EventStoreError::EventSerializationError(_)
+            | EventStoreError::EventMetaDataSerializationError(_)
+            | EventStoreError::EventDeserializationError(_)
+            | EventStoreError::SnapshotSerializationError(_)
+            | EventStoreError::SnapshotDeserializationError(_)
+            | EventStoreError::PayloadSerializerError(_)
+            | EventStoreError::SaveEventsError(_)
+            | EventStoreError::SaveSnapshotError(_)
+            | EventStoreError::GetEventsError(_)
+            | EventStoreError::GetSnapshotError(_)
+            | EventStoreError::ApplySnapshotError(_)
+            | EventStoreError::ApplyEventError(_)
+            | EventStoreError::StorageEngineError(_)
+            | EventStoreError::StorageEngineErrorOther(_)
+            | EventStoreError::StorageEngineConnectionError(_)
+            | EventStoreError::BlobStoreError(_)
+            | EventStoreError::StorageDecodeError { .. } => EventStoreError::WithContext {
+                context,
+                source: Box::new(self),
+            },
+            other => other,
+        }
+    }
+
+    /// The context attached to this error, if any.
+    pub fn context(&self) -> Option<&ErrorContext> {
+        match self {
+            EventStoreError::WithContext { context, .. } => Some(context),
+            _ => None,
+        }
+    }
+
+    /// The error without any attached context, for matching on what went wrong.
+    pub fn root_cause(&self) -> &EventStoreError {
+        match self {
+            EventStoreError::WithContext { source, .. } => source.root_cause(),
+            other => other,
+        }
+    }
+}
+
+/// Where a failure happened: the operation and, as far as known, the aggregate and event.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ErrorContext {
+    pub operation: &'static str,
+    pub aggregate_type: Option<String>,
+    pub aggregate_id: Option<AggregateId>,
+    pub version: Option<i64>,
+    pub event_type: Option<String>,
 }
 
+impl ErrorContext {
+    pub fn new(operation: &'static str) -> ErrorContext {
+        ErrorContext {
+            operation,
+            aggregate_type: None,
+            aggregate_id: None,
+            version: None,
+            event_type: None,
+        }
+    }
+
+    pub fn aggregate(mut self, aggregate_type: &str, aggregate_id: AggregateId) -> ErrorContext {
+        self.aggregate_type = Some(aggregate_type.to_string());
+        self.aggregate_id = Some(aggregate_id);
+        self
+    }
+
+    pub fn version(mut self, version: i64) -> ErrorContext {
+        self.version = Some(version);
+        self
+    }
+
+    pub fn event_type(mut self, event_type: &str) -> ErrorContext {
+        self.event_type = Some(event_type.to_string());
+        self
+    }
+
+    /// Context naming an event: its aggregate, version and type.
+    pub fn event(operation: &'static str, event: &Event) -> ErrorContext {
+        ErrorContext::new(operation)
+            .aggregate(&event.aggregate_type, event.aggregate_id)
+            .version(event.version)
+            .event_type(&event.event_type)
+    }
+}
+
+impl fmt::Display for ErrorContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} failed", self.operation)?;
+        match (&self.aggregate_type, &self.aggregate_id) {
+            (Some(aggregate_type), Some(aggregate_id)) => write!(f, " for {aggregate_type} {aggregate_id}")?,
+            (Some(aggregate_type), None) => write!(f, " for {aggregate_type}")?,
+            _ => {}
+        }
+        if let Some(version) = self.version {
+            write!(f, " at version {version}")?;
+        }
+        if let Some(event_type) = &self.event_type {
+            write!(f, " ({event_type})")?;
+        }
+        Ok(())
+    }
+}
 
 impl<T> From<PoisonError<T>> for EventStoreError {
     fn from(_err: PoisonError<T>) -> Self {
@@ -85,4 +318,56 @@ impl<T> From<PoisonError<T>> for EventStoreError {
     }
 }
 
+#[cfg(feature = "context")]
+impl From<tokio::time::error::Elapsed> for EventStoreError {
+    fn from(_err: tokio::time::error::Elapsed) -> Self {
+        Self::CommitTimeout
+    }
+}
+
+#[cfg(feature = "context")]
+impl From<tokio::sync::TryLockError> for EventStoreError {
+    fn from(_err: tokio::sync::TryLockError) -> Self {
+        Self::ContextPoisonError
+    }
+}
 
+#[cfg(all(test, feature = "context"))]
+mod tests {
+    use std::time::Duration;
+    use super::EventStoreError;
+    use crate::event::numbered_id;
+
+    #[tokio::test]
+    async fn ensure_elapsed_maps_to_commit_timeout() {
+        let result: Result<(), EventStoreError> = async {
+            tokio::time::timeout(Duration::from_millis(1), std::future::pending::<()>()).await?;
+            Ok(())
+        }.await;
+        assert!(matches!(result, Err(EventStoreError::CommitTimeout)));
+    }
+
+    #[test]
+    fn ensure_try_lock_error_maps_to_context_poison_error() {
+        let mutex = tokio::sync::Mutex::new(());
+        let _guard = mutex.try_lock().unwrap();
+        let result: Result<(), EventStoreError> = mutex.try_lock().map(|_| ()).map_err(Into::into);
+        assert!(matches!(result, Err(EventStoreError::ContextPoisonError)));
+    }
+
+    #[test]
+    fn ensure_context_is_displayed_and_source_is_kept() {
+        use std::error::Error;
+        use super::ErrorContext;
+
+        let context = ErrorContext::new("write_updates").aggregate("account", numbered_id(7)).version(3).event_type("credited");
+        let error = EventStoreError::StorageEngineErrorOther("disk full".to_string()).with_context(context.clone());
+        assert_eq!(error.to_string(), format!("write_updates failed for account {} at version 3 (credited): Error in storage engine.", numbered_id(7)));
+        assert_eq!(error.context(), Some(&context));
+        assert_eq!(error.source().unwrap().to_string(), "Error in storage engine.");
+        assert!(matches!(error.root_cause(), EventStoreError::StorageEngineErrorOther(_)));
+
+        let error = EventStoreError::AggregateInstanceNotFound.with_context(context);
+        assert!(error.context().is_none());
+    }
+}
@@ -1,37 +1,93 @@
+use std::fmt;
+use chrono::{DateTime, Utc};
 use serde::Serialize;
 use serde::de::DeserializeOwned;
-use crate::EventStoreError;
+use crate::{arguments, payload::Payload, EventStoreError};
+
+/// Maximum number of bytes of the data, and of the metadata, rendered by `Event::pretty`
+/// before each is truncated.
+pub const PRETTY_MAX_BYTES: usize = 4096;
+
+/// Placeholder rendered in place of redacted metadata values.
+pub const REDACTED: &str = "***";
+
+/// Metadata key holding the type name of the command that produced the event.
+pub const COMMAND_TYPE_KEY: &str = "command_type";
+
+/// Metadata key holding the JSON of the command that produced the event.
+pub const COMMAND_PAYLOAD_KEY: &str = "command_payload";
+
+/// Identifies an aggregate instance: `i64` by default, `uuid::Uuid` with the `uuid-ids` feature.
+#[cfg(not(feature = "uuid-ids"))]
+pub type AggregateId = i64;
+#[cfg(feature = "uuid-ids")]
+pub type AggregateId = uuid::Uuid;
+
+/// The `n`th id of engines numbering their instances: `n` itself, or with `uuid-ids` the
+/// uuid holding `n` in its low bits, so numbered ids keep their order either way.
+#[cfg(not(feature = "uuid-ids"))]
+pub fn numbered_id(n: i64) -> AggregateId {
+    n
+}
+
+#[cfg(feature = "uuid-ids")]
+pub fn numbered_id(n: i64) -> AggregateId {
+    uuid::Uuid::from_u64_pair(0, n as u64)
+}
 
 /// Event is a representation of a change in the aggregate state.
 #[derive(Clone, Debug)]
 pub struct Event {
-    pub aggregate_id: i64,
+    pub aggregate_id: AggregateId,
     pub aggregate_type: String,
     pub version: i64,
     pub event_type: String,
-    pub data: String,
-    pub metadata: Option<String>
+    /// JSON, unless read from storage without being decoded, see `Payload`.
+    pub data: Payload,
+    pub metadata: Option<String>,
+    /// When the event was published, if it was stamped.
+    pub created_at: Option<DateTime<Utc>>,
+    /// Position in the store's global commit order, set once the event is stored. Events
+    /// that are not written yet have none.
+    pub position: Option<i64>,
+    /// Hash chaining the event to its predecessor, when the store hashes events.
+    pub hash: Option<String>,
 }
 
 impl Event {
     pub fn new<T>(
-        aggregate_id: i64, 
+        aggregate_id: AggregateId, 
         aggregate_type: &str, 
         version: i64, 
         event_type: &str, 
         data: &T) -> Result<Event, EventStoreError>
         where T: Serialize + DeserializeOwned
     {
+        arguments::aggregate_id(aggregate_id)?;
+        arguments::event_version(version)?;
         let state = serde_json::to_string(&data).map_err(EventStoreError::EventSerializationError)?;
-        
-        Ok(Event {
+        Ok(Event::from_payload(aggregate_id, aggregate_type, version, event_type, state))
+    }
+
+    /// Creates an event around an already serialized payload, which is stored as is.
+    pub(crate) fn from_payload(
+        aggregate_id: AggregateId,
+        aggregate_type: &str,
+        version: i64,
+        event_type: &str,
+        data: impl Into<Payload>) -> Event
+    {
+        Event {
             aggregate_id,
             aggregate_type: aggregate_type.to_string(),
             version,
             event_type: event_type.to_string(),
-            data: state,
-            metadata: None
-        })
+            data: data.into(),
+            metadata: None,
+            created_at: None,
+            position: None,
+            hash: None,
+        }
     }
 
     pub fn add_metadata<T>(&mut self, metadata: &T) -> Result<(), EventStoreError>
@@ -53,16 +109,148 @@ impl Event {
     }
 
 
+    /// Deserializes the command recorded with the event, or None if none was recorded.
+    /// Redacted fields hold `REDACTED`, so they only deserialize into string fields.
+    pub fn command<C>(&self) -> Result<Option<C>, EventStoreError>
+        where C: DeserializeOwned
+    {
+        let Some(metadata) = self.metadata.as_deref() else {
+            return Ok(None);
+        };
+        let metadata: serde_json::Value = serde_json::from_str(metadata).map_err(EventStoreError::EventDeserializationError)?;
+        match metadata.get(COMMAND_PAYLOAD_KEY).and_then(serde_json::Value::as_str) {
+            Some(payload) => serde_json::from_str(payload).map(Some).map_err(EventStoreError::EventDeserializationError),
+            None => Ok(None),
+        }
+    }
+
     pub fn deserialize<T>(&self) -> Result<T, EventStoreError>
         where T: Serialize + DeserializeOwned
     {
-        serde_json::from_str(&self.data).map_err(EventStoreError::EventDeserializationError)
+        serde_json::from_str(self.data.json()?).map_err(EventStoreError::EventDeserializationError)
+    }
+
+    /// Records `clock_adjusted: "true"` in the metadata after created_at was moved forward.
+    pub fn mark_clock_adjusted(&mut self) -> Result<(), EventStoreError> {
+        let mut metadata = match self.metadata.as_deref() {
+            Some(metadata) => match serde_json::from_str::<serde_json::Value>(metadata).map_err(EventStoreError::EventDeserializationError)? {
+                serde_json::Value::Object(map) => map,
+                _ => serde_json::Map::new(),
+            },
+            None => serde_json::Map::new(),
+        };
+        metadata.insert("clock_adjusted".to_string(), serde_json::Value::String("true".to_string()));
+        self.add_metadata(&metadata)
+    }
+
+    /// Returns the keys present in the event metadata, if any.
+    pub fn metadata_keys(&self) -> Vec<String> {
+        let metadata = self.metadata.as_deref()
+            .and_then(|metadata| serde_json::from_str::<serde_json::Value>(metadata).ok());
+        match metadata {
+            Some(serde_json::Value::Object(map)) => map.keys().cloned().collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Renders the data and metadata as indented JSON for human consumption.
+    pub fn pretty(&self) -> String {
+        self.pretty_redacted(&[])
+    }
+
+    /// Renders the event like `pretty`, masking the values of the given metadata keys.
+    ///
+    /// Data and metadata are each truncated after `PRETTY_MAX_BYTES` of their indented
+    /// rendering, so log output stays bounded.
+    pub fn pretty_redacted(&self, redact_fields: &[String]) -> String {
+        let mut output = format!("{}\ndata: ", self);
+        match &self.data {
+            Payload::Json(data) => output.push_str(&truncated(pretty_json(data))),
+            binary => output.push_str(&binary.to_string()),
+        }
+
+        if let Some(metadata) = &self.metadata {
+            let rendered = match serde_json::from_str::<serde_json::Value>(metadata) {
+                Ok(serde_json::Value::Object(mut map)) => {
+                    for key in redact_fields {
+                        if let Some(value) = map.get_mut(key) {
+                            *value = serde_json::Value::String(REDACTED.to_string());
+                        }
+                    }
+                    serde_json::to_string_pretty(&map).unwrap_or_else(|_| metadata.clone())
+                },
+                _ => pretty_json(metadata),
+            };
+            output.push_str("\nmetadata: ");
+            output.push_str(&truncated(rendered));
+        }
+        output
+    }
+}
+
+/// Cuts `rendered` after `PRETTY_MAX_BYTES`, noting how much was left off.
+fn truncated(mut rendered: String) -> String {
+    if rendered.len() <= PRETTY_MAX_BYTES {
+        return rendered;
+    }
+    let mut end = PRETTY_MAX_BYTES;
+    while !rendered.is_char_boundary(end) {
+        end -= 1;
+    }
+    let cut = rendered.len() - end;
+    rendered.truncate(end);
+    rendered.push_str(&format!("... ({} bytes truncated)", cut));
+    rendered
+}
+
+fn pretty_json(json: &str) -> String {
+    serde_json::from_str::<serde_json::Value>(json)
+        .and_then(|value| serde_json::to_string_pretty(&value))
+        .unwrap_or_else(|_| json.to_string())
+}
+
+/// Single-line summary, e.g. `account/42 v7 deposit 128B meta[ip_address,user]`.
+///
+/// Only metadata keys are shown, never their values, so the summary is safe for logs.
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{} v{} {} {}B",
+            self.aggregate_type,
+            self.aggregate_id,
+            self.version,
+            self.event_type,
+            self.data.len())?;
+
+        let keys = self.metadata_keys();
+        if !keys.is_empty() {
+            write!(f, " meta[{}]", keys.join(","))?;
+        }
+        Ok(())
+    }
+}
+
+/// Replaces the value of every object key named in `fields`, at any depth, with `REDACTED`.
+#[cfg(feature = "context")]
+pub(crate) fn redact_json(value: &mut serde_json::Value, fields: &[&str]) {
+    match value {
+        serde_json::Value::Object(map) => {
+            for (key, value) in map.iter_mut() {
+                if fields.contains(&key.as_str()) {
+                    *value = serde_json::Value::String(REDACTED.to_string());
+                } else {
+                    redact_json(value, fields);
+                }
+            }
+        },
+        serde_json::Value::Array(values) => values.iter_mut().for_each(|value| redact_json(value, fields)),
+        _ => {},
     }
 }
 
 #[cfg(test)]
 mod tests {
     use serde::{Serialize, Deserialize};
+    use super::numbered_id;
 
     #[derive(Serialize, Deserialize, Debug)]
     struct SampleState {
@@ -78,9 +266,9 @@ mod tests {
             name: "test".to_string(),
         };
 
-        let event = super::Event::new(1, "test", 1, "test", &state).unwrap();
+        let event = super::Event::new(numbered_id(1), "test", 1, "test", &state).unwrap();
 
-        assert_eq!(event.aggregate_id, 1);
+        assert_eq!(event.aggregate_id, numbered_id(1));
         assert_eq!(event.aggregate_type, "test");
         assert_eq!(event.version, 1);
         assert_eq!(event.event_type, "test");
@@ -96,12 +284,80 @@ mod tests {
             name: "test".to_string(),
         };
 
-        let event = super::Event::new(1, "test", 1, "test", &state).unwrap();
+        let event = super::Event::new(numbered_id(1), "test", 1, "test", &state).unwrap();
 
         let deserialized: SampleState = event.deserialize().unwrap();
 
         assert_eq!(deserialized.value, 1);
         assert_eq!(deserialized.name, "test");
     }
+
+    #[test]
+    fn test_event_display_summary() {
+        let state = SampleState {
+            value: 1,
+            name: "test".to_string(),
+        };
+
+        let mut event = super::Event::new(numbered_id(42), "account", 7, "deposit", &state).unwrap();
+        let mut metadata = std::collections::HashMap::new();
+        metadata.insert("user".to_string(), "chavez".to_string());
+        metadata.insert("ip_address".to_string(), "10.100.1.100".to_string());
+        event.add_metadata(&metadata).unwrap();
+
+        assert_eq!(event.to_string(), format!("account/{} v7 deposit 25B meta[ip_address,user]", numbered_id(42)));
+    }
+
+    #[test]
+    fn test_event_pretty_masks_redacted_fields() {
+        let state = SampleState {
+            value: 1,
+            name: "test".to_string(),
+        };
+
+        let mut event = super::Event::new(numbered_id(42), "account", 7, "deposit", &state).unwrap();
+        let mut metadata = std::collections::HashMap::new();
+        metadata.insert("user".to_string(), "chavez".to_string());
+        metadata.insert("ip_address".to_string(), "10.100.1.100".to_string());
+        event.add_metadata(&metadata).unwrap();
+
+        let pretty = event.pretty_redacted(&["ip_address".to_string()]);
+        assert!(pretty.contains("\"user\": \"chavez\""));
+        assert!(!pretty.contains("10.100.1.100"));
+        assert!(pretty.contains(super::REDACTED));
+    }
+
+    #[test]
+    fn test_event_pretty_truncates_large_payloads() {
+        let state = SampleState {
+            value: 1,
+            name: "x".repeat(super::PRETTY_MAX_BYTES * 2),
+        };
+
+        let event = super::Event::new(numbered_id(1), "test", 1, "test", &state).unwrap();
+        let pretty = event.pretty();
+
+        assert!(pretty.len() < super::PRETTY_MAX_BYTES + 200);
+        assert!(pretty.contains("bytes truncated"));
+        assert!(pretty.contains("data: {\n  \"name\": \"xxx"));
+    }
+
+    #[test]
+    fn test_event_pretty_truncates_large_metadata() {
+        let state = SampleState {
+            value: 1,
+            name: "test".to_string(),
+        };
+
+        let mut event = super::Event::new(numbered_id(1), "test", 1, "test", &state).unwrap();
+        let mut metadata = std::collections::HashMap::new();
+        metadata.insert("note".to_string(), "y".repeat(super::PRETTY_MAX_BYTES * 2));
+        event.add_metadata(&metadata).unwrap();
+        let pretty = event.pretty();
+
+        assert!(pretty.len() < super::PRETTY_MAX_BYTES + 300);
+        assert!(pretty.contains("\"note\": \"yyy"));
+        assert!(pretty.ends_with("bytes truncated)"));
+    }
 }
 
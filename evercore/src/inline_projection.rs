@@ -0,0 +1,96 @@
+use std::{any::Any, collections::HashMap, marker::PhantomData, ops::Deref, sync::{RwLock, RwLockReadGuard}};
+use crate::{event::Event, EventStoreError};
+
+type ApplyFn = Box<dyn Fn(&mut (dyn Any + Send + Sync), &Event) + Send + Sync>;
+
+struct InlineProjection {
+    state: Box<dyn Any + Send + Sync>,
+    apply: ApplyFn,
+}
+
+/// Closure based read models held in process memory and updated after each commit.
+///
+/// Inline projections are strictly single-process: they only see commits made through the
+/// `EventStore` they are registered on, keep no checkpoint, and are rebuilt from the global
+/// feed when registered. Writes from other processes are not reflected until the projection
+/// is registered again (e.g. on the next start). Use a full projection runner when several
+/// processes write to the same store.
+#[derive(Default)]
+pub struct InlineProjections {
+    projections: RwLock<HashMap<String, InlineProjection>>,
+    /// Held shared by each write from storing its events until they are applied, and
+    /// exclusively by a registration's last catch-up read, so no commit falls in between.
+    commits: tokio::sync::RwLock<()>,
+}
+
+impl InlineProjections {
+    /// Held by a write while it stores and applies its events.
+    pub(crate) async fn lock_commit(&self) -> tokio::sync::RwLockReadGuard<'_, ()> {
+        self.commits.read().await
+    }
+
+    /// Holds back commits while a registration catches up and inserts its projection.
+    pub(crate) async fn lock_registration(&self) -> tokio::sync::RwLockWriteGuard<'_, ()> {
+        self.commits.write().await
+    }
+
+    pub(crate) fn insert<S, F>(&self, name: &str, state: S, f: F) -> Result<(), EventStoreError>
+    where
+        S: Any + Send + Sync,
+        F: Fn(&mut S, &Event) + Send + Sync + 'static,
+    {
+        let apply: ApplyFn = Box::new(move |state, event| {
+            if let Some(state) = state.downcast_mut::<S>() {
+                f(state, event);
+            }
+        });
+        let projection = InlineProjection {
+            state: Box::new(state),
+            apply,
+        };
+        self.projections.write()?.insert(name.to_string(), projection);
+        Ok(())
+    }
+
+    /// Applies committed events to every registered projection.
+    pub(crate) fn apply(&self, events: &[Event]) -> Result<(), EventStoreError> {
+        if events.is_empty() {
+            return Ok(());
+        }
+        let mut projections = self.projections.write()?;
+        for projection in projections.values_mut() {
+            for event in events {
+                (projection.apply)(projection.state.as_mut(), event);
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn state<S: Any + Send + Sync>(&self, name: &str) -> Option<ProjectionState<'_, S>> {
+        let guard = self.projections.read().ok()?;
+        guard.get(name)?.state.downcast_ref::<S>()?;
+        Some(ProjectionState {
+            guard,
+            name: name.to_string(),
+            _state: PhantomData,
+        })
+    }
+}
+
+/// Read access to the state of an inline projection. Commits wait while this is held.
+pub struct ProjectionState<'a, S> {
+    guard: RwLockReadGuard<'a, HashMap<String, InlineProjection>>,
+    name: String,
+    _state: PhantomData<S>,
+}
+
+impl<'a, S: Any + Send + Sync> Deref for ProjectionState<'a, S> {
+    type Target = S;
+
+    fn deref(&self) -> &S {
+        self.guard[&self.name]
+            .state
+            .downcast_ref::<S>()
+            .expect("projection state type is checked when the guard is created")
+    }
+}
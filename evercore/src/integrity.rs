@@ -0,0 +1,156 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use crate::{event::Event, EventStoreError};
+#[cfg(feature = "context")]
+use std::collections::HashMap;
+#[cfg(feature = "context")]
+use crate::{clock::StreamKey, EventStore};
+
+/// Rewrites a JSON document with object keys sorted and no insignificant whitespace, so equal
+/// documents serialize to the same bytes.
+pub fn canonical_json(json: &str) -> Result<String, EventStoreError> {
+    let value: Value = serde_json::from_str(json).map_err(EventStoreError::EventDeserializationError)?;
+    let mut canonical = String::with_capacity(json.len());
+    write_canonical(&value, &mut canonical);
+    Ok(canonical)
+}
+
+fn write_canonical(value: &Value, out: &mut String) {
+    match value {
+        Value::Array(items) => {
+            out.push('[');
+            for (index, item) in items.iter().enumerate() {
+                if index > 0 {
+                    out.push(',');
+                }
+                write_canonical(item, out);
+            }
+            out.push(']');
+        }
+        Value::Object(fields) => {
+            let mut keys: Vec<&String> = fields.keys().collect();
+            keys.sort();
+            out.push('{');
+            for (index, key) in keys.into_iter().enumerate() {
+                if index > 0 {
+                    out.push(',');
+                }
+                out.push_str(&Value::String(key.clone()).to_string());
+                out.push(':');
+                write_canonical(&fields[key], out);
+            }
+            out.push('}');
+        }
+        scalar => out.push_str(&scalar.to_string()),
+    }
+}
+
+/// SHA-256 over the aggregate id, version, event type, data as stored and the hash of the
+/// previous event in the stream, as lowercase hex. Each field is length prefixed so fields cannot run together.
+pub fn event_hash(event: &Event, previous: Option<&str>) -> String {
+    let mut hasher = Sha256::new();
+    let aggregate_id = event.aggregate_id.to_string();
+    let version = event.version.to_string();
+    let fields = [
+        aggregate_id.as_bytes(),
+        version.as_bytes(),
+        event.event_type.as_bytes(),
+        event.data.as_bytes(),
+        previous.unwrap_or_default().as_bytes(),
+    ];
+    for field in fields {
+        hasher.update((field.len() as u64).to_be_bytes());
+        hasher.update(field);
+    }
+    hasher.finalize().iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// 32 lowercase hex digits unique to this call, hashed from the time, process and a counter
+/// so any prefix of them is as unlikely to repeat.
+pub(crate) fn unique_token() -> String {
+    static CALLS: AtomicU64 = AtomicU64::new(0);
+    let mut hasher = Sha256::new();
+    hasher.update(SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos().to_be_bytes());
+    hasher.update(std::process::id().to_be_bytes());
+    hasher.update(CALLS.fetch_add(1, Ordering::Relaxed).to_be_bytes());
+    hasher.finalize()[..16].iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Checks consecutive events of one stream against their hashes. `previous` is the hash of
+/// the event before the first one. Events stored without a hash are not checked.
+pub fn verify_chain(previous: Option<&str>, events: &[Event]) -> Result<(), EventStoreError> {
+    let mut previous = previous.map(str::to_string);
+    for event in events {
+        if let Some(hash) = &event.hash {
+            if *hash != event_hash(event, previous.as_deref()) {
+                return Err(EventStoreError::IntegrityViolation { version: event.version });
+            }
+        }
+        previous = event.hash.clone();
+    }
+    Ok(())
+}
+
+/// Outcome of `EventStore::verify_feed_integrity`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct FeedIntegrityReport {
+    /// Events compared with their stored hash.
+    pub events_checked: u64,
+    /// Events stored without a hash, which cannot be checked.
+    pub unhashed: u64,
+    /// Feed position of the first event not matching its hash. Checking stops there.
+    pub first_divergent_position: Option<i64>,
+}
+
+/// Checks events read from the global feed against their hashes, following the chain of
+/// each stream from event to event. The first event seen of a stream is chained to the
+/// hash stored with its predecessor.
+#[cfg(feature = "context")]
+#[derive(Default)]
+pub(crate) struct FeedVerifier {
+    heads: HashMap<StreamKey, Option<String>>,
+}
+
+#[cfg(feature = "context")]
+impl FeedVerifier {
+    /// Whether `event`, as stored, matches its hash. Events without a hash pass. Expects
+    /// the events of a stream in order, as the feed returns them.
+    pub async fn verify(&mut self, event_store: &EventStore, event: &Event) -> Result<bool, EventStoreError> {
+        let key = (event.aggregate_type.clone(), event.aggregate_id);
+        let previous = match self.heads.remove(&key) {
+            Some(previous) => previous,
+            None => event_store.previous_hash(event).await?,
+        };
+        let matches = event.hash.as_ref().is_none_or(|hash| *hash == event_hash(event, previous.as_deref()));
+        // The stored hash carries the chain on, so only the changed event diverges.
+        self.heads.insert(key, event.hash.clone());
+        Ok(matches)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event::numbered_id;
+
+    #[test]
+    fn ensure_canonical_json_sorts_keys_and_drops_whitespace() {
+        let canonical = canonical_json(r#"{ "b": [1, {"z": null, "a": "x y"}], "a": 1.5 }"#).unwrap();
+        assert_eq!(canonical, r#"{"a":1.5,"b":[1,{"a":"x y","z":null}]}"#);
+    }
+
+    #[test]
+    fn ensure_chain_detects_changed_events() {
+        let mut first = Event::new(numbered_id(1), "account", 1, "created", &1).unwrap();
+        first.hash = Some(event_hash(&first, None));
+        let mut second = Event::new(numbered_id(1), "account", 2, "credited", &5).unwrap();
+        second.hash = Some(event_hash(&second, first.hash.as_deref()));
+        let mut events = vec![first, second];
+        assert!(verify_chain(None, &events).is_ok());
+
+        events[1].data = "6".into();
+        assert!(matches!(verify_chain(None, &events), Err(EventStoreError::IntegrityViolation { version: 2 })));
+    }
+}
@@ -0,0 +1,260 @@
+use std::{collections::HashMap, future::Future, pin::Pin, sync::{Arc, Mutex}, time::Duration};
+use tokio::{sync::watch, task::JoinHandle, time::Instant};
+use crate::{metrics::Histogram, operational::OperationalEvent, retention::RetentionPolicy, EventStoreError, SharedEventStore};
+
+/// Future returned by a maintenance job.
+pub type JobFuture = Pin<Box<dyn Future<Output = Result<(), EventStoreError>> + Send>>;
+
+type Job = Box<dyn Fn(SharedEventStore) -> JobFuture + Send + Sync>;
+
+/// How often a maintenance job runs.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Schedule {
+    pub interval: Duration,
+    /// Up to this much is added to each interval at random, so stores started together do
+    /// not run their jobs in lockstep.
+    pub jitter: Duration,
+}
+
+impl Schedule {
+    pub fn every(interval: Duration) -> Schedule {
+        Schedule {
+            interval,
+            jitter: Duration::ZERO,
+        }
+    }
+
+    pub fn jitter(mut self, jitter: Duration) -> Schedule {
+        self.jitter = jitter;
+        self
+    }
+
+    fn next_delay(&self) -> Duration {
+        if self.jitter.is_zero() {
+            return self.interval;
+        }
+        let fraction = (uuid::Uuid::new_v4().as_u128() % 1_000_000) as f64 / 1_000_000.0;
+        self.interval + self.jitter.mul_f64(fraction)
+    }
+}
+
+impl From<Duration> for Schedule {
+    fn from(interval: Duration) -> Schedule {
+        Schedule::every(interval)
+    }
+}
+
+/// Runs and failures of one maintenance job.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct JobMetrics {
+    pub runs: u64,
+    pub failures: u64,
+    pub elapsed_micros: Histogram,
+    /// The error of the latest failed run.
+    pub last_error: Option<String>,
+}
+
+struct ScheduledJob {
+    name: String,
+    schedule: Schedule,
+    run: Job,
+}
+
+/// Periodic maintenance for a store, created with `EventStore::maintenance`.
+///
+/// Jobs run one at a time on a single background task started by `start`, each after its
+/// interval has passed since it last finished. Every run is timed and reported with
+/// `OperationalEvent::MaintenanceJobCompleted`.
+pub struct MaintenanceScheduler {
+    event_store: SharedEventStore,
+    jobs: Vec<ScheduledJob>,
+}
+
+impl MaintenanceScheduler {
+    pub(crate) fn new(event_store: SharedEventStore) -> MaintenanceScheduler {
+        MaintenanceScheduler {
+            event_store,
+            jobs: Vec::new(),
+        }
+    }
+
+    /// Registers a job. `name` identifies it in metrics and operational events.
+    pub fn job<F, Fut>(mut self, name: &str, schedule: impl Into<Schedule>, job: F) -> MaintenanceScheduler
+    where
+        F: Fn(SharedEventStore) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), EventStoreError>> + Send + 'static,
+    {
+        self.jobs.push(ScheduledJob {
+            name: name.to_string(),
+            schedule: schedule.into(),
+            run: Box::new(move |event_store| Box::pin(job(event_store))),
+        });
+        self
+    }
+
+    /// Applies `policy` with `EventStore::apply_retention`, as the job "retention".
+    pub fn retention(self, schedule: impl Into<Schedule>, policy: RetentionPolicy) -> MaintenanceScheduler {
+        self.job("retention", schedule, move |event_store| {
+            let policy = policy.clone();
+            async move { event_store.apply_retention(&policy).await.map(|_| ()) }
+        })
+    }
+
+    /// Archives newly committed events with `archiver`, as the job "archive".
+    #[cfg(feature = "archive")]
+    pub fn archive(self, schedule: impl Into<Schedule>, archiver: crate::archive::Archiver) -> MaintenanceScheduler {
+        self.job("archive", schedule, move |_| {
+            let archiver = archiver.clone();
+            async move { archiver.run_once().await.map(|_| ()) }
+        })
+    }
+
+    /// Starts running the jobs in the background. They keep running until the returned
+    /// handle is shut down or dropped.
+    pub fn start(self) -> MaintenanceHandle {
+        let (stop, stopped) = watch::channel(false);
+        let metrics = Arc::new(Mutex::new(HashMap::new()));
+        let task = tokio::spawn(run_jobs(self.event_store, self.jobs, metrics.clone(), stopped));
+        MaintenanceHandle { stop, task, metrics }
+    }
+}
+
+async fn run_jobs(
+    event_store: SharedEventStore,
+    jobs: Vec<ScheduledJob>,
+    metrics: Arc<Mutex<HashMap<String, JobMetrics>>>,
+    mut stopped: watch::Receiver<bool>,
+) {
+    let started = Instant::now();
+    let mut due: Vec<Instant> = jobs.iter().map(|job| started + job.schedule.next_delay()).collect();
+    loop {
+        let Some((index, at)) = due.iter().copied().enumerate().min_by_key(|(_, at)| *at) else {
+            return;
+        };
+        tokio::select! {
+            _ = tokio::time::sleep_until(at) => {},
+            // A closed channel means the handle was dropped, which stops the loop as well.
+            _ = stopped.changed() => return,
+        }
+
+        let job = &jobs[index];
+        let run_started = Instant::now();
+        // Debug formatted, as the display of most errors leaves out their details.
+        let error = (job.run)(event_store.clone()).await.err().map(|e| format!("{:?}", e));
+        let elapsed = run_started.elapsed();
+        if let Some(error) = &error {
+            tracing::warn!(job = job.name.as_str(), error = error.as_str(), "maintenance job failed");
+        }
+        if let Ok(mut metrics) = metrics.lock() {
+            let job_metrics: &mut JobMetrics = metrics.entry(job.name.clone()).or_default();
+            job_metrics.runs += 1;
+            job_metrics.elapsed_micros.record(u64::try_from(elapsed.as_micros()).unwrap_or(u64::MAX));
+            if error.is_some() {
+                job_metrics.failures += 1;
+                job_metrics.last_error = error.clone();
+            }
+        }
+        event_store.emit(OperationalEvent::MaintenanceJobCompleted {
+            job: job.name.clone(),
+            elapsed,
+            error,
+        });
+        due[index] = Instant::now() + job.schedule.next_delay();
+    }
+}
+
+/// Controls a started `MaintenanceScheduler`. Dropping the handle stops the scheduler
+/// once the job running at that moment, if any, has finished.
+pub struct MaintenanceHandle {
+    stop: watch::Sender<bool>,
+    task: JoinHandle<()>,
+    metrics: Arc<Mutex<HashMap<String, JobMetrics>>>,
+}
+
+impl MaintenanceHandle {
+    /// Metrics per job name, for jobs that have run at least once.
+    pub fn metrics(&self) -> Result<HashMap<String, JobMetrics>, EventStoreError> {
+        Ok(self.metrics.lock()?.clone())
+    }
+
+    /// Stops the scheduler, waiting for a job that is running to finish.
+    pub async fn shutdown(self) {
+        let _ = self.stop.send(true);
+        // The task only fails if a job panicked, which has been reported already.
+        let _ = self.task.await;
+    }
+}
+
+#[cfg(all(test, feature = "memory"))]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tokio::sync::broadcast;
+    use super::*;
+    use crate::{memory::MemoryStorageEngine, EventStore};
+
+    #[tokio::test]
+    async fn ensure_registered_jobs_run_until_shutdown() {
+        let event_store = EventStore::new(MemoryStorageEngine::new());
+        let mut operational_events = event_store.operational_events();
+        let counted = Arc::new(AtomicUsize::new(0));
+        let counter = counted.clone();
+
+        let handle = event_store.maintenance()
+            .job("count", Schedule::every(Duration::from_millis(5)).jitter(Duration::from_millis(5)), move |_| {
+                let counter = counter.clone();
+                async move {
+                    counter.fetch_add(1, Ordering::SeqCst);
+                    Ok(())
+                }
+            })
+            .job("fail", Duration::from_millis(5), |_| async { Err(EventStoreError::ContextErrorOther("broken".to_string())) })
+            .retention(Duration::from_millis(5), RetentionPolicy::new().snapshots_to_keep(1))
+            .start();
+
+        tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                let metrics = handle.metrics().unwrap();
+                if ["count", "fail", "retention"].iter().all(|job| metrics.get(*job).is_some_and(|job| job.runs >= 2)) {
+                    break;
+                }
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+        }).await.expect("every job runs repeatedly");
+
+        let metrics = handle.metrics().unwrap();
+        assert_eq!(metrics["count"].failures, 0);
+        assert_eq!(metrics["fail"].failures, metrics["fail"].runs);
+        assert!(metrics["fail"].last_error.as_deref().unwrap().contains("broken"));
+        let mut reported = Vec::new();
+        loop {
+            match operational_events.try_recv() {
+                Ok(OperationalEvent::MaintenanceJobCompleted { job, .. }) => reported.push(job),
+                Ok(_) | Err(broadcast::error::TryRecvError::Lagged(_)) => continue,
+                Err(_) => break,
+            }
+        }
+        assert!(reported.iter().any(|job| job == "count"));
+
+        tokio::time::timeout(Duration::from_millis(500), handle.shutdown()).await.expect("shutdown is prompt");
+        let runs = counted.load(Ordering::SeqCst);
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        assert_eq!(counted.load(Ordering::SeqCst), runs);
+    }
+
+    #[tokio::test]
+    async fn ensure_configured_retention_is_scheduled() {
+        let event_store = EventStore::builder(MemoryStorageEngine::new())
+            .retention(Duration::from_millis(5), RetentionPolicy::new().snapshots_to_keep(1))
+            .build()
+            .unwrap();
+        assert_eq!(event_store.retention_policy().unwrap().snapshots_to_keep, Some(1));
+
+        let handle = event_store.maintenance().start();
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while !handle.metrics().unwrap().contains_key("retention") {
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+        }).await.expect("the retention job runs");
+        handle.shutdown().await;
+    }
+}
@@ -1,29 +1,134 @@
-use std::{sync::{Arc, Mutex}, collections::HashMap};
+use std::{sync::{Arc, Mutex, atomic::{AtomicI64, Ordering}}, collections::{BTreeMap, HashMap}};
 
-use crate::{ EventStoreError, event::Event, snapshot::Snapshot, EventStoreStorageEngine};
+use crate::{ AggregateId, EventStoreError, event::{Event, numbered_id}, snapshot::Snapshot, EventStoreStorageEngine};
+use crate::{AggregateInstance, CreateOutcome, DedupKey, DuplicateKeyPolicy, EngineCapabilities, LookupKey, LookupKeyChange, MigrationReport, PurgeReport, RewriteReport, SnapshotInfo, TypeInfo, WriteBatch, WrittenEvent};
+use chrono::{DateTime, Utc};
+use crate::clock::{ClockSkewPolicy, enforce_monotonic_created_at, stamped_streams};
+use crate::storage_engine::suffixed_natural_key;
+use crate::arguments;
+
+/// (aggregate_type, key_name, key_value)
+type LookupKeyIndex = (String, String, String);
 
 
 type SharedMemoryStore = Arc<Mutex<MemoryStore>>;
 
 #[derive(Default)]
 pub struct MemoryStore {
-    id: i64, 
+    /// Last aggregate id handed out, shared by the stores of every tenant of an engine so
+    /// ids stay unique across tenants.
+    ids: Arc<AtomicI64>,
+    /// Last position assigned in the global feed; positions of purged events are not reused.
+    position: i64,
     events: Vec<Event>,
     snapshots: Vec<Snapshot>,
-    natural_key_map: HashMap<String, i64>,
+    natural_key_map: HashMap<String, AggregateId>,
+    instances: Vec<AggregateInstance>,
+    lookup_keys: HashMap<LookupKeyIndex, Vec<AggregateId>>,
+    dedup_keys: HashMap<String, DedupKey>,
+    checkpoints: HashMap<String, i64>,
 }
 
 impl MemoryStore {
     pub fn new() -> MemoryStore {
         MemoryStore {
-            id: 0,
+            ids: Arc::new(AtomicI64::new(0)),
+            position: 0,
             events: Vec::new(),
             snapshots: Vec::new(),
             natural_key_map: HashMap::new(),
+            instances: Vec::new(),
+            lookup_keys: HashMap::new(),
+            dedup_keys: HashMap::new(),
+            checkpoints: HashMap::new(),
+        }
+    }
+
+    /// An empty store handing out ids from the same sequence as this one.
+    fn sharing_ids(&self) -> MemoryStore {
+        MemoryStore {
+            ids: self.ids.clone(),
+            ..MemoryStore::new()
+        }
+    }
+
+    fn next_id(&mut self) -> AggregateId {
+        numbered_id(self.ids.fetch_add(1, Ordering::SeqCst) + 1)
+    }
+
+    /// Keeps ids handed out later above an id restored from elsewhere.
+    #[cfg(not(feature = "uuid-ids"))]
+    fn reserve_id(&mut self, id: AggregateId) {
+        self.ids.fetch_max(id, Ordering::SeqCst);
+    }
+
+    /// Keeps ids handed out later above a numbered id restored from elsewhere; other uuids
+    /// cannot collide with numbered ones.
+    #[cfg(feature = "uuid-ids")]
+    fn reserve_id(&mut self, id: AggregateId) {
+        let (high, low) = id.as_u64_pair();
+        if high == 0 && low <= i64::MAX as u64 {
+            self.ids.fetch_max(low as i64, Ordering::SeqCst);
+        }
+    }
+
+    fn instance(&self, aggregate_type: &str, aggregate_id: AggregateId) -> Option<&AggregateInstance> {
+        self.instances.iter().find(|instance| instance.id == aggregate_id && instance.aggregate_type == aggregate_type)
+    }
+
+    fn instance_mut(&mut self, aggregate_type: &str, aggregate_id: AggregateId) -> Result<&mut AggregateInstance, EventStoreError> {
+        self.instances.iter_mut()
+            .find(|instance| instance.id == aggregate_id && instance.aggregate_type == aggregate_type)
+            .ok_or(EventStoreError::AggregateInstanceNotFound)
+    }
+
+    fn apply_lookup_key_change(&mut self, change: &LookupKeyChange) {
+        match change {
+            LookupKeyChange::Add(key) => {
+                let ids = self.lookup_keys.entry(lookup_key_index(key)).or_default();
+                if !ids.contains(&key.aggregate_id) {
+                    ids.push(key.aggregate_id);
+                }
+            },
+            LookupKeyChange::Remove(key) => {
+                if let Some(ids) = self.lookup_keys.get_mut(&lookup_key_index(key)) {
+                    ids.retain(|id| *id != key.aggregate_id);
+                }
+            },
         }
     }
 }
 
+fn lookup_key_index(key: &LookupKey) -> LookupKeyIndex {
+    (key.aggregate_type.clone(), key.key_name.clone(), key.key_value.clone())
+}
+
+/// Types named by `names` or by the events they cover, sorted by name.
+fn tally_types<'a>(
+    names: impl Iterator<Item = &'a str>,
+    events: impl Iterator<Item = (&'a str, &'a Event)>,
+    with_counts: bool,
+) -> Vec<TypeInfo> {
+    let new_type = |name: &str| TypeInfo {
+        name: name.to_string(),
+        id: None,
+        first_seen: None,
+        usage: with_counts.then_some(0),
+    };
+    let mut types: BTreeMap<&str, TypeInfo> = names.map(|name| (name, new_type(name))).collect();
+    for (name, event) in events {
+        let info = types.entry(name).or_insert_with(|| new_type(name));
+        if with_counts {
+            info.usage = info.usage.map(|usage| usage + 1);
+            info.first_seen = match (info.first_seen, event.created_at) {
+                (Some(seen), Some(created_at)) => Some(seen.min(created_at)),
+                (seen, created_at) => seen.or(created_at),
+            };
+        }
+    }
+    types.into_values().collect()
+}
+
 
 
 type SharedMemoryStorageEngine = Arc<MemoryStorageEngine>;
@@ -33,15 +138,50 @@ type SharedMemoryStorageEngine = Arc<MemoryStorageEngine>;
 /// This is a simple in-memory storage engine for EventStore. It is not intended for production use.
 /// It is useful for testing and as a reference implementation.
 ///
+/// Each tenant scoped with `for_tenant` gets a store of its own, checkpoints included, so
+/// tenants share nothing but the aggregate id sequence.
 pub struct MemoryStorageEngine {
     memory_store: SharedMemoryStore,
+    /// The store of the unscoped engine.
+    root_store: SharedMemoryStore,
+    /// Stores of the tenants by id, shared by every handle on the engine.
+    tenant_stores: Arc<Mutex<HashMap<String, SharedMemoryStore>>>,
+    tenant_id: Option<String>,
+    clock_skew_policy: ClockSkewPolicy,
+    duplicate_key_policy: DuplicateKeyPolicy,
 }
 
 impl MemoryStorageEngine {
     pub fn new() -> SharedMemoryStorageEngine {
+        MemoryStorageEngine::with_clock_skew_policy(ClockSkewPolicy::default())
+    }
+
+    /// Creates an engine that handles out of order created_at timestamps using `policy`.
+    pub fn with_clock_skew_policy(policy: ClockSkewPolicy) -> SharedMemoryStorageEngine {
+        MemoryStorageEngine::with_policies(policy, DuplicateKeyPolicy::default()).into()
+    }
+
+    /// Creates an engine that resolves taken natural keys using `policy`.
+    pub fn with_duplicate_key_policy(policy: DuplicateKeyPolicy) -> SharedMemoryStorageEngine {
+        MemoryStorageEngine::with_policies(ClockSkewPolicy::default(), policy).into()
+    }
+
+    fn with_policies(clock_skew_policy: ClockSkewPolicy, duplicate_key_policy: DuplicateKeyPolicy) -> MemoryStorageEngine {
+        let memory_store: SharedMemoryStore = Arc::new(Mutex::new(MemoryStore::new()));
         MemoryStorageEngine {
-            memory_store: Arc::new(Mutex::new(MemoryStore::new())), 
-        }.into()
+            memory_store: memory_store.clone(),
+            root_store: memory_store,
+            tenant_stores: Arc::new(Mutex::new(HashMap::new())),
+            tenant_id: None,
+            clock_skew_policy,
+            duplicate_key_policy,
+        }
+    }
+
+    /// The unscoped store and the store of every tenant created so far.
+    fn all_stores(&self) -> Vec<SharedMemoryStore> {
+        let tenant_stores = self.tenant_stores.lock().unwrap();
+        std::iter::once(self.root_store.clone()).chain(tenant_stores.values().cloned()).collect()
     }
 
     pub fn snapshot_count(&self) -> usize {
@@ -66,19 +206,89 @@ impl MemoryStorageEngine {
 #[async_trait::async_trait]
 impl EventStoreStorageEngine for MemoryStorageEngine {
 
-    async fn create_aggregate_instance(&self, _aggregate_type: &str, natural_key: Option<&str>) -> Result<i64, EventStoreError> {
+    async fn create_aggregate_instance_with_policy(
+        &self,
+        aggregate_type: &str,
+        natural_key: Option<&str>,
+        policy: Option<DuplicateKeyPolicy>,
+    ) -> Result<CreateOutcome, EventStoreError> {
         let mut memory_store = self.memory_store.lock().unwrap();
-        memory_store.id += 1;
-        let id = memory_store.id;
 
-        if let Some(n) = natural_key {
-            memory_store.natural_key_map.insert(n.to_string(), id);
+        let key_used = match natural_key {
+            Some(natural_key) => {
+                let mut key = natural_key.to_string();
+                let mut attempt = 1;
+                while let Some(existing) = memory_store.natural_key_map.get(&key) {
+                    match policy.unwrap_or(self.duplicate_key_policy) {
+                        DuplicateKeyPolicy::Error => return Err(EventStoreError::DuplicateNaturalKey(key)),
+                        DuplicateKeyPolicy::ReturnExisting => {
+                            return Ok(CreateOutcome { id: *existing, created: false, key_used: Some(key) });
+                        },
+                        DuplicateKeyPolicy::Suffix => {
+                            attempt += 1;
+                            key = suffixed_natural_key(natural_key, attempt);
+                        },
+                    }
+                }
+                Some(key)
+            },
+            None => None,
+        };
+
+        let id = memory_store.next_id();
+        if let Some(key) = &key_used {
+            memory_store.natural_key_map.insert(key.clone(), id);
         }
+        memory_store.instances.push(AggregateInstance {
+            id,
+            aggregate_type: aggregate_type.to_string(),
+            natural_key: key_used.clone(),
+            deleted_at: None,
+        });
 
-        Ok(id)
+        Ok(CreateOutcome { id, created: true, key_used })
     }
 
-    async fn get_aggregate_instance_id(&self, _aggregate_type: &str, natural_key: &str) -> Result<Option<i64>, EventStoreError> {
+    async fn list_aggregate_instances(&self, aggregate_type: &str, offset: usize, limit: usize) -> Result<Vec<AggregateInstance>, EventStoreError> {
+        let memory_store = self.memory_store.lock().unwrap();
+        let instances = memory_store.instances.iter()
+            .filter(|instance| instance.aggregate_type == aggregate_type)
+            .skip(offset)
+            .take(limit)
+            .cloned()
+            .collect();
+        Ok(instances)
+    }
+
+    async fn list_lookup_keys(&self, aggregate_type: &str, offset: usize, limit: usize) -> Result<Vec<LookupKey>, EventStoreError> {
+        let memory_store = self.memory_store.lock().unwrap();
+        let mut keys: Vec<LookupKey> = memory_store.lookup_keys.iter()
+            .filter(|((key_type, _, _), _)| key_type == aggregate_type)
+            .flat_map(|((_, key_name, key_value), ids)| ids.iter().map(move |id| LookupKey {
+                aggregate_id: *id,
+                aggregate_type: aggregate_type.to_string(),
+                key_name: key_name.clone(),
+                key_value: key_value.clone(),
+            }))
+            .collect();
+        keys.sort_by(|a, b| (a.aggregate_id, &a.key_name, &a.key_value).cmp(&(b.aggregate_id, &b.key_name, &b.key_value)));
+        Ok(keys.into_iter().skip(offset).take(limit).collect())
+    }
+
+    async fn list_aggregate_types(&self, with_counts: bool) -> Result<Vec<TypeInfo>, EventStoreError> {
+        let memory_store = self.memory_store.lock().unwrap();
+        let names = memory_store.instances.iter().map(|instance| instance.aggregate_type.as_str());
+        let events = memory_store.events.iter().map(|event| (event.aggregate_type.as_str(), event));
+        Ok(tally_types(names, events, with_counts))
+    }
+
+    async fn list_event_types(&self, with_counts: bool) -> Result<Vec<TypeInfo>, EventStoreError> {
+        let memory_store = self.memory_store.lock().unwrap();
+        let events = memory_store.events.iter().map(|event| (event.event_type.as_str(), event));
+        Ok(tally_types(std::iter::empty(), events, with_counts))
+    }
+
+    async fn get_aggregate_instance_id(&self, _aggregate_type: &str, natural_key: &str) -> Result<Option<AggregateId>, EventStoreError> {
         let memory_store = self.memory_store.lock().unwrap();
         let id = memory_store.natural_key_map.get(natural_key);
         match id {
@@ -87,12 +297,125 @@ impl EventStoreStorageEngine for MemoryStorageEngine {
         }
     }
 
+    async fn find_aggregate_instance(&self, aggregate_type: &str, natural_key: &str) -> Result<Option<AggregateInstance>, EventStoreError> {
+        let memory_store = self.memory_store.lock().unwrap();
+        let Some(id) = memory_store.natural_key_map.get(natural_key) else {
+            return Ok(None);
+        };
+        Ok(memory_store.instance(aggregate_type, *id).cloned())
+    }
+
+    async fn restore_aggregate_instance(&self, aggregate_type: &str, aggregate_id: AggregateId, natural_key: Option<&str>) -> Result<(), EventStoreError> {
+        arguments::aggregate_id(aggregate_id)?;
+        let mut memory_store = self.memory_store.lock().unwrap();
+        if memory_store.instances.iter().any(|instance| instance.id == aggregate_id) {
+            return Err(EventStoreError::StorageEngineErrorOther(format!("Aggregate instance {} already exists.", aggregate_id)));
+        }
+        if let Some(key) = natural_key {
+            if memory_store.natural_key_map.contains_key(key) {
+                return Err(EventStoreError::DuplicateNaturalKey(key.to_string()));
+            }
+            memory_store.natural_key_map.insert(key.to_string(), aggregate_id);
+        }
+        memory_store.reserve_id(aggregate_id);
+        memory_store.instances.push(AggregateInstance {
+            id: aggregate_id,
+            aggregate_type: aggregate_type.to_string(),
+            natural_key: natural_key.map(str::to_string),
+            deleted_at: None,
+        });
+        Ok(())
+    }
+
+    async fn soft_delete_aggregate(&self, aggregate_type: &str, aggregate_id: AggregateId, deleted_at: DateTime<Utc>) -> Result<(), EventStoreError> {
+        let mut memory_store = self.memory_store.lock().unwrap();
+        let instance = memory_store.instance_mut(aggregate_type, aggregate_id)?;
+        instance.deleted_at.get_or_insert(deleted_at);
+        Ok(())
+    }
+
+    async fn resurrect_aggregate(&self, aggregate_type: &str, aggregate_id: AggregateId) -> Result<(), EventStoreError> {
+        let mut memory_store = self.memory_store.lock().unwrap();
+        memory_store.instance_mut(aggregate_type, aggregate_id)?.deleted_at = None;
+        Ok(())
+    }
+
+    async fn purge_aggregate(&self, aggregate_id: AggregateId, aggregate_type: &str) -> Result<PurgeReport, EventStoreError> {
+        let mut memory_store = self.memory_store.lock().unwrap();
+        let natural_key = memory_store.instance_mut(aggregate_type, aggregate_id)?.natural_key.clone();
+        memory_store.instances.retain(|instance| !(instance.id == aggregate_id && instance.aggregate_type == aggregate_type));
+        if let Some(natural_key) = natural_key {
+            memory_store.natural_key_map.remove(&natural_key);
+        }
+
+        let mut report = PurgeReport::default();
+        let of_aggregate = |id: AggregateId, other_type: &str| id == aggregate_id && other_type == aggregate_type;
+        let before = memory_store.events.len();
+        memory_store.events.retain(|event| !of_aggregate(event.aggregate_id, &event.aggregate_type));
+        report.events = before - memory_store.events.len();
+        let before = memory_store.snapshots.len();
+        memory_store.snapshots.retain(|snapshot| !of_aggregate(snapshot.aggregate_id, &snapshot.aggregate_type));
+        report.snapshots = before - memory_store.snapshots.len();
+        for ((key_type, _, _), ids) in memory_store.lookup_keys.iter_mut() {
+            if key_type == aggregate_type {
+                let before = ids.len();
+                ids.retain(|id| *id != aggregate_id);
+                report.lookup_keys += before - ids.len();
+            }
+        }
+        let before = memory_store.dedup_keys.len();
+        memory_store.dedup_keys.retain(|_, dedup_key| dedup_key.aggregate_id != aggregate_id);
+        report.dedup_keys = before - memory_store.dedup_keys.len();
+        Ok(report)
+    }
+
+    async fn replace_events(&self, aggregate_id: AggregateId, aggregate_type: &str, expected_version: i64, events: &[Event]) -> Result<RewriteReport, EventStoreError> {
+        let mut memory_store = self.memory_store.lock().unwrap();
+        let of_aggregate = |id: AggregateId, other_type: &str| id == aggregate_id && other_type == aggregate_type;
+        let actual = memory_store.events.iter()
+            .filter(|event| of_aggregate(event.aggregate_id, &event.aggregate_type))
+            .map(|event| event.version)
+            .max()
+            .unwrap_or(0);
+        if actual != expected_version {
+            return Err(EventStoreError::VersionConflict {
+                aggregate_type: aggregate_type.to_string(),
+                aggregate_id,
+                expected: expected_version,
+                actual,
+            });
+        }
+
+        let mut report = RewriteReport::default();
+        let before = memory_store.events.len();
+        memory_store.events.retain(|event| !of_aggregate(event.aggregate_id, &event.aggregate_type));
+        report.events_removed = before - memory_store.events.len();
+        let before = memory_store.snapshots.len();
+        memory_store.snapshots.retain(|snapshot| !of_aggregate(snapshot.aggregate_id, &snapshot.aggregate_type));
+        report.snapshots_removed = before - memory_store.snapshots.len();
+        for event in events {
+            let mut event = event.clone();
+            memory_store.position += 1;
+            event.position = Some(memory_store.position);
+            memory_store.events.push(event);
+        }
+        report.events_written = events.len();
+        Ok(report)
+    }
+
+    async fn aggregate_deleted_at(&self, aggregate_type: &str, aggregate_id: AggregateId) -> Result<Option<DateTime<Utc>>, EventStoreError> {
+        let memory_store = self.memory_store.lock().unwrap();
+        Ok(memory_store.instance(aggregate_type, aggregate_id).and_then(|instance| instance.deleted_at))
+    }
+
     async fn read_events(
         &self,
-        aggregate_id: i64,
+        aggregate_id: AggregateId,
         aggregate_type: &str,
         version: i64,
     ) -> Result<Vec<Event>, EventStoreError> {
+        arguments::aggregate_id(aggregate_id)?;
+        arguments::non_negative("version", version)?;
         let memory_store = self.memory_store.lock().unwrap();
         let mut events = Vec::new();
 
@@ -101,12 +424,14 @@ impl EventStoreStorageEngine for MemoryStorageEngine {
                 events.push(event.clone());
             }
         }
+        // Imports can append older versions after newer ones.
+        events.sort_by_key(|event| event.version);
         Ok(events)
     }
 
     async fn read_snapshot(
         &self,
-        aggregate_id: i64,
+        aggregate_id: AggregateId,
         aggregate_type: &str,
     ) -> Result<Option<Snapshot>, EventStoreError> {
         let memory_store = self.memory_store.lock().unwrap();
@@ -119,20 +444,301 @@ impl EventStoreStorageEngine for MemoryStorageEngine {
         Ok(None)
     }
 
-    async fn write_updates(&self, events: &[Event], snapshots: &[Snapshot]) -> Result<(), EventStoreError> {
+    async fn read_snapshots(&self, aggregate_id: AggregateId, aggregate_type: &str, limit: usize) -> Result<Vec<SnapshotInfo>, EventStoreError> {
+        let memory_store = self.memory_store.lock().unwrap();
+        let mut snapshots: Vec<SnapshotInfo> = memory_store.snapshots.iter()
+            .filter(|snapshot| snapshot.aggregate_id == aggregate_id && snapshot.aggregate_type == aggregate_type)
+            .map(|snapshot| SnapshotInfo { version: snapshot.version, data_size: snapshot.data.len() })
+            .collect();
+        snapshots.sort_by_key(|snapshot| std::cmp::Reverse(snapshot.version));
+        snapshots.truncate(limit);
+        Ok(snapshots)
+    }
+
+    async fn read_snapshot_at(&self, aggregate_id: AggregateId, aggregate_type: &str, max_version: i64) -> Result<Option<Snapshot>, EventStoreError> {
+        arguments::aggregate_id(aggregate_id)?;
+        arguments::non_negative("max_version", max_version)?;
+        let memory_store = self.memory_store.lock().unwrap();
+        let snapshot = memory_store.snapshots.iter()
+            .filter(|snapshot| snapshot.aggregate_id == aggregate_id && snapshot.aggregate_type == aggregate_type)
+            .filter(|snapshot| snapshot.version <= max_version)
+            .max_by_key(|snapshot| snapshot.version);
+        Ok(snapshot.cloned())
+    }
+
+    async fn batch_read_snapshots(&self, requests: &[(AggregateId, &str)]) -> Result<HashMap<AggregateId, Snapshot>, EventStoreError> {
+        let memory_store = self.memory_store.lock().unwrap();
+        let mut snapshots = HashMap::new();
+        // Later snapshots replace earlier ones, matching read_snapshot.
+        for snapshot in &memory_store.snapshots {
+            let requested = requests.iter()
+                .any(|(aggregate_id, aggregate_type)| snapshot.aggregate_id == *aggregate_id && snapshot.aggregate_type == *aggregate_type);
+            if requested {
+                snapshots.insert(snapshot.aggregate_id, snapshot.clone());
+            }
+        }
+        Ok(snapshots)
+    }
+
+    async fn write_updates(&self, events: &[Event], snapshots: &[Snapshot]) -> Result<Vec<WrittenEvent>, EventStoreError> {
+        self.write_batch(&WriteBatch { events, snapshots, ..Default::default() }).await
+    }
+
+    async fn write_batch(&self, batch: &WriteBatch<'_>) -> Result<Vec<WrittenEvent>, EventStoreError> {
         let mut memory_store = self.memory_store.lock().unwrap();
-        for event in events {
-            memory_store.events.push(event.clone());
+
+        for (index, dedup_key) in batch.dedup_keys.iter().enumerate() {
+            let repeated = batch.dedup_keys[..index].iter().any(|other| other.key == dedup_key.key);
+            if repeated || memory_store.dedup_keys.contains_key(&dedup_key.key) {
+                return Err(EventStoreError::DuplicateEvent(dedup_key.key.clone()));
+            }
+        }
+
+        // A version that is stored already was written by someone else since the aggregate
+        // was loaded.
+        let mut stored_versions: HashMap<(&str, AggregateId), Vec<i64>> = HashMap::new();
+        for event in batch.events {
+            let key = (event.aggregate_type.as_str(), event.aggregate_id);
+            let versions = stored_versions.entry(key).or_insert_with(|| memory_store.events.iter()
+                .filter(|stored| stored.aggregate_id == event.aggregate_id && stored.aggregate_type == event.aggregate_type)
+                .map(|stored| stored.version)
+                .collect());
+            if versions.contains(&event.version) {
+                return Err(EventStoreError::VersionConflict {
+                    aggregate_type: event.aggregate_type.clone(),
+                    aggregate_id: event.aggregate_id,
+                    expected: event.version - 1,
+                    actual: versions.iter().copied().max().unwrap_or(0),
+                });
+            }
+        }
+
+        let mut heads = HashMap::new();
+        for (aggregate_type, aggregate_id) in stamped_streams(batch.events) {
+            let head = memory_store.events.iter()
+                .filter(|event| event.aggregate_id == aggregate_id && event.aggregate_type == aggregate_type)
+                .max_by_key(|event| event.version)
+                .and_then(|event| event.created_at);
+            if let Some(head) = head {
+                heads.insert((aggregate_type, aggregate_id), head);
+            }
+        }
+        let mut events = batch.events.to_vec();
+        enforce_monotonic_created_at(&mut events, &mut heads, self.clock_skew_policy)?;
+
+        // Positions follow the order read_all_events walks the event list in.
+        for event in events.iter_mut() {
+            memory_store.position += 1;
+            event.position = Some(memory_store.position);
         }
-        for snapshot in snapshots {
+        let written = events.iter()
+            .map(|event| WrittenEvent {
+                position: event.position,
+                ..WrittenEvent::unpositioned(event)
+            })
+            .collect();
+        memory_store.events.extend(events);
+        for snapshot in batch.snapshots {
             memory_store.snapshots.push(snapshot.clone());
         }
+        for change in batch.lookup_keys {
+            memory_store.apply_lookup_key_change(change);
+        }
+        for dedup_key in batch.dedup_keys {
+            memory_store.dedup_keys.insert(dedup_key.key.clone(), dedup_key.clone());
+        }
+        Ok(written)
+    }
+
+    async fn read_all_events(&self, from_position: i64, limit: usize) -> Result<Vec<Event>, EventStoreError> {
+        arguments::non_negative("from_position", from_position)?;
+        let memory_store = self.memory_store.lock().unwrap();
+        let skip = memory_store.events.partition_point(|event| event.position.unwrap_or_default() <= from_position);
+        let events = memory_store.events.iter()
+            .skip(skip)
+            .take(limit)
+            .cloned()
+            .collect();
+        Ok(events)
+    }
+
+    async fn head_position(&self) -> Result<i64, EventStoreError> {
+        Ok(self.memory_store.lock().unwrap().position)
+    }
+
+    async fn read_events_until_position(&self, aggregate_id: AggregateId, aggregate_type: &str, version: i64, max_position: i64) -> Result<Vec<Event>, EventStoreError> {
+        arguments::aggregate_id(aggregate_id)?;
+        arguments::non_negative("version", version)?;
+        arguments::non_negative("max_position", max_position)?;
+        let memory_store = self.memory_store.lock().unwrap();
+        let events = memory_store.events.iter()
+            .take_while(|event| event.position.unwrap_or_default() <= max_position)
+            .filter(|event| event.aggregate_id == aggregate_id && event.aggregate_type == aggregate_type && event.version > version)
+            .cloned()
+            .collect();
+        Ok(events)
+    }
+
+    async fn add_lookup_key(&self, key: &LookupKey) -> Result<(), EventStoreError> {
+        let mut memory_store = self.memory_store.lock().unwrap();
+        memory_store.apply_lookup_key_change(&LookupKeyChange::Add(key.clone()));
+        Ok(())
+    }
+
+    async fn remove_lookup_key(&self, key: &LookupKey) -> Result<(), EventStoreError> {
+        let mut memory_store = self.memory_store.lock().unwrap();
+        memory_store.apply_lookup_key_change(&LookupKeyChange::Remove(key.clone()));
         Ok(())
     }
 
+    async fn find_by_lookup_key(&self, aggregate_type: &str, key_name: &str, key_value: &str) -> Result<Vec<AggregateId>, EventStoreError> {
+        let memory_store = self.memory_store.lock().unwrap();
+        let index = (aggregate_type.to_string(), key_name.to_string(), key_value.to_string());
+        Ok(memory_store.lookup_keys.get(&index).cloned().unwrap_or_default())
+    }
+
+    async fn has_dedup_key(&self, key: &str) -> Result<bool, EventStoreError> {
+        let memory_store = self.memory_store.lock().unwrap();
+        Ok(memory_store.dedup_keys.contains_key(key))
+    }
+
+    async fn prune_dedup_keys(&self, older_than: DateTime<Utc>) -> Result<usize, EventStoreError> {
+        let mut memory_store = self.memory_store.lock().unwrap();
+        let before = memory_store.dedup_keys.len();
+        memory_store.dedup_keys.retain(|_, dedup_key| dedup_key.created_at >= older_than);
+        Ok(before - memory_store.dedup_keys.len())
+    }
+
+    async fn read_checkpoint(&self, projection: &str) -> Result<Option<i64>, EventStoreError> {
+        let memory_store = self.memory_store.lock().unwrap();
+        Ok(memory_store.checkpoints.get(projection).copied())
+    }
+
+    async fn write_checkpoint(&self, projection: &str, position: i64) -> Result<(), EventStoreError> {
+        arguments::non_negative("position", position)?;
+        let mut memory_store = self.memory_store.lock().unwrap();
+        memory_store.checkpoints.insert(projection.to_string(), position);
+        Ok(())
+    }
+
+    async fn prune_snapshots(&self, keep: usize) -> Result<usize, EventStoreError> {
+        let mut memory_store = self.memory_store.lock().unwrap();
+        let mut newer: HashMap<(String, AggregateId), Vec<i64>> = HashMap::new();
+        for snapshot in &memory_store.snapshots {
+            newer.entry((snapshot.aggregate_type.clone(), snapshot.aggregate_id)).or_default().push(snapshot.version);
+        }
+        let before = memory_store.snapshots.len();
+        memory_store.snapshots.retain(|snapshot| {
+            let versions = &newer[&(snapshot.aggregate_type.clone(), snapshot.aggregate_id)];
+            versions.iter().filter(|version| **version > snapshot.version).count() < keep
+        });
+        Ok(before - memory_store.snapshots.len())
+    }
+
+    async fn migrate_aggregate_type(&self, old_type: &str, new_type: &str) -> Result<MigrationReport, EventStoreError> {
+        if let Some(tenant_id) = &self.tenant_id {
+            return Err(EventStoreError::StorageEngineErrorOther(
+                format!("Aggregate types are shared by every tenant and cannot be migrated through tenant '{}'.", tenant_id)));
+        }
+        let mut report = MigrationReport::default();
+        if old_type == new_type {
+            return Ok(report);
+        }
+        for memory_store in self.all_stores() {
+            migrate_store(&mut memory_store.lock().unwrap(), old_type, new_type, &mut report);
+        }
+        Ok(report)
+    }
+
+    async fn get_aggregate_version(&self, aggregate_id: AggregateId, aggregate_type: &str) -> Result<i64, EventStoreError> {
+        let memory_store = self.memory_store.lock().unwrap();
+        let version = memory_store.events.iter()
+            .filter(|event| event.aggregate_id == aggregate_id && event.aggregate_type == aggregate_type)
+            .map(|event| event.version)
+            .max()
+            .unwrap_or(0);
+        Ok(version)
+    }
+
+    async fn earliest_event_version(&self, aggregate_id: AggregateId, aggregate_type: &str) -> Result<Option<i64>, EventStoreError> {
+        let memory_store = self.memory_store.lock().unwrap();
+        let version = memory_store.events.iter()
+            .filter(|event| event.aggregate_id == aggregate_id && event.aggregate_type == aggregate_type)
+            .map(|event| event.version)
+            .min();
+        Ok(version)
+    }
+
+    fn for_tenant(&self, tenant_id: &str) -> Result<Arc<dyn EventStoreStorageEngine + Send + Sync>, EventStoreError> {
+        arguments::tenant_id(tenant_id)?;
+        let memory_store = self.tenant_stores.lock().unwrap()
+            .entry(tenant_id.to_string())
+            .or_insert_with(|| Arc::new(Mutex::new(self.root_store.lock().unwrap().sharing_ids())))
+            .clone();
+        Ok(Arc::new(MemoryStorageEngine {
+            memory_store,
+            root_store: self.root_store.clone(),
+            tenant_stores: self.tenant_stores.clone(),
+            tenant_id: Some(tenant_id.to_string()),
+            clock_skew_policy: self.clock_skew_policy,
+            duplicate_key_policy: self.duplicate_key_policy,
+        }))
+    }
+
+    fn tenant_id(&self) -> Option<&str> {
+        self.tenant_id.as_deref()
+    }
+
+    fn capabilities(&self) -> EngineCapabilities {
+        EngineCapabilities::all()
+    }
+
+    fn engine_name(&self) -> &str {
+        "MemoryStorageEngine"
+    }
+
+    fn description(&self) -> String {
+        match &self.tenant_id {
+            Some(tenant_id) => format!("MemoryStorageEngine (in-process, non-persistent, tenant '{}')", tenant_id),
+            None => "MemoryStorageEngine (in-process, non-persistent)".to_string(),
+        }
+    }
+
+}
+
+/// Moves everything of `old_type` in one store to `new_type`, adding what moved to `report`.
+fn migrate_store(memory_store: &mut MemoryStore, old_type: &str, new_type: &str, report: &mut MigrationReport) {
+    for instance in memory_store.instances.iter_mut().filter(|instance| instance.aggregate_type == old_type) {
+        instance.aggregate_type = new_type.to_string();
+        report.aggregate_instances += 1;
+    }
+    for event in memory_store.events.iter_mut().filter(|event| event.aggregate_type == old_type) {
+        event.aggregate_type = new_type.to_string();
+        report.events += 1;
+    }
+    for snapshot in memory_store.snapshots.iter_mut().filter(|snapshot| snapshot.aggregate_type == old_type) {
+        snapshot.aggregate_type = new_type.to_string();
+        report.snapshots += 1;
+    }
+
+    let old_keys: Vec<LookupKeyIndex> = memory_store.lookup_keys.keys()
+        .filter(|(aggregate_type, _, _)| aggregate_type == old_type)
+        .cloned()
+        .collect();
+    for old_key in old_keys {
+        let ids = memory_store.lookup_keys.remove(&old_key).unwrap_or_default();
+        report.lookup_keys += ids.len();
+        let merged = memory_store.lookup_keys.entry((new_type.to_string(), old_key.1, old_key.2)).or_default();
+        for id in ids {
+            if !merged.contains(&id) {
+                merged.push(id);
+            }
+        }
+    }
 }
 
 #[cfg(test)]
+#[allow(dead_code, clippy::cloned_ref_to_slice_refs)]
 mod tests {
     use serde::{Serialize, Deserialize};
 
@@ -162,7 +768,7 @@ mod tests {
         let aggregate_type = "test";
         let natural_key = "test";
         let id = storage_engine.create_aggregate_instance(aggregate_type, Some(natural_key)).await.unwrap();
-        assert_eq!(id, 1);
+        assert_eq!(id, numbered_id(1));
     }
 
     #[tokio::test]
@@ -173,7 +779,30 @@ mod tests {
         storage_engine.create_aggregate_instance(aggregate_type, Some(natural_key)).await.unwrap();
 
         let id = storage_engine.get_aggregate_instance_id(aggregate_type, natural_key).await.unwrap().unwrap();
-        assert_eq!(id, 1);
+        assert_eq!(id, numbered_id(1));
+    }
+
+    #[tokio::test]
+    async fn ensure_duplicate_natural_keys_follow_policy() {
+        let storage_engine = MemoryStorageEngine::new();
+        let first = storage_engine.create_aggregate_instance_with_policy("user", Some("alice"), None).await.unwrap();
+        assert_eq!(first, CreateOutcome { id: numbered_id(1), created: true, key_used: Some("alice".to_string()) });
+
+        let result = storage_engine.create_aggregate_instance("user", Some("alice")).await;
+        assert!(matches!(result, Err(EventStoreError::DuplicateNaturalKey(ref key)) if key == "alice"));
+
+        let existing = storage_engine.create_aggregate_instance_with_policy("user", Some("alice"), Some(DuplicateKeyPolicy::ReturnExisting)).await.unwrap();
+        assert_eq!(existing, CreateOutcome { id: numbered_id(1), created: false, key_used: Some("alice".to_string()) });
+
+        let suffixed = storage_engine.create_aggregate_instance_with_policy("user", Some("alice"), Some(DuplicateKeyPolicy::Suffix)).await.unwrap();
+        assert_eq!(suffixed, CreateOutcome { id: numbered_id(2), created: true, key_used: Some("alice-2".to_string()) });
+        let suffixed = storage_engine.create_aggregate_instance_with_policy("user", Some("alice"), Some(DuplicateKeyPolicy::Suffix)).await.unwrap();
+        assert_eq!(suffixed.key_used.as_deref(), Some("alice-3"));
+        assert_eq!(storage_engine.get_aggregate_instance_id("user", "alice-3").await.unwrap(), Some(suffixed.id));
+
+        let storage_engine = MemoryStorageEngine::with_duplicate_key_policy(DuplicateKeyPolicy::ReturnExisting);
+        let first = storage_engine.create_aggregate_instance("user", Some("bob")).await.unwrap();
+        assert_eq!(storage_engine.create_aggregate_instance("user", Some("bob")).await.unwrap(), first);
     }
 
     #[tokio::test]
@@ -183,27 +812,27 @@ mod tests {
             email: "rtest@example.com".to_string(),
         };
 
-        let event = Event::new(1, "test", 1, "created", &event_data).unwrap();
+        let event = Event::new(numbered_id(1), "test", 1, "created", &event_data).unwrap();
         
         let state = UserState {
             name: "test".to_string(),
             email: "rtest@example.com".to_string(),
         };
-        let snapshot = Snapshot::new(1, "test", 1, &state).unwrap();
+        let snapshot = Snapshot::new(numbered_id(1), "test", 1, &state).unwrap();
 
         let storage_engine = MemoryStorageEngine::new();
         storage_engine.write_updates(&[event.clone()], &[snapshot.clone()]).await.unwrap();
 
-        let events = storage_engine.read_events(1, "test", 0).await.unwrap();
-        let retrieved_snapshot = storage_engine.read_snapshot(1, "test").await.unwrap().unwrap();
+        let events = storage_engine.read_events(numbered_id(1), "test", 0).await.unwrap();
+        let retrieved_snapshot = storage_engine.read_snapshot(numbered_id(1), "test").await.unwrap().unwrap();
 
         assert_eq!(events[0].data, event.data);
-        assert_eq!(events[0].aggregate_id, 1);
+        assert_eq!(events[0].aggregate_id, numbered_id(1));
         assert_eq!(events[0].event_type, "created");
         assert_eq!(events[0].version, 1);
 
         assert_eq!(retrieved_snapshot.data, snapshot.data);
-        assert_eq!(retrieved_snapshot.aggregate_id, 1);
+        assert_eq!(retrieved_snapshot.aggregate_id, numbered_id(1));
         assert_eq!(retrieved_snapshot.aggregate_type, "test");
         assert_eq!(retrieved_snapshot.version, 1);
 
@@ -217,11 +846,240 @@ mod tests {
         assert!(result.is_none());
     }
 
+    #[tokio::test]
+    async fn ensure_aggregate_version_is_highest_event_version() {
+        let storage_engine = MemoryStorageEngine::new();
+        assert_eq!(storage_engine.get_aggregate_version(numbered_id(1), "test").await.unwrap(), 0);
+
+        let event_data = UserCreate {
+            name: "test".to_string(),
+            email: "rtest@example.com".to_string(),
+        };
+        let events = vec![
+            Event::new(numbered_id(1), "test", 1, "created", &event_data).unwrap(),
+            Event::new(numbered_id(1), "test", 2, "updated", &event_data).unwrap(),
+            Event::new(numbered_id(2), "test", 1, "created", &event_data).unwrap(),
+        ];
+        storage_engine.write_updates(&events, &[]).await.unwrap();
+
+        assert_eq!(storage_engine.get_aggregate_version(numbered_id(1), "test").await.unwrap(), 2);
+        assert_eq!(storage_engine.get_aggregate_version(numbered_id(2), "test").await.unwrap(), 1);
+        assert_eq!(storage_engine.get_aggregate_version(numbered_id(1), "other").await.unwrap(), 0);
+        assert_eq!(storage_engine.earliest_event_version(numbered_id(1), "test").await.unwrap(), Some(1));
+        assert_eq!(storage_engine.earliest_event_version(numbered_id(1), "other").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn ensure_lookup_keys_are_shared_and_removable() {
+        let storage_engine = MemoryStorageEngine::new();
+        let key = |aggregate_id: AggregateId| LookupKey {
+            aggregate_id,
+            aggregate_type: "order".to_string(),
+            key_name: "customer_id".to_string(),
+            key_value: "42".to_string(),
+        };
+
+        storage_engine.add_lookup_key(&key(numbered_id(1))).await.unwrap();
+        storage_engine.add_lookup_key(&key(numbered_id(2))).await.unwrap();
+        storage_engine.add_lookup_key(&key(numbered_id(2))).await.unwrap();
+
+        let ids = storage_engine.find_by_lookup_key("order", "customer_id", "42").await.unwrap();
+        assert_eq!(ids, vec![numbered_id(1), numbered_id(2)]);
+
+        storage_engine.remove_lookup_key(&key(numbered_id(1))).await.unwrap();
+        let ids = storage_engine.find_by_lookup_key("order", "customer_id", "42").await.unwrap();
+        assert_eq!(ids, vec![numbered_id(2)]);
+
+        let ids = storage_engine.find_by_lookup_key("invoice", "customer_id", "42").await.unwrap();
+        assert!(ids.is_empty());
+    }
+
+    fn stamped_event(version: i64, created_at: chrono::DateTime<chrono::Utc>) -> Event {
+        let event_data = UserCreate {
+            name: "test".to_string(),
+            email: "rtest@example.com".to_string(),
+        };
+        let mut event = Event::new(numbered_id(1), "test", version, "updated", &event_data).unwrap();
+        event.created_at = Some(created_at);
+        event
+    }
+
+    #[tokio::test]
+    async fn ensure_skewed_created_at_is_clamped() {
+        let storage_engine = MemoryStorageEngine::new();
+        let now = chrono::Utc::now();
+        let skewed = now - chrono::Duration::seconds(30);
+
+        storage_engine.write_updates(&[stamped_event(1, now), stamped_event(2, skewed)], &[]).await.unwrap();
+        storage_engine.write_updates(&[stamped_event(3, skewed)], &[]).await.unwrap();
+
+        let events = storage_engine.read_events(numbered_id(1), "test", 0).await.unwrap();
+        assert!(events.iter().all(|event| event.created_at == Some(now)));
+        assert!(events[0].metadata.is_none());
+        assert_eq!(events[1].metadata_keys(), vec!["clock_adjusted".to_string()]);
+        assert_eq!(events[2].metadata_keys(), vec!["clock_adjusted".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn ensure_skewed_created_at_fails_when_configured() {
+        let storage_engine = MemoryStorageEngine::with_clock_skew_policy(ClockSkewPolicy::Fail);
+        let now = chrono::Utc::now();
+        let skewed = now - chrono::Duration::seconds(30);
+
+        let result = storage_engine.write_updates(&[stamped_event(1, now), stamped_event(2, skewed)], &[]).await;
+        assert!(matches!(result, Err(EventStoreError::ClockSkew(_))));
+        assert!(storage_engine.read_events(numbered_id(1), "test", 0).await.unwrap().is_empty());
+
+        storage_engine.write_updates(&[stamped_event(1, now)], &[]).await.unwrap();
+        let result = storage_engine.write_updates(&[stamped_event(2, skewed)], &[]).await;
+        assert!(matches!(result, Err(EventStoreError::ClockSkew(_))));
+        assert_eq!(storage_engine.read_events(numbered_id(1), "test", 0).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn ensure_global_feed_pages_in_commit_order() {
+        let storage_engine = MemoryStorageEngine::new();
+        let event_data = UserCreate {
+            name: "test".to_string(),
+            email: "rtest@example.com".to_string(),
+        };
+        let events = vec![
+            Event::new(numbered_id(1), "test", 1, "created", &event_data).unwrap(),
+            Event::new(numbered_id(2), "test", 1, "created", &event_data).unwrap(),
+            Event::new(numbered_id(1), "test", 2, "updated", &event_data).unwrap(),
+        ];
+        storage_engine.write_updates(&events, &[]).await.unwrap();
+
+        let first_page = storage_engine.read_all_events(0, 2).await.unwrap();
+        assert_eq!(first_page.iter().map(|event| (event.aggregate_id, event.position)).collect::<Vec<_>>(),
+            vec![(numbered_id(1), Some(1)), (numbered_id(2), Some(2))]);
+
+        let second_page = storage_engine.read_all_events(2, 2).await.unwrap();
+        assert_eq!(second_page.len(), 1);
+        assert_eq!(second_page[0].position, Some(3));
+        assert_eq!(second_page[0].version, 2);
+
+        let positions: Vec<_> = storage_engine.read_events(numbered_id(1), "test", 0).await.unwrap().iter().map(|event| event.position).collect();
+        assert_eq!(positions, vec![Some(1), Some(3)]);
+    }
+
+    #[test]
+    fn ensure_description_names_engine() {
+        let storage_engine = MemoryStorageEngine::new();
+        assert_eq!(storage_engine.description(), "MemoryStorageEngine (in-process, non-persistent)");
+    }
+
     #[tokio::test]
     async fn ensure_missing_snapshot_returns_none() {
         let storage_engine = MemoryStorageEngine::new();
-        let retrieved_snapshot = storage_engine.read_snapshot(1, "test").await.unwrap();
+        let retrieved_snapshot = storage_engine.read_snapshot(numbered_id(1), "test").await.unwrap();
         assert!(retrieved_snapshot.is_none());
     }
 
+    #[tokio::test]
+    async fn ensure_lists_aggregate_instances_by_type() {
+        let storage_engine = MemoryStorageEngine::new();
+        storage_engine.create_aggregate_instance("user", Some("alice")).await.unwrap();
+        storage_engine.create_aggregate_instance("order", None).await.unwrap();
+        storage_engine.create_aggregate_instance("user", None).await.unwrap();
+        storage_engine.create_aggregate_instance("user", Some("carol")).await.unwrap();
+
+        let users = storage_engine.list_aggregate_instances("user", 0, 10).await.unwrap();
+        assert_eq!(users.iter().map(|instance| instance.id).collect::<Vec<_>>(), vec![numbered_id(1), numbered_id(3), numbered_id(4)]);
+        assert_eq!(users[0].natural_key.as_deref(), Some("alice"));
+
+        let page = storage_engine.list_aggregate_instances("user", 1, 1).await.unwrap();
+        assert_eq!(page, vec![AggregateInstance { id: numbered_id(3), aggregate_type: "user".to_string(), natural_key: None, deleted_at: None }]);
+        assert!(storage_engine.list_aggregate_instances("invoice", 0, 10).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn ensure_aggregate_type_can_be_migrated() {
+        let storage_engine = MemoryStorageEngine::new();
+        let event_data = "data".to_string();
+        let id = storage_engine.create_aggregate_instance("purchase", None).await.unwrap();
+        let events = vec![
+            Event::new(id, "purchase", 1, "created", &event_data).unwrap(),
+            Event::new(id, "purchase", 2, "paid", &event_data).unwrap(),
+        ];
+        let snapshot = Snapshot::new(id, "purchase", 2, &event_data).unwrap();
+        storage_engine.write_updates(&events, &[snapshot]).await.unwrap();
+        let key = LookupKey {
+            aggregate_id: id,
+            aggregate_type: "purchase".to_string(),
+            key_name: "customer".to_string(),
+            key_value: "c-1".to_string(),
+        };
+        storage_engine.add_lookup_key(&key).await.unwrap();
+
+        let report = storage_engine.migrate_aggregate_type("purchase", "order").await.unwrap();
+        assert_eq!(report, MigrationReport { aggregate_instances: 1, events: 2, snapshots: 1, lookup_keys: 1 });
+
+        assert!(storage_engine.read_events(id, "purchase", 0).await.unwrap().is_empty());
+        assert_eq!(storage_engine.read_events(id, "order", 0).await.unwrap().len(), 2);
+        assert_eq!(storage_engine.read_snapshot(id, "order").await.unwrap().unwrap().version, 2);
+        assert_eq!(storage_engine.find_by_lookup_key("order", "customer", "c-1").await.unwrap(), vec![id]);
+        assert_eq!(storage_engine.list_aggregate_instances("order", 0, 10).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn ensure_batch_reads_latest_snapshots() {
+        let storage_engine = MemoryStorageEngine::new();
+        let data = "data".to_string();
+        let snapshots = vec![
+            Snapshot::new(numbered_id(1), "test", 1, &data).unwrap(),
+            Snapshot::new(numbered_id(2), "test", 1, &data).unwrap(),
+            Snapshot::new(numbered_id(1), "test", 5, &data).unwrap(),
+            Snapshot::new(numbered_id(3), "other", 2, &data).unwrap(),
+        ];
+        storage_engine.write_updates(&[], &snapshots).await.unwrap();
+
+        let latest = storage_engine.batch_read_snapshots(&[(numbered_id(1), "test"), (numbered_id(3), "test"), (numbered_id(4), "test")]).await.unwrap();
+        assert_eq!(latest.len(), 1);
+        assert_eq!(latest[&numbered_id(1)].version, 5);
+    }
+
+    #[cfg(feature = "context")]
+    #[tokio::test]
+    async fn ensure_tampered_events_fail_verification() {
+        let storage_engine = MemoryStorageEngine::new();
+        let event_store = crate::EventStore::builder(storage_engine.clone())
+            .hash_events(true)
+            .verify_hashes(true)
+            .build()
+            .unwrap();
+        let user = UserCreate { name: "test".to_string(), email: "test@example.com".to_string() };
+        for version in 1..=3 {
+            let event = Event::new(numbered_id(1), "user", version, "updated", &user).unwrap();
+            event_store.write_updates(&[event], &[]).await.unwrap();
+        }
+
+        let events = event_store.get_events(numbered_id(1), "user", 0).await.unwrap();
+        assert!(events.iter().all(|event| event.hash.is_some()));
+        assert_eq!(event_store.get_events(numbered_id(1), "user", 1).await.unwrap().len(), 2);
+
+        storage_engine.memory_store.lock().unwrap().events[1].data = r#"{"name":"mallory"}"#.into();
+        let result = event_store.get_events(numbered_id(1), "user", 0).await;
+        assert!(matches!(result, Err(EventStoreError::IntegrityViolation { version: 2 })));
+        let result = event_store.get_events(numbered_id(1), "user", 1).await;
+        assert!(matches!(result, Err(EventStoreError::IntegrityViolation { version: 2 })));
+    }
+
+    #[tokio::test]
+    async fn ensure_retention_keeps_newest_snapshots() {
+        let storage_engine = MemoryStorageEngine::new();
+        let data = "data".to_string();
+        let snapshots = vec![
+            Snapshot::new(numbered_id(1), "test", 1, &data).unwrap(),
+            Snapshot::new(numbered_id(1), "test", 5, &data).unwrap(),
+            Snapshot::new(numbered_id(1), "test", 3, &data).unwrap(),
+            Snapshot::new(numbered_id(2), "test", 1, &data).unwrap(),
+        ];
+        storage_engine.write_updates(&[], &snapshots).await.unwrap();
+
+        assert_eq!(storage_engine.prune_snapshots(2).await.unwrap(), 1);
+        let versions: Vec<i64> = storage_engine.read_snapshots(numbered_id(1), "test", 10).await.unwrap().iter().map(|info| info.version).collect();
+        assert_eq!(versions, vec![5, 3]);
+        assert_eq!(storage_engine.read_snapshots(numbered_id(2), "test", 10).await.unwrap().len(), 1);
+    }
 }
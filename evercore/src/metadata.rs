@@ -0,0 +1,45 @@
+use std::{collections::{BTreeMap, HashMap}, sync::Arc};
+use serde_json::{Map, Value};
+use crate::{event::Event, EventStoreError};
+
+/// Supplies metadata for every event published through the store's contexts, such as the
+/// id of the request being served. Metadata added to the context wins on the same key.
+pub type MetadataProvider = Arc<dyn Fn() -> HashMap<String, String> + Send + Sync>;
+
+/// The required keys absent from `metadata`, in the order they are required.
+pub(crate) fn missing_keys<V>(metadata: &HashMap<String, V>, required: &[String]) -> Vec<String> {
+    required.iter().filter(|key| !metadata.contains_key(*key)).cloned().collect()
+}
+
+/// Stored events lacking required metadata, found by `EventStore::audit_required_metadata`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MetadataAudit {
+    pub events_scanned: u64,
+    /// Events missing at least one required key, by event type.
+    pub missing_by_event_type: BTreeMap<String, u64>,
+    /// Events missing each required key.
+    pub missing_by_key: BTreeMap<String, u64>,
+}
+
+impl MetadataAudit {
+    /// Whether every scanned event carries every required key.
+    pub fn is_clean(&self) -> bool {
+        self.missing_by_event_type.is_empty()
+    }
+
+    pub(crate) fn record(&mut self, event: &Event, required: &[String]) -> Result<(), EventStoreError> {
+        self.events_scanned += 1;
+        let metadata: HashMap<String, Value> = event.deserialize_metadata::<Map<String, Value>>()?
+            .map(|metadata| metadata.into_iter().collect())
+            .unwrap_or_default();
+        let missing = missing_keys(&metadata, required);
+        if missing.is_empty() {
+            return Ok(());
+        }
+        *self.missing_by_event_type.entry(event.event_type.clone()).or_default() += 1;
+        for key in missing {
+            *self.missing_by_key.entry(key).or_default() += 1;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,113 @@
+use std::time::Duration;
+
+/// Number of power of two buckets a `Histogram` keeps; the last one takes every larger value.
+const BUCKET_COUNT: usize = 32;
+
+/// What replaying an aggregate cost, recorded by `EventContext::load`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LoadStats {
+    pub snapshot_used: bool,
+    pub snapshot_version: Option<i64>,
+    pub events_replayed: usize,
+    /// Time spent applying the snapshot and events, which is mostly deserialization.
+    pub elapsed: Duration,
+    /// Payload bytes of the snapshot and events applied.
+    pub bytes: usize,
+}
+
+/// Distribution of recorded values in power of two buckets.
+///
+/// Bucket `i` counts values below `2^i` that did not fit an earlier bucket, so bucket 0
+/// holds zeros, bucket 1 ones, bucket 2 values 2..=3 and so on.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Histogram {
+    buckets: [u64; BUCKET_COUNT],
+    count: u64,
+    sum: u64,
+    max: u64,
+}
+
+impl Default for Histogram {
+    fn default() -> Histogram {
+        Histogram {
+            buckets: [0; BUCKET_COUNT],
+            count: 0,
+            sum: 0,
+            max: 0,
+        }
+    }
+}
+
+impl Histogram {
+    pub fn record(&mut self, value: u64) {
+        let index = (u64::BITS - value.leading_zeros()) as usize;
+        self.buckets[index.min(BUCKET_COUNT - 1)] += 1;
+        self.count += 1;
+        self.sum = self.sum.saturating_add(value);
+        self.max = self.max.max(value);
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn sum(&self) -> u64 {
+        self.sum
+    }
+
+    pub fn max(&self) -> u64 {
+        self.max
+    }
+
+    pub fn mean(&self) -> Option<f64> {
+        (self.count > 0).then(|| self.sum as f64 / self.count as f64)
+    }
+
+    /// Count per bucket, see the type docs for the bucket bounds.
+    pub fn buckets(&self) -> &[u64] {
+        &self.buckets
+    }
+}
+
+/// Load costs of one aggregate type, accumulated by the store.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct LoadMetrics {
+    pub loads: u64,
+    pub snapshot_loads: u64,
+    pub events_replayed: Histogram,
+    pub elapsed_micros: Histogram,
+    pub bytes: Histogram,
+}
+
+impl LoadMetrics {
+    pub(crate) fn record(&mut self, stats: &LoadStats) {
+        self.loads += 1;
+        if stats.snapshot_used {
+            self.snapshot_loads += 1;
+        }
+        self.events_replayed.record(stats.events_replayed as u64);
+        self.elapsed_micros.record(u64::try_from(stats.elapsed.as_micros()).unwrap_or(u64::MAX));
+        self.bytes.record(stats.bytes as u64);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ensure_histogram_buckets_by_power_of_two() {
+        let mut histogram = Histogram::default();
+        for value in [0, 1, 2, 3, 4, 1000] {
+            histogram.record(value);
+        }
+        histogram.record(u64::MAX);
+
+        assert_eq!(&histogram.buckets()[..4], &[1, 1, 2, 1]);
+        assert_eq!(histogram.buckets()[10], 1);
+        assert_eq!(histogram.buckets()[BUCKET_COUNT - 1], 1);
+        assert_eq!(histogram.count(), 7);
+        assert_eq!(histogram.max(), u64::MAX);
+        assert_eq!(histogram.sum(), u64::MAX);
+    }
+}
@@ -0,0 +1,60 @@
+use std::sync::Arc;
+use tokio::sync::broadcast;
+use uuid::Uuid;
+use crate::event::Event;
+
+/// Number of notifications a slow subscriber may fall behind before it starts missing
+/// them (see `tokio::sync::broadcast`).
+pub(crate) const NOTIFICATION_CAPACITY: usize = 1024;
+
+/// One successful commit, broadcast by `EventStore::watch_commits`, so subscribers see
+/// what a request wrote together instead of reassembling it from single events.
+#[derive(Clone, Debug)]
+pub struct CommitNotification {
+    /// The committed contexts: one, or several for `EventStore::commit_all`.
+    pub context_ids: Vec<Uuid>,
+    /// The commit receipt's events in publish order, shared between subscribers.
+    pub events: Arc<Vec<Event>>,
+}
+
+/// Broadcasts committed events, one at a time and grouped per commit. Both are sent from
+/// the same receipt after the write; subscribing to either never writes anything again.
+#[derive(Clone)]
+pub(crate) struct Notifier {
+    events: broadcast::Sender<Event>,
+    commits: broadcast::Sender<CommitNotification>,
+}
+
+impl Notifier {
+    pub fn new() -> Notifier {
+        Notifier {
+            events: broadcast::channel(NOTIFICATION_CAPACITY).0,
+            commits: broadcast::channel(NOTIFICATION_CAPACITY).0,
+        }
+    }
+
+    pub fn watch_events(&self) -> broadcast::Receiver<Event> {
+        self.events.subscribe()
+    }
+
+    pub fn watch_commits(&self) -> broadcast::Receiver<CommitNotification> {
+        self.commits.subscribe()
+    }
+
+    /// Announces a commit. Commits that wrote no events are not announced, and nothing is
+    /// copied for a granularity nobody subscribes to.
+    pub fn notify(&self, context_ids: Vec<Uuid>, events: &[Event]) {
+        if events.is_empty() {
+            return;
+        }
+        // Sending only fails when nobody is subscribed, which is fine.
+        if self.events.receiver_count() > 0 {
+            for event in events {
+                let _ = self.events.send(event.clone());
+            }
+        }
+        if self.commits.receiver_count() > 0 {
+            let _ = self.commits.send(CommitNotification { context_ids, events: Arc::new(events.to_vec()) });
+        }
+    }
+}
@@ -0,0 +1,244 @@
+use std::collections::HashMap;
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+use crate::aggregate::{Aggregate, CanRequest, Composable, ComposedAggregate};
+use crate::{deadline, EventStoreError, SharedEventContext, SharedEventStore};
+
+/// Key of a queued command naming the command registered with `CommandRegistry::command`.
+pub const COMMAND_KEY: &str = "command";
+/// Key of a queued command holding the command itself.
+pub const PAYLOAD_KEY: &str = "payload";
+
+/// How often `EventStore::apply_commands_offline` runs the queue when other writers
+/// keep moving the aggregate's head, unless the current deadline passes first.
+const OFFLINE_ATTEMPTS: usize = 3;
+
+type CommandHandler<T> = Box<dyn Fn(&mut ComposedAggregate<T>, Value) -> Result<(), EventStoreError> + Send + Sync>;
+
+/// Routes commands queued as JSON to an aggregate's `CanRequest` implementations by name.
+pub struct CommandRegistry<T>
+where
+    T: DeserializeOwned + Default + Serialize + Composable
+{
+    handlers: HashMap<String, CommandHandler<T>>,
+}
+
+impl<T> CommandRegistry<T>
+where
+    T: 'static + DeserializeOwned + Default + Serialize + Composable + Clone
+{
+    pub fn new() -> CommandRegistry<T> {
+        CommandRegistry {
+            handlers: HashMap::new(),
+        }
+    }
+
+    /// Registers `TCommand` under `name`, the value of `COMMAND_KEY` in queued commands.
+    pub fn command<TCommand, TEvent>(mut self, name: &str) -> CommandRegistry<T>
+    where
+        TCommand: 'static + Serialize + DeserializeOwned,
+        TEvent: 'static + Serialize + DeserializeOwned,
+        T: CanRequest<TCommand, TEvent>
+    {
+        let command_name = name.to_string();
+        self.handlers.insert(name.to_string(), Box::new(move |aggregate, payload| {
+            let command: TCommand = serde_json::from_value(payload)
+                .map_err(|e| EventStoreError::RequestProcessingError(format!("invalid payload for '{}': {}", command_name, e)))?;
+            aggregate.request::<TCommand, TEvent>(command)
+        }));
+        self
+    }
+
+    /// Requests a queued command of the form `{"command": name, "payload": command}`.
+    fn dispatch(&self, aggregate: &mut ComposedAggregate<T>, queued: &Value) -> Result<(), EventStoreError> {
+        let name = queued.get(COMMAND_KEY).and_then(Value::as_str)
+            .ok_or_else(|| EventStoreError::RequestProcessingError(format!("queued command has no '{}'", COMMAND_KEY)))?;
+        let handler = self.handlers.get(name)
+            .ok_or_else(|| EventStoreError::RequestProcessingError(format!("unknown command '{}'", name)))?;
+        handler(aggregate, queued.get(PAYLOAD_KEY).cloned().unwrap_or(Value::Null))
+    }
+}
+
+impl<T> Default for CommandRegistry<T>
+where
+    T: 'static + DeserializeOwned + Default + Serialize + Composable + Clone
+{
+    fn default() -> CommandRegistry<T> {
+        CommandRegistry::new()
+    }
+}
+
+/// What became of one queued command.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CommandOutcome {
+    /// The command's event was committed at `version`.
+    Applied { version: i64 },
+    /// The command was refused and nothing was written for it.
+    Rejected { reason: String },
+}
+
+impl CommandOutcome {
+    pub fn is_applied(&self) -> bool {
+        matches!(self, CommandOutcome::Applied { .. })
+    }
+}
+
+pub(crate) async fn apply_commands<T>(
+    event_store: &SharedEventStore,
+    aggregate_type: &str,
+    natural_key: &str,
+    commands: &[Value],
+    registry: &CommandRegistry<T>,
+) -> Result<Vec<CommandOutcome>, EventStoreError>
+where
+    T: 'static + DeserializeOwned + Default + Serialize + Composable + Clone
+{
+    if T::default().get_type() != aggregate_type {
+        return Err(EventStoreError::InvalidArgument {
+            parameter: "aggregate_type".to_string(),
+            value: aggregate_type.to_string(),
+            reason: format!("the registry handles '{}'", T::default().get_type()),
+        });
+    }
+
+    let mut attempt = 1;
+    loop {
+        let context = event_store.get_context();
+        let outcomes = request_all(&context, natural_key, commands, registry).await?;
+        let committed = context.commit().await;
+        match committed {
+            Err(error) if attempt < OFFLINE_ATTEMPTS && deadline::check().is_ok() && matches!(error.root_cause(), EventStoreError::VersionConflict { .. }) => {
+                tracing::debug!(attempt, "replaying offline commands after a version conflict");
+                attempt += 1;
+            }
+            Err(error) => return Err(error),
+            Ok(_) => return Ok(outcomes),
+        }
+    }
+}
+
+/// Requests every command on the aggregate at its current head, or on a new instance when
+/// there is none with the natural key.
+async fn request_all<T>(
+    context: &SharedEventContext,
+    natural_key: &str,
+    commands: &[Value],
+    registry: &CommandRegistry<T>,
+) -> Result<Vec<CommandOutcome>, EventStoreError>
+where
+    T: 'static + DeserializeOwned + Default + Serialize + Composable + Clone
+{
+    let aggregate_type = T::default().get_type().to_string();
+    let existing = context.get_aggregate_instance_id(&aggregate_type, natural_key).await?;
+    let mut aggregate = match existing {
+        Some(id) => ComposedAggregate::<T>::load(context, id).await?,
+        None => ComposedAggregate::<T>::new(context, Some(natural_key)).await?,
+    };
+
+    let mut outcomes = Vec::with_capacity(commands.len());
+    for command in commands {
+        let outcome = match registry.dispatch(&mut aggregate, command) {
+            Ok(()) => CommandOutcome::Applied { version: aggregate.version() },
+            // Debug formatted, as the display of most errors leaves out their details.
+            Err(error) => CommandOutcome::Rejected { reason: format!("{:?}", error) },
+        };
+        outcomes.push(outcome);
+    }
+    Ok(outcomes)
+}
+
+#[cfg(all(test, feature = "memory"))]
+mod tests {
+    use serde::{Serialize, Deserialize};
+    use serde_json::json;
+    use crate::{EventStore, event::Event, memory::MemoryStorageEngine};
+    use super::*;
+
+    #[derive(Default, Clone, Serialize, Deserialize)]
+    struct Account {
+        balance: i64,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    struct Deposit {
+        amount: i64,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    struct Withdraw {
+        amount: i64,
+    }
+
+    impl Composable for Account {
+        fn get_type(&self) -> &str {
+            "account"
+        }
+
+        fn apply_event(&mut self, event: &Event) -> Result<(), EventStoreError> {
+            match event.event_type.as_str() {
+                "deposited" => self.balance += event.deserialize::<Deposit>()?.amount,
+                "withdrawn" => self.balance -= event.deserialize::<Withdraw>()?.amount,
+                _ => {}
+            }
+            Ok(())
+        }
+    }
+
+    impl CanRequest<Deposit, Deposit> for Account {
+        fn request(&self, request: Deposit) -> Result<(String, Deposit), EventStoreError> {
+            Ok(("deposited".to_string(), request))
+        }
+    }
+
+    impl CanRequest<Withdraw, Withdraw> for Account {
+        fn request(&self, request: Withdraw) -> Result<(String, Withdraw), EventStoreError> {
+            if request.amount > self.balance {
+                return Err(EventStoreError::RequestProcessingError("insufficient funds".to_string()));
+            }
+            Ok(("withdrawn".to_string(), request))
+        }
+    }
+
+    fn registry() -> CommandRegistry<Account> {
+        CommandRegistry::new()
+            .command::<Deposit, Deposit>("deposit")
+            .command::<Withdraw, Withdraw>("withdraw")
+    }
+
+    #[tokio::test]
+    async fn ensure_rejected_commands_do_not_stop_the_queue() {
+        let event_store = EventStore::new(MemoryStorageEngine::new());
+        let context = event_store.get_context();
+        let mut account = ComposedAggregate::<Account>::new(&context, Some("acc-1")).await.unwrap();
+        account.request(Deposit { amount: 10 }).unwrap();
+        context.commit().await.unwrap();
+
+        // Queued offline before the client saw the first deposit.
+        let queue = vec![
+            json!({ "command": "deposit", "payload": { "amount": 5 } }),
+            json!({ "command": "withdraw", "payload": { "amount": 100 } }),
+            json!({ "command": "close", "payload": {} }),
+            json!({ "command": "withdraw", "payload": { "amount": 12 } }),
+        ];
+        let outcomes = event_store.apply_commands_offline("account", "acc-1", queue, &registry()).await.unwrap();
+
+        assert_eq!(outcomes[0], CommandOutcome::Applied { version: 2 });
+        assert!(matches!(&outcomes[1], CommandOutcome::Rejected { reason } if reason.contains("insufficient funds")));
+        assert!(matches!(&outcomes[2], CommandOutcome::Rejected { reason } if reason.contains("unknown command 'close'")));
+        assert_eq!(outcomes[3], CommandOutcome::Applied { version: 3 });
+        let loaded = ComposedAggregate::<Account>::load(&event_store.get_context(), account.id()).await.unwrap();
+        assert_eq!((loaded.version(), loaded.state().balance), (3, 3));
+    }
+
+    #[tokio::test]
+    async fn ensure_offline_commands_create_missing_aggregates() {
+        let event_store = EventStore::new(MemoryStorageEngine::new());
+        let queue = vec![json!({ "command": "deposit", "payload": { "amount": "ten" } }), json!({ "command": "deposit", "payload": { "amount": 7 } })];
+        let outcomes = event_store.apply_commands_offline("account", "acc-2", queue, &registry()).await.unwrap();
+        assert!(!outcomes[0].is_applied());
+        assert_eq!(outcomes[1], CommandOutcome::Applied { version: 1 });
+
+        let result = event_store.apply_commands_offline("wallet", "acc-2", Vec::new(), &registry()).await;
+        assert!(matches!(result, Err(EventStoreError::InvalidArgument { .. })));
+    }
+}
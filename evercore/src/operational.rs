@@ -0,0 +1,53 @@
+use crate::{retention::RetentionReport, AggregateId, MigrationReport, PurgeReport, RewriteReport};
+
+/// Number of operational events a slow subscriber may fall behind before it starts
+/// missing them (see `tokio::sync::broadcast`).
+pub(crate) const OPERATIONAL_EVENT_CAPACITY: usize = 64;
+
+/// Store-level happenings, as opposed to domain events, for logging and alerting bridges.
+///
+/// Operational events are process-local and never persisted. Subscribe with
+/// `EventStore::operational_events`; events emitted while nobody listens are dropped.
+#[derive(Clone, Debug, PartialEq)]
+pub enum OperationalEvent {
+    /// An aggregate type was renamed by `EventStore::migrate_aggregate_type`.
+    AggregateTypeMigrated {
+        old_type: String,
+        new_type: String,
+        report: MigrationReport,
+    },
+    /// An aggregate and all of its data were deleted by `EventStore::purge_aggregate`.
+    AggregatePurged {
+        aggregate_type: String,
+        aggregate_id: AggregateId,
+        report: PurgeReport,
+    },
+    /// The events of an aggregate were replaced by `EventStore::rewrite_stream`.
+    StreamRewritten {
+        aggregate_type: String,
+        aggregate_id: AggregateId,
+        report: RewriteReport,
+    },
+    /// `EventStore::apply_retention` finished.
+    RetentionCompleted(RetentionReport),
+    /// An inline projection was rebuilt from the global feed and registered.
+    InlineProjectionRegistered {
+        name: String,
+        events_replayed: usize,
+    },
+    /// A job of a `MaintenanceScheduler` ran, failing with `error` if set.
+    MaintenanceJobCompleted {
+        job: String,
+        elapsed: std::time::Duration,
+        error: Option<String>,
+    },
+    /// A `ProjectionRunner` verifying hashes set aside an event not matching its hash
+    /// instead of handing it to the projection.
+    EventQuarantined {
+        projection: String,
+        aggregate_type: String,
+        aggregate_id: AggregateId,
+        version: i64,
+        position: Option<i64>,
+    },
+}
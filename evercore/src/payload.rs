@@ -0,0 +1,202 @@
+use std::fmt;
+use crate::EventStoreError;
+
+/// The serialized data of an event or snapshot.
+///
+/// Events and snapshots are created holding JSON. A store built with a binary
+/// `PayloadSerializer` converts their payloads to bytes as it writes them and back to JSON
+/// as it reads them, so aggregates and projections only ever see JSON.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Payload {
+    /// JSON text.
+    Json(String),
+    /// Bytes written by a binary `PayloadSerializer`, as read from storage.
+    Binary(Vec<u8>),
+}
+
+impl Payload {
+    /// Size of the payload in bytes.
+    pub fn len(&self) -> usize {
+        self.as_bytes().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The payload as stored: the UTF-8 of JSON, or the bytes of a binary format.
+    pub fn as_bytes(&self) -> &[u8] {
+        match self {
+            Payload::Json(json) => json.as_bytes(),
+            Payload::Binary(bytes) => bytes,
+        }
+    }
+
+    /// The JSON text, or None for a binary payload.
+    pub fn as_json(&self) -> Option<&str> {
+        match self {
+            Payload::Json(json) => Some(json),
+            Payload::Binary(_) => None,
+        }
+    }
+
+    /// The JSON text, failing with `PayloadSerializerError` for a binary payload, which
+    /// has to be read through the store that wrote it to be decoded.
+    pub fn json(&self) -> Result<&str, EventStoreError> {
+        self.as_json().ok_or_else(|| EventStoreError::PayloadSerializerError(
+            format!("binary payload of {} bytes read without decoding it", self.len())))
+    }
+
+    pub fn is_binary(&self) -> bool {
+        matches!(self, Payload::Binary(_))
+    }
+}
+
+impl From<String> for Payload {
+    fn from(json: String) -> Payload {
+        Payload::Json(json)
+    }
+}
+
+impl From<&str> for Payload {
+    fn from(json: &str) -> Payload {
+        Payload::Json(json.to_string())
+    }
+}
+
+impl From<Vec<u8>> for Payload {
+    fn from(bytes: Vec<u8>) -> Payload {
+        Payload::Binary(bytes)
+    }
+}
+
+impl PartialEq<str> for Payload {
+    fn eq(&self, other: &str) -> bool {
+        self.as_json() == Some(other)
+    }
+}
+
+impl PartialEq<&str> for Payload {
+    fn eq(&self, other: &&str) -> bool {
+        self.as_json() == Some(*other)
+    }
+}
+
+impl PartialEq<String> for Payload {
+    fn eq(&self, other: &String) -> bool {
+        self.as_json() == Some(other.as_str())
+    }
+}
+
+/// The JSON text, or the size of a binary payload.
+impl fmt::Display for Payload {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Payload::Json(json) => f.write_str(json),
+            Payload::Binary(bytes) => write!(f, "<{} bytes binary>", bytes.len()),
+        }
+    }
+}
+
+/// Converts event and snapshot payloads between JSON and the format they are stored in,
+/// set with `EventStoreBuilder::payload_serializer`. Payloads are converted after
+/// encryption and before hashing, so hashes cover the stored bytes.
+///
+/// Binary serializers need an engine with `EngineCapabilities::BINARY_PAYLOADS`. A store
+/// reads back the payloads it wrote, so changing the serializer of a store with data needs
+/// the data migrated, except that payloads stored as JSON are always read as they are.
+pub trait PayloadSerializer: Send + Sync {
+    /// Short name of the format, e.g. "json".
+    fn name(&self) -> &'static str;
+
+    /// Whether `encode` produces `Payload::Binary`.
+    fn is_binary(&self) -> bool;
+
+    /// Converts JSON text to the stored format.
+    fn encode(&self, json: &str) -> Result<Payload, EventStoreError>;
+
+    /// Converts bytes read from storage back to JSON text.
+    fn decode(&self, bytes: &[u8]) -> Result<String, EventStoreError>;
+}
+
+fn serializer_error(serializer: &dyn PayloadSerializer, error: impl fmt::Display) -> EventStoreError {
+    EventStoreError::PayloadSerializerError(format!("{}: {}", serializer.name(), error))
+}
+
+/// Stores payloads as JSON text, the default.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct JsonSerializer;
+
+impl PayloadSerializer for JsonSerializer {
+    fn name(&self) -> &'static str {
+        "json"
+    }
+
+    fn is_binary(&self) -> bool {
+        false
+    }
+
+    fn encode(&self, json: &str) -> Result<Payload, EventStoreError> {
+        Ok(Payload::Json(json.to_string()))
+    }
+
+    /// Engines with binary data columns return JSON as its UTF-8 bytes.
+    fn decode(&self, bytes: &[u8]) -> Result<String, EventStoreError> {
+        String::from_utf8(bytes.to_vec()).map_err(|e| serializer_error(self, e))
+    }
+}
+
+/// Stores payloads as MessagePack, which is smaller than JSON and faster to parse. Object
+/// keys read back in sorted order.
+#[cfg(feature = "msgpack")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct MessagePackSerializer;
+
+#[cfg(feature = "msgpack")]
+impl PayloadSerializer for MessagePackSerializer {
+    fn name(&self) -> &'static str {
+        "msgpack"
+    }
+
+    fn is_binary(&self) -> bool {
+        true
+    }
+
+    fn encode(&self, json: &str) -> Result<Payload, EventStoreError> {
+        let value: serde_json::Value = serde_json::from_str(json).map_err(|e| serializer_error(self, e))?;
+        rmp_serde::to_vec(&value).map(Payload::Binary).map_err(|e| serializer_error(self, e))
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Result<String, EventStoreError> {
+        let value: serde_json::Value = rmp_serde::from_slice(bytes).map_err(|e| serializer_error(self, e))?;
+        serde_json::to_string(&value).map_err(|e| serializer_error(self, e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{JsonSerializer, Payload, PayloadSerializer};
+
+    #[test]
+    fn ensure_json_serializer_keeps_text() {
+        let json = r#"{"value":1,"name":"test"}"#;
+        let payload = JsonSerializer.encode(json).unwrap();
+
+        assert_eq!(payload, json);
+        assert_eq!(JsonSerializer.decode(payload.as_bytes()).unwrap(), json);
+        assert!(Payload::Binary(vec![0xc0]).json().is_err());
+    }
+
+    #[cfg(feature = "msgpack")]
+    #[test]
+    fn ensure_msgpack_serializer_round_trips() {
+        use super::MessagePackSerializer;
+
+        let json = r#"{"name":"test","tags":["a","b"],"value":-1.5}"#;
+        let payload = MessagePackSerializer.encode(json).unwrap();
+
+        assert!(payload.is_binary());
+        assert!(payload.len() < json.len());
+        assert_eq!(MessagePackSerializer.decode(payload.as_bytes()).unwrap(), json);
+    }
+}
@@ -0,0 +1,336 @@
+use std::collections::HashMap;
+use std::time::Duration;
+use async_trait::async_trait;
+use tokio::sync::watch;
+use crate::{AggregateId, clock::StreamKey, event::Event, snapshot::Snapshot, EventStoreError, SharedEventStore, store::FEED_PAGE_SIZE};
+use crate::{integrity::FeedVerifier, operational::OperationalEvent};
+
+/// A read model built from the global feed by a `ProjectionRunner`.
+#[async_trait]
+pub trait Projection: Send {
+    /// Identifies the projection's checkpoint in the storage engine. Keep it stable; a new
+    /// name starts the projection over from the beginning of the feed.
+    fn name(&self) -> &str;
+
+    /// Aggregate types the projection consumes; events of other types are skipped.
+    fn aggregate_types(&self) -> Vec<String>;
+
+    async fn handle(&mut self, event: &Event) -> Result<(), EventStoreError>;
+
+    /// Seeds the projection from an aggregate's latest snapshot. Return false if the
+    /// projection cannot use snapshots, in which case the aggregate's events are replayed.
+    fn apply_snapshot(&mut self, aggregate_type: &str, aggregate_id: AggregateId, snapshot: &Snapshot) -> Result<bool, EventStoreError> {
+        let _ = (aggregate_type, aggregate_id, snapshot);
+        Ok(false)
+    }
+}
+
+/// Feeds the global feed to a projection, recording how far it got as the projection's
+/// checkpoint in the storage engine.
+///
+/// The first `catch_up` resumes from the stored checkpoint, so a new runner picks up where
+/// the previous one stopped. The checkpoint is written after each page of events, which
+/// makes delivery at least once: events handled after the last checkpoint before a crash
+/// are handled again.
+pub struct ProjectionRunner {
+    event_store: SharedEventStore,
+    position: i64,
+    /// Whether the stored checkpoint was read, or is to be ignored after a bootstrap.
+    resumed: bool,
+    /// Versions already covered by a bootstrap snapshot, per aggregate.
+    floors: HashMap<StreamKey, i64>,
+    /// Set when events are checked against their hashes before being handled.
+    verifier: Option<FeedVerifier>,
+    quarantined: Vec<Event>,
+}
+
+impl ProjectionRunner {
+    pub fn new(event_store: SharedEventStore) -> ProjectionRunner {
+        ProjectionRunner {
+            event_store,
+            position: 0,
+            resumed: false,
+            floors: HashMap::new(),
+            verifier: None,
+            quarantined: Vec::new(),
+        }
+    }
+
+    /// Checks each event against its stored hash before handling it. An event that does
+    /// not match, such as a row changed by hand, is quarantined instead: the projection
+    /// never sees it, `quarantined` lists it and `OperationalEvent::EventQuarantined` is
+    /// emitted. Events stored without a hash are handled as usual.
+    pub fn verify_hashes(mut self, verify: bool) -> ProjectionRunner {
+        self.verifier = verify.then(FeedVerifier::default);
+        self
+    }
+
+    /// Events set aside by hash verification, as stored, in feed order.
+    pub fn quarantined(&self) -> &[Event] {
+        &self.quarantined
+    }
+
+    /// Global feed position of the last event handled.
+    pub fn position(&self) -> i64 {
+        self.position
+    }
+
+    /// Seeds the projection from the latest snapshot of every instance of its aggregate types,
+    /// instead of replaying their history. The following `catch_up` calls skip events the
+    /// snapshots already cover, starting from the beginning of the feed rather than the
+    /// stored checkpoint. Returns the number of snapshots applied.
+    pub async fn bootstrap_from_snapshots<P: Projection>(&mut self, projection: &mut P) -> Result<usize, EventStoreError> {
+        self.resumed = true;
+        let storage_engine = &self.event_store.storage_engine;
+        let mut applied = 0;
+        for aggregate_type in projection.aggregate_types() {
+            let mut offset = 0;
+            loop {
+                let instances = storage_engine.list_aggregate_instances(&aggregate_type, offset, FEED_PAGE_SIZE).await?;
+                if instances.is_empty() {
+                    break;
+                }
+                offset += instances.len();
+
+                let requests: Vec<(AggregateId, &str)> = instances.iter()
+                    .map(|instance| (instance.id, aggregate_type.as_str()))
+                    .collect();
+                let mut snapshots = storage_engine.batch_read_snapshots(&requests).await?;
+                for instance in &instances {
+                    let Some(snapshot) = self.event_store.decoded_snapshot(snapshots.remove(&instance.id)).await? else {
+                        continue;
+                    };
+                    if projection.apply_snapshot(&aggregate_type, instance.id, &snapshot)? {
+                        self.floors.insert((aggregate_type.clone(), instance.id), snapshot.version);
+                        applied += 1;
+                    }
+                }
+            }
+        }
+        Ok(applied)
+    }
+
+    /// Hands every event after the current position to the projection, then returns how many
+    /// were handled. Call repeatedly to follow the live feed, or use `run`.
+    pub async fn catch_up<P: Projection>(&mut self, projection: &mut P) -> Result<usize, EventStoreError> {
+        if !self.resumed {
+            let checkpoint = self.event_store.storage_engine.read_checkpoint(projection.name()).await?;
+            self.position = self.position.max(checkpoint.unwrap_or(0));
+            self.resumed = true;
+        }
+
+        let aggregate_types = projection.aggregate_types();
+        let mut applied = 0;
+        loop {
+            let events = self.event_store.storage_engine.read_all_events(self.position, FEED_PAGE_SIZE).await?;
+            let Some(last) = events.last() else {
+                break;
+            };
+            let next_position = last.position.unwrap_or(self.position + events.len() as i64);
+            // Hashes cover the events as stored, so they are checked before decryption.
+            let events = match &mut self.verifier {
+                Some(verifier) => {
+                    let mut verified = Vec::with_capacity(events.len());
+                    for event in events {
+                        if !aggregate_types.contains(&event.aggregate_type) || verifier.verify(&self.event_store, &event).await? {
+                            verified.push(event);
+                            continue;
+                        }
+                        self.event_store.emit(OperationalEvent::EventQuarantined {
+                            projection: projection.name().to_string(),
+                            aggregate_type: event.aggregate_type.clone(),
+                            aggregate_id: event.aggregate_id,
+                            version: event.version,
+                            position: event.position,
+                        });
+                        self.quarantined.push(event);
+                    }
+                    verified
+                }
+                None => events,
+            };
+            let events = self.event_store.decoded(events).await?;
+
+            for event in &events {
+                if !aggregate_types.contains(&event.aggregate_type) {
+                    continue;
+                }
+                let key = (event.aggregate_type.clone(), event.aggregate_id);
+                if let Some(floor) = self.floors.get(&key) {
+                    if event.version <= *floor {
+                        continue;
+                    }
+                    // Past the snapshot; the feed alone decides from here on.
+                    self.floors.remove(&key);
+                }
+                projection.handle(event).await?;
+                applied += 1;
+            }
+            self.event_store.storage_engine.write_checkpoint(projection.name(), next_position).await?;
+            self.position = next_position;
+        }
+        Ok(applied)
+    }
+
+    /// Catches up every `poll_interval` until `shutdown` is signalled or its sender dropped,
+    /// then catches up once more to drain what was committed before. Stops at the first error.
+    pub async fn run<P: Projection>(&mut self, projection: &mut P, poll_interval: Duration, mut shutdown: watch::Receiver<bool>) -> Result<(), EventStoreError> {
+        loop {
+            self.catch_up(projection).await?;
+            tokio::select! {
+                _ = tokio::time::sleep(poll_interval) => {},
+                _ = shutdown.changed() => break,
+            }
+        }
+        self.catch_up(projection).await?;
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "memory"))]
+mod tests {
+    use std::collections::HashMap;
+    use serde::{Serialize, Deserialize};
+    use crate::{EventStore, memory::MemoryStorageEngine, snapshot::SnapshotPolicy};
+    use crate::aggregate::{CanRequest, Composable, ComposedAggregate};
+    use super::*;
+    use crate::event::{AggregateId, numbered_id};
+
+    #[derive(Default, Clone, Serialize, Deserialize)]
+    struct Wallet {
+        balance: i64,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    struct Deposit {
+        amount: i64,
+    }
+
+    impl Composable for Wallet {
+        fn get_type(&self) -> &str {
+            "wallet"
+        }
+
+        fn apply_event(&mut self, event: &Event) -> Result<(), EventStoreError> {
+            let deposit: Deposit = event.deserialize()?;
+            self.balance += deposit.amount;
+            Ok(())
+        }
+    }
+
+    impl CanRequest<Deposit, Deposit> for Wallet {
+        fn request(&self, request: Deposit) -> Result<(String, Deposit), EventStoreError> {
+            Ok(("deposited".to_string(), request))
+        }
+    }
+
+    #[derive(Default, PartialEq, Debug)]
+    struct Balances {
+        name: String,
+        balances: HashMap<AggregateId, i64>,
+        use_snapshots: bool,
+        events_applied: usize,
+    }
+
+    #[async_trait]
+    impl Projection for Balances {
+        fn name(&self) -> &str {
+            &self.name
+        }
+
+        fn aggregate_types(&self) -> Vec<String> {
+            vec!["wallet".to_string()]
+        }
+
+        async fn handle(&mut self, event: &Event) -> Result<(), EventStoreError> {
+            let deposit: Deposit = event.deserialize()?;
+            *self.balances.entry(event.aggregate_id).or_default() += deposit.amount;
+            self.events_applied += 1;
+            Ok(())
+        }
+
+        fn apply_snapshot(&mut self, _aggregate_type: &str, aggregate_id: AggregateId, snapshot: &Snapshot) -> Result<bool, EventStoreError> {
+            if !self.use_snapshots {
+                return Ok(false);
+            }
+            let wallet: Wallet = snapshot.to_state()?;
+            self.balances.insert(aggregate_id, wallet.balance);
+            Ok(true)
+        }
+    }
+
+    async fn deposit(event_store: &SharedEventStore, id: Option<AggregateId>, amounts: &[i64]) {
+        let context = event_store.get_context();
+        let mut wallet = match id {
+            Some(id) => ComposedAggregate::<Wallet>::load(&context, id).await.unwrap(),
+            None => ComposedAggregate::<Wallet>::new(&context, None).await.unwrap(),
+        };
+        for amount in amounts {
+            wallet.request(Deposit { amount: *amount }).unwrap();
+        }
+        context.commit().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn ensure_bootstrap_matches_full_rebuild() {
+        let event_store = EventStore::builder(MemoryStorageEngine::new())
+            .snapshot_policy("wallet", SnapshotPolicy::EveryNEvents(3))
+            .build()
+            .unwrap();
+        deposit(&event_store, None, &[1, 2, 3, 4, 5, 6, 7]).await;
+        deposit(&event_store, None, &[10, 20]).await;
+        deposit(&event_store, Some(numbered_id(1)), &[100]).await;
+
+        let mut rebuilt = Balances { name: "rebuilt".to_string(), ..Default::default() };
+        let mut rebuild_runner = ProjectionRunner::new(event_store.clone());
+        rebuild_runner.catch_up(&mut rebuilt).await.unwrap();
+
+        let mut bootstrapped = Balances { name: "bootstrapped".to_string(), use_snapshots: true, ..Default::default() };
+        let mut bootstrap_runner = ProjectionRunner::new(event_store.clone());
+        assert_eq!(bootstrap_runner.bootstrap_from_snapshots(&mut bootstrapped).await.unwrap(), 1);
+        bootstrap_runner.catch_up(&mut bootstrapped).await.unwrap();
+
+        assert_eq!(bootstrapped.balances, rebuilt.balances);
+        assert!(bootstrapped.events_applied < rebuilt.events_applied);
+
+        // Both follow the live feed the same way afterwards.
+        deposit(&event_store, Some(numbered_id(2)), &[30]).await;
+        deposit(&event_store, Some(numbered_id(1)), &[1000]).await;
+        assert_eq!(rebuild_runner.catch_up(&mut rebuilt).await.unwrap(), 2);
+        assert_eq!(bootstrap_runner.catch_up(&mut bootstrapped).await.unwrap(), 2);
+        assert_eq!(bootstrapped.balances, rebuilt.balances);
+        assert_eq!(bootstrap_runner.position(), rebuild_runner.position());
+    }
+
+    #[tokio::test]
+    async fn ensure_bootstrap_ignores_stored_checkpoint() {
+        let event_store = EventStore::builder(MemoryStorageEngine::new())
+            .snapshot_policy("wallet", SnapshotPolicy::EveryNEvents(2))
+            .build()
+            .unwrap();
+        deposit(&event_store, None, &[1, 2, 3]).await;
+        let mut balances = Balances { name: "balances".to_string(), ..Default::default() };
+        ProjectionRunner::new(event_store.clone()).catch_up(&mut balances).await.unwrap();
+
+        // A rebuilt read model starts from the snapshots, not where the old one left off.
+        let mut rebuilt = Balances { name: "balances".to_string(), use_snapshots: true, ..Default::default() };
+        let mut runner = ProjectionRunner::new(event_store.clone());
+        runner.bootstrap_from_snapshots(&mut rebuilt).await.unwrap();
+        runner.catch_up(&mut rebuilt).await.unwrap();
+        assert_eq!(rebuilt.balances, balances.balances);
+    }
+
+    #[tokio::test]
+    async fn ensure_run_drains_feed_on_shutdown() {
+        let event_store = EventStore::new(MemoryStorageEngine::new());
+        let (stop, shutdown) = watch::channel(false);
+        deposit(&event_store, None, &[5]).await;
+        stop.send(true).unwrap();
+
+        let mut balances = Balances { name: "balances".to_string(), ..Default::default() };
+        let mut runner = ProjectionRunner::new(event_store.clone());
+        runner.run(&mut balances, Duration::from_secs(60), shutdown).await.unwrap();
+        assert_eq!(balances.balances, HashMap::from([(numbered_id(1), 5)]));
+        assert_eq!(event_store.storage_engine.read_checkpoint("balances").await.unwrap(), Some(1));
+    }
+}
@@ -0,0 +1,74 @@
+use std::{sync::{atomic::{AtomicUsize, Ordering}, Arc}, time::Duration};
+use tokio::sync::{OwnedRwLockReadGuard, OwnedRwLockWriteGuard, RwLock};
+use crate::EventStoreError;
+
+/// What a commit does while the store is quiesced.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum QuiescePolicy {
+    /// Wait until the `QuiesceGuard` is dropped.
+    #[default]
+    Wait,
+    /// Fail at once with `EventStoreError::Quiesced`.
+    FailFast,
+}
+
+/// Lets writes through one at a time as readers of a lock that `EventStore::quiesce`
+/// takes exclusively. The lock is write preferring, so once a quiesce is waiting new
+/// writes queue behind it while the writes already in flight finish.
+pub(crate) struct WriteGate {
+    lock: Arc<RwLock<()>>,
+    active: Arc<AtomicUsize>,
+    policy: QuiescePolicy,
+}
+
+/// Held while a write runs; counts it as active until dropped.
+pub(crate) struct WritePermit {
+    _guard: OwnedRwLockReadGuard<()>,
+    active: Arc<AtomicUsize>,
+}
+
+impl Drop for WritePermit {
+    fn drop(&mut self) {
+        self.active.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Keeps the store's writes paused. Writes resume when the guard is dropped.
+pub struct QuiesceGuard {
+    _guard: OwnedRwLockWriteGuard<()>,
+}
+
+impl WriteGate {
+    pub(crate) fn new(policy: QuiescePolicy) -> WriteGate {
+        WriteGate {
+            lock: Arc::new(RwLock::new(())),
+            active: Arc::new(AtomicUsize::new(0)),
+            policy,
+        }
+    }
+
+    pub(crate) async fn enter(&self) -> Result<WritePermit, EventStoreError> {
+        let guard = match self.policy {
+            QuiescePolicy::Wait => self.lock.clone().read_owned().await,
+            QuiescePolicy::FailFast => self.lock.clone().try_read_owned().map_err(|_| EventStoreError::Quiesced)?,
+        };
+        self.active.fetch_add(1, Ordering::SeqCst);
+        Ok(WritePermit {
+            _guard: guard,
+            active: self.active.clone(),
+        })
+    }
+
+    /// Waits for the writes in flight to finish, failing with `QuiesceTimeout` if they take
+    /// longer than `timeout`. Writes started while waiting are held back either way.
+    pub(crate) async fn quiesce(&self, timeout: Duration) -> Result<QuiesceGuard, EventStoreError> {
+        let guard = tokio::time::timeout(timeout, self.lock.clone().write_owned()).await
+            .map_err(|_| EventStoreError::QuiesceTimeout)?;
+        debug_assert_eq!(self.active.load(Ordering::SeqCst), 0);
+        Ok(QuiesceGuard { _guard: guard })
+    }
+
+    pub(crate) fn active_writes(&self) -> usize {
+        self.active.load(Ordering::SeqCst)
+    }
+}
@@ -0,0 +1,134 @@
+use std::{collections::HashMap, sync::{OnceLock, RwLock}};
+use crate::{EventStoreError, SharedEventContext, SharedEventStore};
+
+/// Name aggregates are bound to unless their state declares otherwise.
+pub const DEFAULT_STORE: &str = "default";
+
+/// Named event stores for applications that talk to more than one store.
+///
+/// Registering a store binds it to its name. Aggregates declare their store through
+/// `Composable::store_name`, and contexts from a registered store refuse to load or
+/// publish aggregates that belong to a different store.
+#[derive(Default)]
+pub struct EventStoreRegistry {
+    stores: RwLock<HashMap<String, SharedEventStore>>,
+}
+
+impl EventStoreRegistry {
+    pub fn new() -> EventStoreRegistry {
+        EventStoreRegistry::default()
+    }
+
+    /// The process-wide registry.
+    pub fn global() -> &'static EventStoreRegistry {
+        static GLOBAL: OnceLock<EventStoreRegistry> = OnceLock::new();
+        GLOBAL.get_or_init(EventStoreRegistry::new)
+    }
+
+    /// Registers a store under `name`. Names are unique and a store can only have one name.
+    pub fn register(&self, name: &str, store: SharedEventStore) -> Result<(), EventStoreError> {
+        let mut stores = self.stores.write()?;
+        if stores.contains_key(name) {
+            return Err(EventStoreError::ConfigurationError(vec![
+                format!("an event store is already registered as '{}'", name)]));
+        }
+        if store.registered_name.get_or_init(|| name.to_string()) != name {
+            return Err(EventStoreError::ConfigurationError(vec![
+                format!("event store is already registered as '{}'", store.registered_name().unwrap_or_default())]));
+        }
+        stores.insert(name.to_string(), store);
+        Ok(())
+    }
+
+    /// Looks up a store by name.
+    pub fn get(&self, name: &str) -> Option<SharedEventStore> {
+        self.stores.read().ok()?.get(name).cloned()
+    }
+
+    /// Creates a new context on the named store.
+    pub fn context(&self, name: &str) -> Result<SharedEventContext, EventStoreError> {
+        let store = self.get(name).ok_or_else(|| EventStoreError::UnknownStore(name.to_string()))?;
+        Ok(store.get_context())
+    }
+}
+
+#[cfg(all(test, feature = "memory"))]
+mod tests {
+    use serde::{Serialize, Deserialize};
+    use crate::{EventStore, EventStoreError, EventStoreStorageEngine, event::Event};
+    use crate::aggregate::{Composable, CanRequest, ComposedAggregate};
+    use crate::memory::MemoryStorageEngine;
+    use super::EventStoreRegistry;
+    use crate::event::numbered_id;
+
+    #[derive(Default, Clone, Serialize, Deserialize)]
+    struct PageViews {
+        count: i64,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    struct RecordView;
+
+    impl Composable for PageViews {
+        fn get_type(&self) -> &str {
+            "page_views"
+        }
+
+        fn apply_event(&mut self, _event: &Event) -> Result<(), EventStoreError> {
+            self.count += 1;
+            Ok(())
+        }
+
+        fn store_name(&self) -> &str {
+            "analytics"
+        }
+    }
+
+    impl CanRequest<RecordView, RecordView> for PageViews {
+        fn request(&self, request: RecordView) -> Result<(String, RecordView), EventStoreError> {
+            Ok(("viewed".to_string(), request))
+        }
+    }
+
+    #[tokio::test]
+    async fn ensure_new_in_uses_the_declared_store() {
+        let ops = MemoryStorageEngine::new();
+        let analytics = MemoryStorageEngine::new();
+        let registry = EventStoreRegistry::new();
+        registry.register("default", EventStore::new(ops.clone())).unwrap();
+        registry.register("analytics", EventStore::new(analytics.clone())).unwrap();
+
+        let mut views = ComposedAggregate::<PageViews>::new_in(&registry, None).await.unwrap();
+        views.request(RecordView).unwrap();
+        views.context().unwrap().commit().await.unwrap();
+
+        assert_eq!(analytics.read_events(numbered_id(1), "page_views", 0).await.unwrap().len(), 1);
+        assert!(ops.read_events(numbered_id(1), "page_views", 0).await.unwrap().is_empty());
+
+        let views = ComposedAggregate::<PageViews>::load_in(&registry, numbered_id(1)).await.unwrap();
+        assert_eq!(views.state().count, 1);
+    }
+
+    #[tokio::test]
+    async fn ensure_cross_store_publish_fails() {
+        let registry = EventStoreRegistry::new();
+        registry.register("default", EventStore::new(MemoryStorageEngine::new())).unwrap();
+        registry.register("analytics", EventStore::new(MemoryStorageEngine::new())).unwrap();
+
+        let ops_context = registry.context("default").unwrap();
+        let mut views = ComposedAggregate::<PageViews>::new(&ops_context, None).await.unwrap();
+        let result = views.request(RecordView);
+        assert!(matches!(result, Err(EventStoreError::WrongStore((expected, actual))) if expected == "analytics" && actual == "default"));
+    }
+
+    #[test]
+    fn ensure_registration_errors() {
+        let registry = EventStoreRegistry::new();
+        let store = EventStore::new(MemoryStorageEngine::new());
+        registry.register("ops", store.clone()).unwrap();
+
+        assert!(matches!(registry.register("ops", EventStore::new(MemoryStorageEngine::new())), Err(EventStoreError::ConfigurationError(_))));
+        assert!(matches!(registry.register("other", store), Err(EventStoreError::ConfigurationError(_))));
+        assert!(matches!(registry.context("missing"), Err(EventStoreError::UnknownStore(_))));
+    }
+}
@@ -0,0 +1,33 @@
+use chrono::Duration;
+
+/// Which stored data `EventStore::apply_retention` removes.
+#[derive(Clone, Debug, Default)]
+pub struct RetentionPolicy {
+    /// Dedup keys older than this are pruned, ending their de-duplication window.
+    pub dedup_key_max_age: Option<Duration>,
+    /// Only this many of the newest snapshots are kept per aggregate.
+    pub snapshots_to_keep: Option<usize>,
+}
+
+impl RetentionPolicy {
+    pub fn new() -> RetentionPolicy {
+        RetentionPolicy::default()
+    }
+
+    pub fn dedup_key_max_age(mut self, max_age: Duration) -> RetentionPolicy {
+        self.dedup_key_max_age = Some(max_age);
+        self
+    }
+
+    pub fn snapshots_to_keep(mut self, count: usize) -> RetentionPolicy {
+        self.snapshots_to_keep = Some(count);
+        self
+    }
+}
+
+/// What a retention run removed.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RetentionReport {
+    pub dedup_keys_pruned: usize,
+    pub snapshots_pruned: usize,
+}
@@ -0,0 +1,734 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::{EventStoreError, event::Event, snapshot::Snapshot, EventStoreStorageEngine};
+use crate::{AggregateInstance, CreateOutcome, DedupKey, DuplicateKeyPolicy, EngineCapabilities, LookupKey, LookupKeyChange, MigrationReport, PurgeReport, RewriteReport, SnapshotInfo, TypeInfo, WriteBatch, WrittenEvent};
+use chrono::{DateTime, Utc};
+
+type SharedStorageEngine = Arc<dyn EventStoreStorageEngine + Send + Sync>;
+
+/// Decides which shard owns a newly created aggregate instance.
+///
+/// Routing must be deterministic for natural keys so that `get_aggregate_instance_id`
+/// can find the owning shard again without consulting every shard.
+pub trait ShardRouter {
+    fn route(&self, aggregate_type: &str, natural_key: Option<&str>, shard_count: usize) -> usize;
+}
+
+/// Default router: hashes `(aggregate_type, natural_key)` with FNV-1a and spreads
+/// aggregates without a natural key round-robin.
+#[derive(Default)]
+pub struct HashRouter {
+    next: AtomicUsize,
+}
+
+impl HashRouter {
+    pub fn new() -> HashRouter {
+        HashRouter::default()
+    }
+}
+
+impl ShardRouter for HashRouter {
+    fn route(&self, aggregate_type: &str, natural_key: Option<&str>, shard_count: usize) -> usize {
+        match natural_key {
+            Some(natural_key) => {
+                let mut hash: u64 = 0xcbf29ce484222325;
+                for byte in aggregate_type.bytes().chain([0u8]).chain(natural_key.bytes()) {
+                    hash ^= byte as u64;
+                    hash = hash.wrapping_mul(0x100000001b3);
+                }
+                (hash % shard_count as u64) as usize
+            },
+            None => self.next.fetch_add(1, Ordering::Relaxed) % shard_count,
+        }
+    }
+}
+
+/// How far a reader of the merged feed got in the global feed of each shard, as read by
+/// `ShardedStorageEngine::read_merged_feed`. Starts at 0 for every shard.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ShardPositions(Vec<i64>);
+
+impl ShardPositions {
+    /// Position of the last event read from `shard`, 0 before its first event.
+    pub fn shard(&self, shard: usize) -> i64 {
+        self.0.get(shard).copied().unwrap_or(0)
+    }
+
+    /// Positions by shard index; shards past the end have not been read from.
+    pub fn positions(&self) -> &[i64] {
+        &self.0
+    }
+}
+
+/// A page of the merged feed.
+#[derive(Clone, Debug)]
+pub struct MergedFeedPage {
+    pub events: Vec<Event>,
+    /// Where to continue reading, including the events of this page.
+    pub next: ShardPositions,
+}
+
+/// Storage engine that spreads aggregates over several underlying engines.
+///
+/// The owning shard is encoded in the low digits of the aggregate id
+/// (`id = local_id * shard_count + shard`), so reads route without a lookup table.
+/// A write touching aggregates on more than one shard is rejected with
+/// `EventStoreError::CrossShardCommit` since atomicity could not be guaranteed.
+///
+/// The shards' global feeds are merged by timestamp with `read_merged_feed`, resuming
+/// from a position per shard rather than the single position of `read_all_events`.
+pub struct ShardedStorageEngine {
+    shards: Vec<SharedStorageEngine>,
+    router: Arc<dyn ShardRouter + Send + Sync>,
+    tenant_id: Option<String>,
+}
+
+impl ShardedStorageEngine {
+    /// Fails with `InvalidArgument` without shards.
+    pub fn new(shards: Vec<SharedStorageEngine>, router: Arc<dyn ShardRouter + Send + Sync>) -> Result<Arc<ShardedStorageEngine>, EventStoreError> {
+        if shards.is_empty() {
+            return Err(EventStoreError::InvalidArgument {
+                parameter: "shard count".to_string(),
+                value: "0".to_string(),
+                reason: "must be at least 1".to_string(),
+            });
+        }
+        Ok(ShardedStorageEngine {
+            shards,
+            router,
+            tenant_id: None,
+        }.into())
+    }
+
+    pub fn shard_count(&self) -> usize {
+        self.shards.len()
+    }
+
+    /// Returns the index of the shard owning the given aggregate id.
+    pub fn shard_of(&self, aggregate_id: i64) -> usize {
+        aggregate_id.rem_euclid(self.shards.len() as i64) as usize
+    }
+
+    /// Encodes a shard's id, or feed position, failing when it does not fit an i64.
+    fn to_global_id(&self, shard: usize, local_id: i64) -> Result<i64, EventStoreError> {
+        local_id.checked_mul(self.shards.len() as i64)
+            .and_then(|id| id.checked_add(shard as i64))
+            .ok_or_else(|| EventStoreError::StorageEngineErrorOther(
+                format!("Id {} of shard {} is too large to encode with {} shards.", local_id, shard, self.shards.len())))
+    }
+
+    /// The shard the router picks, checked to be one of the shards.
+    fn route(&self, aggregate_type: &str, natural_key: Option<&str>) -> Result<usize, EventStoreError> {
+        let shard = self.router.route(aggregate_type, natural_key, self.shards.len());
+        if shard >= self.shards.len() {
+            return Err(EventStoreError::StorageEngineErrorOther(
+                format!("Shard router picked shard {} of {}.", shard, self.shards.len())));
+        }
+        Ok(shard)
+    }
+
+    fn to_local_id(&self, aggregate_id: i64) -> (usize, i64) {
+        (self.shard_of(aggregate_id), aggregate_id.div_euclid(self.shards.len() as i64))
+    }
+
+    fn to_local_key(&self, key: &LookupKey) -> (usize, LookupKey) {
+        let (shard, local_id) = self.to_local_id(key.aggregate_id);
+        let mut key = key.clone();
+        key.aggregate_id = local_id;
+        (shard, key)
+    }
+
+    /// Determines the single shard touched by a batch, rejecting batches spanning several.
+    fn batch_shard(&self, batch: &WriteBatch<'_>) -> Result<Option<usize>, EventStoreError> {
+        let mut shards: Vec<usize> = batch.events.iter().map(|e| e.aggregate_id)
+            .chain(batch.snapshots.iter().map(|s| s.aggregate_id))
+            .chain(batch.lookup_keys.iter().map(|change| match change {
+                LookupKeyChange::Add(key) | LookupKeyChange::Remove(key) => key.aggregate_id,
+            }))
+            .chain(batch.dedup_keys.iter().map(|dedup_key| dedup_key.aggregate_id))
+            .map(|id| self.shard_of(id))
+            .collect();
+        shards.sort_unstable();
+        shards.dedup();
+
+        match shards.len() {
+            0 => Ok(None),
+            1 => Ok(Some(shards[0])),
+            _ => Err(EventStoreError::CrossShardCommit(shards)),
+        }
+    }
+
+    /// Combines the type lists of every shard. Type ids are per shard, so they are dropped.
+    fn merge_types(lists: Vec<Vec<TypeInfo>>) -> Vec<TypeInfo> {
+        let mut types: BTreeMap<String, TypeInfo> = BTreeMap::new();
+        for info in lists.into_iter().flatten() {
+            match types.get_mut(&info.name) {
+                None => {
+                    types.insert(info.name.clone(), TypeInfo { id: None, ..info });
+                }
+                Some(merged) => {
+                    merged.usage = merged.usage.zip(info.usage).map(|(usage, more)| usage + more);
+                    merged.first_seen = match (merged.first_seen, info.first_seen) {
+                        (Some(seen), Some(other)) => Some(seen.min(other)),
+                        (seen, other) => seen.or(other),
+                    };
+                }
+            }
+        }
+        types.into_values().collect()
+    }
+
+    fn check_positions(&self, positions: &ShardPositions) -> Result<(), EventStoreError> {
+        if positions.0.len() > self.shards.len() {
+            return Err(EventStoreError::InvalidArgument {
+                parameter: "shard positions".to_string(),
+                value: format!("{:?}", positions.0),
+                reason: format!("has more entries than the {} shards", self.shards.len()),
+            });
+        }
+        Ok(())
+    }
+
+    /// Reads up to `limit` events after `after` from the global feeds of every shard,
+    /// merged by `created_at`, with ties going by shard index and events without one first.
+    /// Each shard's events keep their feed order. Aggregate ids are the sharded ones, and positions are
+    /// encoded like them (`position * shard_count + shard`).
+    pub async fn read_merged_feed(&self, after: &ShardPositions, limit: usize) -> Result<MergedFeedPage, EventStoreError> {
+        self.check_positions(after)?;
+        let mut pending: Vec<VecDeque<Event>> = Vec::with_capacity(self.shards.len());
+        for (shard, engine) in self.shards.iter().enumerate() {
+            pending.push(engine.read_all_events(after.shard(shard), limit).await?.into());
+        }
+
+        // Each shard returned up to `limit` events, so one that may hold more cannot run out
+        // before the page is full.
+        let mut next = ShardPositions((0..self.shards.len()).map(|shard| after.shard(shard)).collect());
+        let mut events = Vec::new();
+        while events.len() < limit {
+            let earliest = pending.iter().enumerate()
+                .filter_map(|(shard, events)| events.front().map(|event| (event.created_at, shard)))
+                .min();
+            let Some((_, shard)) = earliest else {
+                break;
+            };
+            let mut event = pending[shard].pop_front().expect("the shard has a pending event");
+            let position = event.position.ok_or_else(|| EventStoreError::StorageEngineErrorOther(
+                format!("Shard {} returned a feed event without a position.", shard)))?;
+            next.0[shard] = position;
+            event.aggregate_id = self.to_global_id(shard, event.aggregate_id)?;
+            event.position = Some(self.to_global_id(shard, position)?);
+            events.push(event);
+        }
+        Ok(MergedFeedPage { events, next })
+    }
+
+    /// The latest position of every shard's global feed.
+    pub async fn merged_head(&self) -> Result<ShardPositions, EventStoreError> {
+        let mut positions = Vec::with_capacity(self.shards.len());
+        for engine in &self.shards {
+            positions.push(engine.head_position().await?);
+        }
+        Ok(ShardPositions(positions))
+    }
+
+    /// Per-shard checkpoint of a merged feed reader, kept in each shard's checkpoints under
+    /// the reader's name. Shards without one start from 0.
+    pub async fn read_merged_checkpoint(&self, projection: &str) -> Result<ShardPositions, EventStoreError> {
+        let mut positions = Vec::with_capacity(self.shards.len());
+        for engine in &self.shards {
+            positions.push(engine.read_checkpoint(projection).await?.unwrap_or(0));
+        }
+        Ok(ShardPositions(positions))
+    }
+
+    /// Records the position reached in each shard. Shards are written one after another, so
+    /// after a failure some may keep an older position and their events are read again.
+    pub async fn write_merged_checkpoint(&self, projection: &str, positions: &ShardPositions) -> Result<(), EventStoreError> {
+        self.check_positions(positions)?;
+        for (shard, engine) in self.shards.iter().enumerate() {
+            engine.write_checkpoint(projection, positions.shard(shard)).await?;
+        }
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait]
+impl EventStoreStorageEngine for ShardedStorageEngine {
+
+    /// Routes on the requested natural key, so the shard's policy resolves conflicts. Keys
+    /// suffixed by `DuplicateKeyPolicy::Suffix` stay on that shard but may route elsewhere
+    /// when looked up.
+    async fn create_aggregate_instance_with_policy(
+        &self,
+        aggregate_type: &str,
+        natural_key: Option<&str>,
+        policy: Option<DuplicateKeyPolicy>,
+    ) -> Result<CreateOutcome, EventStoreError> {
+        let shard = self.route(aggregate_type, natural_key)?;
+        let mut outcome = self.shards[shard].create_aggregate_instance_with_policy(aggregate_type, natural_key, policy).await?;
+        outcome.id = self.to_global_id(shard, outcome.id)?;
+        Ok(outcome)
+    }
+
+    async fn get_aggregate_instance_id(&self, aggregate_type: &str, natural_key: &str) -> Result<Option<i64>, EventStoreError> {
+        let shard = self.route(aggregate_type, Some(natural_key))?;
+        let local_id = self.shards[shard].get_aggregate_instance_id(aggregate_type, natural_key).await?;
+        local_id.map(|id| self.to_global_id(shard, id)).transpose()
+    }
+
+    async fn find_aggregate_instance(&self, aggregate_type: &str, natural_key: &str) -> Result<Option<AggregateInstance>, EventStoreError> {
+        let shard = self.route(aggregate_type, Some(natural_key))?;
+        let mut instance = self.shards[shard].find_aggregate_instance(aggregate_type, natural_key).await?;
+        if let Some(instance) = instance.as_mut() {
+            instance.id = self.to_global_id(shard, instance.id)?;
+        }
+        Ok(instance)
+    }
+
+    async fn soft_delete_aggregate(&self, aggregate_type: &str, aggregate_id: i64, deleted_at: DateTime<Utc>) -> Result<(), EventStoreError> {
+        let (shard, local_id) = self.to_local_id(aggregate_id);
+        self.shards[shard].soft_delete_aggregate(aggregate_type, local_id, deleted_at).await
+    }
+
+    async fn purge_aggregate(&self, aggregate_id: i64, aggregate_type: &str) -> Result<PurgeReport, EventStoreError> {
+        let (shard, local_id) = self.to_local_id(aggregate_id);
+        self.shards[shard].purge_aggregate(local_id, aggregate_type).await
+    }
+
+    async fn replace_events(&self, aggregate_id: i64, aggregate_type: &str, expected_version: i64, events: &[Event]) -> Result<RewriteReport, EventStoreError> {
+        let (shard, local_id) = self.to_local_id(aggregate_id);
+        let events: Vec<Event> = events.iter().cloned().map(|mut event| {
+            event.aggregate_id = local_id;
+            event
+        }).collect();
+        self.shards[shard].replace_events(local_id, aggregate_type, expected_version, &events).await
+    }
+
+    async fn restore_aggregate_instance(&self, aggregate_type: &str, aggregate_id: i64, natural_key: Option<&str>) -> Result<(), EventStoreError> {
+        let (shard, local_id) = self.to_local_id(aggregate_id);
+        // Natural keys are found on the shard they route to.
+        if natural_key.is_some() && self.route(aggregate_type, natural_key)? != shard {
+            return Err(EventStoreError::InvalidArgument {
+                parameter: "natural_key".to_string(),
+                value: natural_key.unwrap_or_default().to_string(),
+                reason: format!("routes to another shard than instance {}", aggregate_id),
+            });
+        }
+        self.shards[shard].restore_aggregate_instance(aggregate_type, local_id, natural_key).await
+    }
+
+    async fn resurrect_aggregate(&self, aggregate_type: &str, aggregate_id: i64) -> Result<(), EventStoreError> {
+        let (shard, local_id) = self.to_local_id(aggregate_id);
+        self.shards[shard].resurrect_aggregate(aggregate_type, local_id).await
+    }
+
+    async fn aggregate_deleted_at(&self, aggregate_type: &str, aggregate_id: i64) -> Result<Option<DateTime<Utc>>, EventStoreError> {
+        let (shard, local_id) = self.to_local_id(aggregate_id);
+        self.shards[shard].aggregate_deleted_at(aggregate_type, local_id).await
+    }
+
+    async fn read_events(
+        &self,
+        aggregate_id: i64,
+        aggregate_type: &str,
+        version: i64,
+    ) -> Result<Vec<Event>, EventStoreError> {
+        let (shard, local_id) = self.to_local_id(aggregate_id);
+        let mut events = self.shards[shard].read_events(local_id, aggregate_type, version).await?;
+        for event in events.iter_mut() {
+            event.aggregate_id = aggregate_id;
+        }
+        Ok(events)
+    }
+
+    async fn read_snapshot(
+        &self,
+        aggregate_id: i64,
+        aggregate_type: &str,
+    ) -> Result<Option<Snapshot>, EventStoreError> {
+        let (shard, local_id) = self.to_local_id(aggregate_id);
+        let mut snapshot = self.shards[shard].read_snapshot(local_id, aggregate_type).await?;
+        if let Some(snapshot) = snapshot.as_mut() {
+            snapshot.aggregate_id = aggregate_id;
+        }
+        Ok(snapshot)
+    }
+
+    async fn read_snapshots(&self, aggregate_id: i64, aggregate_type: &str, limit: usize) -> Result<Vec<SnapshotInfo>, EventStoreError> {
+        let (shard, local_id) = self.to_local_id(aggregate_id);
+        self.shards[shard].read_snapshots(local_id, aggregate_type, limit).await
+    }
+
+    async fn read_snapshot_at(&self, aggregate_id: i64, aggregate_type: &str, max_version: i64) -> Result<Option<Snapshot>, EventStoreError> {
+        let (shard, local_id) = self.to_local_id(aggregate_id);
+        let mut snapshot = self.shards[shard].read_snapshot_at(local_id, aggregate_type, max_version).await?;
+        if let Some(snapshot) = snapshot.as_mut() {
+            snapshot.aggregate_id = aggregate_id;
+        }
+        Ok(snapshot)
+    }
+
+    async fn write_updates(&self, events: &[Event], snapshots: &[Snapshot]) -> Result<Vec<WrittenEvent>, EventStoreError> {
+        self.write_batch(&WriteBatch { events, snapshots, ..Default::default() }).await
+    }
+
+    async fn write_batch(&self, batch: &WriteBatch<'_>) -> Result<Vec<WrittenEvent>, EventStoreError> {
+        let Some(shard) = self.batch_shard(batch)? else {
+            return Ok(Vec::new());
+        };
+
+        let events: Vec<Event> = batch.events.iter().cloned().map(|mut event| {
+            event.aggregate_id = self.to_local_id(event.aggregate_id).1;
+            event
+        }).collect();
+        let snapshots: Vec<Snapshot> = batch.snapshots.iter().cloned().map(|mut snapshot| {
+            snapshot.aggregate_id = self.to_local_id(snapshot.aggregate_id).1;
+            snapshot
+        }).collect();
+        let lookup_keys: Vec<LookupKeyChange> = batch.lookup_keys.iter().map(|change| match change {
+            LookupKeyChange::Add(key) => LookupKeyChange::Add(self.to_local_key(key).1),
+            LookupKeyChange::Remove(key) => LookupKeyChange::Remove(self.to_local_key(key).1),
+        }).collect();
+
+        let dedup_keys: Vec<DedupKey> = batch.dedup_keys.iter().cloned().map(|mut dedup_key| {
+            dedup_key.aggregate_id = self.to_local_id(dedup_key.aggregate_id).1;
+            dedup_key
+        }).collect();
+
+        let local_batch = WriteBatch {
+            events: &events,
+            snapshots: &snapshots,
+            lookup_keys: &lookup_keys,
+            dedup_keys: &dedup_keys,
+        };
+        let written = self.shards[shard].write_batch(&local_batch).await?;
+        // Shard positions are not global, so only the timestamps are passed on.
+        Ok(written.into_iter().zip(batch.events).map(|(written, event)| WrittenEvent {
+            created_at: written.created_at,
+            ..WrittenEvent::unpositioned(event)
+        }).collect())
+    }
+
+    async fn add_lookup_key(&self, key: &LookupKey) -> Result<(), EventStoreError> {
+        let (shard, key) = self.to_local_key(key);
+        self.shards[shard].add_lookup_key(&key).await
+    }
+
+    async fn remove_lookup_key(&self, key: &LookupKey) -> Result<(), EventStoreError> {
+        let (shard, key) = self.to_local_key(key);
+        self.shards[shard].remove_lookup_key(&key).await
+    }
+
+    async fn find_by_lookup_key(&self, aggregate_type: &str, key_name: &str, key_value: &str) -> Result<Vec<i64>, EventStoreError> {
+        let mut ids = Vec::new();
+        for (shard, engine) in self.shards.iter().enumerate() {
+            for local_id in engine.find_by_lookup_key(aggregate_type, key_name, key_value).await? {
+                ids.push(self.to_global_id(shard, local_id)?);
+            }
+        }
+        ids.sort_unstable();
+        Ok(ids)
+    }
+
+    /// Dedup keys live on the shard of their aggregate, so lookups check every shard.
+    /// Uniqueness is only enforced within a shard at write time.
+    async fn has_dedup_key(&self, key: &str) -> Result<bool, EventStoreError> {
+        for engine in &self.shards {
+            if engine.has_dedup_key(key).await? {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    async fn prune_dedup_keys(&self, older_than: DateTime<Utc>) -> Result<usize, EventStoreError> {
+        let mut pruned = 0;
+        for engine in &self.shards {
+            pruned += engine.prune_dedup_keys(older_than).await?;
+        }
+        Ok(pruned)
+    }
+
+    async fn prune_snapshots(&self, keep: usize) -> Result<usize, EventStoreError> {
+        let mut pruned = 0;
+        for engine in &self.shards {
+            pruned += engine.prune_snapshots(keep).await?;
+        }
+        Ok(pruned)
+    }
+
+    /// Migrates each shard in turn. Shards are not migrated atomically with each other.
+    async fn migrate_aggregate_type(&self, old_type: &str, new_type: &str) -> Result<MigrationReport, EventStoreError> {
+        let mut report = MigrationReport::default();
+        for engine in &self.shards {
+            let shard_report = engine.migrate_aggregate_type(old_type, new_type).await?;
+            report.aggregate_instances += shard_report.aggregate_instances;
+            report.events += shard_report.events;
+            report.snapshots += shard_report.snapshots;
+            report.lookup_keys += shard_report.lookup_keys;
+        }
+        Ok(report)
+    }
+
+    async fn list_aggregate_types(&self, with_counts: bool) -> Result<Vec<TypeInfo>, EventStoreError> {
+        let mut lists = Vec::with_capacity(self.shards.len());
+        for engine in &self.shards {
+            lists.push(engine.list_aggregate_types(with_counts).await?);
+        }
+        Ok(ShardedStorageEngine::merge_types(lists))
+    }
+
+    async fn list_event_types(&self, with_counts: bool) -> Result<Vec<TypeInfo>, EventStoreError> {
+        let mut lists = Vec::with_capacity(self.shards.len());
+        for engine in &self.shards {
+            lists.push(engine.list_event_types(with_counts).await?);
+        }
+        Ok(ShardedStorageEngine::merge_types(lists))
+    }
+
+    async fn get_aggregate_version(&self, aggregate_id: i64, aggregate_type: &str) -> Result<i64, EventStoreError> {
+        let (shard, local_id) = self.to_local_id(aggregate_id);
+        self.shards[shard].get_aggregate_version(local_id, aggregate_type).await
+    }
+
+    async fn earliest_event_version(&self, aggregate_id: i64, aggregate_type: &str) -> Result<Option<i64>, EventStoreError> {
+        let (shard, local_id) = self.to_local_id(aggregate_id);
+        self.shards[shard].earliest_event_version(local_id, aggregate_type).await
+    }
+
+    /// Scopes every shard to the tenant, routing with the same router.
+    fn for_tenant(&self, tenant_id: &str) -> Result<SharedStorageEngine, EventStoreError> {
+        let shards = self.shards.iter()
+            .map(|shard| shard.for_tenant(tenant_id))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Arc::new(ShardedStorageEngine {
+            shards,
+            router: self.router.clone(),
+            tenant_id: Some(tenant_id.to_string()),
+        }))
+    }
+
+    fn tenant_id(&self) -> Option<&str> {
+        self.tenant_id.as_deref()
+    }
+
+    /// Capabilities shared by every shard, except the global feed: resuming the merged feed
+    /// takes a position per shard, so it is read with `read_merged_feed` instead.
+    fn capabilities(&self) -> EngineCapabilities {
+        self.shards.iter()
+            .fold(EngineCapabilities::all(), |capabilities, shard| capabilities.intersection(shard.capabilities()))
+            .difference(EngineCapabilities::GLOBAL_FEED)
+    }
+
+    fn engine_name(&self) -> &str {
+        "ShardedStorageEngine"
+    }
+
+    fn description(&self) -> String {
+        let shards: Vec<String> = self.shards.iter().map(|shard| shard.description()).collect();
+        format!("ShardedStorageEngine [{}]", shards.join(", "))
+    }
+}
+
+#[cfg(all(test, feature = "memory"))]
+mod tests {
+    use serde::{Serialize, Deserialize};
+
+    use super::*;
+    use crate::memory::MemoryStorageEngine;
+
+    #[derive(Serialize, Deserialize, Debug)]
+    struct UserCreate {
+        name: String,
+    }
+
+    fn sharded() -> (Arc<ShardedStorageEngine>, Arc<MemoryStorageEngine>, Arc<MemoryStorageEngine>) {
+        let shard0 = MemoryStorageEngine::new();
+        let shard1 = MemoryStorageEngine::new();
+        let engine = ShardedStorageEngine::new(vec![shard0.clone(), shard1.clone()], Arc::new(HashRouter::new())).unwrap();
+        (engine, shard0, shard1)
+    }
+
+    #[tokio::test]
+    async fn ensure_natural_keys_route_deterministically() {
+        let (engine, _, _) = sharded();
+
+        let mut ids = Vec::new();
+        for i in 0..10 {
+            let key = format!("user-{}", i);
+            let id = engine.create_aggregate_instance("user", Some(&key)).await.unwrap();
+            ids.push((key, id));
+        }
+
+        for (key, id) in ids {
+            let found = engine.get_aggregate_instance_id("user", &key).await.unwrap().unwrap();
+            assert_eq!(found, id);
+        }
+    }
+
+    #[tokio::test]
+    async fn ensure_type_lists_merge_across_shards() {
+        let (engine, _, _) = sharded();
+        let data = UserCreate { name: "test".to_string() };
+        for _ in 0..2 {
+            let id = engine.create_aggregate_instance("user", None).await.unwrap();
+            let event = Event::new(id, "user", 1, "created", &data).unwrap();
+            engine.write_updates(&[event], &[]).await.unwrap();
+        }
+
+        let types = engine.list_event_types(true).await.unwrap();
+        assert_eq!(types.len(), 1);
+        assert_eq!((types[0].name.as_str(), types[0].id, types[0].usage), ("created", None, Some(2)));
+    }
+
+    #[tokio::test]
+    async fn ensure_reads_route_to_owning_shard() {
+        let (engine, shard0, shard1) = sharded();
+        let first = engine.create_aggregate_instance("user", None).await.unwrap();
+        let second = engine.create_aggregate_instance("user", None).await.unwrap();
+        assert_ne!(engine.shard_of(first), engine.shard_of(second));
+
+        let data = UserCreate { name: "test".to_string() };
+        for id in [first, second] {
+            let event = Event::new(id, "user", 1, "created", &data).unwrap();
+            let snapshot = Snapshot::new(id, "user", 1, &data).unwrap();
+            engine.write_updates(&[event], &[snapshot]).await.unwrap();
+        }
+
+        let events = engine.read_events(first, "user", 0).await.unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].aggregate_id, first);
+
+        let snapshot = engine.read_snapshot(second, "user").await.unwrap().unwrap();
+        assert_eq!(snapshot.aggregate_id, second);
+        assert_eq!(engine.get_aggregate_version(second, "user").await.unwrap(), 1);
+
+        assert_eq!(shard0.snapshot_count(), 1);
+        assert_eq!(shard1.snapshot_count(), 1);
+    }
+
+    #[tokio::test]
+    async fn ensure_lookup_keys_span_shards() {
+        let (engine, _, _) = sharded();
+        let first = engine.create_aggregate_instance("order", None).await.unwrap();
+        let second = engine.create_aggregate_instance("order", None).await.unwrap();
+
+        for aggregate_id in [first, second] {
+            let key = LookupKey {
+                aggregate_id,
+                aggregate_type: "order".to_string(),
+                key_name: "customer_id".to_string(),
+                key_value: "42".to_string(),
+            };
+            engine.add_lookup_key(&key).await.unwrap();
+        }
+
+        let ids = engine.find_by_lookup_key("order", "customer_id", "42").await.unwrap();
+        assert_eq!(ids, vec![first, second]);
+    }
+
+    #[tokio::test]
+    async fn ensure_tenants_are_scoped_on_every_shard() {
+        let (engine, shard0, shard1) = sharded();
+        let tenant = engine.for_tenant("acme").unwrap();
+        let first = tenant.create_aggregate_instance("user", Some("alice")).await.unwrap();
+        let second = tenant.create_aggregate_instance("user", None).await.unwrap();
+        assert_eq!(tenant.tenant_id(), Some("acme"));
+
+        let data = UserCreate { name: "test".to_string() };
+        for id in [first, second] {
+            let snapshot = Snapshot::new(id, "user", 1, &data).unwrap();
+            tenant.write_updates(&[Event::new(id, "user", 1, "created", &data).unwrap()], &[snapshot]).await.unwrap();
+            assert_eq!(tenant.get_aggregate_version(id, "user").await.unwrap(), 1);
+            assert_eq!(engine.get_aggregate_version(id, "user").await.unwrap(), 0);
+        }
+        assert_eq!(tenant.get_aggregate_instance_id("user", "alice").await.unwrap(), Some(first));
+        assert_eq!(engine.get_aggregate_instance_id("user", "alice").await.unwrap(), None);
+        assert_eq!(shard0.snapshot_count() + shard1.snapshot_count(), 0);
+    }
+
+    #[tokio::test]
+    async fn ensure_cross_shard_commit_is_rejected() {
+        let (engine, shard0, shard1) = sharded();
+        let first = engine.create_aggregate_instance("user", None).await.unwrap();
+        let second = engine.create_aggregate_instance("user", None).await.unwrap();
+
+        let data = UserCreate { name: "test".to_string() };
+        let events = vec![
+            Event::new(first, "user", 1, "created", &data).unwrap(),
+            Event::new(second, "user", 1, "created", &data).unwrap(),
+        ];
+
+        let result = engine.write_updates(&events, &[]).await;
+        assert!(matches!(result, Err(EventStoreError::CrossShardCommit(_))));
+        assert_eq!(shard0.get_aggregate_version(1, "user").await.unwrap(), 0);
+        assert_eq!(shard1.get_aggregate_version(1, "user").await.unwrap(), 0);
+    }
+
+    struct OutOfRangeRouter;
+
+    impl ShardRouter for OutOfRangeRouter {
+        fn route(&self, _aggregate_type: &str, _natural_key: Option<&str>, shard_count: usize) -> usize {
+            shard_count
+        }
+    }
+
+    #[tokio::test]
+    async fn ensure_invalid_configurations_are_errors() {
+        let no_shards = ShardedStorageEngine::new(Vec::new(), Arc::new(HashRouter::new()));
+        assert!(matches!(no_shards, Err(EventStoreError::InvalidArgument { .. })));
+
+        let engine = ShardedStorageEngine::new(vec![MemoryStorageEngine::new()], Arc::new(OutOfRangeRouter)).unwrap();
+        assert!(matches!(engine.create_aggregate_instance("user", None).await, Err(EventStoreError::StorageEngineErrorOther(_))));
+        assert!(matches!(engine.get_aggregate_instance_id("user", "alice").await, Err(EventStoreError::StorageEngineErrorOther(_))));
+    }
+
+    #[tokio::test]
+    async fn ensure_ids_too_large_to_encode_are_errors() {
+        let (engine, shard0, _) = sharded();
+        let key = LookupKey {
+            aggregate_id: i64::MAX / 2 + 1,
+            aggregate_type: "order".to_string(),
+            key_name: "customer_id".to_string(),
+            key_value: "42".to_string(),
+        };
+        shard0.add_lookup_key(&key).await.unwrap();
+        assert!(matches!(engine.find_by_lookup_key("order", "customer_id", "42").await, Err(EventStoreError::StorageEngineErrorOther(_))));
+    }
+
+    #[tokio::test]
+    async fn ensure_merged_feed_orders_shards_by_timestamp() {
+        let (engine, _, _) = sharded();
+        let first = engine.create_aggregate_instance("user", None).await.unwrap();
+        let second = engine.create_aggregate_instance("user", None).await.unwrap();
+        assert_ne!(engine.shard_of(first), engine.shard_of(second));
+
+        // The second shard's events fall between those of the first.
+        let started = Utc::now();
+        let data = UserCreate { name: "test".to_string() };
+        for (id, offsets) in [(first, [0, 2, 4]), (second, [1, 3, 5])] {
+            let events: Vec<Event> = offsets.iter().enumerate().map(|(i, offset)| {
+                let mut event = Event::new(id, "user", i as i64 + 1, "renamed", &data).unwrap();
+                event.created_at = Some(started + chrono::Duration::seconds(*offset));
+                event
+            }).collect();
+            engine.write_updates(&events, &[]).await.unwrap();
+        }
+
+        let page = engine.read_merged_feed(&ShardPositions::default(), 4).await.unwrap();
+        let order: Vec<(i64, i64)> = page.events.iter().map(|event| (event.aggregate_id, event.version)).collect();
+        assert_eq!(order, vec![(first, 1), (second, 1), (first, 2), (second, 2)]);
+        assert!(page.events.iter().all(|event| engine.shard_of(event.position.unwrap()) == engine.shard_of(event.aggregate_id)));
+
+        engine.write_merged_checkpoint("feed", &page.next).await.unwrap();
+        let resumed = engine.read_merged_checkpoint("feed").await.unwrap();
+        assert_eq!(resumed, page.next);
+        let rest = engine.read_merged_feed(&resumed, 10).await.unwrap();
+        let order: Vec<(i64, i64)> = rest.events.iter().map(|event| (event.aggregate_id, event.version)).collect();
+        assert_eq!(order, vec![(first, 3), (second, 3)]);
+        assert_eq!(rest.next, engine.merged_head().await.unwrap());
+        assert!(engine.read_merged_feed(&rest.next, 10).await.unwrap().events.is_empty());
+    }
+}
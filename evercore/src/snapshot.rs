@@ -1,17 +1,23 @@
+use std::{fmt, sync::Arc};
+use chrono::{DateTime, Duration, Utc};
 use serde::{Serialize, de::DeserializeOwned};
-use crate::EventStoreError;
+use crate::{payload::Payload, AggregateId, EventStoreError};
 
 /// Snapshot is a representation of the aggregate state at a given point in time.
 #[derive(Clone, Debug)]
 pub struct Snapshot {
-    pub aggregate_id: i64,
+    pub aggregate_id: AggregateId,
     pub aggregate_type: String,
     pub version: i64,
-    pub data: String,
+    /// JSON, unless read from storage without being decoded, see `Payload`.
+    pub data: Payload,
+    /// When the snapshot was taken; stamped when it is captured by a context, None for
+    /// snapshots stored without a timestamp.
+    pub created_at: Option<DateTime<Utc>>,
 }
 
 impl Snapshot {
-    pub fn new<T>(aggregate_id: i64, aggregate_type: &str, version: i64, data: &T) -> Result<Snapshot, EventStoreError>
+    pub fn new<T>(aggregate_id: AggregateId, aggregate_type: &str, version: i64, data: &T) -> Result<Snapshot, EventStoreError>
         where T: Serialize + DeserializeOwned
     {
         let state = serde_json::to_string(&data).map_err(EventStoreError::SnapshotSerializationError)?;
@@ -20,21 +26,143 @@ impl Snapshot {
             aggregate_id,
             aggregate_type: aggregate_type.to_string(),
             version,
-            data: state,
+            data: Payload::Json(state),
+            created_at: None,
         })
     }
 
+    /// Fails with `AggregateTypeMismatch` unless the snapshot belongs to `aggregate_type`.
+    pub fn check_type(&self, aggregate_type: &str) -> Result<(), EventStoreError> {
+        if self.aggregate_type != aggregate_type {
+            return Err(EventStoreError::AggregateTypeMismatch {
+                requested: aggregate_type.to_string(),
+                stored: self.aggregate_type.clone(),
+            });
+        }
+        Ok(())
+    }
+
     pub fn to_state<T>(&self) -> Result<T, EventStoreError>
         where T: Serialize + DeserializeOwned
     {
-        serde_json::from_str(&self.data).map_err(EventStoreError::SnapshotDeserializationError)
+        serde_json::from_str(self.data.json()?).map_err(EventStoreError::SnapshotDeserializationError)
+    }
+}
+
+/// Single-line summary, e.g. `account/42 v10 snapshot 512B`.
+impl fmt::Display for Snapshot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{} v{} snapshot {}B",
+            self.aggregate_type,
+            self.aggregate_id,
+            self.version,
+            self.data.len())
     }
 }
 
+/// When an aggregate is snapshotted. The store picks, in order, the policy configured for
+/// the aggregate type with `EventStoreBuilder::snapshot_policy`, the aggregate's own
+/// `Aggregate::snapshot_policy`, the store's `default_snapshot_policy`, and finally
+/// `EveryNEvents` of the aggregate's `snapshot_frequency`.
+#[derive(Clone)]
+pub enum SnapshotPolicy {
+    /// Snapshot every n-th version; never when n is not positive.
+    EveryNEvents(i64),
+    /// Never snapshot, e.g. to turn snapshots off for one type under a store default.
+    Never,
+    /// Snapshot once the oldest event not covered by a snapshot is older than the duration.
+    OlderThan(Duration),
+    /// Snapshot once the last snapshot is older than the duration. Aggregates without a
+    /// known snapshot count from their oldest event not covered by one.
+    AfterDuration(Duration),
+    /// Snapshot when either policy would.
+    Or(Box<SnapshotPolicy>, Box<SnapshotPolicy>),
+    /// Snapshot when the rule says so, e.g. every n events since the last snapshot.
+    Custom(SnapshotRule),
+}
+
+/// Decides on snapshots for `SnapshotPolicy::Custom`.
+pub type SnapshotRule = Arc<dyn Fn(&SnapshotCheck) -> bool + Send + Sync>;
+
+impl fmt::Debug for SnapshotPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SnapshotPolicy::EveryNEvents(n) => f.debug_tuple("EveryNEvents").field(n).finish(),
+            SnapshotPolicy::Never => f.write_str("Never"),
+            SnapshotPolicy::OlderThan(age) => f.debug_tuple("OlderThan").field(age).finish(),
+            SnapshotPolicy::AfterDuration(age) => f.debug_tuple("AfterDuration").field(age).finish(),
+            SnapshotPolicy::Or(first, second) => f.debug_tuple("Or").field(first).field(second).finish(),
+            SnapshotPolicy::Custom(_) => f.write_str("Custom(..)"),
+        }
+    }
+}
+
+/// Custom policies are equal only to clones of themselves.
+impl PartialEq for SnapshotPolicy {
+    fn eq(&self, other: &SnapshotPolicy) -> bool {
+        match (self, other) {
+            (SnapshotPolicy::EveryNEvents(a), SnapshotPolicy::EveryNEvents(b)) => a == b,
+            (SnapshotPolicy::Never, SnapshotPolicy::Never) => true,
+            (SnapshotPolicy::OlderThan(a), SnapshotPolicy::OlderThan(b)) => a == b,
+            (SnapshotPolicy::AfterDuration(a), SnapshotPolicy::AfterDuration(b)) => a == b,
+            (SnapshotPolicy::Or(a1, a2), SnapshotPolicy::Or(b1, b2)) => a1 == b1 && a2 == b2,
+            (SnapshotPolicy::Custom(a), SnapshotPolicy::Custom(b)) => Arc::ptr_eq(a, b),
+            _ => false,
+        }
+    }
+}
+
+/// What a `SnapshotPolicy` decides on when an event is published.
+#[derive(Clone, Copy, Debug)]
+pub struct SnapshotCheck {
+    /// Version of the event being published.
+    pub version: i64,
+    /// Events since the last snapshot loaded or captured, counting the one being published.
+    pub events_since_snapshot: i64,
+    /// When the last snapshot loaded or captured was taken, if known.
+    pub last_snapshot_at: Option<DateTime<Utc>>,
+    /// created_at of the oldest event not yet covered by a snapshot, if known.
+    pub pending_since: Option<DateTime<Utc>>,
+    /// The store clock's current time.
+    pub now: DateTime<Utc>,
+}
+
+impl SnapshotPolicy {
+    /// Combines two policies; a snapshot is taken when either one asks for it.
+    pub fn or(self, other: SnapshotPolicy) -> SnapshotPolicy {
+        SnapshotPolicy::Or(Box::new(self), Box::new(other))
+    }
+
+    pub fn should_snapshot(&self, check: &SnapshotCheck) -> bool {
+        match self {
+            SnapshotPolicy::EveryNEvents(n) => *n > 0 && check.version % n == 0,
+            SnapshotPolicy::Never => false,
+            SnapshotPolicy::OlderThan(age) => check.pending_since
+                .is_some_and(|pending_since| check.now - pending_since > *age),
+            SnapshotPolicy::AfterDuration(age) => check.last_snapshot_at.or(check.pending_since)
+                .is_some_and(|since| check.now - since > *age),
+            SnapshotPolicy::Or(first, second) => first.should_snapshot(check) || second.should_snapshot(check),
+            SnapshotPolicy::Custom(rule) => rule(check),
+        }
+    }
+
+    /// Whether the policy looks at timestamps rather than versions alone; custom rules are
+    /// assumed to.
+    pub fn uses_time(&self) -> bool {
+        match self {
+            SnapshotPolicy::EveryNEvents(_) | SnapshotPolicy::Never => false,
+            SnapshotPolicy::OlderThan(_) | SnapshotPolicy::AfterDuration(_) | SnapshotPolicy::Custom(_) => true,
+            SnapshotPolicy::Or(first, second) => first.uses_time() || second.uses_time(),
+        }
+    }
+}
 
 #[cfg(test)]
 mod tests {
     use serde::{Serialize, Deserialize};
+    use chrono::{Duration, Utc};
+    use super::{SnapshotCheck, SnapshotPolicy};
+    use crate::event::numbered_id;
 
     #[derive(Serialize, Deserialize, Debug)]
     struct SampleState {
@@ -50,9 +178,9 @@ mod tests {
             name: "test".to_string(),
         };
 
-        let snapshot = super::Snapshot::new(1, "test", 1, &state).unwrap();
+        let snapshot = super::Snapshot::new(numbered_id(1), "test", 1, &state).unwrap();
 
-        assert_eq!(snapshot.aggregate_id, 1);
+        assert_eq!(snapshot.aggregate_id, numbered_id(1));
         assert_eq!(snapshot.aggregate_type, "test");
         assert_eq!(snapshot.version, 1);
         assert_eq!(snapshot.data, "{\"value\":1,\"name\":\"test\"}");
@@ -67,12 +195,61 @@ mod tests {
             name: "test".to_string(),
         };
 
-        let snapshot = super::Snapshot::new(1, "test", 1, &state).unwrap();
+        let snapshot = super::Snapshot::new(numbered_id(1), "test", 1, &state).unwrap();
 
         let deserialized: SampleState = snapshot.to_state().unwrap();
 
         assert_eq!(deserialized.value, 1);
         assert_eq!(deserialized.name, "test");
     }
-}
 
+    #[test]
+    fn test_snapshot_display_summary() {
+        let state = SampleState {
+            value: 1,
+            name: "test".to_string(),
+        };
+
+        let snapshot = super::Snapshot::new(numbered_id(42), "account", 10, &state).unwrap();
+        assert_eq!(snapshot.to_string(), format!("account/{} v10 snapshot 25B", numbered_id(42)));
+    }
+
+    #[test]
+    fn ensure_policies_combine() {
+        let now = Utc::now();
+        let policy = SnapshotPolicy::EveryNEvents(10).or(SnapshotPolicy::OlderThan(Duration::hours(1)));
+        let check = |version, pending_minutes: Option<i64>| SnapshotCheck {
+            version,
+            events_since_snapshot: version,
+            last_snapshot_at: None,
+            pending_since: pending_minutes.map(|minutes| now - Duration::minutes(minutes)),
+            now,
+        };
+
+        assert!(policy.uses_time());
+        assert!(!SnapshotPolicy::EveryNEvents(10).uses_time());
+        assert!(policy.should_snapshot(&check(10, None)));
+        assert!(policy.should_snapshot(&check(3, Some(61))));
+        assert!(!policy.should_snapshot(&check(3, Some(59))));
+        assert!(!policy.should_snapshot(&check(3, None)));
+    }
+
+    #[test]
+    fn ensure_after_duration_counts_from_last_snapshot() {
+        let now = Utc::now();
+        let policy = SnapshotPolicy::AfterDuration(Duration::hours(1));
+        let check = |snapshot_minutes: Option<i64>, pending_minutes: Option<i64>| SnapshotCheck {
+            version: 3,
+            events_since_snapshot: 1,
+            last_snapshot_at: snapshot_minutes.map(|minutes| now - Duration::minutes(minutes)),
+            pending_since: pending_minutes.map(|minutes| now - Duration::minutes(minutes)),
+            now,
+        };
+
+        assert!(policy.uses_time());
+        assert!(policy.should_snapshot(&check(Some(61), Some(1))));
+        assert!(!policy.should_snapshot(&check(Some(59), Some(120))));
+        assert!(policy.should_snapshot(&check(None, Some(61))));
+        assert!(!SnapshotPolicy::Never.should_snapshot(&check(Some(600), Some(600))));
+    }
+}
@@ -1,25 +1,537 @@
-use crate::{snapshot::Snapshot, EventStoreError, event::Event};
+use std::{collections::HashMap, sync::Arc};
+use chrono::{DateTime, Utc};
+use crate::{snapshot::Snapshot, EventStoreError, event::{AggregateId, Event}};
 
+/// A change to an aggregate's non-unique lookup keys.
+#[derive(Clone, Debug, PartialEq)]
+pub enum LookupKeyChange {
+    Add(LookupKey),
+    Remove(LookupKey),
+}
+
+/// A secondary, non-unique key used to find aggregates (e.g. a customer id shared by orders).
+#[derive(Clone, Debug, PartialEq)]
+pub struct LookupKey {
+    pub aggregate_id: AggregateId,
+    pub aggregate_type: String,
+    pub key_name: String,
+    pub key_value: String,
+}
+
+/// An aggregate instance as recorded by the storage engine.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AggregateInstance {
+    pub id: AggregateId,
+    pub aggregate_type: String,
+    pub natural_key: Option<String>,
+    /// When the instance was soft deleted, if it is.
+    pub deleted_at: Option<DateTime<Utc>>,
+}
+
+impl AggregateInstance {
+    pub fn is_deleted(&self) -> bool {
+        self.deleted_at.is_some()
+    }
+}
+
+/// A stored snapshot described without its payload.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SnapshotInfo {
+    pub version: i64,
+    /// Length of the serialized state in bytes.
+    pub data_size: usize,
+}
+
+/// What creating an aggregate instance does when its natural key is already taken.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DuplicateKeyPolicy {
+    /// Fail with `EventStoreError::DuplicateNaturalKey`.
+    #[default]
+    Error,
+    /// Return the id of the instance that holds the key.
+    ReturnExisting,
+    /// Create the instance under the first free key of `key-2`, `key-3`, ...
+    Suffix,
+}
+
+/// Result of creating an aggregate instance under a `DuplicateKeyPolicy`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CreateOutcome {
+    pub id: AggregateId,
+    /// False when an existing instance was returned instead.
+    pub created: bool,
+    /// The natural key the instance holds, which differs from the requested one after suffixing.
+    pub key_used: Option<String>,
+}
+
+/// The natural key tried on the given attempt under `DuplicateKeyPolicy::Suffix`: the key
+/// itself first, then `key-2`, `key-3`, ...
+pub fn suffixed_natural_key(natural_key: &str, attempt: usize) -> String {
+    match attempt {
+        0 | 1 => natural_key.to_string(),
+        attempt => format!("{natural_key}-{attempt}"),
+    }
+}
+
+/// Rows moved to the new type by `migrate_aggregate_type`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MigrationReport {
+    pub aggregate_instances: usize,
+    pub events: usize,
+    pub snapshots: usize,
+    pub lookup_keys: usize,
+}
+
+/// Rows removed by `purge_aggregate`, besides the instance itself.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PurgeReport {
+    pub events: usize,
+    pub snapshots: usize,
+    pub lookup_keys: usize,
+    pub dedup_keys: usize,
+}
+
+/// Rows changed by `replace_events`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RewriteReport {
+    pub events_removed: usize,
+    pub events_written: usize,
+    pub snapshots_removed: usize,
+}
+
+/// Records that an upstream message was ingested, so replays of it can be skipped.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DedupKey {
+    pub key: String,
+    pub aggregate_id: AggregateId,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Everything written atomically by a context commit.
+#[derive(Default)]
+pub struct WriteBatch<'a> {
+    pub events: &'a [Event],
+    pub snapshots: &'a [Snapshot],
+    pub lookup_keys: &'a [LookupKeyChange],
+    pub dedup_keys: &'a [DedupKey],
+}
+
+impl WriteBatch<'_> {
+    /// Whether the batch carries anything beyond events and snapshots.
+    pub fn has_extras(&self) -> bool {
+        !self.lookup_keys.is_empty() || !self.dedup_keys.is_empty()
+    }
+}
+
+/// An aggregate or event type known to the storage engine.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TypeInfo {
+    pub name: String,
+    /// The engine's id for the type, for engines keeping type tables.
+    pub id: Option<i64>,
+    /// Timestamp of the earliest stamped event of the type. Only filled when counting.
+    pub first_seen: Option<DateTime<Utc>>,
+    /// Number of stored events of the type, for aggregate types the events of its
+    /// aggregates. Only filled when counting.
+    pub usage: Option<u64>,
+}
+
+/// What the engine assigned to an event it wrote, returned by `write_batch` in input order.
+#[derive(Clone, Debug, PartialEq)]
+pub struct WrittenEvent {
+    pub aggregate_id: AggregateId,
+    pub version: i64,
+    /// Position in the global feed, for engines keeping one.
+    pub position: Option<i64>,
+    /// The timestamp as stored, after clock skew handling.
+    pub created_at: Option<DateTime<Utc>>,
+    pub hash: Option<String>,
+}
+
+impl WrittenEvent {
+    /// Describes an event stored as is, without a feed position.
+    pub fn unpositioned(event: &Event) -> WrittenEvent {
+        WrittenEvent {
+            aggregate_id: event.aggregate_id,
+            version: event.version,
+            position: None,
+            created_at: event.created_at,
+            hash: event.hash.clone(),
+        }
+    }
+
+    /// Copies the assigned fields onto the caller's copy of the event.
+    pub fn enrich(&self, event: &mut Event) {
+        event.position = self.position;
+        event.created_at = self.created_at;
+        event.hash = self.hash.clone();
+    }
+}
+
+/// Copies of `events` carrying what the engine assigned to them.
+#[cfg(feature = "context")]
+pub(crate) fn enriched(events: &[Event], written: &[WrittenEvent]) -> Vec<Event> {
+    events.iter().zip(written).map(|(event, written)| {
+        let mut event = event.clone();
+        written.enrich(&mut event);
+        event
+    }).collect()
+}
+
+/// Bit-set of optional features a storage engine supports.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct EngineCapabilities(u32);
+
+impl EngineCapabilities {
+    /// Reading every event in commit order via `read_all_events`.
+    pub const GLOBAL_FEED: EngineCapabilities = EngineCapabilities(1);
+    /// Scoping aggregates to tenants with `for_tenant`.
+    pub const TENANT_COLUMN: EngineCapabilities = EngineCapabilities(1 << 1);
+    /// Writing outbox messages in the commit transaction.
+    pub const OUTBOX: EngineCapabilities = EngineCapabilities(1 << 2);
+    /// Querying events by metadata values.
+    pub const JSON_METADATA_QUERIES: EngineCapabilities = EngineCapabilities(1 << 3);
+    /// Soft deleting and resurrecting aggregates.
+    pub const SOFT_DELETE: EngineCapabilities = EngineCapabilities(1 << 4);
+    /// Storing `Payload::Binary` event and snapshot data, needed by binary `PayloadSerializer`s.
+    pub const BINARY_PAYLOADS: EngineCapabilities = EngineCapabilities(1 << 5);
+
+    const NAMES: [(EngineCapabilities, &'static str); 6] = [
+        (EngineCapabilities::GLOBAL_FEED, "global feed"),
+        (EngineCapabilities::TENANT_COLUMN, "tenant column"),
+        (EngineCapabilities::OUTBOX, "outbox"),
+        (EngineCapabilities::JSON_METADATA_QUERIES, "JSON metadata queries"),
+        (EngineCapabilities::SOFT_DELETE, "soft delete"),
+        (EngineCapabilities::BINARY_PAYLOADS, "binary payloads"),
+    ];
+
+    pub const fn empty() -> EngineCapabilities {
+        EngineCapabilities(0)
+    }
+
+    pub const fn all() -> EngineCapabilities {
+        EngineCapabilities(0b111111)
+    }
+
+    pub const fn contains(&self, other: EngineCapabilities) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub const fn intersection(&self, other: EngineCapabilities) -> EngineCapabilities {
+        EngineCapabilities(self.0 & other.0)
+    }
+
+    pub const fn difference(&self, other: EngineCapabilities) -> EngineCapabilities {
+        EngineCapabilities(self.0 & !other.0)
+    }
+
+    pub const fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+
+    /// Human readable names of the capabilities in the set.
+    pub fn names(&self) -> Vec<&'static str> {
+        EngineCapabilities::NAMES.iter()
+            .filter(|(capability, _)| self.contains(*capability))
+            .map(|(_, name)| *name)
+            .collect()
+    }
+}
+
+impl std::ops::BitOr for EngineCapabilities {
+    type Output = EngineCapabilities;
+
+    fn bitor(self, rhs: EngineCapabilities) -> EngineCapabilities {
+        EngineCapabilities(self.0 | rhs.0)
+    }
+}
 
 /// EventStorageEnging is a trait that must be implemented by any storage engine that is to be used by the event store.
 #[async_trait::async_trait]
 pub trait EventStoreStorageEngine {
-    async fn create_aggregate_instance(&self, aggregate_type: &str, natural_key: Option<&str>) -> Result<i64, EventStoreError>;
-    async fn get_aggregate_instance_id(&self, aggregate_type: &str, natural_key: &str) -> Result<Option<i64>, EventStoreError>;
+    async fn create_aggregate_instance(&self, aggregate_type: &str, natural_key: Option<&str>) -> Result<AggregateId, EventStoreError> {
+        Ok(self.create_aggregate_instance_with_policy(aggregate_type, natural_key, None).await?.id)
+    }
+
+    /// Creates an aggregate instance, resolving a taken natural key with `policy`, or with the
+    /// engine's configured policy when `None`. Must be atomic against concurrent creates.
+    async fn create_aggregate_instance_with_policy(
+        &self,
+        aggregate_type: &str,
+        natural_key: Option<&str>,
+        policy: Option<DuplicateKeyPolicy>,
+    ) -> Result<CreateOutcome, EventStoreError>;
+
+    async fn get_aggregate_instance_id(&self, aggregate_type: &str, natural_key: &str) -> Result<Option<AggregateId>, EventStoreError>;
+
+    /// The instance holding `natural_key`, including whether it is soft deleted. The default
+    /// only resolves the id, for engines without soft deletes.
+    async fn find_aggregate_instance(&self, aggregate_type: &str, natural_key: &str) -> Result<Option<AggregateInstance>, EventStoreError> {
+        let id = self.get_aggregate_instance_id(aggregate_type, natural_key).await?;
+        Ok(id.map(|id| AggregateInstance {
+            id,
+            aggregate_type: aggregate_type.to_string(),
+            natural_key: Some(natural_key.to_string()),
+            deleted_at: None,
+        }))
+    }
 
+    /// Marks the instance deleted as of `deleted_at`, keeping its stream and natural key.
+    /// Deleting it again keeps the first timestamp. Fails with `AggregateInstanceNotFound`
+    /// for unknown instances. Engines advertising `SOFT_DELETE` must implement this.
+    async fn soft_delete_aggregate(&self, aggregate_type: &str, aggregate_id: AggregateId, deleted_at: DateTime<Utc>) -> Result<(), EventStoreError> {
+        let _ = (aggregate_type, aggregate_id, deleted_at);
+        Err(EventStoreError::StorageEngineErrorOther(
+            format!("{} does not support soft deleting aggregates.", self.engine_name())))
+    }
+
+    /// Clears the deletion of a soft deleted instance; instances that are not deleted are
+    /// left as they are. Fails with `AggregateInstanceNotFound` for unknown instances.
+    async fn resurrect_aggregate(&self, aggregate_type: &str, aggregate_id: AggregateId) -> Result<(), EventStoreError> {
+        let _ = (aggregate_type, aggregate_id);
+        Err(EventStoreError::StorageEngineErrorOther(
+            format!("{} does not support soft deleting aggregates.", self.engine_name())))
+    }
+
+    /// Creates an instance under the id and natural key it had in another store, for
+    /// restoring a backup. Instances created afterwards get higher ids. Fails if the id or
+    /// the natural key is taken.
+    async fn restore_aggregate_instance(&self, aggregate_type: &str, aggregate_id: AggregateId, natural_key: Option<&str>) -> Result<(), EventStoreError> {
+        let _ = (aggregate_type, aggregate_id, natural_key);
+        Err(EventStoreError::StorageEngineErrorOther(
+            format!("{} does not support restoring aggregate instances.", self.engine_name())))
+    }
+
+    /// When the instance was soft deleted, or None if it is not deleted or unknown.
+    async fn aggregate_deleted_at(&self, aggregate_type: &str, aggregate_id: AggregateId) -> Result<Option<DateTime<Utc>>, EventStoreError> {
+        let _ = (aggregate_type, aggregate_id);
+        Ok(None)
+    }
+
+    /// Returns the aggregate's events with a version above `version`, in ascending version
+    /// order regardless of the order they were written in. Loading relies on this.
+    /// Engines keeping a global feed set each event's `position`.
     async fn read_events(
         &self,
-        aggregate_id: i64,
+        aggregate_id: AggregateId,
         aggregate_type: &str,
         version: i64,
     ) -> Result<Vec<Event>, EventStoreError>;
 
     async fn read_snapshot(
         &self,
-        aggregate_id: i64,
+        aggregate_id: AggregateId,
         aggregate_type: &str,
     ) -> Result<Option<Snapshot>, EventStoreError>;
-    async fn write_updates(&self, events: &[Event], snapshot: &[Snapshot]) -> Result<(), EventStoreError>;
+
+    /// Reads the latest snapshot of each (aggregate_id, aggregate_type) pair, keyed by
+    /// aggregate id. Aggregates without a snapshot are left out. The default reads them
+    /// one at a time.
+    async fn batch_read_snapshots(&self, requests: &[(AggregateId, &str)]) -> Result<HashMap<AggregateId, Snapshot>, EventStoreError> {
+        let mut snapshots = HashMap::new();
+        for (aggregate_id, aggregate_type) in requests {
+            if let Some(snapshot) = self.read_snapshot(*aggregate_id, aggregate_type).await? {
+                snapshots.insert(*aggregate_id, snapshot);
+            }
+        }
+        Ok(snapshots)
+    }
+    /// Every stored snapshot of the aggregate, newest first, at most `limit`. Optional; meant
+    /// for inspecting how a snapshot policy behaves.
+    async fn read_snapshots(&self, aggregate_id: AggregateId, aggregate_type: &str, limit: usize) -> Result<Vec<SnapshotInfo>, EventStoreError> {
+        let _ = (aggregate_id, aggregate_type, limit);
+        Err(EventStoreError::StorageEngineErrorOther(
+            format!("{} does not support reading snapshot history.", self.engine_name())))
+    }
+
+    /// Version of the aggregate's newest stored snapshot. Engines should override this when
+    /// reading the whole snapshot is expensive.
+    async fn snapshot_head_version(&self, aggregate_id: AggregateId, aggregate_type: &str) -> Result<Option<i64>, EventStoreError> {
+        Ok(self.read_snapshot(aggregate_id, aggregate_type).await?.map(|snapshot| snapshot.version))
+    }
+
+    /// The newest snapshot at or below `max_version`. Engines keeping snapshot history should
+    /// override this; the default only considers the latest snapshot.
+    async fn read_snapshot_at(&self, aggregate_id: AggregateId, aggregate_type: &str, max_version: i64) -> Result<Option<Snapshot>, EventStoreError> {
+        let snapshot = self.read_snapshot(aggregate_id, aggregate_type).await?;
+        Ok(snapshot.filter(|snapshot| snapshot.version <= max_version))
+    }
+
+    /// Writes events and snapshots atomically, returning what was assigned to each event.
+    async fn write_updates(&self, events: &[Event], snapshot: &[Snapshot]) -> Result<Vec<WrittenEvent>, EventStoreError>;
+
+    /// Writes a batch atomically. Engines supporting lookup keys must override this;
+    /// the default only handles batches without extras.
+    async fn write_batch(&self, batch: &WriteBatch<'_>) -> Result<Vec<WrittenEvent>, EventStoreError> {
+        if batch.has_extras() {
+            return Err(EventStoreError::StorageEngineErrorOther(
+                format!("{} does not support atomic lookup key changes.", self.engine_name())));
+        }
+        self.write_updates(batch.events, batch.snapshots).await
+    }
+
+    /// Reads events across all aggregates in commit order, starting after `from_position`.
+    /// Returned events carry their `position`. Engines without a global feed return an error.
+    async fn read_all_events(&self, from_position: i64, limit: usize) -> Result<Vec<Event>, EventStoreError> {
+        let _ = (from_position, limit);
+        Err(EventStoreError::StorageEngineErrorOther(
+            format!("{} does not support reading the global feed.", self.engine_name())))
+    }
+
+    /// Position of the latest event in the global feed, or 0 when there are none.
+    async fn head_position(&self) -> Result<i64, EventStoreError> {
+        Err(EventStoreError::StorageEngineErrorOther(
+            format!("{} does not support reading the global feed.", self.engine_name())))
+    }
+
+    /// Like `read_events`, leaving out events past `max_position` in the global feed.
+    /// Returned events carry their `position`.
+    async fn read_events_until_position(&self, aggregate_id: AggregateId, aggregate_type: &str, version: i64, max_position: i64) -> Result<Vec<Event>, EventStoreError> {
+        let _ = (aggregate_id, aggregate_type, version, max_position);
+        Err(EventStoreError::StorageEngineErrorOther(
+            format!("{} does not support reading the global feed.", self.engine_name())))
+    }
+
+    /// Lists the instances of an aggregate type in creation order.
+    /// Engines that cannot enumerate instances return an error.
+    async fn list_aggregate_instances(&self, aggregate_type: &str, offset: usize, limit: usize) -> Result<Vec<AggregateInstance>, EventStoreError> {
+        let _ = (aggregate_type, offset, limit);
+        Err(EventStoreError::StorageEngineErrorOther(
+            format!("{} does not support listing aggregate instances.", self.engine_name())))
+    }
+
+    /// Lists the lookup keys of an aggregate type, ordered by aggregate id, name and value.
+    /// Engines that cannot enumerate lookup keys return an error.
+    async fn list_lookup_keys(&self, aggregate_type: &str, offset: usize, limit: usize) -> Result<Vec<LookupKey>, EventStoreError> {
+        let _ = (aggregate_type, offset, limit);
+        Err(EventStoreError::StorageEngineErrorOther(
+            format!("{} does not support listing lookup keys.", self.engine_name())))
+    }
+
+    /// Lists the known aggregate types by name. `with_counts` adds usage and first seen,
+    /// which costs a scan of the events. Engines that cannot enumerate types return an error.
+    async fn list_aggregate_types(&self, with_counts: bool) -> Result<Vec<TypeInfo>, EventStoreError> {
+        let _ = with_counts;
+        Err(EventStoreError::StorageEngineErrorOther(
+            format!("{} does not support listing aggregate types.", self.engine_name())))
+    }
+
+    /// Lists the known event types by name, see `list_aggregate_types`.
+    async fn list_event_types(&self, with_counts: bool) -> Result<Vec<TypeInfo>, EventStoreError> {
+        let _ = with_counts;
+        Err(EventStoreError::StorageEngineErrorOther(
+            format!("{} does not support listing event types.", self.engine_name())))
+    }
+
+    /// Moves every instance, event, snapshot and lookup key of `old_type` to `new_type`
+    /// atomically, in every tenant since type names are shared. Handles scoped with
+    /// `for_tenant` refuse it. Engines that cannot rename types return an error.
+    async fn migrate_aggregate_type(&self, old_type: &str, new_type: &str) -> Result<MigrationReport, EventStoreError> {
+        let _ = (old_type, new_type);
+        Err(EventStoreError::StorageEngineErrorOther(
+            format!("{} does not support migrating aggregate types.", self.engine_name())))
+    }
+
+    /// Deletes the instance with its events, snapshots, lookup keys and dedup keys
+    /// atomically, so its natural key can be taken again. Fails with
+    /// `AggregateInstanceNotFound` for unknown instances. Engines that cannot purge return
+    /// an error.
+    async fn purge_aggregate(&self, aggregate_id: AggregateId, aggregate_type: &str) -> Result<PurgeReport, EventStoreError> {
+        let _ = (aggregate_id, aggregate_type);
+        Err(EventStoreError::StorageEngineErrorOther(
+            format!("{} does not support purging aggregates.", self.engine_name())))
+    }
+
+    /// Replaces the events of an aggregate with `events` atomically and deletes its
+    /// snapshots, which were taken from the old events. `events` belong to the aggregate and
+    /// are numbered from 1; they are appended to the global feed at new positions. Fails
+    /// with `VersionConflict`, changing nothing, when the aggregate is no longer at
+    /// `expected_version`. Engines that cannot replace events return an error.
+    async fn replace_events(&self, aggregate_id: AggregateId, aggregate_type: &str, expected_version: i64, events: &[Event]) -> Result<RewriteReport, EventStoreError> {
+        let _ = (aggregate_id, aggregate_type, expected_version, events);
+        Err(EventStoreError::StorageEngineErrorOther(
+            format!("{} does not support replacing events.", self.engine_name())))
+    }
+
+    async fn add_lookup_key(&self, key: &LookupKey) -> Result<(), EventStoreError>;
+    async fn remove_lookup_key(&self, key: &LookupKey) -> Result<(), EventStoreError>;
+
+    /// Returns the ids of all aggregates of the given type carrying the lookup key.
+    async fn find_by_lookup_key(&self, aggregate_type: &str, key_name: &str, key_value: &str) -> Result<Vec<AggregateId>, EventStoreError>;
+
+    /// Whether a dedup key has been recorded. Keys are written through `write_batch`
+    /// and must be unique; writing a batch with a recorded key fails with `DuplicateEvent`.
+    async fn has_dedup_key(&self, key: &str) -> Result<bool, EventStoreError>;
+
+    /// Deletes dedup keys recorded before `older_than`, returning how many were removed.
+    async fn prune_dedup_keys(&self, older_than: DateTime<Utc>) -> Result<usize, EventStoreError>;
+
+    /// Deletes all but the newest `keep` snapshots of every aggregate, returning how many were
+    /// removed. Engines offloading snapshots to a blob store delete the blobs as well.
+    async fn prune_snapshots(&self, keep: usize) -> Result<usize, EventStoreError> {
+        let _ = keep;
+        Err(EventStoreError::StorageEngineErrorOther(
+            format!("{} does not support pruning snapshots.", self.engine_name())))
+    }
+
+    /// Global feed position a projection recorded with `write_checkpoint`, or None if it has
+    /// not recorded one. Engines that cannot store checkpoints return an error.
+    async fn read_checkpoint(&self, projection: &str) -> Result<Option<i64>, EventStoreError> {
+        let _ = projection;
+        Err(EventStoreError::StorageEngineErrorOther(
+            format!("{} does not support projection checkpoints.", self.engine_name())))
+    }
+
+    /// Records the global feed position a projection has handled, replacing the previous one.
+    async fn write_checkpoint(&self, projection: &str, position: i64) -> Result<(), EventStoreError> {
+        let _ = (projection, position);
+        Err(EventStoreError::StorageEngineErrorOther(
+            format!("{} does not support projection checkpoints.", self.engine_name())))
+    }
+
+    /// Returns the highest stored event version for the aggregate, or 0 if it has no events.
+    async fn get_aggregate_version(&self, aggregate_id: AggregateId, aggregate_type: &str) -> Result<i64, EventStoreError>;
+
+    /// Returns the lowest stored event version for the aggregate, or None if it has no events.
+    /// Above 1 once older events have been pruned. The default reads the whole stream.
+    async fn earliest_event_version(&self, aggregate_id: AggregateId, aggregate_type: &str) -> Result<Option<i64>, EventStoreError> {
+        let events = self.read_events(aggregate_id, aggregate_type, 0).await?;
+        Ok(events.iter().map(|event| event.version).min())
+    }
+
+    /// A handle on the same storage scoped to `tenant_id` (checked with
+    /// `arguments::tenant_id`). It reads and writes only the tenant's aggregate instances,
+    /// events, snapshots, lookup keys, dedup keys and projection checkpoints, its global
+    /// feed holds only the tenant's events, and natural keys are unique per tenant. The
+    /// unscoped engine sees none of the tenants' data. Aggregate ids stay unique across
+    /// tenants, while type names are shared by all of them.
+    ///
+    /// Engines supporting it advertise `EngineCapabilities::TENANT_COLUMN`.
+    fn for_tenant(&self, tenant_id: &str) -> Result<Arc<dyn EventStoreStorageEngine + Send + Sync>, EventStoreError> {
+        let _ = tenant_id;
+        Err(EventStoreError::StorageEngineErrorOther(
+            format!("{} does not support tenants.", self.engine_name())))
+    }
+
+    /// The tenant a handle returned by `for_tenant` is scoped to, None for an unscoped engine.
+    fn tenant_id(&self) -> Option<&str> {
+        None
+    }
+
+    /// Optional features this engine supports. Engines must only advertise what they implement.
+    fn capabilities(&self) -> EngineCapabilities {
+        EngineCapabilities::empty()
+    }
+
+    /// Short name identifying the engine implementation.
+    fn engine_name(&self) -> &str {
+        std::any::type_name::<Self>()
+    }
+
+    /// Human readable description of the engine and its configuration, for log output.
+    fn description(&self) -> String {
+        self.engine_name().to_string()
+    }
 }
 
 
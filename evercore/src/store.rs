@@ -0,0 +1,3354 @@
+use crate::clock::{Clock, StreamKey, SystemClock};
+use crate::contexts::{run_commit_hooks, CapturedWrites, CommitReceipt, EventContext, EventContextPool};
+use crate::coordinator::{batch_streams, CommitCoordinator};
+use crate::cursor::{Cursor, CursorKind, Page, ViewToken};
+use crate::deadline;
+use crate::inline_projection::{InlineProjections, ProjectionState};
+use crate::integrity::{canonical_json, event_hash, verify_chain, FeedIntegrityReport, FeedVerifier};
+use crate::maintenance::{MaintenanceScheduler, Schedule};
+use crate::metadata::{MetadataAudit, MetadataProvider};
+use crate::metrics::{LoadMetrics, LoadStats};
+use crate::notifications::{CommitNotification, Notifier};
+use crate::offline::{self, CommandOutcome, CommandRegistry};
+use crate::aggregate::{Aggregate, Composable};
+use serde::{de::DeserializeOwned, Serialize};
+use crate::operational::{OperationalEvent, OPERATIONAL_EVENT_CAPACITY};
+use crate::payload::{JsonSerializer, Payload, PayloadSerializer};
+use crate::quiesce::{QuiesceGuard, QuiescePolicy, WriteGate};
+use crate::retention::{RetentionPolicy, RetentionReport};
+use crate::storage_engine::enriched;
+#[cfg(feature = "encryption")]
+use crate::encryption::{ChaCha20Poly1305Encryption, EncryptedFields, EventEncryption, FieldEncryption, KeyStore};
+
+use std::{any::Any, collections::HashMap, sync::{Arc, Mutex, OnceLock}, future::Future};
+use tokio::sync::broadcast;
+use uuid::Uuid;
+
+use crate::event::Event;
+use crate::snapshot::{Snapshot, SnapshotPolicy};
+use crate::{AggregateId, CreateOutcome, DuplicateKeyPolicy, EngineCapabilities, EventStoreError, EventStoreStorageEngine};
+use crate::{AggregateInstance, MigrationReport, PurgeReport, RewriteReport, SnapshotInfo, TypeInfo, WriteBatch, WrittenEvent};
+use crate::arguments;
+
+
+/// EventStore is the main struct for the event store.
+#[derive(Clone)]
+pub struct EventStore {
+    pub(crate) storage_engine: Arc<dyn EventStoreStorageEngine + Send + Sync>,
+    redact_fields: Vec<String>,
+    inline_projections: Arc<InlineProjections>,
+    pub(crate) registered_name: OnceLock<String>,
+    duplicate_policy: DuplicatePolicy,
+    commit_coordinator: Option<Arc<CommitCoordinator>>,
+    operational_events: broadcast::Sender<OperationalEvent>,
+    notifier: Notifier,
+    pub(crate) store_id: Uuid,
+    clock: Arc<dyn Clock>,
+    snapshot_policies: HashMap<String, SnapshotPolicy>,
+    default_snapshot_policy: Option<SnapshotPolicy>,
+    record_commands: bool,
+    load_metrics: Arc<Mutex<HashMap<String, LoadMetrics>>>,
+    hash_events: bool,
+    verify_hashes: bool,
+    write_gate: Arc<WriteGate>,
+    required_metadata: Vec<String>,
+    metadata_providers: Vec<MetadataProvider>,
+    max_metadata_entries: usize,
+    max_page_size: usize,
+    retention: Option<(Schedule, RetentionPolicy)>,
+    payload_serializer: Arc<dyn PayloadSerializer>,
+    #[cfg(feature = "encryption")]
+    encryption: Option<FieldEncryption>,
+}
+
+/// What `EventContext::publish_dedup` does when its dedup key was already ingested.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DuplicatePolicy {
+    /// Skip the event without reporting an error.
+    #[default]
+    Ignore,
+    /// Fail with `EventStoreError::DuplicateEvent`.
+    Error,
+}
+
+/// Metadata entries a context holds at most unless configured otherwise.
+pub const DEFAULT_MAX_METADATA_ENTRIES: usize = 256;
+
+/// Largest page a single read returns unless configured otherwise.
+pub const DEFAULT_MAX_PAGE_SIZE: usize = 10_000;
+
+/// Number of events read per page when replaying the global feed.
+pub(crate) const FEED_PAGE_SIZE: usize = 500;
+
+pub type SharedEventStore = Arc<EventStore>;
+pub type SharedEventContext = Arc<EventContext>;
+
+/// Builder for an EventStore with non-default options.
+pub struct EventStoreBuilder {
+    storage_engine: Arc<dyn EventStoreStorageEngine + Send + Sync>,
+    redact_fields: Vec<String>,
+    required_capabilities: EngineCapabilities,
+    duplicate_policy: DuplicatePolicy,
+    commit_concurrency: Option<usize>,
+    clock: Arc<dyn Clock>,
+    snapshot_policies: HashMap<String, SnapshotPolicy>,
+    default_snapshot_policy: Option<SnapshotPolicy>,
+    record_commands: bool,
+    hash_events: bool,
+    verify_hashes: bool,
+    quiesce_policy: QuiescePolicy,
+    required_metadata: Vec<String>,
+    metadata_providers: Vec<MetadataProvider>,
+    max_metadata_entries: usize,
+    max_page_size: usize,
+    retention: Option<(Schedule, RetentionPolicy)>,
+    payload_serializer: Arc<dyn PayloadSerializer>,
+    #[cfg(feature = "encryption")]
+    key_store: Option<Arc<dyn KeyStore>>,
+    #[cfg(feature = "encryption")]
+    event_encryption: Arc<dyn EventEncryption>,
+    #[cfg(feature = "encryption")]
+    encrypted_fields: HashMap<String, EncryptedFields>,
+}
+
+impl EventStoreBuilder {
+    pub fn new(storage_engine: Arc<dyn EventStoreStorageEngine + Send + Sync>) -> EventStoreBuilder {
+        EventStoreBuilder {
+            storage_engine,
+            redact_fields: Vec::new(),
+            required_capabilities: EngineCapabilities::empty(),
+            duplicate_policy: DuplicatePolicy::default(),
+            commit_concurrency: None,
+            clock: Arc::new(SystemClock),
+            snapshot_policies: HashMap::new(),
+            default_snapshot_policy: None,
+            record_commands: false,
+            hash_events: false,
+            verify_hashes: false,
+            quiesce_policy: QuiescePolicy::default(),
+            required_metadata: Vec::new(),
+            metadata_providers: Vec::new(),
+            max_metadata_entries: DEFAULT_MAX_METADATA_ENTRIES,
+            max_page_size: DEFAULT_MAX_PAGE_SIZE,
+            retention: None,
+            payload_serializer: Arc::new(JsonSerializer),
+            #[cfg(feature = "encryption")]
+            key_store: None,
+            #[cfg(feature = "encryption")]
+            event_encryption: Arc::new(ChaCha20Poly1305Encryption),
+            #[cfg(feature = "encryption")]
+            encrypted_fields: HashMap::new(),
+        }
+    }
+
+    /// Metadata keys whose values are masked when events are formatted for output.
+    pub fn redact_fields(mut self, fields: &[&str]) -> EventStoreBuilder {
+        self.redact_fields = fields.iter().map(|field| field.to_string()).collect();
+        self
+    }
+
+    /// How `publish_dedup` treats an already ingested dedup key (skipped by default).
+    pub fn on_duplicate(mut self, policy: DuplicatePolicy) -> EventStoreBuilder {
+        self.duplicate_policy = policy;
+        self
+    }
+
+    /// Engine capabilities the application relies on; `build` fails if the engine lacks any.
+    pub fn require_capabilities(mut self, capabilities: EngineCapabilities) -> EventStoreBuilder {
+        self.required_capabilities = self.required_capabilities | capabilities;
+        self
+    }
+
+    /// Routes commits through a `CommitCoordinator`: commits on the same aggregate run one
+    /// after another, disjoint ones in parallel with at most `max_concurrency` in flight.
+    pub fn commit_coordinator(mut self, max_concurrency: usize) -> EventStoreBuilder {
+        self.commit_concurrency = Some(max_concurrency);
+        self
+    }
+
+    /// Clock used to stamp events and evaluate time based policies (the system clock by default).
+    pub fn clock(mut self, clock: Arc<dyn Clock>) -> EventStoreBuilder {
+        self.clock = clock;
+        self
+    }
+
+    /// Snapshots `aggregate_type` according to `policy`, whatever policy the aggregate asks for.
+    pub fn snapshot_policy(mut self, aggregate_type: &str, policy: SnapshotPolicy) -> EventStoreBuilder {
+        self.snapshot_policies.insert(aggregate_type.to_string(), policy);
+        self
+    }
+
+    /// Snapshots aggregates without a policy of their own according to `policy` instead of
+    /// their `snapshot_frequency`.
+    pub fn default_snapshot_policy(mut self, policy: SnapshotPolicy) -> EventStoreBuilder {
+        self.default_snapshot_policy = Some(policy);
+        self
+    }
+
+    /// Records the command behind each event requested through `ComposedAggregate` in the
+    /// event metadata, masking the aggregate's `redact_command_fields`. Off by default.
+    pub fn record_commands(mut self, record: bool) -> EventStoreBuilder {
+        self.record_commands = record;
+        self
+    }
+
+    /// Canonicalizes the JSON data and metadata of published events and chains each event to
+    /// its predecessor with a SHA-256 hash, see `integrity::event_hash`. Off by default.
+    pub fn hash_events(mut self, hash: bool) -> EventStoreBuilder {
+        self.hash_events = hash;
+        self
+    }
+
+    /// Checks hashed events against their hashes when they are read for an aggregate,
+    /// failing with `IntegrityViolation` on a mismatch. Off by default.
+    pub fn verify_hashes(mut self, verify: bool) -> EventStoreBuilder {
+        self.verify_hashes = verify;
+        self
+    }
+
+    /// What commits do while the store is quiesced (they wait by default).
+    pub fn quiesce_policy(mut self, policy: QuiescePolicy) -> EventStoreBuilder {
+        self.quiesce_policy = policy;
+        self
+    }
+
+    /// Metadata keys every event must carry, such as the acting user. Publishing fails with
+    /// `MissingMetadata` listing the keys absent from the context and its providers, and
+    /// committing fails with `MetadataExpired` once a required value added with an expiry
+    /// has expired. `EventStore::audit_required_metadata` checks stored events.
+    pub fn required_metadata(mut self, keys: &[&str]) -> EventStoreBuilder {
+        self.required_metadata = keys.iter().map(|key| key.to_string()).collect();
+        self
+    }
+
+    /// Adds metadata from `provider` to every event, under what the context holds. Providers
+    /// are asked in registration order, later ones winning on the same key.
+    pub fn metadata_provider<F>(mut self, provider: F) -> EventStoreBuilder
+    where
+        F: Fn() -> HashMap<String, String> + Send + Sync + 'static
+    {
+        self.metadata_providers.push(Arc::new(provider));
+        self
+    }
+
+    /// Most metadata entries a context holds; adding more fails with
+    /// `MetadataLimitExceeded`. Defaults to `DEFAULT_MAX_METADATA_ENTRIES`.
+    pub fn max_metadata_entries(mut self, limit: usize) -> EventStoreBuilder {
+        self.max_metadata_entries = limit;
+        self
+    }
+
+    /// Largest `limit` accepted by paged reads such as `read_all_events_page`; larger ones
+    /// fail with `InvalidArgument`. Defaults to `DEFAULT_MAX_PAGE_SIZE`.
+    pub fn max_page_size(mut self, limit: usize) -> EventStoreBuilder {
+        self.max_page_size = limit;
+        self
+    }
+
+    /// Applies `policy` on `schedule` as the "retention" job of every scheduler returned by
+    /// `EventStore::maintenance`.
+    pub fn retention(mut self, schedule: impl Into<Schedule>, policy: RetentionPolicy) -> EventStoreBuilder {
+        self.retention = Some((schedule.into(), policy));
+        self
+    }
+
+    /// Format event and snapshot data is stored in (JSON by default), see
+    /// `payload::PayloadSerializer`. Binary formats need an engine supporting binary payloads.
+    pub fn payload_serializer(mut self, serializer: Arc<dyn PayloadSerializer>) -> EventStoreBuilder {
+        self.payload_serializer = serializer;
+        self
+    }
+
+    /// Where the per-aggregate keys for `encrypted_fields` are kept.
+    #[cfg(feature = "encryption")]
+    pub fn key_store(mut self, key_store: Arc<dyn KeyStore>) -> EventStoreBuilder {
+        self.key_store = Some(key_store);
+        self
+    }
+
+    /// Cipher sealing the `encrypted_fields` (ChaCha20-Poly1305 by default), see
+    /// `encryption::EventEncryption`.
+    #[cfg(feature = "encryption")]
+    pub fn event_encryption(mut self, cipher: Arc<dyn EventEncryption>) -> EventStoreBuilder {
+        self.event_encryption = cipher;
+        self
+    }
+
+    /// Encrypts `fields` of `aggregate_type` at rest, see `encryption::EncryptedFields`.
+    /// Needs a `key_store`.
+    #[cfg(feature = "encryption")]
+    pub fn encrypted_fields(mut self, aggregate_type: &str, fields: EncryptedFields) -> EventStoreBuilder {
+        self.encrypted_fields.insert(aggregate_type.to_string(), fields);
+        self
+    }
+
+    /// Validates the configuration and builds the store.
+    /// Every problem found is reported at once in a `ConfigurationError`.
+    pub fn build(self) -> Result<SharedEventStore, EventStoreError> {
+        let problems = self.validate();
+        if !problems.is_empty() {
+            return Err(EventStoreError::ConfigurationError(problems));
+        }
+
+        Ok(Into::into(EventStore {
+            storage_engine: self.storage_engine,
+            redact_fields: self.redact_fields,
+            inline_projections: Arc::new(InlineProjections::default()),
+            registered_name: OnceLock::new(),
+            duplicate_policy: self.duplicate_policy,
+            commit_coordinator: self.commit_concurrency.map(|limit| Arc::new(CommitCoordinator::new(limit))),
+            operational_events: broadcast::channel(OPERATIONAL_EVENT_CAPACITY).0,
+            notifier: Notifier::new(),
+            store_id: Uuid::new_v4(),
+            clock: self.clock,
+            snapshot_policies: self.snapshot_policies,
+            default_snapshot_policy: self.default_snapshot_policy,
+            record_commands: self.record_commands,
+            load_metrics: Arc::new(Mutex::new(HashMap::new())),
+            hash_events: self.hash_events,
+            verify_hashes: self.verify_hashes,
+            write_gate: Arc::new(WriteGate::new(self.quiesce_policy)),
+            required_metadata: self.required_metadata,
+            metadata_providers: self.metadata_providers,
+            max_metadata_entries: self.max_metadata_entries,
+            max_page_size: self.max_page_size,
+            retention: self.retention,
+            payload_serializer: self.payload_serializer,
+            #[cfg(feature = "encryption")]
+            encryption: self.key_store.map(|key_store| FieldEncryption::new(key_store, self.event_encryption, self.encrypted_fields)),
+        }))
+    }
+
+    fn validate(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+
+        let missing = self.required_capabilities.difference(self.storage_engine.capabilities());
+        for capability in missing.names() {
+            problems.push(format!("{} does not support {}", self.storage_engine.engine_name(), capability));
+        }
+
+        for (index, field) in self.redact_fields.iter().enumerate() {
+            if field.is_empty() {
+                problems.push("redact_fields contains an empty field name".to_string());
+            } else if self.redact_fields[..index].contains(field) {
+                problems.push(format!("redact field '{}' is listed more than once", field));
+            }
+        }
+
+        if self.commit_concurrency == Some(0) {
+            problems.push("commit coordinator concurrency must be at least 1".to_string());
+        }
+
+        if self.payload_serializer.is_binary() && !self.storage_engine.capabilities().contains(EngineCapabilities::BINARY_PAYLOADS) {
+            problems.push(format!("{} does not support binary payloads, needed by the {} serializer",
+                self.storage_engine.engine_name(), self.payload_serializer.name()));
+        }
+
+        #[cfg(feature = "encryption")]
+        for (aggregate_type, fields) in &self.encrypted_fields {
+            if self.key_store.is_none() {
+                problems.push(format!("encrypted fields of '{}' need a key store", aggregate_type));
+            }
+            for pointer in fields.pointers().filter(|pointer| !pointer.starts_with('/')) {
+                problems.push(format!("encrypted field '{}' of '{}' is not a JSON pointer", pointer, aggregate_type));
+            }
+        }
+
+        problems
+    }
+}
+
+impl EventStore {
+
+    /// Create a new EventStore with the given storage engine.
+    pub fn new(storage_engine: Arc<dyn EventStoreStorageEngine + Send + Sync>) -> SharedEventStore {
+        EventStoreBuilder::new(storage_engine).build()
+            .expect("the default configuration is always valid")
+    }
+
+    /// Create a builder for an EventStore with the given storage engine.
+    pub fn builder(storage_engine: Arc<dyn EventStoreStorageEngine + Send + Sync>) -> EventStoreBuilder {
+        EventStoreBuilder::new(storage_engine)
+    }
+
+    /// A handle on this store scoped to `tenant_id`, through
+    /// `EventStoreStorageEngine::for_tenant`: its contexts load and publish only the
+    /// tenant's aggregates, its feed holds only the tenant's events, and natural keys are
+    /// unique per tenant. It keeps the store's configuration, write gate and commit
+    /// coordinator, while inline projections, notifications and operational events are its
+    /// own. Fails when the engine does not support tenants.
+    pub fn for_tenant(&self, tenant_id: &str) -> Result<SharedEventStore, EventStoreError> {
+        arguments::tenant_id(tenant_id)?;
+        let storage_engine = self.storage_engine.for_tenant(tenant_id)?;
+        Ok(Arc::new(EventStore {
+            storage_engine,
+            inline_projections: Arc::new(InlineProjections::default()),
+            registered_name: OnceLock::new(),
+            operational_events: broadcast::channel(OPERATIONAL_EVENT_CAPACITY).0,
+            notifier: Notifier::new(),
+            store_id: Uuid::new_v4(),
+            ..self.clone()
+        }))
+    }
+
+    /// The tenant the store was scoped to with `for_tenant`, None for an unscoped store.
+    pub fn tenant_id(&self) -> Option<&str> {
+        self.storage_engine.tenant_id()
+    }
+
+    /// Name the store was registered under in an `EventStoreRegistry`, if any.
+    pub fn registered_name(&self) -> Option<&str> {
+        self.registered_name.get().map(String::as_str)
+    }
+
+    pub fn duplicate_policy(&self) -> DuplicatePolicy {
+        self.duplicate_policy
+    }
+
+    /// Whether requested commands are recorded in the metadata of their events.
+    pub fn records_commands(&self) -> bool {
+        self.record_commands
+    }
+
+    /// Whether published events are canonicalized and hash chained.
+    pub fn hashes_events(&self) -> bool {
+        self.hash_events
+    }
+
+    /// The retention policy configured with `EventStoreBuilder::retention`, if any.
+    pub fn retention_policy(&self) -> Option<&RetentionPolicy> {
+        self.retention.as_ref().map(|(_, policy)| policy)
+    }
+
+    /// The current time according to the store's clock.
+    pub fn now(&self) -> chrono::DateTime<chrono::Utc> {
+        self.clock.now()
+    }
+
+    pub(crate) fn max_metadata_entries(&self) -> usize {
+        self.max_metadata_entries
+    }
+
+    pub(crate) fn required_metadata(&self) -> &[String] {
+        &self.required_metadata
+    }
+
+    /// What the metadata providers supply for an event published now.
+    pub(crate) fn provided_metadata(&self) -> HashMap<String, String> {
+        self.metadata_providers.iter().flat_map(|provider| provider()).collect()
+    }
+
+    /// The policy snapshotting `source`, in the order described on `SnapshotPolicy`.
+    pub(crate) fn snapshot_policy<'a, A: Aggregate<'a> + ?Sized>(&self, source: &A) -> SnapshotPolicy {
+        self.snapshot_policies.get(source.aggregate_type()).cloned()
+            .or_else(|| source.snapshot_policy())
+            .or_else(|| self.default_snapshot_policy.clone())
+            .unwrap_or_else(|| SnapshotPolicy::EveryNEvents(source.snapshot_frequency().into()))
+    }
+
+    /// Load costs recorded so far, per aggregate type.
+    pub fn load_metrics(&self) -> Result<HashMap<String, LoadMetrics>, EventStoreError> {
+        Ok(self.load_metrics.lock()?.clone())
+    }
+
+    pub(crate) fn record_load(&self, aggregate_type: &str, stats: &LoadStats) -> Result<(), EventStoreError> {
+        self.load_metrics.lock()?
+            .entry(aggregate_type.to_string())
+            .or_default()
+            .record(stats);
+        Ok(())
+    }
+
+    /// Subscribes to store-level operational events emitted from now on.
+    pub fn operational_events(&self) -> broadcast::Receiver<OperationalEvent> {
+        self.operational_events.subscribe()
+    }
+
+    pub(crate) fn emit(&self, event: OperationalEvent) {
+        // Sending only fails when nobody is subscribed, which is fine.
+        let _ = self.operational_events.send(event);
+    }
+
+    /// Subscribes to each event committed from now on, in commit order.
+    pub fn watch_events(&self) -> broadcast::Receiver<Event> {
+        self.notifier.watch_events()
+    }
+
+    /// Subscribes to the commits made from now on, one notification per commit holding all
+    /// of its events. Commits that wrote no events are left out.
+    pub fn watch_commits(&self) -> broadcast::Receiver<CommitNotification> {
+        self.notifier.watch_commits()
+    }
+
+    pub(crate) fn notify_commit(&self, context_ids: Vec<Uuid>, receipt: &CommitReceipt) {
+        self.notifier.notify(context_ids, &receipt.events);
+    }
+
+    /// Describes the underlying storage engine.
+    pub fn description(&self) -> String {
+        self.storage_engine.description()
+    }
+
+    /// Renders an event as indented JSON with the configured redact_fields masked.
+    pub fn format_event(&self, event: &Event) -> String {
+        event.pretty_redacted(&self.redact_fields)
+    }
+
+    pub async fn next_aggregate_id(&self, aggregate_type: &str, natural_key: Option<&str>) -> Result<AggregateId, EventStoreError> {
+        self.storage_engine.create_aggregate_instance(aggregate_type, natural_key).await 
+    }
+
+    /// Resolves the id of the aggregate instance created with `natural_key`, if any.
+    pub async fn get_aggregate_instance_id(&self, aggregate_type: &str, natural_key: &str) -> Result<Option<AggregateId>, EventStoreError> {
+        self.storage_engine.get_aggregate_instance_id(aggregate_type, natural_key).await
+    }
+
+    /// The instance holding `natural_key`, including whether it is soft deleted, so callers
+    /// can decide to resurrect it.
+    pub async fn find_aggregate_instance(&self, aggregate_type: &str, natural_key: &str) -> Result<Option<AggregateInstance>, EventStoreError> {
+        self.storage_engine.find_aggregate_instance(aggregate_type, natural_key).await
+    }
+
+    /// Soft deletes an aggregate: its events are kept, but loading it or committing events
+    /// to it fails with `AggregateDeleted` until it is resurrected.
+    pub async fn soft_delete_aggregate(&self, aggregate_type: &str, aggregate_id: AggregateId) -> Result<(), EventStoreError> {
+        self.storage_engine.soft_delete_aggregate(aggregate_type, aggregate_id, self.now()).await
+    }
+
+    /// Deletes an aggregate for good, e.g. to honour an erasure request: its events,
+    /// snapshots, lookup keys and dedup keys go with the instance in one step, loading it
+    /// fails with `AggregateNotFound` afterwards and its natural key can be taken again.
+    /// Purge only aggregates no request is working on: whether events published through an
+    /// earlier load are written afterwards depends on the engine.
+    pub async fn purge_aggregate(&self, aggregate_id: AggregateId, aggregate_type: &str) -> Result<PurgeReport, EventStoreError> {
+        let report = self.storage_engine.purge_aggregate(aggregate_id, aggregate_type).await?;
+        self.emit(OperationalEvent::AggregatePurged {
+            aggregate_type: aggregate_type.to_string(),
+            aggregate_id,
+            report: report.clone(),
+        });
+        Ok(report)
+    }
+
+    /// Rewrites the history of an aggregate, e.g. to drop a sensitive field from old events:
+    /// `transform` maps each stored event in version order, returning None to drop it. The
+    /// events kept are renumbered from 1 and replace the old ones in one step, appended to
+    /// the global feed at new positions, and the aggregate's snapshots are deleted so the
+    /// next load replays the rewritten events. Of the events returned, only the event type,
+    /// data, metadata and created_at are kept.
+    ///
+    /// Fails with `HistoryUnavailable` when older events were pruned, since the rewritten
+    /// events could not rebuild the state those held, and with `VersionConflict` when events
+    /// were written meanwhile. Like purging, rewrite only aggregates no request is working
+    /// on. Inline projections and feed consumers are not told; rebuild those that folded
+    /// the old events.
+    pub async fn rewrite_stream<F>(&self, aggregate_id: AggregateId, aggregate_type: &str, transform: F) -> Result<RewriteReport, EventStoreError>
+        where F: Fn(Event) -> Option<Event>
+    {
+        arguments::aggregate_id(aggregate_id)?;
+        let _write = self.write_gate.enter().await?;
+        let _permit = match &self.commit_coordinator {
+            Some(coordinator) => Some(coordinator.acquire(vec![(aggregate_type.to_string(), aggregate_id)]).await?),
+            None => None,
+        };
+        if let Some(earliest) = self.storage_engine.earliest_event_version(aggregate_id, aggregate_type).await? {
+            if earliest > 1 {
+                return Err(EventStoreError::HistoryUnavailable { earliest });
+            }
+        }
+
+        let stored = self.get_events(aggregate_id, aggregate_type, 0).await?;
+        let expected_version = stored.last().map_or(0, |event| event.version);
+        let mut events: Vec<Event> = Vec::with_capacity(stored.len());
+        for event in stored.into_iter().filter_map(transform) {
+            let mut event = Event {
+                aggregate_id,
+                aggregate_type: aggregate_type.to_string(),
+                version: events.len() as i64 + 1,
+                position: None,
+                hash: None,
+                ..event
+            };
+            if self.hash_events {
+                event.data = Payload::Json(canonical_json(event.data.json()?)?);
+                event.metadata = event.metadata.as_deref().map(canonical_json).transpose()?;
+            }
+            events.push(event);
+        }
+
+        #[cfg(feature = "encryption")]
+        if let Some(encryption) = &self.encryption {
+            events = encryption.encrypt_events(&events).await?;
+        }
+        if self.payload_serializer.is_binary() {
+            events = self.encoded(&events, |event| &mut event.data)?;
+        }
+        // The rewritten stream starts a new chain, nothing stored precedes it.
+        if self.hash_events {
+            let mut previous: Option<String> = None;
+            for event in &mut events {
+                event.hash = Some(event_hash(event, previous.as_deref()));
+                previous = event.hash.clone();
+            }
+        }
+
+        let report = self.storage_engine.replace_events(aggregate_id, aggregate_type, expected_version, &events).await?;
+        self.emit(OperationalEvent::StreamRewritten {
+            aggregate_type: aggregate_type.to_string(),
+            aggregate_id,
+            report: report.clone(),
+        });
+        Ok(report)
+    }
+
+    /// Makes a soft deleted aggregate loadable again.
+    pub async fn resurrect_aggregate(&self, aggregate_type: &str, aggregate_id: AggregateId) -> Result<(), EventStoreError> {
+        self.storage_engine.resurrect_aggregate(aggregate_type, aggregate_id).await
+    }
+
+    /// Fails with `AggregateDeleted` if the aggregate is soft deleted. Engines without
+    /// soft deletes are not asked.
+    pub(crate) async fn check_not_deleted(&self, aggregate_type: &str, aggregate_id: AggregateId) -> Result<(), EventStoreError> {
+        if !self.storage_engine.capabilities().contains(EngineCapabilities::SOFT_DELETE) {
+            return Ok(());
+        }
+        match self.storage_engine.aggregate_deleted_at(aggregate_type, aggregate_id).await? {
+            Some(_) => Err(EventStoreError::AggregateDeleted { aggregate_type: aggregate_type.to_string(), aggregate_id }),
+            None => Ok(()),
+        }
+    }
+
+    /// Creates an aggregate instance, overriding the engine's `DuplicateKeyPolicy` when `policy` is given.
+    pub async fn create_aggregate_instance(&self, aggregate_type: &str, natural_key: Option<&str>, policy: Option<DuplicateKeyPolicy>) -> Result<CreateOutcome, EventStoreError> {
+        self.storage_engine.create_aggregate_instance_with_policy(aggregate_type, natural_key, policy).await
+    }
+
+    pub async fn get_events(
+        &self,
+        aggregate_id: AggregateId,
+        aggregate_type: &str,
+        version: i64,
+    ) -> Result<Vec<Event>, EventStoreError> {
+        arguments::aggregate_id(aggregate_id)?;
+        arguments::non_negative("version", version)?;
+        if !self.verify_hashes {
+            let events = self.storage_engine.read_events(aggregate_id, aggregate_type, version).await?;
+            return self.decoded(events).await;
+        }
+
+        // Read one event more to anchor the chain at the requested version.
+        let mut events = self.storage_engine.read_events(aggregate_id, aggregate_type, (version - 1).max(0)).await?;
+        let previous = match events.first() {
+            Some(first) if version > 0 && first.version == version => Some(events.remove(0).hash),
+            _ => None,
+        };
+        verify_chain(previous.flatten().as_deref(), &events)?;
+        self.decoded(events).await
+    }
+
+    /// Reads the events of an aggregate up to `max_position` in the global feed, checking
+    /// their hashes when the store verifies them.
+    pub async fn get_events_until_position(
+        &self,
+        aggregate_id: AggregateId,
+        aggregate_type: &str,
+        max_position: i64,
+    ) -> Result<Vec<Event>, EventStoreError> {
+        arguments::aggregate_id(aggregate_id)?;
+        arguments::non_negative("max_position", max_position)?;
+        let events = self.storage_engine.read_events_until_position(aggregate_id, aggregate_type, 0, max_position).await?;
+        if self.verify_hashes {
+            verify_chain(None, &events)?;
+        }
+        self.decoded(events).await
+    }
+
+    /// Takes a view of the store as of now, excluding everything committed later, for
+    /// loading several aggregates at one point in time. Needs an engine with a global feed.
+    pub async fn consistent_view(&self) -> Result<ViewToken, EventStoreError> {
+        let position = self.storage_engine.head_position().await?;
+        Ok(ViewToken::new(self.store_id, position))
+    }
+
+    pub async fn get_snapshot(
+        &self,
+        aggregate_id: AggregateId,
+        aggregate_type: &str,
+    ) -> Result<Option<Snapshot>, EventStoreError> {
+        arguments::aggregate_id(aggregate_id)?;
+        let snapshot = self.storage_engine.read_snapshot(aggregate_id, aggregate_type).await?;
+        snapshot.as_ref().map(|snapshot| snapshot.check_type(aggregate_type)).transpose()?;
+        self.decoded_snapshot(snapshot).await
+    }
+
+    /// Stored snapshots of an aggregate, newest first, at most `limit`.
+    pub async fn read_snapshots(
+        &self,
+        aggregate_id: AggregateId,
+        aggregate_type: &str,
+        limit: usize,
+    ) -> Result<Vec<SnapshotInfo>, EventStoreError> {
+        arguments::aggregate_id(aggregate_id)?;
+        arguments::limit(limit, self.max_page_size)?;
+        self.storage_engine.read_snapshots(aggregate_id, aggregate_type, limit).await
+    }
+
+    /// The newest snapshot of an aggregate at or below `max_version`.
+    pub async fn read_snapshot_at(
+        &self,
+        aggregate_id: AggregateId,
+        aggregate_type: &str,
+        max_version: i64,
+    ) -> Result<Option<Snapshot>, EventStoreError> {
+        arguments::aggregate_id(aggregate_id)?;
+        arguments::non_negative("max_version", max_version)?;
+        let snapshot = self.storage_engine.read_snapshot_at(aggregate_id, aggregate_type, max_version).await?;
+        snapshot.as_ref().map(|snapshot| snapshot.check_type(aggregate_type)).transpose()?;
+        self.decoded_snapshot(snapshot).await
+    }
+
+    /// Events read from storage with their payloads decoded to JSON and their encrypted
+    /// fields decrypted.
+    pub(crate) async fn decoded(&self, mut events: Vec<Event>) -> Result<Vec<Event>, EventStoreError> {
+        for event in &mut events {
+            if let Payload::Binary(bytes) = &event.data {
+                event.data = Payload::Json(self.payload_serializer.decode(bytes)?);
+            }
+        }
+        #[cfg(feature = "encryption")]
+        if let Some(encryption) = &self.encryption {
+            return encryption.decrypt_events(events).await;
+        }
+        Ok(events)
+    }
+
+    pub(crate) async fn decoded_snapshot(&self, mut snapshot: Option<Snapshot>) -> Result<Option<Snapshot>, EventStoreError> {
+        if let Some(Snapshot { data: data @ Payload::Binary(_), .. }) = &mut snapshot {
+            *data = Payload::Json(self.payload_serializer.decode(data.as_bytes())?);
+        }
+        #[cfg(feature = "encryption")]
+        if let Some(encryption) = &self.encryption {
+            return match snapshot {
+                Some(snapshot) => encryption.decrypt_snapshot(snapshot).await.map(Some),
+                None => Ok(None),
+            };
+        }
+        Ok(snapshot)
+    }
+
+    /// Writes events, snapshots and lookup key changes atomically, returning what the
+    /// engine assigned to each event in input order.
+    pub async fn write_batch(&self, batch: &WriteBatch<'_>) -> Result<Vec<WrittenEvent>, EventStoreError> {
+        let _write = self.write_gate.enter().await?;
+        let _permit = match &self.commit_coordinator {
+            Some(coordinator) => Some(coordinator.acquire(batch_streams(batch)).await?),
+            None => None,
+        };
+        let mut checked: Vec<(&str, AggregateId)> = Vec::new();
+        for event in batch.events {
+            let stream = (event.aggregate_type.as_str(), event.aggregate_id);
+            if !checked.contains(&stream) {
+                self.check_not_deleted(stream.0, stream.1).await?;
+                checked.push(stream);
+            }
+        }
+        let current;
+        let batch = if batch.snapshots.is_empty() {
+            batch
+        } else {
+            current = self.current_snapshots(batch.snapshots).await?;
+            &WriteBatch { snapshots: &current, ..*batch }
+        };
+        let plaintext = batch.events;
+        #[cfg(feature = "encryption")]
+        let encrypted;
+        #[cfg(feature = "encryption")]
+        let batch = match &self.encryption {
+            Some(encryption) => {
+                let events = encryption.encrypt_events(batch.events).await?;
+                let snapshots = encryption.encrypt_snapshots(batch.snapshots).await?;
+                encrypted = (events, snapshots);
+                &WriteBatch { events: &encrypted.0, snapshots: &encrypted.1, ..*batch }
+            }
+            None => batch,
+        };
+        let encoded;
+        let batch = if self.payload_serializer.is_binary() {
+            encoded = (self.encoded(batch.events, |event| &mut event.data)?, self.encoded(batch.snapshots, |snapshot| &mut snapshot.data)?);
+            &WriteBatch { events: &encoded.0, snapshots: &encoded.1, ..*batch }
+        } else {
+            batch
+        };
+        let hashed;
+        let batch = if self.hash_events {
+            hashed = self.hash_chain(batch.events).await?;
+            &WriteBatch { events: &hashed, ..*batch }
+        } else {
+            batch
+        };
+        let _projections = self.inline_projections.lock_commit().await;
+        let written = self.storage_engine.write_batch(batch).await?;
+        self.inline_projections.apply(&enriched(plaintext, &written))?;
+        for event in plaintext {
+            tracing::trace!(event = %event, "event written");
+        }
+        Ok(written)
+    }
+
+    /// Copies of `items` with their JSON payloads in the store's format. Hashes are computed
+    /// afterwards, so they cover the stored bytes.
+    fn encoded<T: Clone>(&self, items: &[T], data: impl Fn(&mut T) -> &mut Payload) -> Result<Vec<T>, EventStoreError> {
+        let mut encoded = items.to_vec();
+        for item in &mut encoded {
+            let data = data(item);
+            if let Payload::Json(json) = data {
+                *data = self.payload_serializer.encode(json)?;
+            }
+        }
+        Ok(encoded)
+    }
+
+    /// The snapshots newer than the latest one stored for their aggregate; older ones would
+    /// never be read.
+    async fn current_snapshots(&self, snapshots: &[Snapshot]) -> Result<Vec<Snapshot>, EventStoreError> {
+        let mut current = Vec::with_capacity(snapshots.len());
+        for snapshot in snapshots {
+            let head = self.storage_engine.snapshot_head_version(snapshot.aggregate_id, &snapshot.aggregate_type).await?;
+            if head.is_none_or(|head| snapshot.version > head) {
+                current.push(snapshot.clone());
+            }
+        }
+        Ok(current)
+    }
+
+    /// Copies of `events` carrying their hashes, each chained to the previous event of its
+    /// stream, whether stored or earlier in the batch.
+    async fn hash_chain(&self, events: &[Event]) -> Result<Vec<Event>, EventStoreError> {
+        let mut heads: HashMap<StreamKey, Option<String>> = HashMap::new();
+        let mut hashed = Vec::with_capacity(events.len());
+        for event in events {
+            let key = (event.aggregate_type.clone(), event.aggregate_id);
+            if !heads.contains_key(&key) {
+                heads.insert(key.clone(), self.previous_hash(event).await?);
+            }
+            let mut event = event.clone();
+            event.hash = Some(event_hash(&event, heads[&key].as_deref()));
+            heads.insert(key, event.hash.clone());
+            hashed.push(event);
+        }
+        Ok(hashed)
+    }
+
+    /// The hash stored with the event before `event` in its stream, if any.
+    pub(crate) async fn previous_hash(&self, event: &Event) -> Result<Option<String>, EventStoreError> {
+        if event.version <= 1 {
+            return Ok(None);
+        }
+        let stored = self.storage_engine.read_events(event.aggregate_id, &event.aggregate_type, (event.version - 2).max(0)).await?;
+        let head = stored.into_iter().find(|stored| stored.version == event.version - 1);
+        Ok(head.and_then(|head| head.hash))
+    }
+
+    /// Recomputes the hashes of the global feed events from `from_position` through
+    /// `to_position`, to catch rows changed outside the store. Reports the first event not
+    /// matching its hash; a changed event does not make the ones chained after it diverge.
+    /// Needs an engine with a global feed and events written by a store hashing them.
+    pub async fn verify_feed_integrity(&self, from_position: i64, to_position: i64) -> Result<FeedIntegrityReport, EventStoreError> {
+        arguments::non_negative("from_position", from_position)?;
+        arguments::non_negative("to_position", to_position)?;
+        let mut report = FeedIntegrityReport::default();
+        let mut verifier = FeedVerifier::default();
+        let mut position = (from_position - 1).max(0);
+        while position < to_position {
+            let events = self.storage_engine.read_all_events(position, FEED_PAGE_SIZE).await?;
+            let Some(last) = events.last() else {
+                break;
+            };
+            let next_position = last.position.unwrap_or(position + events.len() as i64);
+            for event in &events {
+                let event_position = event.position.unwrap_or_default();
+                if event_position > to_position {
+                    return Ok(report);
+                }
+                let matches = verifier.verify(self, event).await?;
+                if event.hash.is_none() {
+                    report.unhashed += 1;
+                    continue;
+                }
+                report.events_checked += 1;
+                if !matches {
+                    report.first_divergent_position = Some(event_position);
+                    return Ok(report);
+                }
+            }
+            position = next_position;
+        }
+        Ok(report)
+    }
+
+    /// Pauses writes for a maintenance window. Resolves once the writes in flight have
+    /// finished, or fails with `QuiesceTimeout` after `timeout`. Until the returned guard is
+    /// dropped, commits wait or fail according to the `QuiescePolicy`.
+    ///
+    /// Only writes made through this store (and its clones) are paused.
+    pub async fn quiesce(&self, timeout: std::time::Duration) -> Result<QuiesceGuard, EventStoreError> {
+        self.write_gate.quiesce(timeout).await
+    }
+
+    /// Number of writes currently in flight through this store.
+    pub fn active_writes(&self) -> usize {
+        self.write_gate.active_writes()
+    }
+
+    /// Commits several contexts of this store in one atomic write. On success every context
+    /// is drained and marked committed, and its commit hooks run with the events it
+    /// published; on failure nothing is written and none are.
+    ///
+    /// Contexts must publish to disjoint aggregates: their version sequences were built
+    /// independently, so an aggregate touched by two of them fails with `OverlappingContexts`.
+    ///
+    /// Every context's commit lock is held throughout, taken in `context_id` order so that
+    /// concurrent commits of overlapping sets cannot deadlock.
+    pub async fn commit_all(&self, contexts: &[SharedEventContext]) -> Result<CommitReceipt, EventStoreError> {
+        if contexts.iter().any(|context| context.store_id() != self.store_id) {
+            return Err(EventStoreError::ContextErrorOther("Context belongs to a different event store.".to_string()));
+        }
+        let mut lock_order: Vec<&SharedEventContext> = contexts.iter().collect();
+        lock_order.sort_by_key(|context| context.context_id());
+        lock_order.dedup_by(|a, b| Arc::ptr_eq(a, b));
+        let mut commit_guards = Vec::with_capacity(lock_order.len());
+        for context in lock_order {
+            commit_guards.push(context.lock_commit().await);
+        }
+
+        let mut captured = CapturedWrites::default();
+        let mut counts = Vec::with_capacity(contexts.len());
+        let mut owners: HashMap<StreamKey, usize> = HashMap::new();
+        let mut overlapping: Vec<StreamKey> = Vec::new();
+        for (index, context) in contexts.iter().enumerate() {
+            let writes = context.captured_writes()?;
+            for event in &writes.events {
+                let key = (event.aggregate_type.clone(), event.aggregate_id);
+                let owner = *owners.entry(key.clone()).or_insert(index);
+                if owner != index && !overlapping.contains(&key) {
+                    overlapping.push(key);
+                }
+            }
+            counts.push(writes.counts());
+            captured.extend(writes);
+        }
+        if !overlapping.is_empty() {
+            return Err(EventStoreError::OverlappingContexts(overlapping));
+        }
+
+        let written = self.write_batch(&captured.batch()).await?;
+        for (context, counts) in contexts.iter().zip(&counts) {
+            context.drain_committed(*counts)?;
+            context.mark_committed();
+        }
+        let receipt = CommitReceipt::new(&captured, &written);
+        self.notify_commit(contexts.iter().map(|context| context.context_id()).collect(), &receipt);
+        // Each context's hooks see the events that context published.
+        let mut start = 0;
+        for (context, counts) in contexts.iter().zip(counts) {
+            let events = receipt.events.get(start..start + counts.events).unwrap_or_default();
+            let hooks = context.take_commit_hooks()?;
+            run_commit_hooks(hooks, events).await?;
+            start += counts.events;
+        }
+        Ok(receipt)
+    }
+
+    /// The aggregate types known to the storage engine, sorted by name. `with_counts` adds
+    /// usage counts and first seen timestamps, at the cost of scanning the events.
+    pub async fn aggregate_types(&self, with_counts: bool) -> Result<Vec<TypeInfo>, EventStoreError> {
+        self.storage_engine.list_aggregate_types(with_counts).await
+    }
+
+    /// The event types known to the storage engine, sorted by name, see `aggregate_types`.
+    pub async fn event_types(&self, with_counts: bool) -> Result<Vec<TypeInfo>, EventStoreError> {
+        self.storage_engine.list_event_types(with_counts).await
+    }
+
+    /// Lists instances of an aggregate type a page at a time. Pass the previous page's
+    /// `next` cursor to continue; cursors are only valid for this store instance.
+    pub async fn list_aggregate_instances(&self, aggregate_type: &str, after: Option<&Cursor>, limit: usize) -> Result<Page<AggregateInstance>, EventStoreError> {
+        arguments::limit(limit, self.max_page_size)?;
+        let kind = CursorKind::AggregateInstances(aggregate_type.to_string());
+        let offset = match after {
+            Some(cursor) => cursor.position(self.store_id, &kind)?,
+            None => 0,
+        };
+        let items = self.storage_engine.list_aggregate_instances(aggregate_type, offset as usize, limit).await?;
+        let next = (limit > 0 && items.len() == limit)
+            .then(|| Cursor::new(self.store_id, kind, offset + items.len() as i64));
+        Ok(Page { items, next })
+    }
+
+    /// Reads events across all aggregates in commit order after `from_position`, at most
+    /// `limit` of them. Each carries its `position` to continue from; unlike cursors,
+    /// positions remain valid across store instances, so read models can checkpoint them.
+    pub async fn read_all_events(&self, from_position: i64, limit: usize) -> Result<Vec<Event>, EventStoreError> {
+        arguments::non_negative("from_position", from_position)?;
+        arguments::limit(limit, self.max_page_size)?;
+        let events = self.storage_engine.read_all_events(from_position, limit).await?;
+        self.decoded(events).await
+    }
+
+    /// Reads the global feed a page at a time, in commit order.
+    pub async fn read_all_events_page(&self, after: Option<&Cursor>, limit: usize) -> Result<Page<Event>, EventStoreError> {
+        arguments::limit(limit, self.max_page_size)?;
+        let position = match after {
+            Some(cursor) => cursor.position(self.store_id, &CursorKind::GlobalFeed)?,
+            None => 0,
+        };
+        let items = self.storage_engine.read_all_events(position, limit).await?;
+        let items = self.decoded(items).await?;
+        let next = match items.last() {
+            Some(last) if items.len() == limit => {
+                let last_position = last.position.unwrap_or(position + items.len() as i64);
+                Some(Cursor::new(self.store_id, CursorKind::GlobalFeed, last_position))
+            }
+            _ => None,
+        };
+        Ok(Page { items, next })
+    }
+
+    /// Returns the ids of aggregates of the given type carrying a lookup key.
+    pub async fn find_by_lookup_key(&self, aggregate_type: &str, key_name: &str, key_value: &str) -> Result<Vec<AggregateId>, EventStoreError> {
+        self.storage_engine.find_by_lookup_key(aggregate_type, key_name, key_value).await
+    }
+
+    /// Whether an event with the dedup key has already been ingested.
+    pub async fn has_dedup_key(&self, key: &str) -> Result<bool, EventStoreError> {
+        self.storage_engine.has_dedup_key(key).await
+    }
+
+    /// Scans every stored event for the keys set with `EventStoreBuilder::required_metadata`,
+    /// counting the events that lack some. Needs an engine with a global feed.
+    pub async fn audit_required_metadata(&self) -> Result<MetadataAudit, EventStoreError> {
+        let mut audit = MetadataAudit::default();
+        let mut position = 0;
+        loop {
+            let events = self.storage_engine.read_all_events(position, FEED_PAGE_SIZE).await?;
+            for event in &events {
+                audit.record(event, &self.required_metadata)?;
+            }
+            match events.last() {
+                Some(last) if events.len() == FEED_PAGE_SIZE => position = last.position.unwrap_or(position + events.len() as i64),
+                _ => return Ok(audit),
+            }
+        }
+    }
+
+    /// Removes data that has aged out according to the policy.
+    pub async fn apply_retention(&self, policy: &RetentionPolicy) -> Result<RetentionReport, EventStoreError> {
+        let mut report = RetentionReport::default();
+        if let Some(max_age) = policy.dedup_key_max_age {
+            report.dedup_keys_pruned = self.storage_engine.prune_dedup_keys(self.now() - max_age).await?;
+        }
+        if let Some(count) = policy.snapshots_to_keep {
+            report.snapshots_pruned = self.storage_engine.prune_snapshots(count).await?;
+        }
+        self.emit(OperationalEvent::RetentionCompleted(report.clone()));
+        Ok(report)
+    }
+
+    /// Renames an aggregate type, moving all of its stored data to `new_type` in one step.
+    /// Aggregates loaded before the migration still carry the old type.
+    pub async fn migrate_aggregate_type(&self, old_type: &str, new_type: &str) -> Result<MigrationReport, EventStoreError> {
+        let report = self.storage_engine.migrate_aggregate_type(old_type, new_type).await?;
+        self.emit(OperationalEvent::AggregateTypeMigrated {
+            old_type: old_type.to_string(),
+            new_type: new_type.to_string(),
+            report: report.clone(),
+        });
+        Ok(report)
+    }
+
+    /// Returns the current version of an aggregate without loading its state.
+    pub async fn get_aggregate_version(&self, aggregate_id: AggregateId, aggregate_type: &str) -> Result<i64, EventStoreError> {
+        self.storage_engine.get_aggregate_version(aggregate_id, aggregate_type).await
+    }
+
+    /// Returns the lowest stored event version of an aggregate, or None if it has no events.
+    pub async fn earliest_event_version(&self, aggregate_id: AggregateId, aggregate_type: &str) -> Result<Option<i64>, EventStoreError> {
+        self.storage_engine.earliest_event_version(aggregate_id, aggregate_type).await
+    }
+
+    pub async fn write_updates(&self, events: &[Event], snapshots: &[Snapshot]) -> Result<Vec<WrittenEvent>, EventStoreError> {
+        let batch = WriteBatch {
+            events,
+            snapshots,
+            lookup_keys: &[],
+            dedup_keys: &[],
+        };
+        self.write_batch(&batch).await
+    }
+
+    /// Registers a closure based read model, kept in memory and updated after every commit
+    /// made through this store. The state is rebuilt by replaying the global feed before
+    /// the projection is registered, replacing any projection with the same name. Commits
+    /// wait while the last events are read, so each reaches the projection exactly once.
+    ///
+    /// Inline projections are single-process only, see [`InlineProjections`].
+    pub async fn register_inline_projection<S, F>(&self, name: &str, init: S, f: F) -> Result<(), EventStoreError>
+    where
+        S: Any + Send + Sync,
+        F: Fn(&mut S, &Event) + Send + Sync + 'static,
+    {
+        let mut state = init;
+        let mut position = 0;
+        let mut events_replayed = 0;
+        // Replays without holding up commits, then reads what was committed meanwhile again
+        // under the lock, which is kept until the projection is inserted.
+        let mut registration = None;
+        loop {
+            let events = self.storage_engine.read_all_events(position, FEED_PAGE_SIZE).await?;
+            let events = self.decoded(events).await?;
+            let Some(last) = events.last() else {
+                if registration.is_some() {
+                    break;
+                }
+                registration = Some(self.inline_projections.lock_registration().await);
+                continue;
+            };
+            position = last.position.unwrap_or(position + events.len() as i64);
+            for event in &events {
+                f(&mut state, event);
+            }
+            events_replayed += events.len();
+        }
+        self.inline_projections.insert(name, state, f)?;
+        self.emit(OperationalEvent::InlineProjectionRegistered {
+            name: name.to_string(),
+            events_replayed,
+        });
+        Ok(())
+    }
+
+    /// Returns the current state of an inline projection, or None if no projection with
+    /// that name and state type is registered.
+    pub fn projection_state<S: Any + Send + Sync>(&self, name: &str) -> Option<ProjectionState<'_, S>> {
+        self.inline_projections.state(name)
+    }
+    
+
+    /// Execute a task within a contest, returning a result.
+    pub async fn with_context_returning<Fut, T>(self: SharedEventStore, context_task: impl FnOnce(SharedEventContext) -> Fut ) 
+       -> Result<T, EventStoreError> 
+    where 
+        Fut: Future<Output = Result<T, EventStoreError>> + Send + 'static
+        
+    {
+        let context = self.get_context();
+        let result = match context_task(context.clone()).await {
+            Ok(result) => result,
+            Err(err) => {
+                // Report the task's error even if discarding the context fails.
+                let _ = context.rollback();
+                return Err(err);
+            }
+        };
+        context.commit().await?;
+        Ok(result)
+    }
+
+    /// Execute a task within a contest.
+    pub async fn with_context<Fut>(self: SharedEventStore, context_task: impl FnOnce(SharedEventContext) -> Fut ) 
+       -> Result<(), EventStoreError> 
+    where 
+        Fut: Future<Output = Result<(), EventStoreError>> + Send + 'static
+        
+    {
+        self.with_context_returning(context_task).await
+    }
+
+    /// Like `with_context_returning`, but runs the task again in a fresh context when the
+    /// commit fails with `VersionConflict`, so it reloads its aggregates with the events
+    /// that got in first. Gives up after `max_attempts` runs, or once the current deadline
+    /// has passed, returning the last conflict. Other errors are returned at once.
+    pub async fn with_context_retry<Fut, T>(self: SharedEventStore, max_attempts: usize, context_task: impl Fn(SharedEventContext) -> Fut)
+       -> Result<T, EventStoreError>
+    where
+        Fut: Future<Output = Result<T, EventStoreError>> + Send + 'static
+    {
+        let mut attempt = 1;
+        loop {
+            match self.clone().with_context_returning(&context_task).await {
+                Err(error) if attempt < max_attempts && deadline::check().is_ok() && matches!(error.root_cause(), EventStoreError::VersionConflict { .. }) => {
+                    tracing::debug!(attempt, "retrying context after a version conflict");
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Applies commands queued by an offline client to the instance of `aggregate_type` with
+    /// `natural_key`, creating it if there is none. The commands are requested in order on the
+    /// aggregate at its current head, so their events get the versions that follow it.
+    ///
+    /// A command that is refused does not stop the queue: the commands that were applied are
+    /// committed together and every command's outcome is returned in queue order. The queue
+    /// is replayed from the new head when another writer commits to the aggregate first.
+    pub async fn apply_commands_offline<T>(
+        self: &SharedEventStore,
+        aggregate_type: &str,
+        natural_key: &str,
+        commands: Vec<serde_json::Value>,
+        registry: &CommandRegistry<T>,
+    ) -> Result<Vec<CommandOutcome>, EventStoreError>
+    where
+        T: 'static + DeserializeOwned + Default + Serialize + Composable + Clone
+    {
+        offline::apply_commands(self, aggregate_type, natural_key, &commands, registry).await
+    }
+
+    /// A scheduler for periodic maintenance jobs on this store, such as retention. The
+    /// retention configured with `EventStoreBuilder::retention` is registered already.
+    pub fn maintenance(self: &SharedEventStore) -> MaintenanceScheduler {
+        let scheduler = MaintenanceScheduler::new(self.clone());
+        match &self.retention {
+            Some((schedule, policy)) => scheduler.retention(*schedule, policy.clone()),
+            None => scheduler,
+        }
+    }
+
+    /// An archiver exporting the global feed to `sink_dir` in segments of `segment_size`
+    /// events. Needs an engine with a global feed.
+    #[cfg(feature = "archive")]
+    pub fn archiver(self: &SharedEventStore, sink_dir: impl Into<std::path::PathBuf>, segment_size: usize) -> crate::archive::Archiver {
+        crate::archive::Archiver::new(self.clone(), sink_dir.into(), segment_size)
+    }
+
+    pub fn get_context(self: &SharedEventStore) -> SharedEventContext {
+        Arc::new(EventContext::new(self.clone()))
+    }
+
+    /// Pre-allocates a pool of `size` reusable contexts for this store.
+    pub fn create_context_pool(self: &SharedEventStore, size: usize) -> EventContextPool {
+        EventContextPool::new(self.clone(), size)
+    }
+}
+
+#[cfg(all(test, feature = "memory"))]
+#[allow(clippy::enum_variant_names, clippy::needless_return, clippy::needless_ifs, clippy::unused_enumerate_index)]
+mod tests {
+    use std::collections::HashMap;
+    use serde::{Serialize, Deserialize};
+    use std::sync::Arc;
+    use crate::{aggregate::{Aggregate, AppliesEvents, Composable, CanRequest, ComposedAggregate}, EventStoreError, EventStoreStorageEngine};
+    use crate::event::numbered_id;
+
+
+    #[derive(Default, Clone, Serialize, Deserialize)]
+    struct Account {
+        user_id: i64,
+        balance: i64,
+    }
+    
+    #[derive(Serialize, Deserialize)]
+    struct AccountCreation {
+        user_id: i64,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    struct AccountUpdate {
+        amount: i64,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    enum AccountCommands {
+        CreateAccount(AccountCreation),
+        CreditAccount(AccountUpdate),
+        DebitAccount(AccountUpdate),
+    }
+
+
+    #[derive(Serialize, Deserialize)]
+    enum AccountEvents {
+        AccountCreated(AccountCreation),
+        AccountCredited(AccountUpdate),
+        AccountDebited(AccountUpdate),
+    }
+
+    impl Composable for Account {
+        fn get_type(&self) -> &str {
+            "account"
+        }
+
+        fn apply_event(&mut self, event: &crate::event::Event) -> Result<(), crate::EventStoreError> {
+            self.apply_typed(event)
+        }
+    }
+
+    impl AppliesEvents for Account {
+        type Events = AccountEvents;
+
+        fn apply(&mut self, event: AccountEvents) -> Result<(), crate::EventStoreError> {
+            match event {
+                AccountEvents::AccountCreated(event) => {
+                    self.user_id = event.user_id;
+                },
+                AccountEvents::AccountCredited(event) => {
+                    self.balance += event.amount;
+                },
+                AccountEvents::AccountDebited(event) => {
+                    if event.amount > self.balance {
+                        return Err(EventStoreError::RequestProcessingError("Insufficient funds".to_string()));
+                    }
+                    self.balance -= event.amount;
+                },
+            }
+            return Ok(());
+        }
+    }
+
+
+    impl CanRequest<AccountCommands, AccountEvents> for Account {
+        fn request(&self, request: AccountCommands) -> Result<(String, AccountEvents), crate::EventStoreError> {
+
+            match request {
+                AccountCommands::CreateAccount(command) => {
+                    Ok(("created".to_string(), AccountEvents::AccountCreated(command)))
+                },
+                AccountCommands::CreditAccount(command) => {
+                    if command.amount > self.balance {
+                    }
+                    Ok(("credited".to_string(), AccountEvents::AccountCredited(command)))
+                },
+                AccountCommands::DebitAccount(command) => {
+                    Ok(("debited".to_string(), AccountEvents::AccountDebited(command)))
+                },
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_eventstore() {
+        let memory = crate::memory::MemoryStorageEngine::new();
+        let event_store = crate::EventStore::new(memory);
+        let context = event_store.get_context();
+        {
+            let mut account = ComposedAggregate::<Account>::new(&context, None).await.unwrap();
+
+            account.request(AccountCommands::CreateAccount(AccountCreation { user_id: 1 })).unwrap();
+            account.request(AccountCommands::CreditAccount(AccountUpdate { amount: 100 })).unwrap();
+            account.request(AccountCommands::DebitAccount(AccountUpdate { amount: 50 })).unwrap();
+            account.request(AccountCommands::DebitAccount(AccountUpdate { amount: 10 })).unwrap();
+
+            let state = account.state();
+            assert!(state.balance == 40);
+        }
+        context.commit().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn ensure_events_mutate_state() {
+        let memory = crate::memory::MemoryStorageEngine::new();
+        let event_store = crate::EventStore::new(memory);
+        let context = event_store.clone().get_context();
+        {
+            let mut account = ComposedAggregate::<Account>::new(&context, None).await.unwrap();
+            account.request(AccountCommands::CreateAccount(AccountCreation { user_id: 1 })).unwrap();
+            account.request(AccountCommands::CreditAccount(AccountUpdate { amount: 100 })).unwrap();
+            account.request(AccountCommands::DebitAccount(AccountUpdate { amount: 50 })).unwrap();
+            account.request(AccountCommands::DebitAccount(AccountUpdate { amount: 10 })).unwrap();
+
+            let state = account.state();
+            assert!(state.balance == 40);
+        }
+        context.commit().await.unwrap();
+
+        let context = event_store.get_context();
+        {
+            let account = ComposedAggregate::<Account>::load(&context, numbered_id(1)).await.unwrap();
+            let state = account.state();
+            assert!(state.balance == 40);
+        }
+    }
+
+    #[tokio::test]
+    async fn ensure_events_written_out_of_order_replay_in_version_order() {
+        use crate::event::Event;
+
+        let memory = crate::memory::MemoryStorageEngine::new();
+        let event_store = crate::EventStore::new(memory);
+        let debit = Event::new(numbered_id(1), "account", 3, "debited", &AccountEvents::AccountDebited(AccountUpdate { amount: 30 })).unwrap();
+        event_store.write_updates(&[debit], &[]).await.unwrap();
+        let earlier = vec![
+            Event::new(numbered_id(1), "account", 1, "created", &AccountEvents::AccountCreated(AccountCreation { user_id: 7 })).unwrap(),
+            Event::new(numbered_id(1), "account", 2, "credited", &AccountEvents::AccountCredited(AccountUpdate { amount: 100 })).unwrap(),
+        ];
+        event_store.write_updates(&earlier, &[]).await.unwrap();
+
+        let versions: Vec<i64> = event_store.get_events(numbered_id(1), "account", 0).await.unwrap().iter().map(|event| event.version).collect();
+        assert_eq!(versions, vec![1, 2, 3]);
+
+        let context = event_store.get_context();
+        let account = ComposedAggregate::<Account>::load(&context, numbered_id(1)).await.unwrap();
+        assert_eq!(account.version(), 3);
+        assert_eq!(account.state().balance, 70);
+    }
+
+    #[tokio::test]
+    async fn ensure_new_with_state_builds_on_initial_state() {
+        let memory = crate::memory::MemoryStorageEngine::new();
+        let event_store = crate::EventStore::new(memory);
+        let context = event_store.get_context();
+
+        let initial = Account { user_id: 9, balance: 500 };
+        let mut account = ComposedAggregate::new_with_state(&context, None, initial).await.unwrap();
+        assert_eq!(account.version(), 0);
+        account.request(AccountCommands::CreditAccount(AccountUpdate { amount: 25 })).unwrap();
+
+        let state = account.state();
+        assert_eq!(state.user_id, 9);
+        assert_eq!(state.balance, 525);
+        assert_eq!(context.captured_event_types().unwrap(), vec!["credited".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn ensure_published_events_can_be_asserted() {
+        let memory = crate::memory::MemoryStorageEngine::new();
+        let event_store = crate::EventStore::new(memory);
+        let context = event_store.get_context();
+        context.assert_nothing_published();
+
+        let mut account = ComposedAggregate::<Account>::new(&context, None).await.unwrap();
+        account.request(AccountCommands::CreateAccount(AccountCreation { user_id: 1 })).unwrap();
+        account.request(AccountCommands::DebitAccount(AccountUpdate { amount: 0 })).unwrap();
+        account.request(AccountCommands::DebitAccount(AccountUpdate { amount: 0 })).unwrap();
+
+        context.assert_published("created", 1);
+        context.assert_published("debited", 2);
+        context.assert_published("credited", 0);
+        assert_eq!(context.captured_event_types().unwrap(), vec!["created", "debited", "debited"]);
+    }
+
+    #[tokio::test]
+    #[should_panic(expected = "expected 1 'debited' event(s)")]
+    async fn ensure_assert_published_panics_on_a_wrong_count() {
+        let memory = crate::memory::MemoryStorageEngine::new();
+        let event_store = crate::EventStore::new(memory);
+        let context = event_store.get_context();
+
+        let mut account = ComposedAggregate::<Account>::new(&context, None).await.unwrap();
+        account.request(AccountCommands::CreateAccount(AccountCreation { user_id: 1 })).unwrap();
+
+        context.assert_published("debited", 1);
+    }
+
+    #[tokio::test]
+    async fn ensure_take_captured_drains_without_committing() {
+        let memory = crate::memory::MemoryStorageEngine::new();
+        let event_store = crate::EventStore::new(memory.clone());
+        let context = event_store.get_context();
+
+        let mut account = ComposedAggregate::<Account>::new(&context, None).await.unwrap();
+        account.request(AccountCommands::CreateAccount(AccountCreation { user_id: 1 })).unwrap();
+        account.request(AccountCommands::CreditAccount(AccountUpdate { amount: 100 })).unwrap();
+
+        let taken = context.take_captured().unwrap();
+        assert_eq!(taken.iter().map(|event| event.version).collect::<Vec<_>>(), vec![1, 2]);
+        context.assert_nothing_published();
+
+        context.commit().await.unwrap();
+        assert!(memory.read_events(numbered_id(1), "account", 0).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn ensure_into_state_moves_state_out() {
+        let memory = crate::memory::MemoryStorageEngine::new();
+        let event_store = crate::EventStore::new(memory);
+        let context = event_store.get_context();
+
+        let mut account = ComposedAggregate::<Account>::new(&context, None).await.unwrap();
+        account.request(AccountCommands::CreateAccount(AccountCreation { user_id: 7 })).unwrap();
+        account.request(AccountCommands::CreditAccount(AccountUpdate { amount: 25 })).unwrap();
+
+        let state = account.into_state();
+        assert_eq!(state.user_id, 7);
+        assert_eq!(state.balance, 25);
+    }
+
+    #[tokio::test]
+    async fn ensure_context_pool_reuses_reset_contexts() {
+        let memory = crate::memory::MemoryStorageEngine::new();
+        let event_store = crate::EventStore::new(memory.clone());
+        let pool = event_store.create_context_pool(2);
+        assert_eq!(pool.available(), 2);
+
+        {
+            let context = pool.get();
+            assert_eq!(pool.available(), 1);
+            context.add_metadata("user", "chavez").unwrap();
+            let mut account = ComposedAggregate::<Account>::new(&context, None).await.unwrap();
+            account.request(AccountCommands::CreateAccount(AccountCreation { user_id: 1 })).unwrap();
+        }
+        assert_eq!(pool.available(), 2);
+
+        let context = pool.get();
+        context.commit().await.unwrap();
+        assert!(memory.read_events(numbered_id(1), "account", 0).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn ensure_pooled_contexts_refuse_aggregates_of_earlier_checkouts() {
+        /// Remembers the id of its context instead of holding on to the context.
+        struct Remembering {
+            context_id: uuid::Uuid,
+            version: i64,
+        }
+
+        impl Aggregate<'_> for Remembering {
+            fn id(&self) -> crate::AggregateId { numbered_id(1) }
+            fn id_mut(&mut self, _id: crate::AggregateId) {}
+            fn snapshot_frequency(&self) -> i32 { 0 }
+            fn aggregate_type(&self) -> &str { "remembering" }
+            fn version(&self) -> i64 { self.version }
+            fn apply_snapshot(&mut self, _snapshot: &crate::snapshot::Snapshot) -> Result<(), EventStoreError> { Ok(()) }
+            fn apply_event(&mut self, event: &crate::event::Event) -> Result<(), EventStoreError> {
+                self.version = event.version;
+                Ok(())
+            }
+            fn take_snapshot(&self) -> Result<crate::snapshot::Snapshot, EventStoreError> {
+                crate::snapshot::Snapshot::new(numbered_id(1), "remembering", self.version, &self.version)
+            }
+            fn context_id(&self) -> Option<uuid::Uuid> { Some(self.context_id) }
+        }
+
+        let event_store = crate::EventStore::new(crate::memory::MemoryStorageEngine::new());
+        let pool = event_store.create_context_pool(1);
+        let mut aggregate = {
+            let context = pool.get();
+            let mut aggregate = Remembering { context_id: context.context_id(), version: 0 };
+            context.publish(&mut aggregate, "touched", &1).unwrap();
+            aggregate
+        };
+
+        let context = pool.get();
+        assert_eq!(pool.available(), 0);
+        let result = context.publish(&mut aggregate, "touched", &2);
+        assert!(matches!(result, Err(EventStoreError::WrongContext { aggregate_id }) if aggregate_id == numbered_id(1)));
+        context.assert_nothing_published();
+    }
+
+    #[tokio::test]
+    async fn ensure_with_context_rolls_back_on_error() {
+        let memory = crate::memory::MemoryStorageEngine::new();
+        let event_store = crate::EventStore::new(memory.clone());
+        let used_context = Arc::new(std::sync::Mutex::new(None));
+
+        let stash = used_context.clone();
+        let result = event_store.with_context(move |context| {
+            *stash.lock().unwrap() = Some(context.clone());
+            async move {
+                let mut account = ComposedAggregate::<Account>::new(&context, None).await?;
+                account.request(AccountCommands::CreateAccount(AccountCreation { user_id: 1 }))?;
+                Err(EventStoreError::RequestProcessingError("rejected".to_string()))
+            }
+        }).await;
+
+        assert!(matches!(result, Err(EventStoreError::RequestProcessingError(_))));
+        let context = used_context.lock().unwrap().take().unwrap();
+        context.assert_nothing_published();
+        assert!(memory.read_events(numbered_id(1), "account", 0).await.unwrap().is_empty());
+        assert!(matches!(context.commit().await, Err(EventStoreError::ContextRolledBack)));
+    }
+
+    #[tokio::test]
+    async fn ensure_rolled_back_context_cannot_commit() {
+        let memory = crate::memory::MemoryStorageEngine::new();
+        let event_store = crate::EventStore::new(memory.clone());
+        let context = event_store.get_context();
+        assert!(!context.has_pending_changes().unwrap());
+
+        let mut account = ComposedAggregate::<Account>::new(&context, None).await.unwrap();
+        account.request(AccountCommands::CreateAccount(AccountCreation { user_id: 1 })).unwrap();
+        account.request(AccountCommands::CreditAccount(AccountUpdate { amount: 10 })).unwrap();
+        assert_eq!(context.pending_event_count().unwrap(), 2);
+        assert!(context.has_pending_changes().unwrap());
+
+        context.rollback().unwrap();
+        assert!(context.is_rolled_back());
+        assert!(!context.has_pending_changes().unwrap());
+        assert!(matches!(context.commit().await, Err(EventStoreError::ContextRolledBack)));
+        assert!(matches!(event_store.commit_all(std::slice::from_ref(&context)).await, Err(EventStoreError::ContextRolledBack)));
+        assert!(memory.read_events(numbered_id(1), "account", 0).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn ensure_second_commit_writes_nothing() {
+        let memory = crate::memory::MemoryStorageEngine::new();
+        let event_store = crate::EventStore::new(memory.clone());
+        let context = event_store.get_context();
+        let mut account = ComposedAggregate::<Account>::new(&context, None).await.unwrap();
+        account.request(AccountCommands::CreateAccount(AccountCreation { user_id: 1 })).unwrap();
+
+        assert_eq!(context.commit().await.unwrap().events.len(), 1);
+        assert!(!context.has_pending_changes().unwrap());
+        assert!(context.commit().await.unwrap().events.is_empty());
+        assert_eq!(memory.read_events(numbered_id(1), "account", 0).await.unwrap().len(), 1);
+
+        // Tasks sharing a context write its events once between them.
+        account.request(AccountCommands::CreditAccount(AccountUpdate { amount: 10 })).unwrap();
+        let commits = (0..2).map(|_| {
+            let context = context.clone();
+            tokio::spawn(async move { context.commit().await.unwrap().events.len() })
+        }).collect::<Vec<_>>();
+        let mut written = Vec::new();
+        for commit in commits {
+            written.push(commit.await.unwrap());
+        }
+        written.sort();
+        assert_eq!(written, vec![0, 1]);
+        assert_eq!(memory.read_events(numbered_id(1), "account", 0).await.unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn ensure_commit_receipt_summarizes_the_write() {
+        use crate::snapshot::SnapshotPolicy;
+
+        let memory = crate::memory::MemoryStorageEngine::new();
+        let event_store = crate::EventStore::builder(memory)
+            .snapshot_policy("account", SnapshotPolicy::EveryNEvents(2))
+            .build()
+            .unwrap();
+        let context = event_store.get_context();
+        let mut first = ComposedAggregate::<Account>::new(&context, None).await.unwrap();
+        first.request(AccountCommands::CreateAccount(AccountCreation { user_id: 1 })).unwrap();
+        let mut second = ComposedAggregate::<Account>::new(&context, None).await.unwrap();
+        second.request(AccountCommands::CreateAccount(AccountCreation { user_id: 2 })).unwrap();
+        first.request(AccountCommands::CreditAccount(AccountUpdate { amount: 10 })).unwrap();
+
+        let receipt = context.commit().await.unwrap();
+        assert_eq!(receipt.event_count(), 3);
+        assert_eq!(receipt.snapshot_count, 1);
+        assert_eq!((receipt.first_position(), receipt.last_position()), (Some(1), Some(3)));
+        assert_eq!(receipt.aggregate_ids(), vec![("account".to_string(), numbered_id(1)), ("account".to_string(), numbered_id(2))]);
+
+        let receipt = context.commit().await.unwrap();
+        assert_eq!((receipt.event_count(), receipt.snapshot_count), (0, 0));
+        assert_eq!(receipt.last_position(), None);
+    }
+
+    #[tokio::test]
+    async fn ensure_commit_notifications_group_events_per_commit() {
+        let memory = crate::memory::MemoryStorageEngine::new();
+        let event_store = crate::EventStore::new(memory.clone());
+        let mut commits = event_store.watch_commits();
+        let mut events = event_store.watch_events();
+
+        let context = event_store.get_context();
+        let mut account = ComposedAggregate::<Account>::new(&context, None).await.unwrap();
+        account.request(AccountCommands::CreateAccount(AccountCreation { user_id: 1 })).unwrap();
+        account.request(AccountCommands::CreditAccount(AccountUpdate { amount: 10 })).unwrap();
+        context.commit().await.unwrap();
+        // Nothing left to write, so nothing to announce.
+        context.commit().await.unwrap();
+
+        let other = event_store.get_context();
+        let mut second = ComposedAggregate::<Account>::new(&other, None).await.unwrap();
+        second.request(AccountCommands::CreateAccount(AccountCreation { user_id: 2 })).unwrap();
+        let third = event_store.get_context();
+        let mut third_account = ComposedAggregate::<Account>::new(&third, None).await.unwrap();
+        third_account.request(AccountCommands::CreateAccount(AccountCreation { user_id: 3 })).unwrap();
+        event_store.commit_all(&[other.clone(), third.clone()]).await.unwrap();
+
+        let notification = commits.try_recv().unwrap();
+        assert_eq!(notification.context_ids, vec![context.context_id()]);
+        let types: Vec<_> = notification.events.iter().map(|event| event.event_type.as_str()).collect();
+        assert_eq!(types, vec!["created", "credited"]);
+
+        let notification = commits.try_recv().unwrap();
+        assert_eq!(notification.context_ids, vec![other.context_id(), third.context_id()]);
+        assert_eq!(notification.events.len(), 2);
+        assert!(commits.try_recv().is_err());
+
+        // The per-event channel sees the same events one by one, written only once.
+        let mut single = Vec::new();
+        while let Ok(event) = events.try_recv() {
+            single.push(event.event_type);
+        }
+        assert_eq!(single, vec!["created", "credited", "created", "created"]);
+        assert_eq!(memory.read_events(numbered_id(1), "account", 0).await.unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn ensure_lookup_keys_commit_with_context() {
+        let memory = crate::memory::MemoryStorageEngine::new();
+        let event_store = crate::EventStore::new(memory.clone());
+        let context = event_store.get_context();
+        {
+            for user_id in [1, 2] {
+                let mut account = ComposedAggregate::<Account>::new(&context, None).await.unwrap();
+                account.request(AccountCommands::CreateAccount(AccountCreation { user_id })).unwrap();
+                account.add_lookup_key("branch", "downtown").unwrap();
+            }
+        }
+
+        let ids = ComposedAggregate::<Account>::find_by_lookup_key(&context, "branch", "downtown").await.unwrap();
+        assert!(ids.is_empty());
+
+        context.commit().await.unwrap();
+
+        let context = event_store.get_context();
+        let ids = ComposedAggregate::<Account>::find_by_lookup_key(&context, "branch", "downtown").await.unwrap();
+        assert_eq!(ids, vec![numbered_id(1), numbered_id(2)]);
+
+        let account = ComposedAggregate::<Account>::load(&context, numbered_id(1)).await.unwrap();
+        account.remove_lookup_key("branch", "downtown").unwrap();
+        context.commit().await.unwrap();
+
+        let ids = ComposedAggregate::<Account>::find_by_lookup_key(&context, "branch", "downtown").await.unwrap();
+        assert_eq!(ids, vec![numbered_id(2)]);
+    }
+
+    #[tokio::test]
+    async fn ensure_checkpoint_writes_snapshot_immediately() {
+        let memory = crate::memory::MemoryStorageEngine::new();
+        let event_store = crate::EventStore::new(memory.clone());
+        let context = event_store.get_context();
+
+        let mut account = ComposedAggregate::<Account>::new(&context, None).await.unwrap();
+        account.request(AccountCommands::CreateAccount(AccountCreation { user_id: 1 })).unwrap();
+        account.request(AccountCommands::CreditAccount(AccountUpdate { amount: 15 })).unwrap();
+        account.checkpoint().await.unwrap();
+
+        let snapshot = memory.read_snapshot(numbered_id(1), "account").await.unwrap().unwrap();
+        assert_eq!(snapshot.version, 2);
+        assert!(memory.read_events(numbered_id(1), "account", 0).await.unwrap().is_empty());
+
+        context.commit().await.unwrap();
+        assert_eq!(memory.snapshot_count(), 1);
+        assert_eq!(memory.read_events(numbered_id(1), "account", 0).await.unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn ensure_inline_projection_rebuilds_on_start() {
+        fn count_accounts(total: &mut usize, event: &crate::event::Event) {
+            if event.aggregate_type == "account" && event.event_type == "created" {
+                *total += 1;
+            }
+        }
+
+        let memory = crate::memory::MemoryStorageEngine::new();
+        let event_store = crate::EventStore::new(memory.clone());
+        event_store.register_inline_projection("total_accounts", 0usize, count_accounts).await.unwrap();
+
+        let context = event_store.get_context();
+        for user_id in 1..=3 {
+            let mut account = ComposedAggregate::<Account>::new(&context, None).await.unwrap();
+            account.request(AccountCommands::CreateAccount(AccountCreation { user_id })).unwrap();
+            account.request(AccountCommands::CreditAccount(AccountUpdate { amount: 10 })).unwrap();
+        }
+        assert_eq!(*event_store.projection_state::<usize>("total_accounts").unwrap(), 0);
+        context.commit().await.unwrap();
+        assert_eq!(*event_store.projection_state::<usize>("total_accounts").unwrap(), 3);
+
+        let restarted = crate::EventStore::new(memory.clone());
+        assert!(restarted.projection_state::<usize>("total_accounts").is_none());
+        restarted.register_inline_projection("total_accounts", 0usize, count_accounts).await.unwrap();
+        assert_eq!(*restarted.projection_state::<usize>("total_accounts").unwrap(), 3);
+        assert!(restarted.projection_state::<String>("total_accounts").is_none());
+    }
+
+    #[test]
+    #[cfg(not(feature = "uuid-ids"))]
+    fn ensure_build_reports_every_configuration_problem() {
+        let memory = crate::memory::MemoryStorageEngine::new();
+        let sharded = crate::sharded::ShardedStorageEngine::new(
+            vec![memory.clone(), memory.clone()],
+            Arc::new(crate::sharded::HashRouter::new())).unwrap();
+
+        let result = crate::EventStore::builder(sharded)
+            .require_capabilities(crate::EngineCapabilities::GLOBAL_FEED | crate::EngineCapabilities::OUTBOX)
+            .redact_fields(&["ip_address", "", "ip_address"])
+            .commit_coordinator(0)
+            .build();
+        let Err(EventStoreError::ConfigurationError(problems)) = result else {
+            panic!("expected a configuration error");
+        };
+        assert_eq!(problems, vec![
+            "ShardedStorageEngine does not support global feed".to_string(),
+            "redact_fields contains an empty field name".to_string(),
+            "redact field 'ip_address' is listed more than once".to_string(),
+            "commit coordinator concurrency must be at least 1".to_string(),
+        ]);
+
+        let result = crate::EventStore::builder(memory)
+            .require_capabilities(crate::EngineCapabilities::GLOBAL_FEED | crate::EngineCapabilities::OUTBOX)
+            .build();
+        assert!(result.is_ok());
+    }
+
+    #[tokio::test]
+    async fn ensure_coordinated_commits_are_all_written() {
+        let memory = crate::memory::MemoryStorageEngine::new();
+        let event_store = crate::EventStore::builder(memory.clone())
+            .commit_coordinator(2)
+            .build()
+            .unwrap();
+
+        let mut tasks = Vec::new();
+        for user_id in 0..8 {
+            let event_store = event_store.clone();
+            tasks.push(tokio::spawn(async move {
+                let context = event_store.get_context();
+                let mut account = ComposedAggregate::<Account>::new(&context, None).await.unwrap();
+                account.request(AccountCommands::CreateAccount(AccountCreation { user_id })).unwrap();
+                account.request(AccountCommands::CreditAccount(AccountUpdate { amount: 10 })).unwrap();
+                context.commit().await.unwrap();
+            }));
+        }
+        for task in tasks {
+            task.await.unwrap();
+        }
+
+        for id in 1..=8 {
+            assert_eq!(memory.get_aggregate_version(numbered_id(id), "account").await.unwrap(), 2);
+        }
+    }
+
+    #[tokio::test]
+    async fn ensure_operational_events_are_broadcast() {
+        use crate::operational::OperationalEvent;
+        use crate::retention::{RetentionPolicy, RetentionReport};
+
+        let memory = crate::memory::MemoryStorageEngine::new();
+        let event_store = crate::EventStore::new(memory);
+        let context = event_store.get_context();
+        let mut account = ComposedAggregate::<Account>::new(&context, None).await.unwrap();
+        account.request(AccountCommands::CreateAccount(AccountCreation { user_id: 1 })).unwrap();
+        context.commit().await.unwrap();
+
+        // Nothing is buffered for subscribers that join later.
+        event_store.apply_retention(&RetentionPolicy::new()).await.unwrap();
+        let mut events = event_store.operational_events();
+
+        event_store.register_inline_projection("accounts", 0usize, |count, _event| *count += 1).await.unwrap();
+        event_store.apply_retention(&RetentionPolicy::new()).await.unwrap();
+        event_store.migrate_aggregate_type("account", "customer_account").await.unwrap();
+
+        assert_eq!(events.recv().await.unwrap(), OperationalEvent::InlineProjectionRegistered {
+            name: "accounts".to_string(),
+            events_replayed: 1,
+        });
+        assert_eq!(events.recv().await.unwrap(), OperationalEvent::RetentionCompleted(RetentionReport::default()));
+        let OperationalEvent::AggregateTypeMigrated { old_type, new_type, report } = events.recv().await.unwrap() else {
+            panic!("expected a migration event");
+        };
+        assert_eq!((old_type.as_str(), new_type.as_str(), report.events), ("account", "customer_account", 1));
+        assert!(events.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn ensure_listings_page_with_cursors() {
+        let memory = crate::memory::MemoryStorageEngine::new();
+        let event_store = crate::EventStore::new(memory);
+        let context = event_store.get_context();
+        for user_id in 0..3 {
+            let mut account = ComposedAggregate::<Account>::new(&context, None).await.unwrap();
+            account.request(AccountCommands::CreateAccount(AccountCreation { user_id })).unwrap();
+        }
+        context.commit().await.unwrap();
+
+        let first = event_store.list_aggregate_instances("account", None, 2).await.unwrap();
+        assert_eq!(first.items.iter().map(|instance| instance.id).collect::<Vec<_>>(), vec![numbered_id(1), numbered_id(2)]);
+        let cursor: crate::cursor::Cursor = first.next.unwrap().to_string().parse().unwrap();
+        let second = event_store.list_aggregate_instances("account", Some(&cursor), 2).await.unwrap();
+        assert_eq!(second.items.iter().map(|instance| instance.id).collect::<Vec<_>>(), vec![numbered_id(3)]);
+        assert!(second.next.is_none());
+
+        let feed = event_store.read_all_events_page(None, 2).await.unwrap();
+        let rest = event_store.read_all_events_page(feed.next.as_ref(), 2).await.unwrap();
+        assert_eq!(rest.items.iter().map(|event| event.aggregate_id).collect::<Vec<_>>(), vec![numbered_id(3)]);
+
+        // A cursor from one listing, or from another store, is rejected.
+        assert!(matches!(event_store.read_all_events_page(Some(&cursor), 2).await, Err(EventStoreError::InvalidCursor(_))));
+        assert!(matches!(event_store.list_aggregate_instances("user", Some(&cursor), 2).await, Err(EventStoreError::InvalidCursor(_))));
+        let other_store = crate::EventStore::new(crate::memory::MemoryStorageEngine::new());
+        assert!(matches!(other_store.list_aggregate_instances("account", Some(&cursor), 2).await, Err(EventStoreError::InvalidCursor(_))));
+    }
+
+    #[tokio::test]
+    async fn ensure_time_based_snapshot_is_taken_once() {
+        use crate::clock::{Clock, ManualClock};
+        use crate::snapshot::SnapshotPolicy;
+        use chrono::Duration;
+
+        let clock = Arc::new(ManualClock::new(chrono::Utc::now()));
+        let memory = crate::memory::MemoryStorageEngine::new();
+        let event_store = crate::EventStore::builder(memory)
+            .clock(clock.clone())
+            .snapshot_policy("account", SnapshotPolicy::OlderThan(Duration::hours(1)))
+            .build()
+            .unwrap();
+
+        let context = event_store.get_context();
+        let mut account = ComposedAggregate::<Account>::new(&context, None).await.unwrap();
+        account.request(AccountCommands::CreateAccount(AccountCreation { user_id: 1 })).unwrap();
+        clock.advance(Duration::minutes(30));
+        account.request(AccountCommands::CreditAccount(AccountUpdate { amount: 10 })).unwrap();
+        assert!(context.captured_snapshots().unwrap().is_empty());
+        context.commit().await.unwrap();
+
+        // Loading picks up how long the uncovered events have been waiting.
+        clock.advance(Duration::minutes(31));
+        let context = event_store.get_context();
+        let mut account = ComposedAggregate::<Account>::load(&context, numbered_id(1)).await.unwrap();
+        account.request(AccountCommands::CreditAccount(AccountUpdate { amount: 10 })).unwrap();
+        account.request(AccountCommands::CreditAccount(AccountUpdate { amount: 10 })).unwrap();
+        clock.advance(Duration::minutes(10));
+        account.request(AccountCommands::CreditAccount(AccountUpdate { amount: 10 })).unwrap();
+
+        let snapshots = context.captured_snapshots().unwrap();
+        assert_eq!(snapshots.len(), 1);
+        assert_eq!(snapshots[0].version, 2);
+        let taken_at = Some(clock.now() - Duration::minutes(10));
+        assert_eq!(snapshots[0].created_at, taken_at);
+        assert_eq!(context.captured_events().unwrap()[0].created_at, taken_at);
+
+        context.commit().await.unwrap();
+        let stored = event_store.get_snapshot(numbered_id(1), "account").await.unwrap().unwrap();
+        assert_eq!(stored.created_at, taken_at);
+    }
+
+    async fn ingest(event_store: &crate::SharedEventStore, messages: &[(&str, i64)]) -> Result<(), EventStoreError> {
+        let context = event_store.get_context();
+        let mut account = ComposedAggregate::<Account>::load(&context, numbered_id(1)).await?;
+        for (message_id, amount) in messages {
+            account.request_dedup(AccountCommands::CreditAccount(AccountUpdate { amount: *amount }), message_id).await?;
+        }
+        context.commit().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn ensure_replayed_ingestion_does_not_duplicate_events() {
+        let memory = crate::memory::MemoryStorageEngine::new();
+        let event_store = crate::EventStore::new(memory.clone());
+        let context = event_store.get_context();
+        let mut account = ComposedAggregate::<Account>::new(&context, None).await.unwrap();
+        account.request(AccountCommands::CreateAccount(AccountCreation { user_id: 1 })).unwrap();
+        context.commit().await.unwrap();
+
+        let batch = [("msg-1", 10), ("msg-2", 5), ("msg-1", 10)];
+        ingest(&event_store, &batch).await.unwrap();
+        assert_eq!(memory.read_events(numbered_id(1), "account", 0).await.unwrap().len(), 3);
+
+        ingest(&event_store, &batch).await.unwrap();
+        assert_eq!(memory.read_events(numbered_id(1), "account", 0).await.unwrap().len(), 3);
+
+        let context = event_store.get_context();
+        let account = ComposedAggregate::<Account>::load(&context, numbered_id(1)).await.unwrap();
+        assert_eq!(account.state().balance, 15);
+
+        let policy = crate::retention::RetentionPolicy::new().dedup_key_max_age(chrono::Duration::zero());
+        let report = event_store.apply_retention(&policy).await.unwrap();
+        assert_eq!(report.dedup_keys_pruned, 2);
+        assert!(!event_store.has_dedup_key("msg-1").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn ensure_duplicate_can_be_reported_as_error() {
+        let memory = crate::memory::MemoryStorageEngine::new();
+        let event_store = crate::EventStore::builder(memory.clone())
+            .on_duplicate(crate::DuplicatePolicy::Error)
+            .build()
+            .unwrap();
+        let context = event_store.get_context();
+        let mut account = ComposedAggregate::<Account>::new(&context, None).await.unwrap();
+        account.request(AccountCommands::CreateAccount(AccountCreation { user_id: 1 })).unwrap();
+        context.commit().await.unwrap();
+
+        ingest(&event_store, &[("msg-1", 10)]).await.unwrap();
+        let result = ingest(&event_store, &[("msg-1", 10)]).await;
+        assert!(matches!(result, Err(EventStoreError::DuplicateEvent(key)) if key == "msg-1"));
+        assert_eq!(memory.read_events(numbered_id(1), "account", 0).await.unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn ensure_publish_rejects_aggregate_from_other_context() {
+        let memory = crate::memory::MemoryStorageEngine::new();
+        let event_store = crate::EventStore::new(memory);
+        let context_a = event_store.get_context();
+        let context_b = event_store.get_context();
+        assert_ne!(context_a.context_id(), context_b.context_id());
+
+        let mut account = ComposedAggregate::<Account>::new(&context_a, None).await.unwrap();
+        let creation = AccountEvents::AccountCreated(AccountCreation { user_id: 1 });
+        let result = context_b.publish(&mut account, "created", &creation);
+        assert!(matches!(result, Err(EventStoreError::WrongContext { aggregate_id }) if aggregate_id == numbered_id(1)));
+        assert_eq!(account.version(), 0);
+
+        context_a.publish(&mut account, "created", &creation).unwrap();
+        assert_eq!(account.version(), 1);
+    }
+
+    /// Memory engine whose writes fail while `fail_writes` is set.
+    struct FaultyEngine {
+        inner: Arc<crate::memory::MemoryStorageEngine>,
+        fail_writes: std::sync::atomic::AtomicBool,
+        write_delay: std::sync::Mutex<std::time::Duration>,
+        writes_started: std::sync::atomic::AtomicUsize,
+        /// How long reads of the global feed take to return what they read.
+        read_delay: std::sync::Mutex<std::time::Duration>,
+    }
+
+    impl FaultyEngine {
+        fn new() -> Arc<FaultyEngine> {
+            Arc::new(FaultyEngine {
+                inner: crate::memory::MemoryStorageEngine::new(),
+                fail_writes: std::sync::atomic::AtomicBool::new(false),
+                write_delay: std::sync::Mutex::new(std::time::Duration::ZERO),
+                writes_started: std::sync::atomic::AtomicUsize::new(0),
+                read_delay: std::sync::Mutex::new(std::time::Duration::ZERO),
+            })
+        }
+
+        fn check_writes(&self) -> Result<(), EventStoreError> {
+            match self.fail_writes.load(std::sync::atomic::Ordering::SeqCst) {
+                true => Err(EventStoreError::StorageEngineErrorOther("write failed".to_string())),
+                false => Ok(()),
+            }
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl EventStoreStorageEngine for FaultyEngine {
+        async fn create_aggregate_instance_with_policy(&self, aggregate_type: &str, natural_key: Option<&str>, policy: Option<crate::DuplicateKeyPolicy>) -> Result<crate::CreateOutcome, EventStoreError> {
+            self.inner.create_aggregate_instance_with_policy(aggregate_type, natural_key, policy).await
+        }
+
+        async fn get_aggregate_instance_id(&self, aggregate_type: &str, natural_key: &str) -> Result<Option<crate::AggregateId>, EventStoreError> {
+            self.inner.get_aggregate_instance_id(aggregate_type, natural_key).await
+        }
+
+        async fn read_events(&self, aggregate_id: crate::AggregateId, aggregate_type: &str, version: i64) -> Result<Vec<crate::event::Event>, EventStoreError> {
+            self.inner.read_events(aggregate_id, aggregate_type, version).await
+        }
+
+        async fn read_snapshot(&self, aggregate_id: crate::AggregateId, aggregate_type: &str) -> Result<Option<crate::snapshot::Snapshot>, EventStoreError> {
+            self.inner.read_snapshot(aggregate_id, aggregate_type).await
+        }
+
+        async fn write_updates(&self, events: &[crate::event::Event], snapshots: &[crate::snapshot::Snapshot]) -> Result<Vec<crate::WrittenEvent>, EventStoreError> {
+            self.check_writes()?;
+            self.inner.write_updates(events, snapshots).await
+        }
+
+        async fn write_batch(&self, batch: &crate::WriteBatch<'_>) -> Result<Vec<crate::WrittenEvent>, EventStoreError> {
+            self.check_writes()?;
+            self.writes_started.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            let delay = *self.write_delay.lock().unwrap();
+            if !delay.is_zero() {
+                tokio::time::sleep(delay).await;
+            }
+            self.inner.write_batch(batch).await
+        }
+
+        async fn read_all_events(&self, from_position: i64, limit: usize) -> Result<Vec<crate::event::Event>, EventStoreError> {
+            let events = self.inner.read_all_events(from_position, limit).await;
+            let delay = *self.read_delay.lock().unwrap();
+            if !delay.is_zero() {
+                tokio::time::sleep(delay).await;
+            }
+            events
+        }
+
+        async fn add_lookup_key(&self, key: &crate::LookupKey) -> Result<(), EventStoreError> {
+            self.inner.add_lookup_key(key).await
+        }
+
+        async fn remove_lookup_key(&self, key: &crate::LookupKey) -> Result<(), EventStoreError> {
+            self.inner.remove_lookup_key(key).await
+        }
+
+        async fn find_by_lookup_key(&self, aggregate_type: &str, key_name: &str, key_value: &str) -> Result<Vec<crate::AggregateId>, EventStoreError> {
+            self.inner.find_by_lookup_key(aggregate_type, key_name, key_value).await
+        }
+
+        async fn has_dedup_key(&self, key: &str) -> Result<bool, EventStoreError> {
+            self.inner.has_dedup_key(key).await
+        }
+
+        async fn prune_dedup_keys(&self, older_than: chrono::DateTime<chrono::Utc>) -> Result<usize, EventStoreError> {
+            self.inner.prune_dedup_keys(older_than).await
+        }
+
+        async fn get_aggregate_version(&self, aggregate_id: crate::AggregateId, aggregate_type: &str) -> Result<i64, EventStoreError> {
+            self.inner.get_aggregate_version(aggregate_id, aggregate_type).await
+        }
+    }
+
+    async fn open_account(context: &crate::SharedEventContext, user_id: i64) -> ComposedAggregate<Account> {
+        let mut account = ComposedAggregate::<Account>::new(context, None).await.unwrap();
+        account.request(AccountCommands::CreateAccount(AccountCreation { user_id })).unwrap();
+        account
+    }
+
+    #[tokio::test]
+    async fn ensure_commit_all_writes_disjoint_contexts_together() {
+        let event_store = crate::EventStore::new(crate::memory::MemoryStorageEngine::new());
+        let orders = event_store.get_context();
+        let billing = event_store.get_context();
+        let first = open_account(&orders, 1).await;
+        let second = open_account(&billing, 2).await;
+
+        let receipt = event_store.commit_all(&[orders.clone(), billing.clone()]).await.unwrap();
+        assert!(orders.is_committed() && billing.is_committed());
+        assert_eq!(receipt.events.iter().map(|event| (event.aggregate_id, event.position)).collect::<Vec<_>>(),
+            vec![(first.id(), Some(1)), (second.id(), Some(2))]);
+        assert_eq!(event_store.get_events(first.id(), "account", 0).await.unwrap().len(), 1);
+        assert_eq!(event_store.get_events(second.id(), "account", 0).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn ensure_commit_receipt_carries_assigned_fields() {
+        let event_store = crate::EventStore::new(crate::memory::MemoryStorageEngine::new());
+        let setup = event_store.get_context();
+        open_account(&setup, 1).await;
+        setup.commit().await.unwrap();
+
+        let context = event_store.get_context();
+        let mut account = open_account(&context, 2).await;
+        account.request(AccountCommands::CreditAccount(AccountUpdate { amount: 5 })).unwrap();
+        let receipt = context.commit().await.unwrap();
+
+        assert_eq!(receipt.events.iter().map(|event| (event.version, event.position)).collect::<Vec<_>>(),
+            vec![(1, Some(2)), (2, Some(3))]);
+        let feed = event_store.storage_engine.read_all_events(1, 10).await.unwrap();
+        assert_eq!(receipt.events.iter().map(|event| event.created_at).collect::<Vec<_>>(),
+            feed.iter().map(|event| event.created_at).collect::<Vec<_>>());
+    }
+
+    #[tokio::test]
+    async fn ensure_type_vocabularies_are_listed() {
+        let event_store = crate::EventStore::new(crate::memory::MemoryStorageEngine::new());
+        let context = event_store.get_context();
+        let mut first = open_account(&context, 1).await;
+        open_account(&context, 2).await;
+        first.request(AccountCommands::CreditAccount(AccountUpdate { amount: 5 })).unwrap();
+        first.request(AccountCommands::CreditAccount(AccountUpdate { amount: 5 })).unwrap();
+        context.commit().await.unwrap();
+        event_store.create_aggregate_instance("ledger", None, None).await.unwrap();
+
+        let aggregate_types = event_store.aggregate_types(true).await.unwrap();
+        let summary: Vec<(&str, Option<u64>)> = aggregate_types.iter().map(|info| (info.name.as_str(), info.usage)).collect();
+        assert_eq!(summary, vec![("account", Some(4)), ("ledger", Some(0))]);
+        assert!(aggregate_types[0].first_seen.is_some() && aggregate_types[1].first_seen.is_none());
+
+        let event_types = event_store.event_types(true).await.unwrap();
+        let summary: Vec<(&str, Option<u64>)> = event_types.iter().map(|info| (info.name.as_str(), info.usage)).collect();
+        assert_eq!(summary, vec![("created", Some(2)), ("credited", Some(2))]);
+
+        let cheap = event_store.event_types(false).await.unwrap();
+        assert!(cheap.iter().all(|info| info.usage.is_none() && info.first_seen.is_none()));
+    }
+
+    #[tokio::test]
+    async fn ensure_commit_all_rejects_overlapping_contexts() {
+        let event_store = crate::EventStore::new(crate::memory::MemoryStorageEngine::new());
+        let setup = event_store.get_context();
+        let account = open_account(&setup, 1).await;
+        let other = open_account(&setup, 2).await;
+        setup.commit().await.unwrap();
+
+        let orders = event_store.get_context();
+        let billing = event_store.get_context();
+        for context in [&orders, &billing] {
+            let mut loaded = ComposedAggregate::<Account>::load(context, account.id()).await.unwrap();
+            loaded.request(AccountCommands::CreditAccount(AccountUpdate { amount: 5 })).unwrap();
+        }
+        let mut loaded = ComposedAggregate::<Account>::load(&billing, other.id()).await.unwrap();
+        loaded.request(AccountCommands::CreditAccount(AccountUpdate { amount: 5 })).unwrap();
+
+        let result = event_store.commit_all(&[orders.clone(), billing.clone()]).await;
+        assert!(matches!(result, Err(EventStoreError::OverlappingContexts(ref aggregates)) if aggregates == &vec![("account".to_string(), account.id())]));
+        assert!(!orders.is_committed() && !billing.is_committed());
+        assert_eq!(event_store.get_events(account.id(), "account", 0).await.unwrap().len(), 1);
+        assert_eq!(event_store.get_events(other.id(), "account", 0).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn ensure_commit_all_writes_nothing_on_failure() {
+        let engine = FaultyEngine::new();
+        let event_store = crate::EventStore::new(engine.clone());
+        let orders = event_store.get_context();
+        let billing = event_store.get_context();
+        let first = open_account(&orders, 1).await;
+        let second = open_account(&billing, 2).await;
+
+        engine.fail_writes.store(true, std::sync::atomic::Ordering::SeqCst);
+        let result = event_store.commit_all(&[orders.clone(), billing.clone()]).await;
+        assert!(matches!(result, Err(EventStoreError::StorageEngineErrorOther(_))));
+        assert!(!orders.is_committed() && !billing.is_committed());
+        assert!(event_store.get_events(first.id(), "account", 0).await.unwrap().is_empty());
+        assert!(event_store.get_events(second.id(), "account", 0).await.unwrap().is_empty());
+
+        engine.fail_writes.store(false, std::sync::atomic::Ordering::SeqCst);
+        event_store.commit_all(&[orders.clone(), billing.clone()]).await.unwrap();
+        assert!(orders.is_committed() && billing.is_committed());
+    }
+
+    #[tokio::test]
+    async fn ensure_commits_during_inline_projection_registration_are_applied() {
+        use std::time::Duration;
+
+        let engine = FaultyEngine::new();
+        let event_store = crate::EventStore::new(engine.clone());
+        *engine.read_delay.lock().unwrap() = Duration::from_millis(50);
+        let registration = tokio::spawn({
+            let event_store = event_store.clone();
+            async move { event_store.register_inline_projection("events", 0usize, |count, _event| *count += 1).await }
+        });
+        // Committed after the replay read the feed, before the projection is registered.
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        let context = event_store.get_context();
+        open_account(&context, 1).await;
+        context.commit().await.unwrap();
+
+        registration.await.unwrap().unwrap();
+        assert_eq!(*event_store.projection_state::<usize>("events").unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn ensure_concurrent_commit_alls_write_each_context_once() {
+        use std::time::Duration;
+
+        let engine = FaultyEngine::new();
+        let event_store = crate::EventStore::new(engine.clone());
+        *engine.write_delay.lock().unwrap() = Duration::from_millis(50);
+        let orders = event_store.get_context();
+        let billing = event_store.get_context();
+        let first = open_account(&orders, 1).await;
+        let second = open_account(&billing, 2).await;
+
+        let commits = vec![
+            tokio::spawn({
+                let (event_store, orders, billing) = (event_store.clone(), orders.clone(), billing.clone());
+                async move { event_store.commit_all(&[orders, billing]).await.unwrap().events.len() }
+            }),
+            tokio::spawn({
+                let (event_store, orders, billing) = (event_store.clone(), orders.clone(), billing.clone());
+                async move { event_store.commit_all(&[billing, orders]).await.unwrap().events.len() }
+            }),
+            tokio::spawn({
+                let orders = orders.clone();
+                async move { orders.commit().await.unwrap().events.len() }
+            }),
+        ];
+        let written = tokio::time::timeout(Duration::from_secs(5), async {
+            let mut written = 0;
+            for commit in commits {
+                written += commit.await.unwrap();
+            }
+            written
+        }).await.expect("commits do not deadlock");
+        assert_eq!(written, 2);
+        assert_eq!(event_store.get_events(first.id(), "account", 0).await.unwrap().len(), 1);
+        assert_eq!(event_store.get_events(second.id(), "account", 0).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn ensure_commit_hooks_run_only_after_successful_commit() {
+        let engine = FaultyEngine::new();
+        let event_store = crate::EventStore::new(engine.clone());
+        let context = event_store.get_context();
+        let account = open_account(&context, 1).await;
+        let calls = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let sync_calls = calls.clone();
+        context.on_commit(move |events| {
+            sync_calls.lock().unwrap().push(format!("mail {}", events.len()));
+            Ok(())
+        }).unwrap();
+        let async_calls = calls.clone();
+        context.on_commit_async(move |events| async move {
+            async_calls.lock().unwrap().push(format!("job {:?}", events[0].position));
+            Ok(())
+        }).unwrap();
+
+        engine.fail_writes.store(true, std::sync::atomic::Ordering::SeqCst);
+        assert!(context.commit().await.is_err());
+        assert!(calls.lock().unwrap().is_empty());
+
+        engine.fail_writes.store(false, std::sync::atomic::Ordering::SeqCst);
+        context.commit().await.unwrap();
+        assert_eq!(*calls.lock().unwrap(), vec!["mail 1".to_string(), "job Some(1)".to_string()]);
+        assert_eq!(event_store.get_events(account.id(), "account", 0).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn ensure_context_deadline_bounds_storage_calls() {
+        use tokio::time::{Duration, Instant};
+        use std::sync::atomic::Ordering;
+
+        let engine = FaultyEngine::new();
+        let event_store = crate::EventStore::new(engine.clone());
+        *engine.write_delay.lock().unwrap() = Duration::from_secs(5);
+
+        // A spent budget skips the write without starting it.
+        let context = event_store.get_context();
+        open_account(&context, 1).await;
+        context.set_deadline(Instant::now()).unwrap();
+        assert!(matches!(context.commit().await, Err(EventStoreError::StorageTimeout)));
+        assert_eq!(engine.writes_started.load(Ordering::SeqCst), 0);
+
+        // One running out mid-write cancels it.
+        let context = event_store.get_context();
+        let account = open_account(&context, 2).await;
+        context.set_deadline(Instant::now() + Duration::from_millis(50)).unwrap();
+        let started = Instant::now();
+        assert!(matches!(context.commit().await, Err(EventStoreError::StorageTimeout)));
+        assert!(started.elapsed() < Duration::from_secs(1));
+        assert_eq!(engine.writes_started.load(Ordering::SeqCst), 1);
+        assert!(event_store.get_events(account.id(), "account", 0).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn ensure_failing_commit_hook_keeps_events_committed() {
+        let event_store = crate::EventStore::new(crate::memory::MemoryStorageEngine::new());
+        let context = event_store.get_context();
+        let account = open_account(&context, 1).await;
+        let skipped = Arc::new(std::sync::atomic::AtomicBool::new(true));
+        context.on_commit(|_| Err(EventStoreError::ContextErrorOther("mail server down".to_string()))).unwrap();
+        let later = skipped.clone();
+        context.on_commit(move |_| {
+            later.store(false, std::sync::atomic::Ordering::SeqCst);
+            Ok(())
+        }).unwrap();
+
+        let result = context.commit().await;
+        assert!(matches!(result, Err(EventStoreError::PostCommitHookError(ref source)) if matches!(**source, EventStoreError::ContextErrorOther(_))));
+        assert!(context.is_committed());
+        assert!(skipped.load(std::sync::atomic::Ordering::SeqCst));
+        assert_eq!(event_store.get_events(account.id(), "account", 0).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn ensure_commit_all_hooks_see_their_own_events() {
+        let event_store = crate::EventStore::new(crate::memory::MemoryStorageEngine::new());
+        let orders = event_store.get_context();
+        let billing = event_store.get_context();
+        let first = open_account(&orders, 1).await;
+        let second = open_account(&billing, 2).await;
+        let seen = Arc::new(std::sync::Mutex::new(Vec::new()));
+        for context in [&orders, &billing] {
+            let seen = seen.clone();
+            context.on_commit(move |events| {
+                seen.lock().unwrap().extend(events.iter().map(|event| event.aggregate_id));
+                Ok(())
+            }).unwrap();
+        }
+
+        event_store.commit_all(&[orders.clone(), billing.clone()]).await.unwrap();
+        assert_eq!(*seen.lock().unwrap(), vec![first.id(), second.id()]);
+    }
+
+    #[tokio::test]
+    async fn ensure_hashed_stores_canonicalize_and_chain_events() {
+        let event_store = crate::EventStore::builder(crate::memory::MemoryStorageEngine::new())
+            .hash_events(true)
+            .verify_hashes(true)
+            .build()
+            .unwrap();
+        let context = event_store.get_context();
+        let mut account = ComposedAggregate::<Account>::new(&context, None).await.unwrap();
+        context.publish_json(&mut account, "created", r#"{ "AccountCreated": { "user_id": 7 } }"#).unwrap();
+        context.commit().await.unwrap();
+
+        let context = event_store.get_context();
+        let mut loaded = ComposedAggregate::<Account>::load(&context, account.id()).await.unwrap();
+        loaded.request(AccountCommands::CreditAccount(AccountUpdate { amount: 5 })).unwrap();
+        let receipt = context.commit().await.unwrap();
+
+        let events = event_store.get_events(account.id(), "account", 0).await.unwrap();
+        assert_eq!(events[0].data, r#"{"AccountCreated":{"user_id":7}}"#);
+        assert_eq!(receipt.events[0].hash, events[1].hash);
+        assert_eq!(events[1].hash, Some(crate::integrity::event_hash(&events[1], events[0].hash.as_deref())));
+        let loaded = ComposedAggregate::<Account>::load(&event_store.get_context(), account.id()).await.unwrap();
+        assert_eq!(loaded.state().balance, 5);
+
+        // Rewritten events are canonicalized and chained anew.
+        event_store.rewrite_stream(account.id(), "account", |mut event| {
+            event.data = event.data.to_string().replace(":7", ": 8").into();
+            Some(event)
+        }).await.unwrap();
+        let events = event_store.get_events(account.id(), "account", 0).await.unwrap();
+        assert_eq!(events[0].data, r#"{"AccountCreated":{"user_id":8}}"#);
+        assert_eq!(events[0].hash, Some(crate::integrity::event_hash(&events[0], None)));
+        assert_eq!(events[1].hash, Some(crate::integrity::event_hash(&events[1], events[0].hash.as_deref())));
+    }
+
+    #[cfg(feature = "msgpack")]
+    #[tokio::test]
+    async fn ensure_binary_serializer_stores_bytes_and_reads_json() {
+        let memory = crate::memory::MemoryStorageEngine::new();
+        let event_store = crate::EventStore::builder(memory.clone())
+            .payload_serializer(Arc::new(crate::payload::MessagePackSerializer))
+            .hash_events(true)
+            .verify_hashes(true)
+            .build()
+            .unwrap();
+        let context = event_store.get_context();
+        let mut account = ComposedAggregate::<Account>::new(&context, None).await.unwrap();
+        account.request(AccountCommands::CreateAccount(AccountCreation { user_id: 7 })).unwrap();
+        account.request(AccountCommands::CreditAccount(AccountUpdate { amount: 5 })).unwrap();
+        context.commit().await.unwrap();
+        account.checkpoint().await.unwrap();
+
+        let stored = memory.read_events(account.id(), "account", 0).await.unwrap();
+        assert!(stored.iter().all(|event| event.data.is_binary()));
+        let snapshot = memory.read_snapshot(account.id(), "account").await.unwrap().unwrap();
+        assert!(snapshot.data.is_binary());
+
+        let events = event_store.get_events(account.id(), "account", 0).await.unwrap();
+        assert_eq!(events[0].data, r#"{"AccountCreated":{"user_id":7}}"#);
+        assert_eq!(events[1].hash, Some(crate::integrity::event_hash(&stored[1], stored[0].hash.as_deref())));
+        let snapshot = event_store.get_snapshot(account.id(), "account").await.unwrap().unwrap();
+        assert_eq!(snapshot.to_state::<Account>().unwrap().balance, 5);
+        let loaded = ComposedAggregate::<Account>::load(&event_store.get_context(), account.id()).await.unwrap();
+        assert_eq!(loaded.state().balance, 5);
+    }
+
+    #[test]
+    fn ensure_binary_serializer_needs_binary_engine() {
+        use crate::payload::{Payload, PayloadSerializer};
+
+        struct Binary;
+        impl PayloadSerializer for Binary {
+            fn name(&self) -> &'static str { "binary" }
+            fn is_binary(&self) -> bool { true }
+            fn encode(&self, json: &str) -> Result<Payload, EventStoreError> { Ok(Payload::Binary(json.as_bytes().to_vec())) }
+            fn decode(&self, bytes: &[u8]) -> Result<String, EventStoreError> { Ok(String::from_utf8_lossy(bytes).into_owned()) }
+        }
+        let result = crate::EventStore::builder(FaultyEngine::new()).payload_serializer(Arc::new(Binary)).build();
+        let Err(EventStoreError::ConfigurationError(problems)) = result else {
+            panic!("expected a configuration error");
+        };
+        assert_eq!(problems.len(), 1);
+        assert!(problems[0].ends_with("does not support binary payloads, needed by the binary serializer"));
+    }
+
+    #[tokio::test]
+    async fn ensure_json_payloads_are_published_verbatim() {
+        let event_store = crate::EventStore::new(crate::memory::MemoryStorageEngine::new());
+        let context = event_store.get_context();
+        let mut account = ComposedAggregate::<Account>::new(&context, None).await.unwrap();
+
+        let json = r#"{ "AccountCreated": { "user_id": 7 } }"#;
+        context.publish_json(&mut account, "created", json).unwrap();
+        context.publish_raw_value(&mut account, "credited", serde_json::json!({ "AccountCredited": { "amount": 15 } })).unwrap();
+        assert_eq!(account.version(), 2);
+        assert_eq!(account.state().user_id, 7);
+        assert_eq!(account.state().balance, 15);
+
+        let error = context.publish_json(&mut account, "credited", r#"{ "AccountCredited": "#).unwrap_err();
+        assert!(matches!(error.root_cause(), EventStoreError::EventSerializationError(_)));
+        assert_eq!(error.context().and_then(|context| context.version), Some(3));
+        assert_eq!(account.version(), 2);
+
+        let result = context.publish_json(&mut account, "closed", r#"{ "AccountClosed": {} }"#);
+        assert!(matches!(result, Err(EventStoreError::UnknownEventType(ref event_type)) if event_type == "closed"));
+        assert_eq!(account.version(), 2);
+
+        context.commit().await.unwrap();
+        let events = event_store.get_events(account.id(), "account", 0).await.unwrap();
+        assert_eq!(events[0].data, json);
+        assert_eq!(events[1].data, r#"{"AccountCredited":{"amount":15}}"#);
+    }
+
+    #[tokio::test]
+    async fn ensure_takes_snapshots() {
+        let memory = crate::memory::MemoryStorageEngine::new();
+        let event_store = crate::EventStore::new(memory.clone());
+        let context = event_store.get_context();
+        {
+            let mut account = ComposedAggregate::<Account>::new(&context, None).await.unwrap();
+            account.request(AccountCommands::CreateAccount(AccountCreation { user_id: 1 })).unwrap();
+            for (_i, _) in (0..100).enumerate() {
+                account.request(AccountCommands::CreditAccount(AccountUpdate { amount: 100 })).unwrap();
+            }
+
+            let state = account.state();
+            assert!(state.balance == 100*100);
+        }
+        context.commit().await.unwrap();
+        let context = event_store.get_context();
+        {
+            let account = ComposedAggregate::<Account>::load(&context, numbered_id(1)).await.unwrap();
+            let state = account.state();
+            assert!(state.balance == 100*100);
+        }
+        // Only the newest of the ten snapshots taken is written.
+        assert_eq!(memory.snapshot_count(), 1);
+    }
+
+    #[tokio::test]
+    async fn ensure_snapshots_on_demand_once_per_version() {
+        let memory = crate::memory::MemoryStorageEngine::new();
+        let event_store = crate::EventStore::new(memory.clone());
+        let context = event_store.get_context();
+        let mut account = ComposedAggregate::<Account>::new(&context, None).await.unwrap();
+        account.request(AccountCommands::CreateAccount(AccountCreation { user_id: 1 })).unwrap();
+        account.request(AccountCommands::CreditAccount(AccountUpdate { amount: 30 })).unwrap();
+        account.snapshot_now().unwrap();
+        account.snapshot_now().unwrap();
+        let captured = context.captured_snapshots().unwrap();
+        assert_eq!(captured.iter().map(|snapshot| snapshot.version).collect::<Vec<_>>(), vec![2]);
+        context.commit().await.unwrap();
+
+        let context = event_store.get_context();
+        let loaded = ComposedAggregate::<Account>::load(&context, account.id()).await.unwrap();
+        assert_eq!(context.last_load_stats().unwrap().unwrap().snapshot_version, Some(2));
+        loaded.snapshot_now().unwrap();
+        context.commit().await.unwrap();
+        assert_eq!(memory.snapshot_count(), 1);
+
+        let other = event_store.get_context();
+        assert!(matches!(other.capture_snapshot(&loaded), Err(EventStoreError::WrongContext { .. })));
+        let snapshot = event_store.get_snapshot(account.id(), "account").await.unwrap().unwrap();
+        assert_eq!(snapshot.version, 2);
+        assert_eq!(snapshot.to_state::<Account>().unwrap().balance, 30);
+    }
+
+    #[tokio::test]
+    async fn ensure_snapshot_policies_resolve_per_aggregate() {
+        use crate::snapshot::{SnapshotCheck, SnapshotPolicy};
+
+        #[derive(Default, Clone, Serialize, Deserialize)]
+        struct Tally;
+
+        #[derive(Serialize, Deserialize)]
+        struct Tick;
+
+        impl Composable for Tally {
+            fn get_type(&self) -> &str {
+                "tally"
+            }
+
+            fn apply_event(&mut self, _event: &crate::event::Event) -> Result<(), EventStoreError> {
+                Ok(())
+            }
+
+            fn snapshot_policy(&self) -> Option<SnapshotPolicy> {
+                Some(SnapshotPolicy::Custom(Arc::new(|check: &SnapshotCheck| check.events_since_snapshot > 3)))
+            }
+        }
+
+        impl CanRequest<Tick, Tick> for Tally {
+            fn request(&self, request: Tick) -> Result<(String, Tick), EventStoreError> {
+                Ok(("ticked".to_string(), request))
+            }
+        }
+
+        let captured_versions = |context: &crate::SharedEventContext, aggregate_type: &str| context.captured_snapshots().unwrap()
+            .into_iter()
+            .filter(|snapshot| snapshot.aggregate_type == aggregate_type)
+            .map(|snapshot| snapshot.version)
+            .collect::<Vec<_>>();
+
+        // The store default replaces the frequency of aggregates without a policy, and
+        // aggregates with one keep it.
+        let event_store = crate::EventStore::builder(crate::memory::MemoryStorageEngine::new())
+            .default_snapshot_policy(SnapshotPolicy::EveryNEvents(2))
+            .build()
+            .unwrap();
+        let context = event_store.get_context();
+        let mut account = ComposedAggregate::<Account>::new(&context, None).await.unwrap();
+        account.request(AccountCommands::CreateAccount(AccountCreation { user_id: 1 })).unwrap();
+        account.request(AccountCommands::CreditAccount(AccountUpdate { amount: 10 })).unwrap();
+        let mut tally = ComposedAggregate::<Tally>::new(&context, None).await.unwrap();
+        for _ in 0..7 {
+            tally.request(Tick).unwrap();
+        }
+        assert_eq!(captured_versions(&context, "account"), vec![1]);
+        assert_eq!(captured_versions(&context, "tally"), vec![6]);
+        context.commit().await.unwrap();
+
+        // Counting resumes from the snapshot the aggregate was loaded from.
+        let context = event_store.get_context();
+        let mut tally = ComposedAggregate::<Tally>::load(&context, tally.id()).await.unwrap();
+        for _ in 0..3 {
+            tally.request(Tick).unwrap();
+        }
+        assert_eq!(captured_versions(&context, "tally"), vec![9]);
+
+        // A policy configured for the type wins over the aggregate's own.
+        let event_store = crate::EventStore::builder(crate::memory::MemoryStorageEngine::new())
+            .snapshot_policy("tally", SnapshotPolicy::Never)
+            .build()
+            .unwrap();
+        let context = event_store.get_context();
+        let mut tally = ComposedAggregate::<Tally>::new(&context, None).await.unwrap();
+        for _ in 0..7 {
+            tally.request(Tick).unwrap();
+        }
+        assert!(context.captured_snapshots().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn ensure_after_duration_snapshots_follow_the_last_one() {
+        use crate::clock::ManualClock;
+        use crate::snapshot::SnapshotPolicy;
+        use chrono::Duration;
+
+        let clock = Arc::new(ManualClock::new(chrono::Utc::now()));
+        let event_store = crate::EventStore::builder(crate::memory::MemoryStorageEngine::new())
+            .clock(clock.clone())
+            .snapshot_policy("account", SnapshotPolicy::AfterDuration(Duration::hours(1)))
+            .build()
+            .unwrap();
+
+        // Never snapshotted, so the first event starts the clock.
+        let context = event_store.get_context();
+        let mut account = ComposedAggregate::<Account>::new(&context, None).await.unwrap();
+        account.request(AccountCommands::CreateAccount(AccountCreation { user_id: 1 })).unwrap();
+        clock.advance(Duration::minutes(61));
+        account.request(AccountCommands::CreditAccount(AccountUpdate { amount: 10 })).unwrap();
+        clock.advance(Duration::minutes(30));
+        account.request(AccountCommands::CreditAccount(AccountUpdate { amount: 10 })).unwrap();
+        assert_eq!(context.captured_snapshots().unwrap().iter().map(|snapshot| snapshot.version).collect::<Vec<_>>(), vec![1]);
+        context.commit().await.unwrap();
+
+        // The loaded snapshot's timestamp carries over to the next context.
+        clock.advance(Duration::minutes(31));
+        let context = event_store.get_context();
+        let mut account = ComposedAggregate::<Account>::load(&context, account.id()).await.unwrap();
+        account.request(AccountCommands::CreditAccount(AccountUpdate { amount: 10 })).unwrap();
+        account.request(AccountCommands::CreditAccount(AccountUpdate { amount: 10 })).unwrap();
+        assert_eq!(context.captured_snapshots().unwrap().iter().map(|snapshot| snapshot.version).collect::<Vec<_>>(), vec![3]);
+    }
+    
+    #[tokio::test]
+    async fn ensure_captures_metadata() {
+        let memory = crate::memory::MemoryStorageEngine::new();
+        let event_store = crate::EventStore::new(memory.clone());
+        let context = event_store.get_context();
+        context.add_metadata("user", "chavez").unwrap();
+        context.add_metadata("ip_address", "10.100.1.100").unwrap();
+        {
+            let mut account = ComposedAggregate::<Account>::new(&context, Some("chavez_account")).await.unwrap();
+            account.request(AccountCommands::CreateAccount(AccountCreation { user_id: 1 })).unwrap();
+        }
+        context.commit().await.unwrap();
+
+        let id = memory.get_aggregate_instance_id("account", "chavez_account").await.unwrap().unwrap();
+
+        let events = memory.read_events(id, "account", 0).await.unwrap();
+        let event = events[0].clone();
+        let hashmap: HashMap<String, String> = event.deserialize_metadata().unwrap().unwrap();
+
+        assert_eq!(hashmap.get("user").unwrap(), "chavez");
+        assert_eq!(hashmap.get("ip_address").unwrap(), "10.100.1.100");
+    }
+
+    #[tokio::test]
+    async fn ensure_expired_metadata_is_left_off_events() {
+        use crate::clock::{Clock, ManualClock};
+        use chrono::Duration;
+
+        let clock = Arc::new(ManualClock::new(chrono::Utc::now()));
+        let event_store = crate::EventStore::builder(crate::memory::MemoryStorageEngine::new())
+            .clock(clock.clone())
+            .required_metadata(&["auth_claim"])
+            .build()
+            .unwrap();
+        let context = event_store.get_context();
+        context.add_metadata("user", "chavez").unwrap();
+        context.add_metadata_with_expiry("session", "s-1", clock.now() + Duration::minutes(5)).unwrap();
+        context.add_metadata_with_expiry("auth_claim", "scope:accounts", clock.now() + Duration::minutes(10)).unwrap();
+
+        let mut account = ComposedAggregate::<Account>::new(&context, None).await.unwrap();
+        account.request(AccountCommands::CreateAccount(AccountCreation { user_id: 1 })).unwrap();
+        clock.advance(Duration::minutes(5));
+        account.request(AccountCommands::CreditAccount(AccountUpdate { amount: 10 })).unwrap();
+
+        let events = context.captured_events().unwrap();
+        let first: HashMap<String, String> = events[0].deserialize_metadata().unwrap().unwrap();
+        let second: HashMap<String, String> = events[1].deserialize_metadata().unwrap().unwrap();
+        assert_eq!(first.get("session").map(String::as_str), Some("s-1"));
+        assert_eq!(second.get("session"), None);
+        assert_eq!(second.get("user").map(String::as_str), Some("chavez"));
+
+        // Once a required key expires nothing more is published, and what was is not written.
+        clock.advance(Duration::minutes(5));
+        let result = account.request(AccountCommands::CreditAccount(AccountUpdate { amount: 10 }));
+        assert!(matches!(result, Err(EventStoreError::MissingMetadata { ref keys }) if keys == &["auth_claim"]));
+        let result = context.commit().await;
+        assert!(matches!(result, Err(EventStoreError::MetadataExpired { ref keys }) if keys == &["auth_claim"]));
+        assert!(!context.is_committed());
+
+        context.add_metadata_with_expiry("auth_claim", "scope:accounts", clock.now() + Duration::minutes(5)).unwrap();
+        context.commit().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn ensure_publish_requires_metadata() {
+        let event_store = crate::EventStore::builder(crate::memory::MemoryStorageEngine::new())
+            .required_metadata(&["user", "request_id"])
+            .metadata_provider(|| HashMap::from([("request_id".to_string(), "req-7".to_string())]))
+            .build()
+            .unwrap();
+        let context = event_store.get_context();
+        let mut account = ComposedAggregate::<Account>::new(&context, None).await.unwrap();
+
+        let result = account.request(AccountCommands::CreateAccount(AccountCreation { user_id: 1 }));
+        assert!(matches!(result, Err(EventStoreError::MissingMetadata { ref keys }) if keys == &["user"]));
+        context.assert_nothing_published();
+
+        // The provider supplies the request id, the context the user.
+        context.add_metadata("user", "chavez").unwrap();
+        account.request(AccountCommands::CreateAccount(AccountCreation { user_id: 1 })).unwrap();
+        let metadata: HashMap<String, String> = context.captured_events().unwrap()[0].deserialize_metadata().unwrap().unwrap();
+        assert_eq!(metadata.get("request_id").map(String::as_str), Some("req-7"));
+        context.commit().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn ensure_audit_counts_events_missing_required_metadata() {
+        let memory = crate::memory::MemoryStorageEngine::new();
+        let event_store = crate::EventStore::new(memory.clone());
+        let context = event_store.get_context();
+        let mut account = open_account(&context, 1).await;
+        context.add_metadata("user", "chavez").unwrap();
+        account.request(AccountCommands::CreditAccount(AccountUpdate { amount: 10 })).unwrap();
+        context.add_metadata("request_id", "req-1").unwrap();
+        account.request(AccountCommands::CreditAccount(AccountUpdate { amount: 10 })).unwrap();
+        context.commit().await.unwrap();
+
+        let audited = crate::EventStore::builder(memory)
+            .required_metadata(&["user", "request_id"])
+            .build()
+            .unwrap();
+        let audit = audited.audit_required_metadata().await.unwrap();
+        assert_eq!(audit.events_scanned, 3);
+        assert!(!audit.is_clean());
+        assert_eq!(audit.missing_by_event_type, std::collections::BTreeMap::from([
+            ("created".to_string(), 1),
+            ("credited".to_string(), 1),
+        ]));
+        assert_eq!(audit.missing_by_key, std::collections::BTreeMap::from([
+            ("request_id".to_string(), 2),
+            ("user".to_string(), 1),
+        ]));
+    }
+
+    #[tokio::test]
+    async fn ensure_interleaved_contexts_conflict_on_version() {
+        let event_store = crate::EventStore::new(crate::memory::MemoryStorageEngine::new());
+        let context = event_store.get_context();
+        let id = open_account(&context, 1).await.id();
+        context.commit().await.unwrap();
+
+        let first = event_store.get_context();
+        let second = event_store.get_context();
+        let mut first_account = ComposedAggregate::<Account>::load(&first, id).await.unwrap();
+        let mut second_account = ComposedAggregate::<Account>::load(&second, id).await.unwrap();
+        first_account.request(AccountCommands::CreditAccount(AccountUpdate { amount: 10 })).unwrap();
+        second_account.request(AccountCommands::CreditAccount(AccountUpdate { amount: 20 })).unwrap();
+
+        first.commit().await.unwrap();
+        let result = second.commit().await;
+        assert!(matches!(
+            result,
+            Err(EventStoreError::VersionConflict { ref aggregate_type, aggregate_id, expected: 1, actual: 2 })
+                if aggregate_type == "account" && aggregate_id == id
+        ));
+        assert!(!second.is_committed());
+
+        let account = ComposedAggregate::<Account>::load(&event_store.get_context(), id).await.unwrap();
+        assert_eq!(account.state().balance, 10);
+    }
+
+    #[tokio::test]
+    async fn ensure_with_context_retry_reruns_conflicting_tasks() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let event_store = crate::EventStore::new(crate::memory::MemoryStorageEngine::new());
+        let context = event_store.get_context();
+        let id = open_account(&context, 1).await.id();
+        context.commit().await.unwrap();
+
+        // Credits the account, while a competing writer gets in first on the first
+        // `competing` attempts.
+        let credit = |competing: usize, attempts: Arc<AtomicUsize>| {
+            let competitor = event_store.clone();
+            move |context: crate::SharedEventContext| {
+                let attempts = attempts.clone();
+                let competitor = competitor.clone();
+                async move {
+                    let mut account = ComposedAggregate::<Account>::load(&context, id).await?;
+                    account.request(AccountCommands::CreditAccount(AccountUpdate { amount: 10 }))?;
+                    if attempts.fetch_add(1, Ordering::SeqCst) < competing {
+                        let other = competitor.get_context();
+                        let mut theirs = ComposedAggregate::<Account>::load(&other, id).await?;
+                        theirs.request(AccountCommands::CreditAccount(AccountUpdate { amount: 1 }))?;
+                        other.commit().await?;
+                    }
+                    Ok(())
+                }
+            }
+        };
+
+        let attempts = Arc::new(AtomicUsize::new(0));
+        event_store.clone().with_context_retry(3, credit(1, attempts.clone())).await.unwrap();
+        assert_eq!(attempts.load(Ordering::SeqCst), 2);
+        let account = ComposedAggregate::<Account>::load(&event_store.get_context(), id).await.unwrap();
+        assert_eq!(account.state().balance, 11);
+
+        let attempts = Arc::new(AtomicUsize::new(0));
+        let result = event_store.clone().with_context_retry(2, credit(usize::MAX, attempts.clone())).await;
+        assert!(matches!(result, Err(EventStoreError::VersionConflict { .. })));
+        assert_eq!(attempts.load(Ordering::SeqCst), 2);
+
+        let attempts = Arc::new(AtomicUsize::new(0));
+        let counter = attempts.clone();
+        let result: Result<(), _> = event_store.clone().with_context_retry(3, move |_| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            async { Err(EventStoreError::RequestProcessingError("rejected".to_string())) }
+        }).await;
+        assert!(matches!(result, Err(EventStoreError::RequestProcessingError(_))));
+        assert_eq!(attempts.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn ensure_invalid_arguments_fail_before_reading() {
+        let memory = crate::memory::MemoryStorageEngine::new();
+        let event_store = crate::EventStore::builder(memory.clone()).max_page_size(5).build().unwrap();
+        let context = event_store.get_context();
+        let id = open_account(&context, 1).await.id();
+        context.commit().await.unwrap();
+
+        let invalid = |result: Result<_, EventStoreError>, expected: &str| match result {
+            Err(EventStoreError::InvalidArgument { parameter, .. }) => assert_eq!(parameter, expected),
+            _ => panic!("{} was accepted", expected),
+        };
+        invalid(event_store.get_events(id, "account", -5).await.map(|_| ()), "version");
+        invalid(event_store.get_events(numbered_id(0), "account", 0).await.map(|_| ()), "aggregate_id");
+        invalid(event_store.read_snapshot_at(id, "account", -1).await.map(|_| ()), "max_version");
+        invalid(event_store.read_all_events_page(None, 6).await.map(|_| ()), "limit");
+        invalid(ComposedAggregate::<Account>::load_at_version(&event_store.get_context(), id, -1).await.map(|_| ()), "version");
+        invalid(memory.read_all_events(-1, 1).await.map(|_| ()), "from_position");
+        invalid(crate::event::Event::new(id, "account", 0, "credited", &1).map(|_| ()), "version");
+
+        assert_eq!(event_store.read_all_events_page(None, 5).await.unwrap().items.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn ensure_global_feed_interleaves_aggregates_in_commit_order() {
+        let event_store = crate::EventStore::new(crate::memory::MemoryStorageEngine::new());
+        let context = event_store.get_context();
+        let first = open_account(&context, 1).await.id();
+        let second = open_account(&context, 2).await.id();
+        context.commit().await.unwrap();
+        for (id, amount) in [(second, 5), (first, 10), (second, 15)] {
+            let context = event_store.get_context();
+            let mut account = ComposedAggregate::<Account>::load(&context, id).await.unwrap();
+            account.request(AccountCommands::CreditAccount(AccountUpdate { amount })).unwrap();
+            context.commit().await.unwrap();
+        }
+
+        let feed = event_store.read_all_events(0, 100).await.unwrap();
+        let order: Vec<(crate::AggregateId, i64)> = feed.iter().map(|event| (event.aggregate_id, event.version)).collect();
+        assert_eq!(order, vec![(first, 1), (second, 1), (second, 2), (first, 2), (second, 3)]);
+
+        let mut paged = Vec::new();
+        let mut position = 0;
+        loop {
+            let page = event_store.read_all_events(position, 2).await.unwrap();
+            let Some(last) = page.last() else { break };
+            position = last.position.unwrap();
+            paged.extend(page);
+        }
+        assert_eq!(paged.iter().map(|event| event.position).collect::<Vec<_>>(), feed.iter().map(|event| event.position).collect::<Vec<_>>());
+        let stream = event_store.get_events(second, "account", 0).await.unwrap();
+        assert_eq!(stream.iter().map(|event| event.position.unwrap()).collect::<Vec<_>>(), vec![2, 3, 5]);
+    }
+
+    #[tokio::test]
+    async fn ensure_soft_deleted_aggregates_can_be_resurrected() {
+        let event_store = crate::EventStore::new(crate::memory::MemoryStorageEngine::new());
+        let context = event_store.get_context();
+        let mut account = ComposedAggregate::<Account>::new(&context, Some("chavez_account")).await.unwrap();
+        account.request(AccountCommands::CreateAccount(AccountCreation { user_id: 1 })).unwrap();
+        context.commit().await.unwrap();
+        let id = account.id();
+
+        // A context that loaded the aggregate before the delete cannot commit to it.
+        let stale = event_store.get_context();
+        let mut stale_account = ComposedAggregate::<Account>::load(&stale, id).await.unwrap();
+        stale_account.request(AccountCommands::CreditAccount(AccountUpdate { amount: 5 })).unwrap();
+
+        event_store.soft_delete_aggregate("account", id).await.unwrap();
+        let deleted = |result: Result<_, EventStoreError>| matches!(
+            result,
+            Err(EventStoreError::AggregateDeleted { ref aggregate_type, aggregate_id }) if aggregate_type == "account" && aggregate_id == id
+        );
+        assert!(deleted(ComposedAggregate::<Account>::load(&event_store.get_context(), id).await.map(|_| ())));
+        assert!(deleted(stale.commit().await.map(|_| ())));
+
+        let instance = event_store.find_aggregate_instance("account", "chavez_account").await.unwrap().unwrap();
+        assert_eq!(instance.id, id);
+        assert!(instance.is_deleted());
+        let listed = event_store.list_aggregate_instances("account", None, 10).await.unwrap();
+        assert!(listed.items[0].is_deleted());
+
+        event_store.resurrect_aggregate("account", instance.id).await.unwrap();
+        assert!(!event_store.find_aggregate_instance("account", "chavez_account").await.unwrap().unwrap().is_deleted());
+        let context = event_store.get_context();
+        let mut account = ComposedAggregate::<Account>::load(&context, id).await.unwrap();
+        account.request(AccountCommands::CreditAccount(AccountUpdate { amount: 10 })).unwrap();
+        context.commit().await.unwrap();
+
+        let account = ComposedAggregate::<Account>::load(&event_store.get_context(), id).await.unwrap();
+        assert_eq!(account.version(), 2);
+        assert_eq!(account.state().balance, 10);
+        let missing = event_store.soft_delete_aggregate("account", numbered_id(100)).await;
+        assert!(matches!(missing, Err(EventStoreError::AggregateInstanceNotFound)));
+    }
+
+    #[tokio::test]
+    async fn ensure_purged_aggregates_are_gone() {
+        use crate::operational::OperationalEvent;
+        use crate::PurgeReport;
+
+        let memory = crate::memory::MemoryStorageEngine::new();
+        let event_store = crate::EventStore::new(memory.clone());
+        let context = event_store.get_context();
+        let mut account = ComposedAggregate::<Account>::new(&context, Some("chavez_account")).await.unwrap();
+        account.request(AccountCommands::CreateAccount(AccountCreation { user_id: 1 })).unwrap();
+        account.request_dedup(AccountCommands::CreditAccount(AccountUpdate { amount: 10 }), "payment-1").await.unwrap();
+        account.add_lookup_key("branch", "downtown").unwrap();
+        account.snapshot_now().unwrap();
+        let mut other = ComposedAggregate::<Account>::new(&context, None).await.unwrap();
+        other.request(AccountCommands::CreateAccount(AccountCreation { user_id: 2 })).unwrap();
+        context.commit().await.unwrap();
+        let id = account.id();
+
+        let mut operational_events = event_store.operational_events();
+        let report = event_store.purge_aggregate(id, "account").await.unwrap();
+        assert_eq!(report, PurgeReport { events: 2, snapshots: 1, lookup_keys: 1, dedup_keys: 1 });
+        assert_eq!(operational_events.recv().await.unwrap(), OperationalEvent::AggregatePurged {
+            aggregate_type: "account".to_string(),
+            aggregate_id: id,
+            report,
+        });
+
+        let loaded = ComposedAggregate::<Account>::load(&event_store.get_context(), id).await;
+        assert!(matches!(loaded, Err(EventStoreError::AggregateNotFound(_))));
+        assert_eq!(memory.snapshot_count(), 0);
+        assert!(event_store.find_by_lookup_key("account", "branch", "downtown").await.unwrap().is_empty());
+        assert!(event_store.find_aggregate_instance("account", "chavez_account").await.unwrap().is_none());
+        let missing = event_store.purge_aggregate(id, "account").await;
+        assert!(matches!(missing, Err(EventStoreError::AggregateInstanceNotFound)));
+
+        // The natural key and dedup key are free again, and feed positions are not reused.
+        let context = event_store.get_context();
+        let mut account = ComposedAggregate::<Account>::new(&context, Some("chavez_account")).await.unwrap();
+        account.request(AccountCommands::CreateAccount(AccountCreation { user_id: 1 })).unwrap();
+        assert!(account.request_dedup(AccountCommands::CreditAccount(AccountUpdate { amount: 10 }), "payment-1").await.unwrap());
+        let receipt = context.commit().await.unwrap();
+        assert_eq!(receipt.first_position(), Some(4));
+        let feed = event_store.read_all_events(0, 10).await.unwrap();
+        assert_eq!(feed.iter().map(|event| event.position.unwrap()).collect::<Vec<_>>(), vec![3, 4, 5]);
+        assert_eq!(event_store.read_all_events(3, 10).await.unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn ensure_rewritten_streams_replay_without_old_snapshots() {
+        use crate::operational::OperationalEvent;
+        use crate::RewriteReport;
+
+        let memory = crate::memory::MemoryStorageEngine::new();
+        let event_store = crate::EventStore::new(memory.clone());
+        let context = event_store.get_context();
+        let mut account = ComposedAggregate::<Account>::new(&context, None).await.unwrap();
+        account.request(AccountCommands::CreateAccount(AccountCreation { user_id: 1 })).unwrap();
+        account.request(AccountCommands::CreditAccount(AccountUpdate { amount: 100 })).unwrap();
+        account.request(AccountCommands::DebitAccount(AccountUpdate { amount: 50 })).unwrap();
+        account.request(AccountCommands::CreditAccount(AccountUpdate { amount: 7 })).unwrap();
+        account.snapshot_now().unwrap();
+        context.commit().await.unwrap();
+        let id = account.id();
+
+        // Drops the debit and replaces the user id of the creation event.
+        let mut operational_events = event_store.operational_events();
+        let report = event_store.rewrite_stream(id, "account", |mut event| {
+            match event.event_type.as_str() {
+                "debited" => return None,
+                "created" => event.data = serde_json::json!({ "AccountCreated": { "user_id": 9 } }).to_string().into(),
+                _ => {}
+            }
+            Some(event)
+        }).await.unwrap();
+        assert_eq!(report, RewriteReport { events_removed: 4, events_written: 3, snapshots_removed: 1 });
+        assert_eq!(operational_events.recv().await.unwrap(), OperationalEvent::StreamRewritten {
+            aggregate_type: "account".to_string(),
+            aggregate_id: id,
+            report,
+        });
+        assert_eq!(memory.snapshot_count(), 0);
+
+        let events = event_store.get_events(id, "account", 0).await.unwrap();
+        assert_eq!(events.iter().map(|event| event.version).collect::<Vec<_>>(), vec![1, 2, 3]);
+        assert_eq!(events.iter().map(|event| event.event_type.as_str()).collect::<Vec<_>>(), vec!["created", "credited", "credited"]);
+        assert_eq!(events.iter().map(|event| event.position.unwrap()).collect::<Vec<_>>(), vec![5, 6, 7]);
+
+        let context = event_store.get_context();
+        let mut account = ComposedAggregate::<Account>::load(&context, id).await.unwrap();
+        assert_eq!(account.version(), 3);
+        assert_eq!(account.state().user_id, 9);
+        assert_eq!(account.state().balance, 107);
+        account.request(AccountCommands::DebitAccount(AccountUpdate { amount: 7 })).unwrap();
+        context.commit().await.unwrap();
+        assert_eq!(event_store.get_aggregate_version(id, "account").await.unwrap(), 4);
+    }
+
+    #[tokio::test]
+    async fn ensure_context_metadata_is_capped() {
+        let event_store = crate::EventStore::builder(crate::memory::MemoryStorageEngine::new())
+            .max_metadata_entries(2)
+            .build()
+            .unwrap();
+        let context = event_store.get_context();
+        context.add_metadata("user", "chavez").unwrap();
+        context.add_metadata("tenant", "north").unwrap();
+        context.add_metadata("user", "diaz").unwrap();
+
+        let result = context.add_metadata("request", "42");
+        assert!(matches!(result, Err(EventStoreError::MetadataLimitExceeded { limit: 2 })));
+        let result = context.add_metadata_with_expiry("request", "42", chrono::Utc::now());
+        assert!(matches!(result, Err(EventStoreError::MetadataLimitExceeded { limit: 2 })));
+    }
+
+    #[tokio::test]
+    async fn ensure_stale_snapshots_are_not_written() {
+        let memory = crate::memory::MemoryStorageEngine::new();
+        let event_store = crate::EventStore::new(memory.clone());
+        let context = event_store.get_context();
+        let mut account = ComposedAggregate::<Account>::new(&context, None).await.unwrap();
+        account.request(AccountCommands::CreateAccount(AccountCreation { user_id: 1 })).unwrap();
+        for _ in 0..10 {
+            account.request(AccountCommands::CreditAccount(AccountUpdate { amount: 1 })).unwrap();
+        }
+        let captured = context.captured_snapshots().unwrap();
+        assert_eq!(captured.len(), 1);
+
+        // A checkpoint written meanwhile is newer than the snapshot captured by the context.
+        let mut newer = captured[0].clone();
+        newer.version += 1;
+        event_store.write_updates(&[], &[newer]).await.unwrap();
+        context.commit().await.unwrap();
+
+        assert_eq!(memory.snapshot_count(), 1);
+        assert_eq!(memory.read_snapshot(account.id(), "account").await.unwrap().unwrap().version, captured[0].version + 1);
+    }
+
+    #[tokio::test]
+    async fn ensure_aggregates_load_by_natural_key() {
+        let event_store = crate::EventStore::new(crate::memory::MemoryStorageEngine::new());
+        let context = event_store.get_context();
+        let mut account = ComposedAggregate::<Account>::new(&context, Some("chavez_account")).await.unwrap();
+        account.request(AccountCommands::CreateAccount(AccountCreation { user_id: 1 })).unwrap();
+        account.request(AccountCommands::CreditAccount(AccountUpdate { amount: 25 })).unwrap();
+        context.commit().await.unwrap();
+
+        let context = event_store.get_context();
+        assert_eq!(context.get_aggregate_instance_id("account", "chavez_account").await.unwrap(), Some(account.id()));
+        let loaded = ComposedAggregate::<Account>::load_by_key(&context, "chavez_account").await.unwrap();
+        assert_eq!(loaded.id(), account.id());
+        assert_eq!(loaded.state().balance, 25);
+
+        assert_eq!(context.get_aggregate_instance_id("account", "missing").await.unwrap(), None);
+        let result = ComposedAggregate::<Account>::load_by_key(&context, "missing").await;
+        assert!(matches!(result, Err(EventStoreError::AggregateInstanceNotFound)));
+    }
+
+    #[tokio::test]
+    async fn ensure_saga_id_is_stamped_on_events() {
+        let memory = crate::memory::MemoryStorageEngine::new();
+        let event_store = crate::EventStore::new(memory.clone());
+        let saga_id = uuid::Uuid::new_v4();
+        let context = event_store.get_context();
+        assert_eq!(context.saga_id(), None);
+        context.link_to_saga(saga_id).unwrap();
+        assert_eq!(context.saga_id(), Some(saga_id));
+        {
+            let mut account = ComposedAggregate::<Account>::new(&context, None).await.unwrap();
+            account.request(AccountCommands::CreateAccount(AccountCreation { user_id: 1 })).unwrap();
+        }
+        context.commit().await.unwrap();
+
+        let events = memory.read_events(numbered_id(1), "account", 0).await.unwrap();
+        let metadata: HashMap<String, String> = events[0].deserialize_metadata().unwrap().unwrap();
+        assert_eq!(metadata.get(crate::contexts::SAGA_ID_KEY), Some(&saga_id.to_string()));
+    }
+
+    #[tokio::test]
+    async fn ensure_tenants_cannot_load_each_others_aggregates() {
+        let event_store = crate::EventStore::new(crate::memory::MemoryStorageEngine::new());
+        let acme = event_store.for_tenant("acme").unwrap();
+        let globex = event_store.for_tenant("globex").unwrap();
+        assert_eq!(acme.tenant_id(), Some("acme"));
+        assert_eq!(event_store.tenant_id(), None);
+
+        let context = acme.get_context();
+        let mut account = ComposedAggregate::<Account>::new(&context, Some("chavez_account")).await.unwrap();
+        account.request(AccountCommands::CreateAccount(AccountCreation { user_id: 1 })).unwrap();
+        account.add_lookup_key("branch", "downtown").unwrap();
+        context.commit().await.unwrap();
+
+        // Neither another tenant nor the unscoped store sees the aggregate.
+        for other in [&globex, &event_store] {
+            let loaded = ComposedAggregate::<Account>::load(&other.get_context(), account.id()).await;
+            assert!(matches!(loaded, Err(EventStoreError::AggregateNotFound(_))));
+            assert_eq!(other.get_context().get_aggregate_instance_id("account", "chavez_account").await.unwrap(), None);
+            assert!(other.find_by_lookup_key("account", "branch", "downtown").await.unwrap().is_empty());
+            assert!(other.read_all_events(0, 10).await.unwrap().is_empty());
+        }
+        let loaded = ComposedAggregate::<Account>::load(&acme.get_context(), account.id()).await.unwrap();
+        assert_eq!(loaded.state().user_id, 1);
+
+        // Natural keys are unique per tenant, ids across all of them.
+        let context = globex.get_context();
+        let mut other = ComposedAggregate::<Account>::new(&context, Some("chavez_account")).await.unwrap();
+        other.request(AccountCommands::CreateAccount(AccountCreation { user_id: 2 })).unwrap();
+        context.commit().await.unwrap();
+        assert_ne!(other.id(), account.id());
+        let loaded = ComposedAggregate::<Account>::load_by_key(&acme.get_context(), "chavez_account").await.unwrap();
+        assert_eq!(loaded.id(), account.id());
+
+        assert!(matches!(event_store.for_tenant("acme corp"), Err(EventStoreError::InvalidArgument { .. })));
+    }
+
+    #[tokio::test]
+    async fn ensure_recorded_commands_mask_redacted_fields() {
+        #[derive(Default, Clone, Serialize, Deserialize)]
+        struct Member;
+
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        struct Register {
+            name: String,
+            password_hash: String,
+        }
+
+        impl Composable for Member {
+            fn get_type(&self) -> &str {
+                "member"
+            }
+
+            fn apply_event(&mut self, _event: &crate::event::Event) -> Result<(), EventStoreError> {
+                Ok(())
+            }
+
+            fn redact_command_fields(&self) -> &[&str] {
+                &["password_hash"]
+            }
+        }
+
+        impl CanRequest<Register, Register> for Member {
+            fn request(&self, request: Register) -> Result<(String, Register), EventStoreError> {
+                Ok(("registered".to_string(), request))
+            }
+        }
+
+        let event_store = crate::EventStore::builder(crate::memory::MemoryStorageEngine::new())
+            .record_commands(true)
+            .build()
+            .unwrap();
+        let context = event_store.get_context();
+        context.add_metadata("user", "chavez").unwrap();
+        let mut member = ComposedAggregate::<Member>::new(&context, None).await.unwrap();
+        member.request(Register { name: "Chavez".to_string(), password_hash: "$argon2id$secret".to_string() }).unwrap();
+        context.commit().await.unwrap();
+
+        let events = event_store.get_events(member.id(), "member", 0).await.unwrap();
+        let command: Register = events[0].command().unwrap().unwrap();
+        assert_eq!(command, Register { name: "Chavez".to_string(), password_hash: crate::event::REDACTED.to_string() });
+        let metadata: HashMap<String, String> = events[0].deserialize_metadata().unwrap().unwrap();
+        assert!(metadata[crate::event::COMMAND_TYPE_KEY].ends_with("Register"));
+        assert_eq!(metadata["user"], "chavez");
+        assert!(!events[0].metadata.as_deref().unwrap().contains("secret"));
+    }
+
+    #[tokio::test]
+    async fn ensure_commands_are_not_recorded_by_default() {
+        let event_store = crate::EventStore::new(crate::memory::MemoryStorageEngine::new());
+        let context = event_store.get_context();
+        let mut account = ComposedAggregate::<Account>::new(&context, None).await.unwrap();
+        account.request(AccountCommands::CreateAccount(AccountCreation { user_id: 1 })).unwrap();
+
+        let events = context.captured_events().unwrap();
+        assert_eq!(events[0].metadata, None);
+        assert!(events[0].command::<AccountCommands>().unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn ensure_format_event_masks_redact_fields() {
+        let memory = crate::memory::MemoryStorageEngine::new();
+        let event_store = crate::EventStore::builder(memory.clone())
+            .redact_fields(&["ip_address"])
+            .build()
+            .unwrap();
+        let context = event_store.get_context();
+        context.add_metadata("user", "chavez").unwrap();
+        context.add_metadata("ip_address", "10.100.1.100").unwrap();
+        {
+            let mut account = ComposedAggregate::<Account>::new(&context, None).await.unwrap();
+            account.request(AccountCommands::CreateAccount(AccountCreation { user_id: 1 })).unwrap();
+        }
+        context.commit().await.unwrap();
+
+        let events = memory.read_events(numbered_id(1), "account", 0).await.unwrap();
+        let formatted = event_store.format_event(&events[0]);
+
+        assert!(formatted.starts_with(&format!("account/{} v1 created", numbered_id(1))));
+        assert!(formatted.contains("chavez"));
+        assert!(!formatted.contains("10.100.1.100"));
+    }
+
+    #[tokio::test]
+    async fn ensure_load_failures_name_the_failing_event() {
+        let memory = crate::memory::MemoryStorageEngine::new();
+        let event_store = crate::EventStore::new(memory.clone());
+        let context = event_store.get_context();
+        let mut account = open_account(&context, 1).await;
+        for _ in 0..4 {
+            account.request(AccountCommands::CreditAccount(AccountUpdate { amount: 5 })).unwrap();
+        }
+        context.commit().await.unwrap();
+        let unreadable = crate::event::Event::from_payload(account.id(), "account", 6, "credited", r#"{"AccountCredited":{}}"#.to_string());
+        memory.write_updates(&[unreadable], &[]).await.unwrap();
+
+        let context = event_store.get_context();
+        let error = ComposedAggregate::<Account>::load(&context, account.id()).await.err().unwrap();
+        let expected = crate::ErrorContext::new("load").aggregate("account", account.id()).version(6).event_type("credited");
+        assert_eq!(error.context(), Some(&expected));
+        assert!(error.to_string().starts_with(&format!("load failed for account {} at version 6 (credited): ", account.id())));
+        assert!(std::error::Error::source(&error).is_some());
+    }
+
+    #[tokio::test]
+    async fn ensure_quiesce_waits_for_commits_in_flight() {
+        use std::time::Duration;
+
+        let engine = FaultyEngine::new();
+        let event_store = crate::EventStore::new(engine.clone());
+        *engine.write_delay.lock().unwrap() = Duration::from_millis(200);
+        let context = event_store.get_context();
+        let account = open_account(&context, 1).await;
+        let slow_commit = tokio::spawn({
+            let context = context.clone();
+            async move { context.commit().await.is_ok() }
+        });
+        while event_store.active_writes() == 0 {
+            tokio::task::yield_now().await;
+        }
+
+        let result = event_store.quiesce(Duration::from_millis(10)).await;
+        assert!(matches!(result, Err(EventStoreError::QuiesceTimeout)));
+        let guard = event_store.quiesce(Duration::from_secs(5)).await.unwrap();
+        assert!(slow_commit.await.unwrap());
+        assert_eq!(event_store.active_writes(), 0);
+        assert_eq!(event_store.get_events(account.id(), "account", 0).await.unwrap().len(), 1);
+
+        // Commits made while quiesced wait for the guard to be dropped.
+        *engine.write_delay.lock().unwrap() = Duration::ZERO;
+        let context = event_store.get_context();
+        let second = open_account(&context, 2).await;
+        let paused_commit = tokio::spawn({
+            let context = context.clone();
+            async move { context.commit().await.is_ok() }
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!paused_commit.is_finished());
+        drop(guard);
+        assert!(paused_commit.await.unwrap());
+        assert_eq!(event_store.get_events(second.id(), "account", 0).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn ensure_views_load_aggregates_at_one_point_in_time() {
+        let event_store = crate::EventStore::new(crate::memory::MemoryStorageEngine::new());
+        let context = event_store.get_context();
+        let first = open_account(&context, 1).await;
+        let second = open_account(&context, 2).await;
+        context.commit().await.unwrap();
+
+        let view = event_store.consistent_view().await.unwrap();
+        let context = event_store.get_context();
+        let mut second = ComposedAggregate::<Account>::load(&context, second.id()).await.unwrap();
+        second.request(AccountCommands::CreditAccount(AccountUpdate { amount: 10 })).unwrap();
+        context.commit().await.unwrap();
+
+        let context = event_store.get_context();
+        let first = ComposedAggregate::<Account>::load_at_view(&context, first.id(), &view).await.unwrap();
+        let viewed = ComposedAggregate::<Account>::load_at_view(&context, second.id(), &view).await.unwrap();
+        assert_eq!(first.version(), 1);
+        assert_eq!(viewed.version(), 1);
+        assert_eq!(viewed.state().balance, 0);
+        let current = ComposedAggregate::<Account>::load(&context, second.id()).await.unwrap();
+        assert_eq!(current.state().balance, 10);
+
+        let other_store = crate::EventStore::new(crate::memory::MemoryStorageEngine::new());
+        let foreign = other_store.consistent_view().await.unwrap();
+        let result = ComposedAggregate::<Account>::load_at_view(&context, second.id(), &foreign).await;
+        assert!(matches!(result, Err(EventStoreError::InvalidCursor(_))));
+    }
+
+    #[tokio::test]
+    async fn ensure_concurrent_publishes_of_one_version_are_rejected() {
+        let event_store = crate::EventStore::new(crate::memory::MemoryStorageEngine::new());
+        let context = event_store.get_context();
+        let account = open_account(&context, 1).await;
+        context.commit().await.unwrap();
+
+        // Two tasks load their own copy of the account through one shared context and both
+        // publish version 2.
+        let context = event_store.get_context();
+        let tasks: Vec<_> = (0..2).map(|_| tokio::spawn({
+            let context = context.clone();
+            let id = account.id();
+            async move {
+                let mut account = ComposedAggregate::<Account>::load(&context, id).await.unwrap();
+                match account.request(AccountCommands::CreditAccount(AccountUpdate { amount: 10 })) {
+                    Ok(()) => None,
+                    Err(e) => match e.root_cause() {
+                        EventStoreError::DuplicateVersion { aggregate_type, aggregate_id, version } => Some((aggregate_type.clone(), *aggregate_id, *version)),
+                        other => panic!("unexpected error: {}", other),
+                    },
+                }
+            }
+        })).collect();
+        let mut rejected = Vec::new();
+        for task in tasks {
+            rejected.extend(task.await.unwrap());
+        }
+
+        assert_eq!(rejected, vec![("account".to_string(), account.id(), 2)]);
+        context.commit().await.unwrap();
+        let versions: Vec<i64> = event_store.get_events(account.id(), "account", 0).await.unwrap()
+            .iter().map(|event| event.version).collect();
+        assert_eq!(versions, vec![1, 2]);
+    }
+
+    #[tokio::test]
+    async fn ensure_quiesced_commits_can_fail_fast() {
+        let event_store = crate::EventStore::builder(crate::memory::MemoryStorageEngine::new())
+            .quiesce_policy(crate::quiesce::QuiescePolicy::FailFast)
+            .build()
+            .unwrap();
+        let context = event_store.get_context();
+        open_account(&context, 1).await;
+
+        let guard = event_store.quiesce(std::time::Duration::from_secs(1)).await.unwrap();
+        assert!(matches!(context.commit().await, Err(EventStoreError::Quiesced)));
+        assert!(!context.is_committed());
+        drop(guard);
+        context.commit().await.unwrap();
+    }
+}
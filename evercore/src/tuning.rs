@@ -0,0 +1,386 @@
+use std::{collections::HashMap, sync::Arc, time::{Duration, Instant}};
+use serde::{Deserialize, Serialize};
+use crate::{aggregate::Aggregate, event::Event, snapshot::Snapshot, AggregateId, EventStore, EventStoreError, EventStoreStorageEngine};
+
+/// How many events each synthetic aggregate starts out with.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EventCount {
+    Fixed(usize),
+    /// Any count from `min` to `max`, inclusive, with equal odds.
+    Uniform { min: usize, max: usize },
+}
+
+/// The workload `simulate` drives a store with.
+#[derive(Clone, Debug, PartialEq)]
+pub struct WorkloadSpec {
+    pub aggregates: usize,
+    pub events_per_aggregate: EventCount,
+    /// Operations run against random aggregates once they are seeded. Each loads an
+    /// aggregate and, unless it is a read, publishes and commits one event.
+    pub operations: usize,
+    /// Share of operations that only read, from 0.0 to 1.0.
+    pub read_ratio: f64,
+    /// Size of the padding carried by each event.
+    pub payload_bytes: usize,
+    /// Snapshot frequencies to compare; 0 runs without snapshots.
+    pub snapshot_frequencies: Vec<i32>,
+    /// Seeds the random choices, so a spec always produces the same workload.
+    pub seed: u64,
+}
+
+impl Default for WorkloadSpec {
+    fn default() -> WorkloadSpec {
+        WorkloadSpec {
+            aggregates: 100,
+            events_per_aggregate: EventCount::Uniform { min: 10, max: 200 },
+            operations: 1000,
+            read_ratio: 0.8,
+            payload_bytes: 256,
+            snapshot_frequencies: vec![0, 10, 50, 100],
+            seed: 1,
+        }
+    }
+}
+
+impl WorkloadSpec {
+    pub fn new() -> WorkloadSpec {
+        WorkloadSpec::default()
+    }
+
+    pub fn aggregates(mut self, count: usize) -> WorkloadSpec {
+        self.aggregates = count;
+        self
+    }
+
+    pub fn events_per_aggregate(mut self, count: EventCount) -> WorkloadSpec {
+        self.events_per_aggregate = count;
+        self
+    }
+
+    pub fn operations(mut self, count: usize) -> WorkloadSpec {
+        self.operations = count;
+        self
+    }
+
+    pub fn read_ratio(mut self, ratio: f64) -> WorkloadSpec {
+        self.read_ratio = ratio;
+        self
+    }
+
+    pub fn payload_bytes(mut self, bytes: usize) -> WorkloadSpec {
+        self.payload_bytes = bytes;
+        self
+    }
+
+    pub fn snapshot_frequencies(mut self, frequencies: &[i32]) -> WorkloadSpec {
+        self.snapshot_frequencies = frequencies.to_vec();
+        self
+    }
+
+    pub fn seed(mut self, seed: u64) -> WorkloadSpec {
+        self.seed = seed;
+        self
+    }
+}
+
+/// Distribution of measured latencies.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct LatencySummary {
+    pub count: usize,
+    pub p50: Duration,
+    pub p99: Duration,
+    pub max: Duration,
+}
+
+impl LatencySummary {
+    fn from_samples(mut samples: Vec<Duration>) -> LatencySummary {
+        samples.sort();
+        let percentile = |quantile: f64| {
+            let rank = (quantile * samples.len() as f64).ceil() as usize;
+            samples.get(rank.saturating_sub(1)).copied().unwrap_or_default()
+        };
+        LatencySummary {
+            count: samples.len(),
+            p50: percentile(0.5),
+            p99: percentile(0.99),
+            max: samples.last().copied().unwrap_or_default(),
+        }
+    }
+}
+
+/// What the workload measured with one snapshot frequency.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FrequencyReport {
+    pub snapshot_frequency: i32,
+    /// Loads during the operations; seeding is not measured.
+    pub load: LatencySummary,
+    /// Commits during the operations; seeding is not measured.
+    pub commit: LatencySummary,
+    pub events_written: usize,
+    pub snapshots_written: usize,
+    /// Payload bytes of the events and snapshots written, seeding included.
+    pub bytes_written: usize,
+}
+
+/// Results of `simulate`, one per snapshot frequency in the order of the spec.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TuningReport {
+    pub runs: Vec<FrequencyReport>,
+}
+
+impl TuningReport {
+    /// The run with the lowest p99 load latency.
+    pub fn fastest_loads(&self) -> Option<&FrequencyReport> {
+        self.runs.iter().min_by_key(|run| run.load.p99)
+    }
+}
+
+/// Runs `workload` against `engine` once per snapshot frequency and reports how loads,
+/// commits and storage fared.
+///
+/// Each run writes to a fresh aggregate type named `tuning_f<frequency>_<random suffix>`,
+/// so it can be pointed at a real database without touching existing data. The data
+/// written is left in place.
+pub async fn simulate(workload: &WorkloadSpec, engine: Arc<dyn EventStoreStorageEngine + Send + Sync>) -> Result<TuningReport, EventStoreError> {
+    let mut report = TuningReport::default();
+    for frequency in &workload.snapshot_frequencies {
+        report.runs.push(simulate_frequency(workload, engine.clone(), *frequency).await?);
+    }
+    Ok(report)
+}
+
+async fn simulate_frequency(workload: &WorkloadSpec, engine: Arc<dyn EventStoreStorageEngine + Send + Sync>, frequency: i32) -> Result<FrequencyReport, EventStoreError> {
+    let event_store = EventStore::new(engine);
+    let suffix = uuid::Uuid::new_v4().simple().to_string();
+    let aggregate_type = format!("tuning_f{}_{}", frequency, &suffix[..8]);
+    let padding = "x".repeat(workload.payload_bytes);
+    let mut random = Random::new(workload.seed);
+    let mut written = Written::default();
+
+    let mut ids = Vec::with_capacity(workload.aggregates);
+    for _ in 0..workload.aggregates {
+        let context = event_store.get_context();
+        let id = context.next_aggregate_id(&aggregate_type, None).await?;
+        let mut aggregate = SyntheticAggregate::new(id, &aggregate_type, frequency);
+        let count = match workload.events_per_aggregate {
+            EventCount::Fixed(count) => count,
+            EventCount::Uniform { min, max } => min + random.below(max.saturating_sub(min) + 1),
+        };
+        for _ in 0..count {
+            aggregate.publish(&context, &padding)?;
+        }
+        written.record(&context)?;
+        context.commit().await?;
+        ids.push(id);
+    }
+
+    let mut loads = Vec::with_capacity(workload.operations);
+    let mut commits = Vec::new();
+    for _ in 0..workload.operations {
+        let Some(id) = ids.get(random.below(ids.len())).copied() else {
+            break;
+        };
+        let context = event_store.get_context();
+        let mut aggregate = SyntheticAggregate::new(id, &aggregate_type, frequency);
+        let started = Instant::now();
+        context.load(&mut aggregate).await?;
+        loads.push(started.elapsed());
+
+        if random.fraction() >= workload.read_ratio {
+            aggregate.publish(&context, &padding)?;
+            written.record(&context)?;
+            let started = Instant::now();
+            context.commit().await?;
+            commits.push(started.elapsed());
+        }
+    }
+
+    Ok(FrequencyReport {
+        snapshot_frequency: frequency,
+        load: LatencySummary::from_samples(loads),
+        commit: LatencySummary::from_samples(commits),
+        events_written: written.events,
+        snapshots_written: written.snapshots,
+        bytes_written: written.bytes,
+    })
+}
+
+#[derive(Default)]
+struct Written {
+    events: usize,
+    snapshots: usize,
+    bytes: usize,
+}
+
+impl Written {
+    /// Counts what committing the context writes: its events, and of its snapshots only
+    /// the newest of each aggregate.
+    fn record(&mut self, context: &crate::contexts::EventContext) -> Result<(), EventStoreError> {
+        let events = context.captured_events()?;
+        let mut newest: HashMap<(String, AggregateId), Snapshot> = HashMap::new();
+        for snapshot in context.captured_snapshots()? {
+            let key = (snapshot.aggregate_type.clone(), snapshot.aggregate_id);
+            if newest.get(&key).is_none_or(|kept| snapshot.version > kept.version) {
+                newest.insert(key, snapshot);
+            }
+        }
+        let snapshots: Vec<Snapshot> = newest.into_values().collect();
+        self.events += events.len();
+        self.snapshots += snapshots.len();
+        self.bytes += events.iter().map(|event| event.data.len() + event.metadata.as_ref().map_or(0, String::len)).sum::<usize>();
+        self.bytes += snapshots.iter().map(|snapshot| snapshot.data.len()).sum::<usize>();
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct SyntheticPayload {
+    sequence: i64,
+    padding: String,
+}
+
+/// Aggregate driven by the simulator. Its state is the latest payload, so snapshots
+/// are about as large as events.
+struct SyntheticAggregate {
+    id: AggregateId,
+    aggregate_type: String,
+    snapshot_frequency: i32,
+    version: i64,
+    latest: Option<SyntheticPayload>,
+}
+
+impl SyntheticAggregate {
+    fn new(id: AggregateId, aggregate_type: &str, snapshot_frequency: i32) -> SyntheticAggregate {
+        SyntheticAggregate {
+            id,
+            aggregate_type: aggregate_type.to_string(),
+            snapshot_frequency,
+            version: 0,
+            latest: None,
+        }
+    }
+
+    fn publish(&mut self, context: &crate::contexts::EventContext, padding: &str) -> Result<(), EventStoreError> {
+        let payload = SyntheticPayload {
+            sequence: self.version + 1,
+            padding: padding.to_string(),
+        };
+        context.publish(self, "synthetic_event", &payload)
+    }
+}
+
+impl Aggregate<'_> for SyntheticAggregate {
+    fn id(&self) -> AggregateId {
+        self.id
+    }
+
+    fn id_mut(&mut self, id: AggregateId) {
+        self.id = id;
+    }
+
+    fn snapshot_frequency(&self) -> i32 {
+        self.snapshot_frequency
+    }
+
+    fn aggregate_type(&self) -> &str {
+        &self.aggregate_type
+    }
+
+    fn version(&self) -> i64 {
+        self.version
+    }
+
+    fn apply_snapshot(&mut self, snapshot: &Snapshot) -> Result<(), EventStoreError> {
+        self.latest = snapshot.to_state()?;
+        self.version = snapshot.version;
+        Ok(())
+    }
+
+    fn apply_event(&mut self, event: &Event) -> Result<(), EventStoreError> {
+        self.latest = Some(event.deserialize()?);
+        self.version = event.version;
+        Ok(())
+    }
+
+    fn take_snapshot(&self) -> Result<Snapshot, EventStoreError> {
+        Snapshot::new(self.id, &self.aggregate_type, self.version, &self.latest)
+    }
+}
+
+/// xorshift64*, enough to pick workloads reproducibly without a dependency.
+struct Random(u64);
+
+impl Random {
+    fn new(seed: u64) -> Random {
+        Random(seed.max(1))
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    fn below(&mut self, bound: usize) -> usize {
+        match bound {
+            0 => 0,
+            bound => (self.next() % bound as u64) as usize,
+        }
+    }
+
+    fn fraction(&mut self) -> f64 {
+        (self.next() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+#[cfg(all(test, feature = "memory"))]
+mod tests {
+    use super::*;
+    use crate::memory::MemoryStorageEngine;
+
+    #[tokio::test]
+    async fn ensure_simulation_reports_each_snapshot_frequency() {
+        let workload = WorkloadSpec::new()
+            .aggregates(4)
+            .events_per_aggregate(EventCount::Uniform { min: 3, max: 12 })
+            .operations(40)
+            .read_ratio(0.5)
+            .payload_bytes(64)
+            .snapshot_frequencies(&[0, 5]);
+
+        let report = simulate(&workload, MemoryStorageEngine::new()).await.unwrap();
+
+        assert_eq!(report.runs.iter().map(|run| run.snapshot_frequency).collect::<Vec<_>>(), vec![0, 5]);
+        let (without, with) = (&report.runs[0], &report.runs[1]);
+        assert_eq!(without.snapshots_written, 0);
+        assert!(with.snapshots_written > 0);
+        assert!(with.bytes_written > without.bytes_written);
+        for run in &report.runs {
+            assert_eq!(run.load.count, 40);
+            assert!(run.commit.count > 0 && run.commit.count < 40);
+            assert!(run.load.p50 <= run.load.p99 && run.load.p99 <= run.load.max);
+        }
+        // The same seed drives both runs through the same operations.
+        assert_eq!(without.events_written, with.events_written);
+        assert!(report.fastest_loads().is_some());
+    }
+
+    #[tokio::test]
+    async fn ensure_only_the_newest_snapshot_per_aggregate_is_counted() {
+        let engine = MemoryStorageEngine::new();
+        let event_store = EventStore::new(engine.clone());
+        let context = event_store.get_context();
+        let id = context.next_aggregate_id("tuning", None).await.unwrap();
+        let mut aggregate = SyntheticAggregate::new(id, "tuning", 1);
+        for _ in 0..3 {
+            aggregate.publish(&context, "padding").unwrap();
+        }
+
+        let mut written = Written::default();
+        written.record(&context).unwrap();
+        context.commit().await.unwrap();
+        assert_eq!((written.events, written.snapshots), (3, 1));
+        assert_eq!(engine.read_snapshots(id, "tuning", usize::MAX).await.unwrap().len(), written.snapshots);
+    }
+}
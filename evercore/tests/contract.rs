@@ -0,0 +1,16 @@
+//! Runs the storage contract checks against the memory engine. The report is written to
+//! `contract-memory.md` under cargo's integration test temp directory.
+#![cfg(feature = "memory")]
+
+use evercore::contract::{check_storage_contract, Outcome};
+use evercore::memory::MemoryStorageEngine;
+
+#[tokio::test]
+async fn ensure_memory_engine_satisfies_storage_contract() {
+    let report = check_storage_contract(MemoryStorageEngine::new()).await;
+    let artifact = std::path::Path::new(env!("CARGO_TARGET_TMPDIR")).join("contract-memory.md");
+    std::fs::write(&artifact, report.to_string()).unwrap();
+
+    assert!(report.is_compliant(), "{}", report);
+    assert!(report.checks.iter().all(|check| check.outcome == Outcome::Passed), "{}", report);
+}
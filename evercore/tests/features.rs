@@ -0,0 +1,33 @@
+//! Checks that every combination of the crate's granular features builds on its own. It
+//! runs cargo once per combination, so it is ignored by default; run it with
+//! `cargo test -p evercore --test features -- --ignored`.
+
+use std::path::Path;
+use std::process::Command;
+
+const FEATURES: [&str; 5] = ["core", "memory", "context", "test-util", "derive"];
+
+#[test]
+#[ignore]
+fn ensure_every_feature_combination_builds() {
+    let manifest = concat!(env!("CARGO_MANIFEST_DIR"), "/Cargo.toml");
+    let target_dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("features");
+    let mut failed = Vec::new();
+    for mask in 0..1u32 << FEATURES.len() {
+        let features: Vec<&str> = FEATURES.iter().enumerate()
+            .filter(|(bit, _)| mask & 1 << bit != 0)
+            .map(|(_, feature)| *feature)
+            .collect();
+        let features = features.join(",");
+        let status = Command::new(env!("CARGO"))
+            .args(["check", "--all-targets", "--no-default-features", "--manifest-path", manifest, "--features", &features])
+            .arg("--target-dir").arg(&target_dir)
+            .env("RUSTFLAGS", "-D warnings")
+            .status()
+            .unwrap();
+        if !status.success() {
+            failed.push(features);
+        }
+    }
+    assert!(failed.is_empty(), "feature combinations failed to build: {:?}", failed);
+}
@@ -0,0 +1,62 @@
+//! Run with `cargo test -p evercore --features uuid-ids --test uuid_ids`; the unit tests
+//! use integer ids and only build without the feature.
+#![cfg(all(feature = "uuid-ids", feature = "memory", feature = "context"))]
+
+use serde::{Serialize, Deserialize};
+use evercore::{EventStore, EventStoreError, event::Event, memory::MemoryStorageEngine};
+use evercore::aggregate::{Aggregate, CanRequest, Composable, ComposedAggregate};
+
+#[derive(Default, Clone, Serialize, Deserialize)]
+struct Counter {
+    count: i64,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Increment;
+
+impl Composable for Counter {
+    fn get_type(&self) -> &str {
+        "counter"
+    }
+
+    fn apply_event(&mut self, _event: &Event) -> Result<(), EventStoreError> {
+        self.count += 1;
+        Ok(())
+    }
+
+    fn snapshot_frequency(&self) -> i32 {
+        2
+    }
+}
+
+impl CanRequest<Increment, Increment> for Counter {
+    fn request(&self, request: Increment) -> Result<(String, Increment), EventStoreError> {
+        Ok(("incremented".to_string(), request))
+    }
+}
+
+#[tokio::test]
+async fn ensure_uuid_ids_roundtrip() {
+    let event_store = EventStore::new(MemoryStorageEngine::new());
+    let ctx = event_store.get_context();
+    let mut first = ComposedAggregate::<Counter>::new(&ctx, None).await.unwrap();
+    let second = ComposedAggregate::<Counter>::new(&ctx, Some("second")).await.unwrap();
+    assert_ne!(first.id(), second.id());
+
+    for _ in 0..3 {
+        first.request(Increment).unwrap();
+    }
+    first.add_lookup_key("owner", "alice").unwrap();
+    ctx.commit().await.unwrap();
+
+    let ctx = event_store.get_context();
+    let loaded = ComposedAggregate::<Counter>::load(&ctx, first.id()).await.unwrap();
+    assert_eq!(loaded.state().count, 3);
+    assert!(event_store.get_snapshot(first.id(), "counter").await.unwrap().is_some());
+
+    let found = ComposedAggregate::<Counter>::find_by_lookup_key(&ctx, "owner", "alice").await.unwrap();
+    assert_eq!(found, vec![first.id()]);
+
+    let missing = ComposedAggregate::<Counter>::load(&ctx, uuid::Uuid::new_v4()).await;
+    assert!(matches!(missing, Err(EventStoreError::AggregateNotFound(_))));
+}
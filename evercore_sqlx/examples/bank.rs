@@ -0,0 +1,367 @@
+//! A small bank on SQLite, wired end to end through the public APIs:
+//!
+//! - `Bank` dispatches commands to the user and account aggregates, the way a request
+//!   handler would.
+//! - `Balances` is a projection of every account's balance.
+//! - `WelcomeBonus` is a process manager crediting each new account once.
+//! - `Outbox` relays committed events by printing them.
+//!
+//! The followers poll the global feed until shutdown is signalled, then drain what was
+//! committed before stopping.
+//!
+//! Run with `cargo run -p evercore_sqlx --example bank`.
+
+use std::{collections::BTreeMap, sync::Arc, time::Duration};
+use async_trait::async_trait;
+use serde::{Serialize, Deserialize};
+use sqlx::AnyPool;
+use tokio::{sync::{watch, Mutex}, task::LocalSet};
+use evercore::{AggregateId, EventStore, EventStoreError, SharedEventStore};
+use evercore::aggregate::{Aggregate, CanRequest, Composable, ComposedAggregate};
+use evercore::event::Event;
+use evercore::projection::{Projection, ProjectionRunner};
+use evercore_sqlx::{DbType, SqlxStorageEngine};
+
+const DATABASE_URL: &str = "sqlite://bank.db?mode=rwc";
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+const WELCOME_BONUS: i64 = 25;
+
+#[derive(Default, Clone, Serialize, Deserialize)]
+struct User {
+    name: String,
+    email: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct UserRegistered {
+    name: String,
+    email: String,
+}
+
+impl Composable for User {
+    fn get_type(&self) -> &str {
+        "user"
+    }
+
+    fn apply_event(&mut self, event: &Event) -> Result<(), EventStoreError> {
+        let registered: UserRegistered = event.deserialize()?;
+        self.name = registered.name;
+        self.email = registered.email;
+        Ok(())
+    }
+}
+
+impl CanRequest<UserRegistered, UserRegistered> for User {
+    fn request(&self, request: UserRegistered) -> Result<(String, UserRegistered), EventStoreError> {
+        Ok(("registered".to_string(), request))
+    }
+}
+
+#[derive(Default, Clone, Serialize, Deserialize)]
+struct Account {
+    user_id: AggregateId,
+    balance: i64,
+}
+
+#[derive(Serialize, Deserialize)]
+struct AccountOpened {
+    user_id: AggregateId,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Deposit {
+    amount: i64,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Withdrawal {
+    amount: i64,
+}
+
+impl Composable for Account {
+    fn get_type(&self) -> &str {
+        "account"
+    }
+
+    fn apply_event(&mut self, event: &Event) -> Result<(), EventStoreError> {
+        match event.event_type.as_str() {
+            "opened" => self.user_id = event.deserialize::<AccountOpened>()?.user_id,
+            "deposited" => self.balance += event.deserialize::<Deposit>()?.amount,
+            "withdrawn" => self.balance -= event.deserialize::<Withdrawal>()?.amount,
+            other => return Err(EventStoreError::ApplyEventError(format!("unknown account event '{}'", other))),
+        }
+        Ok(())
+    }
+}
+
+impl CanRequest<AccountOpened, AccountOpened> for Account {
+    fn request(&self, request: AccountOpened) -> Result<(String, AccountOpened), EventStoreError> {
+        Ok(("opened".to_string(), request))
+    }
+}
+
+impl CanRequest<Deposit, Deposit> for Account {
+    fn request(&self, request: Deposit) -> Result<(String, Deposit), EventStoreError> {
+        Ok(("deposited".to_string(), request))
+    }
+}
+
+impl CanRequest<Withdrawal, Withdrawal> for Account {
+    fn request(&self, request: Withdrawal) -> Result<(String, Withdrawal), EventStoreError> {
+        if request.amount > self.balance {
+            return Err(EventStoreError::RequestProcessingError(
+                format!("cannot withdraw {} from a balance of {}", request.amount, self.balance)));
+        }
+        Ok(("withdrawn".to_string(), request))
+    }
+}
+
+/// What a client can ask the bank to do.
+enum Command {
+    RegisterUser { name: String, email: String },
+    OpenAccount { user_id: AggregateId },
+    Deposit { account_id: AggregateId, amount: i64 },
+    Withdraw { account_id: AggregateId, amount: i64 },
+}
+
+/// Runs each command in its own context and commits it. Commands run one at a time, since
+/// two commands writing the same account concurrently would conflict on its version.
+#[derive(Clone)]
+struct Bank {
+    event_store: SharedEventStore,
+    writer: Arc<Mutex<()>>,
+}
+
+impl Bank {
+    /// Returns the id of the aggregate the command was handled by.
+    async fn dispatch(&self, command: Command) -> Result<AggregateId, EventStoreError> {
+        let _writer = self.writer.lock().await;
+        let ctx = self.event_store.get_context();
+        let id = match command {
+            Command::RegisterUser { name, email } => {
+                let mut user = ComposedAggregate::<User>::new(&ctx, Some(&email)).await?;
+                user.request(UserRegistered { name, email })?;
+                user.id()
+            }
+            Command::OpenAccount { user_id } => {
+                let mut account = ComposedAggregate::<Account>::new(&ctx, None).await?;
+                account.request(AccountOpened { user_id })?;
+                account.id()
+            }
+            Command::Deposit { account_id, amount } => {
+                let mut account = ComposedAggregate::<Account>::load(&ctx, account_id).await?;
+                account.request(Deposit { amount })?;
+                account_id
+            }
+            Command::Withdraw { account_id, amount } => {
+                let mut account = ComposedAggregate::<Account>::load(&ctx, account_id).await?;
+                account.request(Withdrawal { amount })?;
+                account_id
+            }
+        };
+        ctx.commit().await?;
+        Ok(id)
+    }
+
+    /// Credits the welcome bonus at most once per account, even if asked again.
+    async fn credit_welcome_bonus(&self, account_id: AggregateId) -> Result<bool, EventStoreError> {
+        let _writer = self.writer.lock().await;
+        let ctx = self.event_store.get_context();
+        let mut account = ComposedAggregate::<Account>::load(&ctx, account_id).await?;
+        let credited = account.request_dedup(Deposit { amount: WELCOME_BONUS }, &format!("welcome-bonus-{}", account_id)).await?;
+        ctx.commit().await?;
+        Ok(credited)
+    }
+}
+
+/// The account balances table.
+#[derive(Default)]
+struct Balances {
+    table: BTreeMap<AggregateId, i64>,
+}
+
+#[async_trait]
+impl Projection for Balances {
+    fn name(&self) -> &str {
+        "balances"
+    }
+
+    fn aggregate_types(&self) -> Vec<String> {
+        vec!["account".to_string()]
+    }
+
+    async fn handle(&mut self, event: &Event) -> Result<(), EventStoreError> {
+        let balance = self.table.entry(event.aggregate_id).or_default();
+        match event.event_type.as_str() {
+            "deposited" => *balance += event.deserialize::<Deposit>()?.amount,
+            "withdrawn" => *balance -= event.deserialize::<Withdrawal>()?.amount,
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+/// Collects newly opened accounts for the bonus to be credited outside the feed.
+#[derive(Default)]
+struct WelcomeBonus {
+    pending: Vec<AggregateId>,
+}
+
+#[async_trait]
+impl Projection for WelcomeBonus {
+    fn name(&self) -> &str {
+        "welcome_bonus"
+    }
+
+    fn aggregate_types(&self) -> Vec<String> {
+        vec!["account".to_string()]
+    }
+
+    async fn handle(&mut self, event: &Event) -> Result<(), EventStoreError> {
+        if event.event_type == "opened" {
+            self.pending.push(event.aggregate_id);
+        }
+        Ok(())
+    }
+}
+
+impl WelcomeBonus {
+    async fn credit_pending(&mut self, bank: &Bank) -> Result<(), EventStoreError> {
+        for account_id in self.pending.drain(..) {
+            bank.credit_welcome_bonus(account_id).await?;
+        }
+        Ok(())
+    }
+}
+
+/// Relays every committed event; a real relay would publish to a broker.
+#[derive(Default)]
+struct Outbox {
+    relayed: usize,
+}
+
+#[async_trait]
+impl Projection for Outbox {
+    fn name(&self) -> &str {
+        "outbox"
+    }
+
+    fn aggregate_types(&self) -> Vec<String> {
+        vec!["user".to_string(), "account".to_string()]
+    }
+
+    async fn handle(&mut self, event: &Event) -> Result<(), EventStoreError> {
+        println!("outbox: {}", event);
+        self.relayed += 1;
+        Ok(())
+    }
+}
+
+/// Waits one poll interval, returning false once shutdown was signalled.
+async fn keep_polling(shutdown: &mut watch::Receiver<bool>) -> bool {
+    tokio::select! {
+        _ = shutdown.changed() => false,
+        _ = tokio::time::sleep(POLL_INTERVAL) => true,
+    }
+}
+
+async fn follow<P: Projection>(event_store: SharedEventStore, mut projection: P, shutdown: watch::Receiver<bool>) -> Result<P, EventStoreError> {
+    ProjectionRunner::new(event_store).run(&mut projection, POLL_INTERVAL, shutdown).await?;
+    Ok(projection)
+}
+
+async fn run_welcome_bonus(bank: Bank, mut shutdown: watch::Receiver<bool>) -> Result<(), EventStoreError> {
+    let mut runner = ProjectionRunner::new(bank.event_store.clone());
+    let mut manager = WelcomeBonus::default();
+    while keep_polling(&mut shutdown).await {
+        runner.catch_up(&mut manager).await?;
+        manager.credit_pending(&bank).await?;
+    }
+    runner.catch_up(&mut manager).await?;
+    manager.credit_pending(&bank).await
+}
+
+/// Final state once the bank has shut down.
+struct Outcome {
+    balances: BTreeMap<AggregateId, i64>,
+    relayed: usize,
+    accounts: Vec<AggregateId>,
+}
+
+async fn run(pool: AnyPool) -> Result<Outcome, EventStoreError> {
+    // Lets the followers read the feed while commands are being written.
+    sqlx::query("PRAGMA journal_mode = WAL").execute(&pool).await
+        .map_err(|e| EventStoreError::StorageEngineError(Box::new(e)))?;
+    let storage = SqlxStorageEngine::new(DbType::Sqlite, pool);
+    storage.drop_tables().await?;
+    storage.build_tables().await?;
+    let event_store = EventStore::new(Arc::new(storage));
+    let bank = Bank { event_store: event_store.clone(), writer: Arc::default() };
+
+    // Loading aggregates borrows them as trait objects, so the tasks stay on this thread.
+    let local = LocalSet::new();
+    local.run_until(async {
+        let (stop_manager, manager_shutdown) = watch::channel(false);
+        let (stop_followers, follower_shutdown) = watch::channel(false);
+        let manager = tokio::task::spawn_local(run_welcome_bonus(bank.clone(), manager_shutdown));
+        let balances = tokio::task::spawn_local(follow(event_store.clone(), Balances::default(), follower_shutdown.clone()));
+        let outbox = tokio::task::spawn_local(follow(event_store.clone(), Outbox::default(), follower_shutdown));
+
+        let alice = bank.dispatch(Command::RegisterUser { name: "Alice".to_string(), email: "alice@example.com".to_string() }).await?;
+        let bob = bank.dispatch(Command::RegisterUser { name: "Bob".to_string(), email: "bob@example.com".to_string() }).await?;
+        let alice_account = bank.dispatch(Command::OpenAccount { user_id: alice }).await?;
+        let bob_account = bank.dispatch(Command::OpenAccount { user_id: bob }).await?;
+        bank.dispatch(Command::Deposit { account_id: alice_account, amount: 100 }).await?;
+        bank.dispatch(Command::Withdraw { account_id: alice_account, amount: 30 }).await?;
+        bank.dispatch(Command::Deposit { account_id: bob_account, amount: 50 }).await?;
+        if let Err(err) = bank.dispatch(Command::Withdraw { account_id: bob_account, amount: 500 }).await {
+            println!("rejected: {:?}", err);
+        }
+
+        // The process manager stops first so the followers see the bonuses it commits.
+        let _ = stop_manager.send(true);
+        manager.await.expect("welcome bonus task panicked")?;
+        let _ = stop_followers.send(true);
+        let balances = balances.await.expect("balances task panicked")?;
+        let outbox = outbox.await.expect("outbox task panicked")?;
+
+        Ok(Outcome {
+            balances: balances.table,
+            relayed: outbox.relayed,
+            accounts: vec![alice_account, bob_account],
+        })
+    }).await
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let pool = AnyPool::connect(DATABASE_URL).await?;
+    let outcome = run(pool).await?;
+    println!("relayed {} events", outcome.relayed);
+    for account_id in &outcome.accounts {
+        println!("account {}: {}", account_id, outcome.balances[account_id]);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn ensure_scenario_projects_final_balances() {
+        let pool = AnyPool::connect("sqlite://bank_example_test.db?mode=rwc").await.unwrap();
+        let outcome = run(pool).await.unwrap();
+
+        let [alice_account, bob_account] = outcome.accounts[..] else {
+            panic!("expected two accounts");
+        };
+        let expected = BTreeMap::from([
+            (alice_account, 100 - 30 + WELCOME_BONUS),
+            (bob_account, 50 + WELCOME_BONUS),
+        ]);
+        assert_eq!(outcome.balances, expected);
+        // 2 registrations, 2 openings, 3 accepted transactions and 2 bonuses.
+        assert_eq!(outcome.relayed, 9);
+    }
+}
@@ -1,43 +1,74 @@
 use crate::QueryBuilder;
+use crate::queries::{TableSpec, TYPE_COLUMNS, INSTANCE_COLUMNS, EVENT_COLUMNS, SNAPSHOT_COLUMNS, LOOKUP_KEY_COLUMNS, DEDUP_KEY_COLUMNS, CHECKPOINT_COLUMNS, SCHEMA_VERSION_COLUMNS, STORE_INFO_COLUMNS};
+/// Types of aggregate instance ids and of the columns referencing them: numbered by
+/// AUTO_INCREMENT, or with `uuid-ids` uuids as text.
+#[cfg(not(feature = "uuid-ids"))]
+const AGGREGATE_ID_TYPES: (&str, &str) = ("BIGINT NOT NULL AUTO_INCREMENT", "BIGINT");
+#[cfg(feature = "uuid-ids")]
+const AGGREGATE_ID_TYPES: (&str, &str) = ("CHAR(36) NOT NULL", "CHAR(36)");
 
-pub(crate) struct MysqlBuilder;
+#[derive(Default)]
+pub(crate) struct MysqlBuilder {
+    pub binary_payloads: bool,
+}
 
 impl QueryBuilder for MysqlBuilder {
-    fn build_queries(&self) -> Vec<String> {
+    fn binary_payloads(&self) -> bool {
+        self.binary_payloads
+    }
+
+    fn binary_type(&self) -> &'static str {
+        "BLOB"
+    }
+
+    fn schema(&self) -> Vec<TableSpec> {
         vec![
-            String::from("CREATE TABLE IF NOT EXISTS aggregate_types (
+            TableSpec::new("aggregate_types", TYPE_COLUMNS, String::from("CREATE TABLE IF NOT EXISTS aggregate_types (
                 id BIGINT NOT NULL AUTO_INCREMENT,
                 name VARCHAR(255) NOT NULL,
                 PRIMARY KEY (id),
                 UNIQUE KEY (name)
-            )"),
-            String::from("CREATE TABLE IF NOT EXISTS event_types (
+            )")),
+            TableSpec::new("event_types", TYPE_COLUMNS, String::from("CREATE TABLE IF NOT EXISTS event_types (
                 id BIGINT NOT NULL AUTO_INCREMENT,
                 name VARCHAR(255) NOT NULL,
                 PRIMARY KEY (id),
                 UNIQUE KEY (name)
-            )"),
-        String::from("CREATE TABLE IF NOT EXISTS aggregate_instance (
-            id BIGINT NOT NULL AUTO_INCREMENT,
+            )")),
+        TableSpec::new("aggregate_instance", INSTANCE_COLUMNS, format!("CREATE TABLE IF NOT EXISTS aggregate_instance (
+            id {},
             aggregate_type_id BIGINT NOT NULL,
             natural_key VARCHAR(255),
+            deleted_at BIGINT,
+            tenant_id VARCHAR(64) NOT NULL DEFAULT '',
             PRIMARY KEY (id),
-            UNIQUE KEY (aggregate_type_id, natural_key),
+            UNIQUE KEY (tenant_id, aggregate_type_id, natural_key),
             CONSTRAINT fk_aggregate_instance_aggregate_type_id
                 FOREIGN KEY(aggregate_type_id)
                     REFERENCES aggregate_types(id)
-        )"),
+        )", AGGREGATE_ID_TYPES.0))
+        .with_added_column("deleted_at", "ALTER TABLE aggregate_instance ADD COLUMN deleted_at BIGINT")
+        .with_added_column("tenant_id", "ALTER TABLE aggregate_instance ADD COLUMN tenant_id VARCHAR(64) NOT NULL DEFAULT ''")
+        // The replaced key also served the foreign key on aggregate_type_id, which needs an
+        // index of its own before the key can go.
+        .with_upgrade("tenant_id", vec![String::from("ALTER TABLE aggregate_instance
+            ADD INDEX fk_aggregate_instance_aggregate_type_id (aggregate_type_id),
+            ADD UNIQUE KEY (tenant_id, aggregate_type_id, natural_key),
+            DROP INDEX aggregate_type_id")]),
 
-        String::from("CREATE TABLE IF NOT EXISTS events (
+        TableSpec::new("events", EVENT_COLUMNS, format!("CREATE TABLE IF NOT EXISTS events (
             id BIGINT NOT NULL AUTO_INCREMENT,
-            aggregate_id BIGINT NOT NULL,
+            aggregate_id {} NOT NULL,
             aggregate_type_id BIGINT NOT NULL,
             version BIGINT NOT NULL,
             event_type_id BIGINT NOT NULL,
-            data TEXT NOT NULL,
+            data {} NOT NULL,
             metadata TEXT,
+            created_at BIGINT,
+            hash VARCHAR(64),
+            tenant_id VARCHAR(64) NOT NULL DEFAULT '',
             PRIMARY KEY (id),
-            UNIQUE KEY (aggregate_id, version),
+            UNIQUE KEY (tenant_id, aggregate_id, version),
             CONSTRAINT fk_event_aggregate_id
                 FOREIGN KEY(aggregate_id)
                     REFERENCES aggregate_instance(id),
@@ -47,28 +78,93 @@ impl QueryBuilder for MysqlBuilder {
             CONSTRAINT fk_event_type_id
                 FOREIGN KEY(event_type_id)
                     REFERENCES event_types(id)
-        )"),
+        )", AGGREGATE_ID_TYPES.1, self.data_type()))
+        .with_column_kind("data", self.data_kind())
+        .with_added_column("hash", "ALTER TABLE events ADD COLUMN hash VARCHAR(64)")
+        .with_added_column("tenant_id", "ALTER TABLE events ADD COLUMN tenant_id VARCHAR(64) NOT NULL DEFAULT ''"),
 
-        String::from("CREATE TABLE IF NOT EXISTS snapshots (
+        TableSpec::new("snapshots", SNAPSHOT_COLUMNS, format!("CREATE TABLE IF NOT EXISTS snapshots (
             id BIGINT NOT NULL AUTO_INCREMENT,
-            aggregate_id BIGINT NOT NULL,
+            aggregate_id {} NOT NULL,
             aggregate_type_id BIGINT NOT NULL,
             version BIGINT NOT NULL,
-            data TEXT NOT NULL,
+            data {} NOT NULL,
+            created_at BIGINT,
+            tenant_id VARCHAR(64) NOT NULL DEFAULT '',
             PRIMARY KEY (id),
-            UNIQUE KEY (aggregate_id, version),
+            UNIQUE KEY (tenant_id, aggregate_id, version),
             CONSTRAINT fk_snapshot_aggregate_id
                 FOREIGN KEY(aggregate_id)
                     REFERENCES aggregate_instance(id),
             CONSTRAINT fk_snapshot_aggregate_type_id
                 FOREIGN KEY(aggregate_type_id)
                     REFERENCES aggregate_types(id)
-        )"),
+        )", AGGREGATE_ID_TYPES.1, self.data_type()))
+        .with_column_kind("data", self.data_kind())
+        .with_added_column("created_at", "ALTER TABLE snapshots ADD COLUMN created_at BIGINT")
+        .with_added_column("tenant_id", "ALTER TABLE snapshots ADD COLUMN tenant_id VARCHAR(64) NOT NULL DEFAULT ''"),
+        TableSpec::new("lookup_keys", LOOKUP_KEY_COLUMNS, format!("CREATE TABLE IF NOT EXISTS lookup_keys (
+            id BIGINT NOT NULL AUTO_INCREMENT,
+            aggregate_id {} NOT NULL,
+            aggregate_type_id BIGINT NOT NULL,
+            key_name VARCHAR(255) NOT NULL,
+            key_value VARCHAR(255) NOT NULL,
+            tenant_id VARCHAR(64) NOT NULL DEFAULT '',
+            PRIMARY KEY (id),
+            INDEX idx_lookup_keys_lookup (aggregate_type_id, key_name, key_value),
+            CONSTRAINT fk_lookup_key_aggregate_id
+                FOREIGN KEY(aggregate_id)
+                    REFERENCES aggregate_instance(id),
+            CONSTRAINT fk_lookup_key_aggregate_type_id
+                FOREIGN KEY(aggregate_type_id)
+                    REFERENCES aggregate_types(id)
+        )", AGGREGATE_ID_TYPES.1))
+        .with_added_column("tenant_id", "ALTER TABLE lookup_keys ADD COLUMN tenant_id VARCHAR(64) NOT NULL DEFAULT ''"),
+        TableSpec::new("dedup_keys", DEDUP_KEY_COLUMNS, format!("CREATE TABLE IF NOT EXISTS dedup_keys (
+            id BIGINT NOT NULL AUTO_INCREMENT,
+            dedup_key VARCHAR(255) NOT NULL,
+            aggregate_id {} NOT NULL,
+            created_at BIGINT NOT NULL,
+            tenant_id VARCHAR(64) NOT NULL DEFAULT '',
+            PRIMARY KEY (id),
+            UNIQUE KEY (tenant_id, dedup_key),
+            INDEX idx_dedup_keys_created_at (created_at),
+            CONSTRAINT fk_dedup_key_aggregate_id
+                FOREIGN KEY(aggregate_id)
+                    REFERENCES aggregate_instance(id)
+        )", AGGREGATE_ID_TYPES.1))
+        .with_added_column("tenant_id", "ALTER TABLE dedup_keys ADD COLUMN tenant_id VARCHAR(64) NOT NULL DEFAULT ''")
+        .with_upgrade("tenant_id", vec![String::from("ALTER TABLE dedup_keys
+            ADD UNIQUE KEY (tenant_id, dedup_key),
+            DROP INDEX dedup_key")]),
+        TableSpec::new("projection_checkpoints", CHECKPOINT_COLUMNS, String::from("CREATE TABLE IF NOT EXISTS projection_checkpoints (
+            name VARCHAR(255) NOT NULL,
+            position BIGINT NOT NULL,
+            tenant_id VARCHAR(64) NOT NULL DEFAULT '',
+            PRIMARY KEY (tenant_id, name)
+        )"))
+        .with_added_column("tenant_id", "ALTER TABLE projection_checkpoints ADD COLUMN tenant_id VARCHAR(64) NOT NULL DEFAULT ''")
+        .with_upgrade("tenant_id", vec![String::from("ALTER TABLE projection_checkpoints
+            DROP PRIMARY KEY,
+            ADD PRIMARY KEY (tenant_id, name)")]),
+        TableSpec::new("schema_version", SCHEMA_VERSION_COLUMNS, String::from("CREATE TABLE IF NOT EXISTS schema_version (
+            version BIGINT NOT NULL
+        )")),
+        TableSpec::new("store_info", STORE_INFO_COLUMNS, String::from("CREATE TABLE IF NOT EXISTS store_info (
+            name VARCHAR(255) NOT NULL,
+            value TEXT NOT NULL,
+            PRIMARY KEY (name)
+        )")),
         ]
     }
 
     fn drop_queries(&self) -> Vec<String> {
         vec![
+            String::from("DROP TABLE IF EXISTS store_info"),
+            String::from("DROP TABLE IF EXISTS schema_version"),
+            String::from("DROP TABLE IF EXISTS projection_checkpoints"),
+            String::from("DROP TABLE IF EXISTS dedup_keys"),
+            String::from("DROP TABLE IF EXISTS lookup_keys"),
             String::from("DROP TABLE IF EXISTS snapshots"),
             String::from("DROP TABLE IF EXISTS events"),
             String::from("DROP TABLE IF EXISTS aggregate_instance"),
@@ -77,6 +173,57 @@ impl QueryBuilder for MysqlBuilder {
         ] 
     }
 
+    fn lock_initialization(&self) -> String {
+        // A negative timeout waits for the lock indefinitely.
+        "SELECT GET_LOCK('evercore_initialization', -1)".to_string()
+    }
+
+    fn unlock_initialization(&self, _succeeded: bool) -> String {
+        "SELECT RELEASE_LOCK('evercore_initialization')".to_string()
+    }
+
+    fn get_schema_version(&self) -> String {
+        "SELECT MAX(version) AS version FROM schema_version".to_string()
+    }
+
+    fn insert_schema_version(&self) -> String {
+        "INSERT INTO schema_version (version) VALUES (?)".to_string()
+    }
+
+    fn list_columns(&self) -> String {
+        "SELECT column_name AS column_name, data_type AS data_type
+         FROM information_schema.columns
+         WHERE table_schema = DATABASE() AND table_name = ?".to_string()
+    }
+
+    fn list_indexes(&self) -> String {
+        "SELECT DISTINCT index_name AS index_name
+         FROM information_schema.statistics
+         WHERE table_schema = DATABASE() AND table_name = ?".to_string()
+    }
+
+    fn list_aggregate_types(&self, with_counts: bool) -> String {
+        if !with_counts {
+            return "SELECT id, name FROM aggregate_types ORDER BY name".to_string();
+        }
+        "SELECT aggregate_types.id, aggregate_types.name, COUNT(events.id) AS usage_count, MIN(events.created_at) AS first_seen
+         FROM aggregate_types
+         LEFT JOIN events ON events.aggregate_type_id = aggregate_types.id AND events.tenant_id = ?
+         GROUP BY aggregate_types.id, aggregate_types.name ORDER BY aggregate_types.name"
+        .to_string()
+    }
+
+    fn list_event_types(&self, with_counts: bool) -> String {
+        if !with_counts {
+            return "SELECT id, name FROM event_types ORDER BY name".to_string();
+        }
+        "SELECT event_types.id, event_types.name, COUNT(events.id) AS usage_count, MIN(events.created_at) AS first_seen
+         FROM event_types
+         LEFT JOIN events ON events.event_type_id = event_types.id AND events.tenant_id = ?
+         GROUP BY event_types.id, event_types.name ORDER BY event_types.name"
+        .to_string()
+    }
+
     fn insert_event_type(&self) -> String {
         "INSERT INTO event_types (name) VALUES (?);".to_string() 
     }
@@ -93,40 +240,257 @@ impl QueryBuilder for MysqlBuilder {
         "SELECT id FROM aggregate_types WHERE name = ?;".to_string() 
     }
 
+    fn get_aggregate_type_ids_batch(&self, count: usize) -> String {
+        let params = vec!["?"; count].join(", ");
+        format!("SELECT id, name FROM aggregate_types WHERE name IN ({params})")
+    }
+
+    #[cfg(not(feature = "uuid-ids"))]
+    fn insert_aggregate_instance(&self) -> String {
+        "INSERT INTO aggregate_instance (aggregate_type_id, natural_key, tenant_id) VALUES (?, ?, ?)".to_string() 
+    }
+
+    #[cfg(feature = "uuid-ids")]
     fn insert_aggregate_instance(&self) -> String {
-        "INSERT INTO aggregate_instance (aggregate_type_id, natural_key) VALUES (?, ?)".to_string() 
+        "INSERT INTO aggregate_instance (id, aggregate_type_id, natural_key, tenant_id) VALUES (?, ?, ?, ?)".to_string()
     }
 
     fn insert_event(&self) -> String {
-        "INSERT INTO events (aggregate_id, aggregate_type_id, version, event_type_id, data, metadata) VALUES (?, ?, ?, ?, ?, ?)".to_string()
+        "INSERT INTO events (aggregate_id, aggregate_type_id, version, event_type_id, data, metadata, created_at, hash, tenant_id) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)".to_string()
     }
 
     fn insert_snapshot(&self) -> String {
-        "INSERT INTO snapshots (aggregate_id, aggregate_type_id, version, data) VALUES (?, ?, ?, ?)".to_string()
+        "INSERT INTO snapshots (aggregate_id, aggregate_type_id, version, data, created_at, tenant_id) VALUES (?, ?, ?, ?, ?, ?)".to_string()
     }
     
     fn get_events(&self) -> String {
-        "SELECT aggregate_id, aggregate_types.name AS aggregate_type, 
-         version, event_types.name AS event_type, data, metadata 
+        "SELECT events.id AS position, aggregate_id, aggregate_types.name AS aggregate_type,
+         version, event_types.name AS event_type, data, metadata, created_at, hash
          FROM events 
          LEFT JOIN aggregate_types ON aggregate_types.id = events.aggregate_type_id
          LEFT JOIN event_types ON event_types.id = events.event_type_id
-         WHERE aggregate_id = ? AND aggregate_type_id = ? AND version > ? ORDER BY version ASC;"
+         WHERE aggregate_id = ? AND aggregate_type_id = ? AND version > ? AND events.tenant_id = ? ORDER BY version ASC;"
         .to_string()
     }
 
+    fn get_all_events(&self) -> String {
+        "SELECT events.id AS position, aggregate_id, aggregate_types.name AS aggregate_type,
+         version, event_types.name AS event_type, data, metadata, created_at, hash
+         FROM events
+         LEFT JOIN aggregate_types ON aggregate_types.id = events.aggregate_type_id
+         LEFT JOIN event_types ON event_types.id = events.event_type_id
+         WHERE events.id > ? AND events.tenant_id = ? ORDER BY events.id ASC LIMIT ?"
+        .to_string()
+    }
+
+    fn get_events_until_position(&self) -> String {
+        "SELECT events.id AS position, aggregate_id, aggregate_types.name AS aggregate_type,
+         version, event_types.name AS event_type, data, metadata, created_at, hash
+         FROM events
+         LEFT JOIN aggregate_types ON aggregate_types.id = events.aggregate_type_id
+         LEFT JOIN event_types ON event_types.id = events.event_type_id
+         WHERE aggregate_id = ? AND aggregate_type_id = ? AND version > ? AND events.id <= ? AND events.tenant_id = ? ORDER BY version ASC"
+        .to_string()
+    }
+
+    fn get_head_position(&self) -> String {
+        "SELECT MAX(id) AS position FROM events WHERE tenant_id = ?".to_string()
+    }
+
+    fn get_snapshot_head_version(&self) -> String {
+        "SELECT MAX(version) AS version FROM snapshots WHERE aggregate_id = ? AND aggregate_type_id = ? AND tenant_id = ?".to_string()
+    }
+
     fn get_snapshot(&self) -> String {
-        "SELECT aggregate_id, aggregate_types.name as aggregate_type, version, data 
+        "SELECT aggregate_id, aggregate_types.name as aggregate_type, version, data, created_at 
          FROM snapshots 
          LEFT JOIN aggregate_types ON aggregate_types.id = snapshots.aggregate_type_id
-         WHERE aggregate_id = ? AND aggregate_type_id = ? ORDER BY version DESC LIMIT 1;"
+         WHERE aggregate_id = ? AND aggregate_type_id = ? AND snapshots.tenant_id = ? ORDER BY version DESC LIMIT 1;"
+        .to_string()
+    }
+
+    fn get_snapshots_history(&self) -> String {
+        "SELECT version, LENGTH(data) AS data_size
+         FROM snapshots
+         WHERE aggregate_id = ? AND aggregate_type_id = ? AND tenant_id = ? ORDER BY version DESC LIMIT ?"
+        .to_string()
+    }
+
+    fn get_snapshot_at(&self) -> String {
+        "SELECT aggregate_id, aggregate_types.name as aggregate_type, version, data, created_at
+         FROM snapshots
+         LEFT JOIN aggregate_types ON aggregate_types.id = snapshots.aggregate_type_id
+         WHERE aggregate_id = ? AND aggregate_type_id = ? AND version <= ? AND snapshots.tenant_id = ? ORDER BY version DESC LIMIT 1"
         .to_string()
     }
 
+    fn get_pruned_snapshots(&self) -> String {
+        format!("SELECT id, {} AS blob_pointer
+         FROM snapshots
+         WHERE (SELECT COUNT(*) FROM snapshots AS newer
+                WHERE newer.aggregate_id = snapshots.aggregate_id
+                AND newer.aggregate_type_id = snapshots.aggregate_type_id
+                AND newer.version > snapshots.version) >= ?
+         AND snapshots.tenant_id = ?", self.blob_pointer())
+    }
+
+    fn delete_snapshot(&self) -> String {
+        "DELETE FROM snapshots WHERE id = ?"
+        .to_string()
+    }
+
+    fn find_aggregate_instance(&self) -> String {
+        "SELECT id, natural_key, deleted_at FROM aggregate_instance WHERE aggregate_type_id = ? AND natural_key = ? AND tenant_id = ?".to_string()
+    }
+
+    fn get_aggregate_deleted_at(&self) -> String {
+        "SELECT deleted_at FROM aggregate_instance WHERE id = ? AND aggregate_type_id = ? AND tenant_id = ?".to_string()
+    }
+
+    fn soft_delete_aggregate(&self) -> String {
+        "UPDATE aggregate_instance SET deleted_at = ? WHERE id = ? AND aggregate_type_id = ? AND tenant_id = ? AND deleted_at IS NULL".to_string()
+    }
+
+    fn resurrect_aggregate(&self) -> String {
+        "UPDATE aggregate_instance SET deleted_at = NULL WHERE id = ? AND aggregate_type_id = ? AND tenant_id = ?".to_string()
+    }
+
+    fn restore_aggregate_instance(&self) -> String {
+        "INSERT INTO aggregate_instance (id, aggregate_type_id, natural_key, tenant_id) VALUES (?, ?, ?, ?)".to_string()
+    }
+
+    fn list_aggregate_instances(&self) -> String {
+        "SELECT id, natural_key, deleted_at FROM aggregate_instance WHERE aggregate_type_id = ? AND tenant_id = ? ORDER BY id LIMIT ? OFFSET ?".to_string()
+    }
+
+    fn restore_type(&self, table: &str) -> String {
+        format!("INSERT INTO {} (id, name) VALUES (?, ?)", table)
+    }
+
+    fn sync_type_ids(&self, _table: &str) -> String {
+        "SELECT 1".to_string()
+    }
+
+    fn sync_aggregate_instance_ids(&self) -> String {
+        // AUTO_INCREMENT moves past explicitly inserted ids by itself.
+        "SELECT 1".to_string()
+    }
+
     fn get_aggregate_instance_id(&self) -> String {
-        "SELECT id FROM aggregate_instance WHERE aggregate_type_id = ? AND natural_key = ?".to_string()
+        "SELECT id FROM aggregate_instance WHERE aggregate_type_id = ? AND natural_key = ? AND tenant_id = ?".to_string()
+    }
+
+    fn get_snapshots_batch(&self, count: usize) -> String {
+        let conditions = vec!["(snapshots.aggregate_id = ? AND snapshots.aggregate_type_id = ?)"; count].join(" OR ");
+        format!(
+            "SELECT aggregate_id, aggregate_type, version, data, created_at FROM (
+                SELECT snapshots.aggregate_id, aggregate_types.name AS aggregate_type, snapshots.version, snapshots.data, snapshots.created_at,
+                    ROW_NUMBER() OVER (PARTITION BY snapshots.aggregate_id, snapshots.aggregate_type_id ORDER BY snapshots.version DESC) AS rn
+                FROM snapshots
+                LEFT JOIN aggregate_types ON aggregate_types.id = snapshots.aggregate_type_id
+                WHERE ({conditions}) AND snapshots.tenant_id = ?
+            ) latest WHERE rn = 1"
+        )
+    }
+
+    fn get_max_version(&self) -> String {
+        "SELECT MAX(version) AS version FROM events WHERE aggregate_id = ? AND aggregate_type_id = ? AND tenant_id = ?".to_string()
+    }
+
+    fn get_min_version(&self) -> String {
+        "SELECT MIN(version) AS version FROM events WHERE aggregate_id = ? AND aggregate_type_id = ? AND tenant_id = ?".to_string()
     }
-}
 
+    fn get_head_created_at(&self) -> String {
+        "SELECT created_at FROM events WHERE aggregate_id = ? AND aggregate_type_id = ? AND tenant_id = ? ORDER BY version DESC LIMIT 1".to_string()
+    }
+
+    fn insert_lookup_key(&self) -> String {
+        "INSERT INTO lookup_keys (aggregate_id, aggregate_type_id, key_name, key_value, tenant_id) VALUES (?, ?, ?, ?, ?)"
+        .to_string()
+    }
 
+    fn delete_lookup_key(&self) -> String {
+        "DELETE FROM lookup_keys WHERE aggregate_id = ? AND aggregate_type_id = ? AND key_name = ? AND key_value = ? AND tenant_id = ?"
+        .to_string()
+    }
+
+    fn find_by_lookup_key(&self) -> String {
+        "SELECT DISTINCT aggregate_id FROM lookup_keys
+         WHERE aggregate_type_id = ? AND key_name = ? AND key_value = ? AND tenant_id = ? ORDER BY aggregate_id"
+        .to_string()
+    }
+
+    fn list_lookup_keys(&self) -> String {
+        "SELECT DISTINCT aggregate_id, key_name, key_value FROM lookup_keys
+         WHERE aggregate_type_id = ? AND tenant_id = ? ORDER BY aggregate_id, key_name, key_value LIMIT ? OFFSET ?"
+        .to_string()
+    }
 
+    fn insert_dedup_key(&self) -> String {
+        "INSERT INTO dedup_keys (dedup_key, aggregate_id, created_at, tenant_id) VALUES (?, ?, ?, ?)"
+        .to_string()
+    }
+
+    fn find_dedup_key(&self) -> String {
+        "SELECT id FROM dedup_keys WHERE dedup_key = ? AND tenant_id = ?".to_string()
+    }
+
+    fn delete_dedup_keys_before(&self) -> String {
+        "DELETE FROM dedup_keys WHERE created_at < ? AND tenant_id = ?".to_string()
+    }
+
+    fn get_checkpoint(&self) -> String {
+        "SELECT position FROM projection_checkpoints WHERE name = ? AND tenant_id = ?".to_string()
+    }
+
+    fn upsert_checkpoint(&self) -> String {
+        "INSERT INTO projection_checkpoints (name, position, tenant_id) VALUES (?, ?, ?)
+         ON DUPLICATE KEY UPDATE position = VALUES(position)".to_string()
+    }
+
+    fn get_store_info(&self) -> String {
+        "SELECT value FROM store_info WHERE name = ?".to_string()
+    }
+
+    fn upsert_store_info(&self) -> String {
+        "INSERT INTO store_info (name, value) VALUES (?, ?)
+         ON DUPLICATE KEY UPDATE value = VALUES(value)".to_string()
+    }
+
+    fn retype_aggregate_instances(&self) -> String {
+        "UPDATE aggregate_instance SET aggregate_type_id = ? WHERE aggregate_type_id = ?".to_string()
+    }
+
+    fn retype_events(&self) -> String {
+        "UPDATE events SET aggregate_type_id = ? WHERE aggregate_type_id = ?".to_string()
+    }
+
+    fn retype_snapshots(&self) -> String {
+        "UPDATE snapshots SET aggregate_type_id = ? WHERE aggregate_type_id = ?".to_string()
+    }
+
+    fn retype_lookup_keys(&self) -> String {
+        "UPDATE lookup_keys SET aggregate_type_id = ? WHERE aggregate_type_id = ?".to_string()
+    }
+
+    fn delete_aggregate_type(&self) -> String {
+        "DELETE FROM aggregate_types WHERE id = ?".to_string()
+    }
+
+    fn purge_stream_rows(&self, table: &str) -> String {
+        format!("DELETE FROM {} WHERE aggregate_id = ? AND aggregate_type_id = ? AND tenant_id = ?", table)
+    }
+
+    fn purge_dedup_keys(&self) -> String {
+        "DELETE FROM dedup_keys WHERE aggregate_id = ? AND tenant_id = ?".to_string()
+    }
+
+    fn delete_aggregate_instance(&self) -> String {
+        "DELETE FROM aggregate_instance WHERE id = ? AND aggregate_type_id = ? AND tenant_id = ?".to_string()
+    }
+
+    fn get_stream_blob_pointers(&self, table: &str) -> String {
+        format!("SELECT {} AS blob_pointer FROM {} WHERE aggregate_id = ? AND aggregate_type_id = ? AND tenant_id = ?", self.blob_pointer(), table)
+    }
+}
@@ -1,42 +1,74 @@
 use crate::QueryBuilder;
+use crate::queries::{TableSpec, TYPE_COLUMNS, INSTANCE_COLUMNS, EVENT_COLUMNS, SNAPSHOT_COLUMNS, LOOKUP_KEY_COLUMNS, DEDUP_KEY_COLUMNS, CHECKPOINT_COLUMNS, SCHEMA_VERSION_COLUMNS, STORE_INFO_COLUMNS};
 
-pub struct PostgresqlBuilder;
+/// Types of aggregate instance ids and of the columns referencing them: numbered by a
+/// sequence, or with `uuid-ids` uuids as text.
+#[cfg(not(feature = "uuid-ids"))]
+const AGGREGATE_ID_TYPES: (&str, &str) = ("BIGSERIAL", "BIGINT");
+#[cfg(feature = "uuid-ids")]
+const AGGREGATE_ID_TYPES: (&str, &str) = ("CHAR(36)", "CHAR(36)");
+
+/// Advisory lock key held while initializing the schema: "evercore" in ASCII.
+const INITIALIZATION_LOCK_KEY: i64 = 0x65766572636f7265;
+
+#[derive(Default)]
+pub struct PostgresqlBuilder {
+    pub binary_payloads: bool,
+}
 
 impl QueryBuilder for PostgresqlBuilder {
+    fn binary_payloads(&self) -> bool {
+        self.binary_payloads
+    }
+
+    fn binary_type(&self) -> &'static str {
+        "BYTEA"
+    }
+
 
-   fn build_queries(&self) -> Vec<String> {
+   fn schema(&self) -> Vec<TableSpec> {
         vec![
-        String::from("CREATE TABLE IF NOT EXISTS aggregate_types (
+        TableSpec::new("aggregate_types", TYPE_COLUMNS, String::from("CREATE TABLE IF NOT EXISTS aggregate_types (
             id BIGSERIAL PRIMARY KEY,
             name VARCHAR(255) NOT NULL,
             UNIQUE(name)
-        );"),
+        );")),
         
-        String::from("CREATE TABLE IF NOT EXISTS event_types (
+        TableSpec::new("event_types", TYPE_COLUMNS, String::from("CREATE TABLE IF NOT EXISTS event_types (
             id BIGSERIAL PRIMARY KEY,
             name VARCHAR(255) NOT NULL,
             UNIQUE(name)
-        );"), 
+        );")), 
 
-        String::from("CREATE TABLE IF NOT EXISTS aggregate_instances (
-            id BIGSERIAL PRIMARY KEY,
+        TableSpec::new("aggregate_instances", INSTANCE_COLUMNS, format!("CREATE TABLE IF NOT EXISTS aggregate_instances (
+            id {} PRIMARY KEY,
             aggregate_type_id BIGINT NOT NULL,
             natural_key VARCHAR(255),
-            UNIQUE(aggregate_type_id, natural_key),
+            deleted_at BIGINT,
+            tenant_id VARCHAR(64) NOT NULL DEFAULT '',
+            UNIQUE(tenant_id, aggregate_type_id, natural_key),
             CONSTRAINT fk_aggregate_type_id
                 FOREIGN KEY(aggregate_type_id)
                     REFERENCES aggregate_types(id)
-        );"),
+        );", AGGREGATE_ID_TYPES.0))
+        .with_added_column("deleted_at", "ALTER TABLE aggregate_instances ADD COLUMN IF NOT EXISTS deleted_at BIGINT;")
+        .with_added_column("tenant_id", "ALTER TABLE aggregate_instances ADD COLUMN IF NOT EXISTS tenant_id VARCHAR(64) NOT NULL DEFAULT '';")
+        .with_upgrade("tenant_id", vec![String::from("ALTER TABLE aggregate_instances
+            DROP CONSTRAINT IF EXISTS aggregate_instances_aggregate_type_id_natural_key_key,
+            ADD UNIQUE(tenant_id, aggregate_type_id, natural_key);")]),
 
-        String::from("CREATE TABLE IF NOT EXISTS events (
+        TableSpec::new("events", EVENT_COLUMNS, format!("CREATE TABLE IF NOT EXISTS events (
             id BIGSERIAL PRIMARY KEY,
-            aggregate_id BIGINT NOT NULL,
+            aggregate_id {} NOT NULL,
             aggregate_type_id BIGINT NOT NULL,
             version BIGINT NOT NULL,
             event_type_id BIGINT NOT NULL,
-            data TEXT NOT NULL,
+            data {} NOT NULL,
             metadata TEXT,
-            UNIQUE(aggregate_id, version),
+            created_at BIGINT,
+            hash TEXT,
+            tenant_id VARCHAR(64) NOT NULL DEFAULT '',
+            UNIQUE(tenant_id, aggregate_id, version),
             CONSTRAINT fk_aggregate_id
                 FOREIGN KEY(aggregate_id)
                     REFERENCES aggregate_instances(id),
@@ -46,26 +78,88 @@ impl QueryBuilder for PostgresqlBuilder {
             CONSTRAINT fk_event_type_id
                 FOREIGN KEY(event_type_id)
                     REFERENCES event_types(id)
-        );"),
-        String::from("CREATE TABLE IF NOT EXISTS snapshots (
+        );", AGGREGATE_ID_TYPES.1, self.data_type()))
+        .with_column_kind("data", self.data_kind())
+        .with_added_column("hash", "ALTER TABLE events ADD COLUMN IF NOT EXISTS hash TEXT;")
+        .with_added_column("tenant_id", "ALTER TABLE events ADD COLUMN IF NOT EXISTS tenant_id VARCHAR(64) NOT NULL DEFAULT '';"),
+        TableSpec::new("snapshots", SNAPSHOT_COLUMNS, format!("CREATE TABLE IF NOT EXISTS snapshots (
             id BIGSERIAL PRIMARY KEY,
-            aggregate_id BIGINT NOT NULL,
+            aggregate_id {} NOT NULL,
             aggregate_type_id BIGINT NOT NULL,
             version BIGINT NOT NULL,
-            data TEXT NOT NULL,
-            UNIQUE(aggregate_id, version),
+            data {} NOT NULL,
+            created_at BIGINT,
+            tenant_id VARCHAR(64) NOT NULL DEFAULT '',
+            UNIQUE(tenant_id, aggregate_id, version),
             CONSTRAINT fk_aggregate_id
                 FOREIGN KEY(aggregate_id)
                     REFERENCES aggregate_instances(id),
             CONSTRAINT fk_aggregate_type_id
                 FOREIGN KEY(aggregate_type_id)
                     REFERENCES aggregate_types(id)
-        );")
+        );", AGGREGATE_ID_TYPES.1, self.data_type()))
+        .with_column_kind("data", self.data_kind())
+        .with_added_column("created_at", "ALTER TABLE snapshots ADD COLUMN IF NOT EXISTS created_at BIGINT;")
+        .with_added_column("tenant_id", "ALTER TABLE snapshots ADD COLUMN IF NOT EXISTS tenant_id VARCHAR(64) NOT NULL DEFAULT '';"),
+        TableSpec::new("lookup_keys", LOOKUP_KEY_COLUMNS, format!("CREATE TABLE IF NOT EXISTS lookup_keys (
+            id BIGSERIAL PRIMARY KEY,
+            aggregate_id {} NOT NULL,
+            aggregate_type_id BIGINT NOT NULL,
+            key_name VARCHAR(255) NOT NULL,
+            key_value VARCHAR(255) NOT NULL,
+            tenant_id VARCHAR(64) NOT NULL DEFAULT '',
+            CONSTRAINT fk_aggregate_id
+                FOREIGN KEY(aggregate_id)
+                    REFERENCES aggregate_instances(id),
+            CONSTRAINT fk_aggregate_type_id
+                FOREIGN KEY(aggregate_type_id)
+                    REFERENCES aggregate_types(id)
+        );", AGGREGATE_ID_TYPES.1))
+        .with_index("CREATE INDEX IF NOT EXISTS idx_lookup_keys_lookup ON lookup_keys (aggregate_type_id, key_name, key_value);")
+        .with_added_column("tenant_id", "ALTER TABLE lookup_keys ADD COLUMN IF NOT EXISTS tenant_id VARCHAR(64) NOT NULL DEFAULT '';"),
+        TableSpec::new("dedup_keys", DEDUP_KEY_COLUMNS, format!("CREATE TABLE IF NOT EXISTS dedup_keys (
+            id BIGSERIAL PRIMARY KEY,
+            dedup_key VARCHAR(255) NOT NULL,
+            aggregate_id {} NOT NULL,
+            created_at BIGINT NOT NULL,
+            tenant_id VARCHAR(64) NOT NULL DEFAULT '',
+            UNIQUE(tenant_id, dedup_key),
+            CONSTRAINT fk_aggregate_id
+                FOREIGN KEY(aggregate_id)
+                    REFERENCES aggregate_instances(id)
+        );", AGGREGATE_ID_TYPES.1))
+        .with_index("CREATE INDEX IF NOT EXISTS idx_dedup_keys_created_at ON dedup_keys (created_at);")
+        .with_added_column("tenant_id", "ALTER TABLE dedup_keys ADD COLUMN IF NOT EXISTS tenant_id VARCHAR(64) NOT NULL DEFAULT '';")
+        .with_upgrade("tenant_id", vec![String::from("ALTER TABLE dedup_keys
+            DROP CONSTRAINT IF EXISTS dedup_keys_dedup_key_key,
+            ADD UNIQUE(tenant_id, dedup_key);")]),
+        TableSpec::new("projection_checkpoints", CHECKPOINT_COLUMNS, String::from("CREATE TABLE IF NOT EXISTS projection_checkpoints (
+            name VARCHAR(255) NOT NULL,
+            position BIGINT NOT NULL,
+            tenant_id VARCHAR(64) NOT NULL DEFAULT '',
+            PRIMARY KEY (tenant_id, name)
+        );"))
+        .with_added_column("tenant_id", "ALTER TABLE projection_checkpoints ADD COLUMN IF NOT EXISTS tenant_id VARCHAR(64) NOT NULL DEFAULT '';")
+        .with_upgrade("tenant_id", vec![String::from("ALTER TABLE projection_checkpoints
+            DROP CONSTRAINT IF EXISTS projection_checkpoints_pkey,
+            ADD PRIMARY KEY (tenant_id, name);")]),
+        TableSpec::new("schema_version", SCHEMA_VERSION_COLUMNS, String::from("CREATE TABLE IF NOT EXISTS schema_version (
+            version BIGINT NOT NULL
+        );")),
+        TableSpec::new("store_info", STORE_INFO_COLUMNS, String::from("CREATE TABLE IF NOT EXISTS store_info (
+            name VARCHAR(255) PRIMARY KEY,
+            value TEXT NOT NULL
+        );")),
         ]
     }
     
     fn drop_queries(&self) -> Vec<String> {
         vec![
+            String::from("DROP TABLE IF EXISTS store_info;"),
+            String::from("DROP TABLE IF EXISTS schema_version;"),
+            String::from("DROP TABLE IF EXISTS projection_checkpoints;"),
+            String::from("DROP TABLE IF EXISTS dedup_keys;"),
+            String::from("DROP TABLE IF EXISTS lookup_keys;"),
             String::from("DROP TABLE IF EXISTS snapshots;"),
             String::from("DROP TABLE IF EXISTS events;"),
             String::from("DROP TABLE IF EXISTS aggregate_instances;"),
@@ -73,7 +167,59 @@ impl QueryBuilder for PostgresqlBuilder {
             String::from("DROP TABLE IF EXISTS aggregate_types;"),
         ]
     }
+
+    fn lock_initialization(&self) -> String {
+        format!("SELECT pg_advisory_lock({INITIALIZATION_LOCK_KEY});")
+    }
+
+    fn unlock_initialization(&self, _succeeded: bool) -> String {
+        format!("SELECT pg_advisory_unlock({INITIALIZATION_LOCK_KEY});")
+    }
+
+    fn get_schema_version(&self) -> String {
+        "SELECT MAX(version) AS version FROM schema_version;".to_string()
+    }
+
+    fn insert_schema_version(&self) -> String {
+        "INSERT INTO schema_version (version) VALUES ($1);".to_string()
+    }
+
+    fn list_columns(&self) -> String {
+        "SELECT column_name::text AS column_name, data_type::text AS data_type
+         FROM information_schema.columns
+         WHERE table_schema = current_schema() AND table_name = $1;"
+        .to_string()
+    }
+
+    fn list_indexes(&self) -> String {
+        "SELECT indexname::text AS index_name
+         FROM pg_indexes
+         WHERE schemaname = current_schema() AND tablename = $1;"
+        .to_string()
+    }
     
+    fn list_aggregate_types(&self, with_counts: bool) -> String {
+        if !with_counts {
+            return "SELECT id, name FROM aggregate_types ORDER BY name;".to_string();
+        }
+        "SELECT aggregate_types.id, aggregate_types.name, COUNT(events.id) AS usage_count, MIN(events.created_at) AS first_seen
+         FROM aggregate_types
+         LEFT JOIN events ON events.aggregate_type_id = aggregate_types.id AND events.tenant_id = $1
+         GROUP BY aggregate_types.id, aggregate_types.name ORDER BY aggregate_types.name;"
+        .to_string()
+    }
+
+    fn list_event_types(&self, with_counts: bool) -> String {
+        if !with_counts {
+            return "SELECT id, name FROM event_types ORDER BY name;".to_string();
+        }
+        "SELECT event_types.id, event_types.name, COUNT(events.id) AS usage_count, MIN(events.created_at) AS first_seen
+         FROM event_types
+         LEFT JOIN events ON events.event_type_id = event_types.id AND events.tenant_id = $1
+         GROUP BY event_types.id, event_types.name ORDER BY event_types.name;"
+        .to_string()
+    }
+
     fn insert_event_type(&self) -> String {
         "INSERT INTO event_types (name) VALUES ($1) RETURNING id;".to_string() 
     }
@@ -91,43 +237,267 @@ impl QueryBuilder for PostgresqlBuilder {
     }
 
 
+    fn get_aggregate_type_ids_batch(&self, count: usize) -> String {
+        let params: Vec<String> = (1..=count).map(|index| format!("${index}")).collect();
+        format!("SELECT id, name FROM aggregate_types WHERE name IN ({});", params.join(", "))
+    }
+
+    #[cfg(not(feature = "uuid-ids"))]
+    fn insert_aggregate_instance(&self) -> String {
+        "INSERT INTO aggregate_instances (aggregate_type_id, natural_key, tenant_id) VALUES ($1, $2, $3) RETURNING id;"
+        .to_string()
+    }
+
+    #[cfg(feature = "uuid-ids")]
     fn insert_aggregate_instance(&self) -> String {
-        "INSERT INTO aggregate_instances (aggregate_type_id, natural_key) VALUES ($1, $2) RETURNING id;"
+        "INSERT INTO aggregate_instances (id, aggregate_type_id, natural_key, tenant_id) VALUES ($1, $2, $3, $4);".to_string()
+    }
+
+    fn get_snapshots_history(&self) -> String {
+        "SELECT version, LENGTH(data) AS data_size
+         FROM snapshots
+         WHERE aggregate_id = $1 AND aggregate_type_id = $2 AND tenant_id = $3 ORDER BY version DESC LIMIT $4;"
+        .to_string()
+    }
+
+    fn get_snapshot_at(&self) -> String {
+        "SELECT aggregate_id, aggregate_types.name as aggregate_type, version, data, created_at
+         FROM snapshots
+         LEFT JOIN aggregate_types ON aggregate_types.id = snapshots.aggregate_type_id
+         WHERE aggregate_id = $1 AND aggregate_type_id = $2 AND version <= $3 AND snapshots.tenant_id = $4 ORDER BY version DESC LIMIT 1;"
+        .to_string()
+    }
+
+    fn get_pruned_snapshots(&self) -> String {
+        format!("SELECT id, {} AS blob_pointer
+         FROM snapshots
+         WHERE (SELECT COUNT(*) FROM snapshots AS newer
+                WHERE newer.aggregate_id = snapshots.aggregate_id
+                AND newer.aggregate_type_id = snapshots.aggregate_type_id
+                AND newer.version > snapshots.version) >= $1
+         AND snapshots.tenant_id = $2;", self.blob_pointer())
+    }
+
+    fn delete_snapshot(&self) -> String {
+        "DELETE FROM snapshots WHERE id = $1;"
         .to_string()
     }
 
+    fn find_aggregate_instance(&self) -> String {
+        "SELECT id, natural_key, deleted_at FROM aggregate_instances WHERE aggregate_type_id = $1 AND natural_key = $2 AND tenant_id = $3;".to_string()
+    }
+
+    fn get_aggregate_deleted_at(&self) -> String {
+        "SELECT deleted_at FROM aggregate_instances WHERE id = $1 AND aggregate_type_id = $2 AND tenant_id = $3;".to_string()
+    }
+
+    fn soft_delete_aggregate(&self) -> String {
+        "UPDATE aggregate_instances SET deleted_at = $1 WHERE id = $2 AND aggregate_type_id = $3 AND tenant_id = $4 AND deleted_at IS NULL;".to_string()
+    }
+
+    fn resurrect_aggregate(&self) -> String {
+        "UPDATE aggregate_instances SET deleted_at = NULL WHERE id = $1 AND aggregate_type_id = $2 AND tenant_id = $3;".to_string()
+    }
+
+    fn restore_aggregate_instance(&self) -> String {
+        "INSERT INTO aggregate_instances (id, aggregate_type_id, natural_key, tenant_id) VALUES ($1, $2, $3, $4);".to_string()
+    }
+
+    fn list_aggregate_instances(&self) -> String {
+        "SELECT id, natural_key, deleted_at FROM aggregate_instances WHERE aggregate_type_id = $1 AND tenant_id = $2 ORDER BY id LIMIT $3 OFFSET $4;".to_string()
+    }
+
+    fn restore_type(&self, table: &str) -> String {
+        format!("INSERT INTO {} (id, name) VALUES ($1, $2);", table)
+    }
+
+    fn sync_type_ids(&self, table: &str) -> String {
+        format!("SELECT setval(pg_get_serial_sequence('{0}', 'id'), (SELECT MAX(id) FROM {0}));", table)
+    }
+
+    fn sync_aggregate_instance_ids(&self) -> String {
+        "SELECT setval(pg_get_serial_sequence('aggregate_instances', 'id'), (SELECT MAX(id) FROM aggregate_instances));".to_string()
+    }
+
     fn get_aggregate_instance_id(&self) -> String {
-        "SELECT id FROM aggregate_instances WHERE aggregate_type_id = $1 AND natural_key = $2;"
+        "SELECT id FROM aggregate_instances WHERE aggregate_type_id = $1 AND natural_key = $2 AND tenant_id = $3;"
         .to_string()
     }
 
     fn insert_event(&self) -> String {
-        "INSERT INTO events (aggregate_id, aggregate_type_id, version, event_type_id, data, metadata) VALUES ( $1, $2, $3, $4, $5, $6)"
+        "INSERT INTO events (aggregate_id, aggregate_type_id, version, event_type_id, data, metadata, created_at, hash, tenant_id) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9) RETURNING id;"
         .to_string()
     }
 
     fn insert_snapshot(&self) -> String {
-        "INSERT INTO snapshots (aggregate_id, aggregate_type_id, version, data) VALUES ($1, $2, $3, $4)"
+        "INSERT INTO snapshots (aggregate_id, aggregate_type_id, version, data, created_at, tenant_id) VALUES ($1, $2, $3, $4, $5, $6)"
         .to_string()
     }
 
     fn get_events(&self) -> String {
-        "SELECT aggregate_id, aggregate_types.name AS aggregate_type, 
-         version, event_types.name AS event_type, data, metadata 
+        "SELECT events.id AS position, aggregate_id, aggregate_types.name AS aggregate_type,
+         version, event_types.name AS event_type, data, metadata, created_at, hash
          FROM events 
          LEFT JOIN aggregate_types ON aggregate_types.id = events.aggregate_type_id
          LEFT JOIN event_types ON event_types.id = events.event_type_id
-         WHERE aggregate_id = $1 AND aggregate_type_id = $2 AND version > $3 ORDER BY version ASC;"
+         WHERE aggregate_id = $1 AND aggregate_type_id = $2 AND version > $3 AND events.tenant_id = $4 ORDER BY version ASC;"
+        .to_string()
+    }
+
+    fn get_all_events(&self) -> String {
+        "SELECT events.id AS position, aggregate_id, aggregate_types.name AS aggregate_type,
+         version, event_types.name AS event_type, data, metadata, created_at, hash
+         FROM events
+         LEFT JOIN aggregate_types ON aggregate_types.id = events.aggregate_type_id
+         LEFT JOIN event_types ON event_types.id = events.event_type_id
+         WHERE events.id > $1 AND events.tenant_id = $2 ORDER BY events.id ASC LIMIT $3;"
+        .to_string()
+    }
+
+    fn get_events_until_position(&self) -> String {
+        "SELECT events.id AS position, aggregate_id, aggregate_types.name AS aggregate_type,
+         version, event_types.name AS event_type, data, metadata, created_at, hash
+         FROM events
+         LEFT JOIN aggregate_types ON aggregate_types.id = events.aggregate_type_id
+         LEFT JOIN event_types ON event_types.id = events.event_type_id
+         WHERE aggregate_id = $1 AND aggregate_type_id = $2 AND version > $3 AND events.id <= $4 AND events.tenant_id = $5 ORDER BY version ASC;"
         .to_string()
     }
 
+    fn get_head_position(&self) -> String {
+        "SELECT MAX(id) AS position FROM events WHERE tenant_id = $1;".to_string()
+    }
+
+    fn get_snapshot_head_version(&self) -> String {
+        "SELECT MAX(version) AS version FROM snapshots WHERE aggregate_id = $1 AND aggregate_type_id = $2 AND tenant_id = $3;".to_string()
+    }
+
     fn get_snapshot(&self) -> String {
-        "SELECT aggregate_id, aggregate_types.name as aggregate_type, version, data 
+        "SELECT aggregate_id, aggregate_types.name as aggregate_type, version, data, created_at 
          FROM snapshots 
          LEFT JOIN aggregate_types ON aggregate_types.id = snapshots.aggregate_type_id
-         WHERE aggregate_id = $1 AND aggregate_type_id = $2 ORDER BY version DESC LIMIT 1;"
+         WHERE aggregate_id = $1 AND aggregate_type_id = $2 AND snapshots.tenant_id = $3 ORDER BY version DESC LIMIT 1;"
         .to_string()
     }
-}
 
+    fn get_snapshots_batch(&self, count: usize) -> String {
+        let conditions: Vec<String> = (0..count)
+            .map(|index| format!("(snapshots.aggregate_id = ${} AND snapshots.aggregate_type_id = ${})", index * 2 + 1, index * 2 + 2))
+            .collect();
+        let conditions = conditions.join(" OR ");
+        let tenant = count * 2 + 1;
+        format!(
+            "SELECT aggregate_id, aggregate_type, version, data, created_at FROM (
+                SELECT snapshots.aggregate_id, aggregate_types.name AS aggregate_type, snapshots.version, snapshots.data, snapshots.created_at,
+                    ROW_NUMBER() OVER (PARTITION BY snapshots.aggregate_id, snapshots.aggregate_type_id ORDER BY snapshots.version DESC) AS rn
+                FROM snapshots
+                LEFT JOIN aggregate_types ON aggregate_types.id = snapshots.aggregate_type_id
+                WHERE ({conditions}) AND snapshots.tenant_id = ${tenant}
+            ) latest WHERE rn = 1;"
+        )
+    }
+
+    fn get_max_version(&self) -> String {
+        "SELECT MAX(version) AS version FROM events WHERE aggregate_id = $1 AND aggregate_type_id = $2 AND tenant_id = $3;"
+        .to_string()
+    }
+
+    fn get_min_version(&self) -> String {
+        "SELECT MIN(version) AS version FROM events WHERE aggregate_id = $1 AND aggregate_type_id = $2 AND tenant_id = $3;"
+        .to_string()
+    }
+
+    fn get_head_created_at(&self) -> String {
+        "SELECT created_at FROM events WHERE aggregate_id = $1 AND aggregate_type_id = $2 AND tenant_id = $3 ORDER BY version DESC LIMIT 1;"
+        .to_string()
+    }
+
+    fn insert_lookup_key(&self) -> String {
+        "INSERT INTO lookup_keys (aggregate_id, aggregate_type_id, key_name, key_value, tenant_id) VALUES ($1, $2, $3, $4, $5);"
+        .to_string()
+    }
+
+    fn delete_lookup_key(&self) -> String {
+        "DELETE FROM lookup_keys WHERE aggregate_id = $1 AND aggregate_type_id = $2 AND key_name = $3 AND key_value = $4 AND tenant_id = $5;"
+        .to_string()
+    }
+
+    fn find_by_lookup_key(&self) -> String {
+        "SELECT DISTINCT aggregate_id FROM lookup_keys
+         WHERE aggregate_type_id = $1 AND key_name = $2 AND key_value = $3 AND tenant_id = $4 ORDER BY aggregate_id;"
+        .to_string()
+    }
+
+    fn list_lookup_keys(&self) -> String {
+        "SELECT DISTINCT aggregate_id, key_name, key_value FROM lookup_keys
+         WHERE aggregate_type_id = $1 AND tenant_id = $2 ORDER BY aggregate_id, key_name, key_value LIMIT $3 OFFSET $4;"
+        .to_string()
+    }
+
+    fn insert_dedup_key(&self) -> String {
+        "INSERT INTO dedup_keys (dedup_key, aggregate_id, created_at, tenant_id) VALUES ($1, $2, $3, $4);"
+        .to_string()
+    }
+
+    fn find_dedup_key(&self) -> String {
+        "SELECT id FROM dedup_keys WHERE dedup_key = $1 AND tenant_id = $2;".to_string()
+    }
+
+    fn delete_dedup_keys_before(&self) -> String {
+        "DELETE FROM dedup_keys WHERE created_at < $1 AND tenant_id = $2;".to_string()
+    }
+
+    fn get_checkpoint(&self) -> String {
+        "SELECT position FROM projection_checkpoints WHERE name = $1 AND tenant_id = $2;".to_string()
+    }
+
+    fn upsert_checkpoint(&self) -> String {
+        "INSERT INTO projection_checkpoints (name, position, tenant_id) VALUES ($1, $2, $3)
+         ON CONFLICT(tenant_id, name) DO UPDATE SET position = EXCLUDED.position;".to_string()
+    }
+
+    fn get_store_info(&self) -> String {
+        "SELECT value FROM store_info WHERE name = $1;".to_string()
+    }
+
+    fn upsert_store_info(&self) -> String {
+        "INSERT INTO store_info (name, value) VALUES ($1, $2)
+         ON CONFLICT(name) DO UPDATE SET value = EXCLUDED.value;".to_string()
+    }
+
+    fn retype_aggregate_instances(&self) -> String {
+        "UPDATE aggregate_instances SET aggregate_type_id = $1 WHERE aggregate_type_id = $2;".to_string()
+    }
+
+    fn retype_events(&self) -> String {
+        "UPDATE events SET aggregate_type_id = $1 WHERE aggregate_type_id = $2;".to_string()
+    }
+
+    fn retype_snapshots(&self) -> String {
+        "UPDATE snapshots SET aggregate_type_id = $1 WHERE aggregate_type_id = $2;".to_string()
+    }
+
+    fn retype_lookup_keys(&self) -> String {
+        "UPDATE lookup_keys SET aggregate_type_id = $1 WHERE aggregate_type_id = $2;".to_string()
+    }
+
+    fn delete_aggregate_type(&self) -> String {
+        "DELETE FROM aggregate_types WHERE id = $1;".to_string()
+    }
+
+    fn purge_stream_rows(&self, table: &str) -> String {
+        format!("DELETE FROM {} WHERE aggregate_id = $1 AND aggregate_type_id = $2 AND tenant_id = $3;", table)
+    }
+
+    fn purge_dedup_keys(&self) -> String {
+        "DELETE FROM dedup_keys WHERE aggregate_id = $1 AND tenant_id = $2;".to_string()
+    }
 
+    fn delete_aggregate_instance(&self) -> String {
+        "DELETE FROM aggregate_instances WHERE id = $1 AND aggregate_type_id = $2 AND tenant_id = $3;".to_string()
+    }
+
+    fn get_stream_blob_pointers(&self, table: &str) -> String {
+        format!("SELECT {} AS blob_pointer FROM {} WHERE aggregate_id = $1 AND aggregate_type_id = $2 AND tenant_id = $3;", self.blob_pointer(), table)
+    }
+}
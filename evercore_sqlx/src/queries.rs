@@ -1,15 +1,391 @@
+/// Broad column type families used when verifying a live schema.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ColumnKind {
+    Integer,
+    Text,
+    Binary,
+}
+
+impl ColumnKind {
+    /// Whether a database reported data type belongs to this family.
+    pub(crate) fn matches(&self, data_type: &str) -> bool {
+        let data_type = data_type.to_lowercase();
+        match self {
+            ColumnKind::Integer => data_type.contains("int") || data_type == "serial" || data_type == "bigserial",
+            ColumnKind::Text => data_type.contains("text") || data_type.contains("char"),
+            ColumnKind::Binary => data_type.contains("blob") || data_type == "bytea" || data_type.contains("binary"),
+        }
+    }
+
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            ColumnKind::Integer => "integer",
+            ColumnKind::Text => "text",
+            ColumnKind::Binary => "binary",
+        }
+    }
+}
+
+pub(crate) type ColumnSpec = (&'static str, ColumnKind);
+
+/// Kind of the aggregate id columns: integers, or with `uuid-ids` uuids as text.
+#[cfg(not(feature = "uuid-ids"))]
+pub(crate) const AGGREGATE_ID_KIND: ColumnKind = ColumnKind::Integer;
+#[cfg(feature = "uuid-ids")]
+pub(crate) const AGGREGATE_ID_KIND: ColumnKind = ColumnKind::Text;
+
+pub(crate) const TYPE_COLUMNS: &[ColumnSpec] = &[
+    ("id", ColumnKind::Integer),
+    ("name", ColumnKind::Text),
+];
+
+pub(crate) const INSTANCE_COLUMNS: &[ColumnSpec] = &[
+    ("id", AGGREGATE_ID_KIND),
+    ("aggregate_type_id", ColumnKind::Integer),
+    ("natural_key", ColumnKind::Text),
+    ("deleted_at", ColumnKind::Integer),
+    ("tenant_id", ColumnKind::Text),
+];
+
+pub(crate) const EVENT_COLUMNS: &[ColumnSpec] = &[
+    ("id", ColumnKind::Integer),
+    ("aggregate_id", AGGREGATE_ID_KIND),
+    ("aggregate_type_id", ColumnKind::Integer),
+    ("version", ColumnKind::Integer),
+    ("event_type_id", ColumnKind::Integer),
+    ("data", ColumnKind::Text),
+    ("metadata", ColumnKind::Text),
+    ("created_at", ColumnKind::Integer),
+    ("hash", ColumnKind::Text),
+    ("tenant_id", ColumnKind::Text),
+];
+
+pub(crate) const SNAPSHOT_COLUMNS: &[ColumnSpec] = &[
+    ("id", ColumnKind::Integer),
+    ("aggregate_id", AGGREGATE_ID_KIND),
+    ("aggregate_type_id", ColumnKind::Integer),
+    ("version", ColumnKind::Integer),
+    ("data", ColumnKind::Text),
+    ("created_at", ColumnKind::Integer),
+    ("tenant_id", ColumnKind::Text),
+];
+
+pub(crate) const LOOKUP_KEY_COLUMNS: &[ColumnSpec] = &[
+    ("id", ColumnKind::Integer),
+    ("aggregate_id", AGGREGATE_ID_KIND),
+    ("aggregate_type_id", ColumnKind::Integer),
+    ("key_name", ColumnKind::Text),
+    ("key_value", ColumnKind::Text),
+    ("tenant_id", ColumnKind::Text),
+];
+
+pub(crate) const DEDUP_KEY_COLUMNS: &[ColumnSpec] = &[
+    ("id", ColumnKind::Integer),
+    ("dedup_key", ColumnKind::Text),
+    ("aggregate_id", AGGREGATE_ID_KIND),
+    ("created_at", ColumnKind::Integer),
+    ("tenant_id", ColumnKind::Text),
+];
+
+pub(crate) const CHECKPOINT_COLUMNS: &[ColumnSpec] = &[
+    ("name", ColumnKind::Text),
+    ("position", ColumnKind::Integer),
+    ("tenant_id", ColumnKind::Text),
+];
+
+pub(crate) const SCHEMA_VERSION_COLUMNS: &[ColumnSpec] = &[
+    ("version", ColumnKind::Integer),
+];
+
+pub(crate) const STORE_INFO_COLUMNS: &[ColumnSpec] = &[
+    ("name", ColumnKind::Text),
+    ("value", ColumnKind::Text),
+];
+
+/// A table the storage engine expects, with its create statement and critical columns.
+pub(crate) struct TableSpec {
+    pub name: &'static str,
+    pub columns: Vec<ColumnSpec>,
+    pub create: String,
+    /// Statements run after `create`, e.g. secondary indexes.
+    pub indexes: Vec<String>,
+    /// Columns added after the table was first released, each with a statement bringing an
+    /// existing table up to date: the one adding it, then any rebuilding constraints over it.
+    pub added_columns: Vec<(&'static str, String)>,
+}
+
+impl TableSpec {
+    pub fn new(name: &'static str, columns: &[ColumnSpec], create: String) -> TableSpec {
+        TableSpec {
+            name,
+            columns: columns.to_vec(),
+            create,
+            indexes: Vec::new(),
+            added_columns: Vec::new(),
+        }
+    }
+
+    pub fn with_index(mut self, index: &str) -> TableSpec {
+        self.indexes.push(index.to_string());
+        self
+    }
+
+    /// Changes the kind expected of a column, for columns whose type depends on configuration.
+    pub fn with_column_kind(mut self, column: &str, kind: ColumnKind) -> TableSpec {
+        for spec in self.columns.iter_mut().filter(|(name, _)| *name == column) {
+            spec.1 = kind;
+        }
+        self
+    }
+
+    pub fn with_added_column(mut self, column: &'static str, statement: &str) -> TableSpec {
+        self.added_columns.push((column, statement.to_string()));
+        self
+    }
+
+    /// Statements run after `column` was added to an existing table, e.g. to replace a
+    /// unique constraint by one covering it.
+    pub fn with_upgrade(mut self, column: &'static str, statements: Vec<String>) -> TableSpec {
+        self.added_columns.extend(statements.into_iter().map(|statement| (column, statement)));
+        self
+    }
+
+    /// The index statements with the name of the index each creates.
+    pub fn named_indexes(&self) -> Vec<(&str, &str)> {
+        self.indexes.iter()
+            .filter_map(|statement| index_name(statement).map(|name| (name, statement.as_str())))
+            .collect()
+    }
+
+    /// The create statement followed by the index statements.
+    pub fn statements(self) -> Vec<String> {
+        let mut statements = vec![self.create];
+        statements.extend(self.indexes);
+        statements
+    }
+}
+
+/// The name in a `CREATE INDEX [IF NOT EXISTS] name ON ...` statement.
+fn index_name(statement: &str) -> Option<&str> {
+    let words: Vec<&str> = statement.split_whitespace().collect();
+    let on = words.iter().position(|word| word.eq_ignore_ascii_case("on"))?;
+    on.checked_sub(1).map(|name| words[name])
+}
+
+/// What the library stores in columns whose content depends on configuration, as
+/// (table, column, note), rendered by `schema_doc`.
+pub(crate) const COLUMN_NOTES: &[(&str, &str, &str)] = &[
+    ("aggregate_instances", "natural_key", "Optional key unique per aggregate type, given to `create_aggregate_instance`."),
+    ("aggregate_instances", "deleted_at", "Microseconds since the Unix epoch when soft deleted, NULL otherwise."),
+    ("aggregate_instances", "tenant_id", "Tenant of the `for_tenant` handle that created the instance, empty for the unscoped engine. Natural keys are unique per tenant."),
+    ("events", "data", "Event payload as JSON, or a blob pointer when offloaded with the `blobs` feature. Binary, in the format of the store's payload serializer, with `with_binary_payloads`."),
+    ("events", "metadata", "Context metadata as JSON, NULL when the context had none."),
+    ("events", "created_at", "Microseconds since the Unix epoch, NULL for events written without a timestamp."),
+    ("events", "hash", "SHA-256 chain hash, filled when the store is built with `hash_events(true)`."),
+    ("events", "tenant_id", "Tenant of the event's aggregate."),
+    ("snapshots", "data", "Aggregate state, stored like the data of events."),
+    ("snapshots", "created_at", "Microseconds since the Unix epoch when the snapshot was taken, NULL for snapshots written without a timestamp."),
+    ("snapshots", "tenant_id", "Tenant of the snapshot's aggregate."),
+    ("lookup_keys", "tenant_id", "Tenant of the key's aggregate."),
+    ("dedup_keys", "created_at", "Microseconds since the Unix epoch, compared against by retention."),
+    ("dedup_keys", "tenant_id", "Tenant that recorded the key. Keys are unique per tenant."),
+    ("projection_checkpoints", "position", "Global feed position of the last event the named projection handled."),
+    ("projection_checkpoints", "tenant_id", "Tenant whose feed the position is in. Names are unique per tenant."),
+    ("schema_version", "version", "Schema versions applied to the database, see `SCHEMA_VERSION`."),
+    ("store_info", "value", "Settings of the database by name, such as its `environment`."),
+];
+
+/// Renders tables as markdown: their columns, create statement (with its constraints),
+/// indexes and the columns added to tables created by earlier releases.
+pub(crate) fn schema_doc(title: &str, tables: &[TableSpec]) -> String {
+    let mut doc = format!("# {}\n", title);
+    for table in tables {
+        doc.push_str(&format!("\n## {}\n\n| Column | Kind | Notes |\n| --- | --- | --- |\n", table.name));
+        for (column, kind) in &table.columns {
+            let mut notes: Vec<&str> = COLUMN_NOTES.iter()
+                .filter(|(note_table, note_column, _)| *note_table == table.name && note_column == column)
+                .map(|(_, _, note)| *note)
+                .collect();
+            if table.added_columns.iter().any(|(added, _)| added == column) {
+                notes.push("Added to existing tables by `build_tables` and `ensure_schema`.");
+            }
+            match notes.is_empty() {
+                true => doc.push_str(&format!("| {} | {} | |\n", column, kind.name())),
+                false => doc.push_str(&format!("| {} | {} | {} |\n", column, kind.name(), notes.join(" "))),
+            }
+        }
+        doc.push_str(&format!("\n```sql\n{}\n```\n", dedent(&table.create)));
+        if !table.indexes.is_empty() {
+            doc.push_str("\nIndexes:\n\n```sql\n");
+            for index in &table.indexes {
+                doc.push_str(&format!("{}\n", index.trim()));
+            }
+            doc.push_str("```\n");
+        }
+        if !table.added_columns.is_empty() {
+            doc.push_str("\nUpgrades:\n\n```sql\n");
+            for (_, statement) in &table.added_columns {
+                doc.push_str(&format!("{}\n", dedent(statement)));
+            }
+            doc.push_str("```\n");
+        }
+    }
+    doc
+}
+
+/// Strips the indentation a statement picked up from the source code it is written in.
+fn dedent(statement: &str) -> String {
+    let mut lines = statement.trim().lines();
+    let first = lines.next().unwrap_or_default();
+    let rest: Vec<&str> = lines.filter(|line| !line.trim().is_empty()).collect();
+    let indent = rest.iter()
+        .map(|line| line.len() - line.trim_start().len())
+        .min()
+        .unwrap_or(0);
+    let mut dedented = vec![first];
+    dedented.extend(rest.iter().map(|line| line[indent..].trim_end()));
+    dedented.join("\n")
+}
+
+/// Queries on instances, events, snapshots, lookup keys and dedup keys are scoped to a
+/// tenant, `''` for the unscoped engine: inserts take it as their last value and lookups as
+/// the parameter after their filters, before a trailing LIMIT. Checkpoints are scoped the
+/// same way, while type names and store_info are shared by every tenant.
 pub (crate) trait QueryBuilder {
-    fn build_queries(&self) -> Vec<String>;
+    /// Whether the data columns of events and snapshots hold bytes instead of text, see
+    /// `SqlxStorageEngine::with_binary_payloads`.
+    fn binary_payloads(&self) -> bool;
+    /// The dialect's type for binary columns.
+    fn binary_type(&self) -> &'static str;
+    /// Type of the data columns of events and snapshots.
+    fn data_type(&self) -> &'static str {
+        match self.binary_payloads() {
+            true => self.binary_type(),
+            false => "TEXT",
+        }
+    }
+    fn data_kind(&self) -> ColumnKind {
+        match self.binary_payloads() {
+            true => ColumnKind::Binary,
+            false => ColumnKind::Text,
+        }
+    }
+    /// Expression selecting the blob pointer a snapshot's data column holds, NULL otherwise.
+    /// Blobs are only offloaded from text data columns.
+    fn blob_pointer(&self) -> &'static str {
+        match self.binary_payloads() {
+            true => "NULL",
+            false => "CASE WHEN data LIKE '{\"__blob\"%' THEN data END",
+        }
+    }
+    fn schema(&self) -> Vec<TableSpec>;
+    fn build_queries(&self) -> Vec<String> {
+        self.schema().into_iter().flat_map(|table| table.statements()).collect()
+    }
     fn drop_queries(&self) -> Vec<String>;
+    fn list_columns(&self) -> String;
+    /// Names of the indexes on a table as `index_name`.
+    fn list_indexes(&self) -> String;
+    /// Blocks until no other connection is initializing the schema.
+    fn lock_initialization(&self) -> String;
+    /// Releases the initialization lock; `succeeded` tells whether the work under it succeeded.
+    fn unlock_initialization(&self, succeeded: bool) -> String;
+    /// The highest recorded schema version as `version`, NULL when none is recorded.
+    fn get_schema_version(&self) -> String;
+    fn insert_schema_version(&self) -> String;
     fn insert_aggregate_type(&self) -> String;
     fn get_aggregate_type(&self) -> String;
+    /// Looks up `count` aggregate type names at once, returning `id` and `name` columns.
+    fn get_aggregate_type_ids_batch(&self, count: usize) -> String;
+    /// Aggregate types by name as `id` and `name`; `with_counts` adds `usage_count` and
+    /// `first_seen` from the events of each type in the tenant given as parameter.
+    fn list_aggregate_types(&self, with_counts: bool) -> String;
+    /// Event types by name, with the same columns as `list_aggregate_types`.
+    fn list_event_types(&self, with_counts: bool) -> String;
     fn insert_event_type(&self) -> String;
+    /// Inserts a type with an explicit id (first parameter) and name into `table`, either
+    /// aggregate_types or event_types.
+    fn restore_type(&self, table: &str) -> String;
+    /// Moves the id sequence of a type table past its highest id, like
+    /// `sync_aggregate_instance_ids`.
+    fn sync_type_ids(&self, table: &str) -> String;
     fn get_event_type(&self) -> String;
+    /// Inserts an instance numbered by the database. With `uuid-ids` the engine generates
+    /// the id and gives it as first parameter.
     fn insert_aggregate_instance(&self) -> String;
     fn insert_event(&self) -> String;
     fn insert_snapshot(&self) -> String;
     fn get_events(&self) -> String;
+    fn get_all_events(&self) -> String;
+    /// An aggregate's events after a version, like `get_events`, up to the global position
+    /// given as fourth parameter, with their `position`.
+    fn get_events_until_position(&self) -> String;
+    /// Position of the latest event as `position`, NULL without events.
+    fn get_head_position(&self) -> String;
     fn get_snapshot(&self) -> String;
+    /// Highest version among an aggregate's snapshots, NULL if it has none.
+    fn get_snapshot_head_version(&self) -> String;
+    /// Latest snapshot for each of `count` (aggregate_id, aggregate_type_id) parameter pairs,
+    /// followed by the tenant.
+    fn get_snapshots_batch(&self, count: usize) -> String;
+    /// Version and payload size of an aggregate's snapshots, newest first, limited by the fourth parameter.
+    fn get_snapshots_history(&self) -> String;
+    /// Newest snapshot at or below the version given as third parameter.
+    fn get_snapshot_at(&self) -> String;
+    /// Ids of the snapshots with at least as many newer snapshots of the same aggregate as
+    /// the first parameter, and their data when it holds a blob pointer, in the tenant given
+    /// as second parameter.
+    fn get_pruned_snapshots(&self) -> String;
+    fn delete_snapshot(&self) -> String;
     fn get_aggregate_instance_id(&self) -> String;
+    /// id, natural_key and deleted_at of the instance holding a natural key.
+    fn find_aggregate_instance(&self) -> String;
+    /// deleted_at of an instance, given its id and type id; no row for unknown instances.
+    fn get_aggregate_deleted_at(&self) -> String;
+    /// Sets deleted_at (first parameter) of an instance that is not deleted yet.
+    fn soft_delete_aggregate(&self) -> String;
+    fn resurrect_aggregate(&self) -> String;
+    /// Inserts an instance with an explicit id (first parameter), type id and natural key.
+    fn restore_aggregate_instance(&self) -> String;
+    /// id, natural_key and deleted_at of the instances of a type id in a tenant, ordered by
+    /// id, with a limit and an offset as third and fourth parameters.
+    fn list_aggregate_instances(&self) -> String;
+    /// Moves the instance id sequence past the highest id, where inserting explicit ids
+    /// does not already do so.
+    fn sync_aggregate_instance_ids(&self) -> String;
+    fn get_max_version(&self) -> String;
+    fn get_min_version(&self) -> String;
+    fn get_head_created_at(&self) -> String;
+    fn insert_lookup_key(&self) -> String;
+    fn delete_lookup_key(&self) -> String;
+    fn find_by_lookup_key(&self) -> String;
+    /// aggregate_id, key_name and key_value of the lookup keys of a type id in a tenant,
+    /// paged like `list_aggregate_instances`.
+    fn list_lookup_keys(&self) -> String;
+    fn insert_dedup_key(&self) -> String;
+    fn find_dedup_key(&self) -> String;
+    fn delete_dedup_keys_before(&self) -> String;
+    fn get_checkpoint(&self) -> String;
+    /// Inserts or moves the checkpoint of a projection (first parameter) to a position,
+    /// within a tenant.
+    fn upsert_checkpoint(&self) -> String;
+    /// The `value` stored in store_info under a name.
+    fn get_store_info(&self) -> String;
+    /// Inserts or replaces the store_info value (second parameter) under a name.
+    fn upsert_store_info(&self) -> String;
+    /// Moves rows from one aggregate type id (second parameter) to another (first parameter).
+    fn retype_aggregate_instances(&self) -> String;
+    fn retype_events(&self) -> String;
+    fn retype_snapshots(&self) -> String;
+    fn retype_lookup_keys(&self) -> String;
+    fn delete_aggregate_type(&self) -> String;
+    /// Deletes the rows of `table` belonging to an aggregate, given its id and type id.
+    fn purge_stream_rows(&self, table: &str) -> String;
+    /// Deletes the dedup keys recorded for an aggregate id.
+    fn purge_dedup_keys(&self) -> String;
+    /// Deletes an instance, given its id and type id.
+    fn delete_aggregate_instance(&self) -> String;
+    /// The blob pointers, or NULLs, in the data column of `table` for an aggregate, given
+    /// its id and type id.
+    fn get_stream_blob_pointers(&self, table: &str) -> String;
 }
-
@@ -0,0 +1,155 @@
+//! Development time schema sync. Brings a database created by an earlier release, or edited
+//! by hand, in line with the schema this release expects while keeping its data, so test
+//! data survives schema changes instead of being dropped with the tables.
+//!
+//! Not meant for production databases: it alters tables without review, and is refused on
+//! databases whose environment is recorded as production (see
+//! `SqlxStorageEngine::set_environment`). Use `build_tables` and `ensure_schema` there, which
+//! only add what is missing.
+
+use sqlx::pool::PoolConnection;
+use evercore::EventStoreError;
+use crate::queries::{ColumnKind, TableSpec};
+use crate::SqlxStorageEngine;
+
+/// Environment name on which `SqlxStorageEngine::sync_schema` refuses to run, compared
+/// ignoring case.
+pub const PRODUCTION_ENVIRONMENT: &str = "production";
+
+/// Options of `SqlxStorageEngine::sync_schema`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SchemaSyncOptions {
+    /// Runs the statements. Without it the report only lists what would be run.
+    pub dev_mode: bool,
+    /// Allows statements losing data: dropping columns the schema does not have and
+    /// recreating columns of an incompatible type.
+    pub allow_destructive: bool,
+}
+
+/// Outcome of `SqlxStorageEngine::sync_schema`, in the order the statements run.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SchemaSyncReport {
+    /// Statements creating missing tables and indexes and adding missing columns.
+    pub additive: Vec<String>,
+    /// Statements dropping or recreating columns.
+    pub destructive: Vec<String>,
+    /// Whether the statements were run.
+    pub applied: bool,
+}
+
+impl SchemaSyncReport {
+    /// Whether the database already matched the schema.
+    pub fn is_empty(&self) -> bool {
+        self.additive.is_empty() && self.destructive.is_empty()
+    }
+}
+
+/// Adds a column as nullable, so existing rows keep working. `binary_type` is the dialect's
+/// type for binary columns.
+fn add_column(table: &str, column: &str, kind: ColumnKind, binary_type: &str) -> String {
+    let data_type = match kind {
+        ColumnKind::Integer => "BIGINT",
+        ColumnKind::Text => "TEXT",
+        ColumnKind::Binary => binary_type,
+    };
+    format!("ALTER TABLE {table} ADD COLUMN {column} {data_type}")
+}
+
+/// The statements adding a missing column: its upgrade statements when the table defines
+/// any, otherwise adding it as nullable.
+pub(crate) fn adding_column(table: &TableSpec, column: &str, kind: ColumnKind, binary_type: &str) -> Vec<String> {
+    let upgrades: Vec<String> = table.added_columns.iter()
+        .filter(|(added, _)| *added == column)
+        .map(|(_, statement)| statement.clone())
+        .collect();
+    match upgrades.is_empty() {
+        true => vec![add_column(table.name, column, kind, binary_type)],
+        false => upgrades,
+    }
+}
+
+fn drop_column(table: &str, column: &str) -> String {
+    format!("ALTER TABLE {table} DROP COLUMN {column}")
+}
+
+impl SqlxStorageEngine {
+    /// Compares the live schema with the expected one, the way `ensure_schema` does, and
+    /// with `dev_mode` set brings it in line: missing tables and indexes are created and
+    /// missing columns added, as nullable when no upgrade statement is defined for them.
+    /// Changes losing data fail with `SchemaMismatch` listing their statements, and nothing
+    /// is run, unless `allow_destructive` is set. Each statement run is logged through
+    /// `tracing` and listed in the report.
+    ///
+    /// Meant for development databases only; fails with `SchemaSyncDisabled` when the
+    /// recorded environment is production. Runs under the same lock as `build_tables`.
+    pub async fn sync_schema(&self, options: SchemaSyncOptions) -> Result<SchemaSyncReport, EventStoreError> {
+        let mut connection = self.get_connection().await?;
+        self.lock_initialization(&mut connection).await?;
+        let result = self.sync_schema_locked(&mut connection, options).await;
+        self.unlock_initialization(&mut connection, result).await
+    }
+
+    async fn sync_schema_locked(&self, connection: &mut PoolConnection<sqlx::Any>, options: SchemaSyncOptions) -> Result<SchemaSyncReport, EventStoreError> {
+        if let Some(environment) = self.stored_environment(connection).await? {
+            if environment.eq_ignore_ascii_case(PRODUCTION_ENVIRONMENT) {
+                return Err(EventStoreError::SchemaSyncDisabled(environment));
+            }
+        }
+
+        let mut report = SchemaSyncReport::default();
+        for table in self.query_builder.schema() {
+            self.plan_table(connection, table, &mut report).await?;
+        }
+        if !options.dev_mode {
+            return Ok(report);
+        }
+        if !report.destructive.is_empty() && !options.allow_destructive {
+            return Err(EventStoreError::SchemaMismatch(report.destructive));
+        }
+
+        for statement in report.additive.iter().chain(&report.destructive) {
+            tracing::info!("sync_schema: {}", statement.trim());
+            self.timed(statement, sqlx::query(statement)
+                .execute(&mut *connection))
+                .await
+                .map_err(|e| EventStoreError::StorageEngineError(Box::new(e)))?;
+        }
+        self.check_schema_version(connection).await?;
+        report.applied = true;
+        Ok(report)
+    }
+
+    /// Adds the statements bringing one table in line to the report.
+    async fn plan_table(&self, connection: &mut PoolConnection<sqlx::Any>, table: TableSpec, report: &mut SchemaSyncReport) -> Result<(), EventStoreError> {
+        let live_columns = self.live_columns(connection, table.name).await?;
+        if live_columns.is_empty() {
+            report.additive.extend(table.statements());
+            return Ok(());
+        }
+
+        let binary_type = self.query_builder.binary_type();
+        for (column, kind) in &table.columns {
+            match live_columns.get(*column) {
+                None => report.additive.extend(adding_column(&table, column, *kind, binary_type)),
+                Some(data_type) if !kind.matches(data_type) => {
+                    report.destructive.push(drop_column(table.name, column));
+                    report.destructive.push(add_column(table.name, column, *kind, binary_type));
+                }
+                Some(_) => {}
+            }
+        }
+        let mut extra: Vec<&String> = live_columns.keys()
+            .filter(|live| !table.columns.iter().any(|(column, _)| column == live))
+            .collect();
+        extra.sort();
+        report.destructive.extend(extra.into_iter().map(|column| drop_column(table.name, column)));
+
+        let live_indexes = self.live_indexes(connection, table.name).await?;
+        for (name, statement) in table.named_indexes() {
+            if !live_indexes.iter().any(|live| live.eq_ignore_ascii_case(name)) {
+                report.additive.push(statement.to_string());
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,199 @@
+//! Export and import of the aggregate and event type tables. Type ids are assigned in
+//! insertion order, so two databases fed the same types in a different order disagree on
+//! them; importing the mappings of one into the other lines them up where possible.
+
+use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+use sqlx::{Connection, Transaction};
+use evercore::EventStoreError;
+use crate::SqlxStorageEngine;
+
+/// A row of a type table.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TypeMapping {
+    pub id: i64,
+    pub name: String,
+}
+
+/// The contents of both type tables, ordered by id.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TypeMappings {
+    pub aggregate_types: Vec<TypeMapping>,
+    pub event_types: Vec<TypeMapping>,
+}
+
+/// How `SqlxStorageEngine::import_type_mappings` treats types the destination already has.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ImportMode {
+    /// Every type must end up with its source id. When a name has another id in the
+    /// destination, or an id belongs to another name, the import fails with
+    /// `TypeIdConflict` listing every such type and writes nothing.
+    StrictIds,
+    /// Types are matched by name. A missing type gets its source id when that is free and
+    /// a new one otherwise; each type whose id differs from the source is reported.
+    RemapNames,
+}
+
+/// A type whose id in the destination differs from its source id.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RemappedType {
+    /// "aggregate_types" or "event_types".
+    pub table: &'static str,
+    pub name: String,
+    pub source_id: i64,
+    pub destination_id: i64,
+}
+
+/// Outcome of `SqlxStorageEngine::import_type_mappings`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ImportReport {
+    /// Types missing from the destination that were added.
+    pub inserted: usize,
+    /// Types whose destination id differs from the source, in source order.
+    pub remapped: Vec<RemappedType>,
+}
+
+#[derive(Clone, Copy)]
+enum TypeTable {
+    Aggregate,
+    Event,
+}
+
+impl TypeTable {
+    fn name(self) -> &'static str {
+        match self {
+            TypeTable::Aggregate => "aggregate_types",
+            TypeTable::Event => "event_types",
+        }
+    }
+}
+
+/// What importing one type does; types the destination has with the same id need nothing.
+enum Step {
+    /// Insert the type with its source id.
+    Restore(TypeMapping),
+    /// Insert the type with a newly assigned id, its source id being taken.
+    Insert(TypeMapping),
+    /// The type exists under another id.
+    Remap(RemappedType),
+}
+
+/// The steps importing `source` into a table holding `existing` takes, or the conflicts
+/// that rule out a strict import.
+fn plan(table: TypeTable, source: &[TypeMapping], existing: &[TypeMapping], mode: ImportMode) -> (Vec<Step>, Vec<String>) {
+    let ids: HashMap<&str, i64> = existing.iter().map(|mapping| (mapping.name.as_str(), mapping.id)).collect();
+    let names: HashMap<i64, &str> = existing.iter().map(|mapping| (mapping.id, mapping.name.as_str())).collect();
+
+    let mut steps = Vec::new();
+    let mut conflicts = Vec::new();
+    for mapping in source {
+        match (ids.get(mapping.name.as_str()), names.get(&mapping.id)) {
+            (Some(id), _) if *id == mapping.id => {}
+            (Some(id), _) => match mode {
+                ImportMode::StrictIds => conflicts.push(format!("{} '{}' has id {}, {} in the source", table.name(), mapping.name, id, mapping.id)),
+                ImportMode::RemapNames => steps.push(Step::Remap(RemappedType {
+                    table: table.name(),
+                    name: mapping.name.clone(),
+                    source_id: mapping.id,
+                    destination_id: *id,
+                })),
+            },
+            (None, Some(name)) => match mode {
+                ImportMode::StrictIds => conflicts.push(format!("{} id {} is '{}', '{}' in the source", table.name(), mapping.id, name, mapping.name)),
+                ImportMode::RemapNames => steps.push(Step::Insert(mapping.clone())),
+            },
+            (None, None) => steps.push(Step::Restore(mapping.clone())),
+        }
+    }
+    (steps, conflicts)
+}
+
+impl SqlxStorageEngine {
+    /// Both type tables, for `import_type_mappings` on another database.
+    pub async fn export_type_mappings(&self) -> Result<TypeMappings, EventStoreError> {
+        Ok(TypeMappings {
+            aggregate_types: self.type_table(TypeTable::Aggregate).await?,
+            event_types: self.type_table(TypeTable::Event).await?,
+        })
+    }
+
+    /// Adds the types of `mappings` missing from this database, keeping their ids where
+    /// `mode` allows, in one transaction. Types already present are left as they are.
+    pub async fn import_type_mappings(&self, mappings: &TypeMappings, mode: ImportMode) -> Result<ImportReport, EventStoreError> {
+        let mut plans = Vec::new();
+        let mut conflicts = Vec::new();
+        for (table, source) in [(TypeTable::Aggregate, &mappings.aggregate_types), (TypeTable::Event, &mappings.event_types)] {
+            let existing = self.type_table(table).await?;
+            let (steps, found) = plan(table, source, &existing, mode);
+            plans.push((table, steps));
+            conflicts.extend(found);
+        }
+        if !conflicts.is_empty() {
+            return Err(EventStoreError::TypeIdConflict(conflicts));
+        }
+
+        let mut report = ImportReport::default();
+        let mut connection = self.get_connection().await?;
+        let mut tx = connection
+            .begin()
+            .await
+            .map_err(|e| EventStoreError::StorageEngineError(Box::new(e)))?;
+        for (table, steps) in plans {
+            // Explicit ids go in first, so the ids assigned afterwards cannot take them.
+            let restore = self.query_builder.restore_type(table.name());
+            for step in &steps {
+                if let Step::Restore(mapping) = step {
+                    self.timed(&restore, sqlx::query(&restore)
+                        .bind(mapping.id)
+                        .bind(&mapping.name)
+                        .execute(&mut tx))
+                        .await
+                        .map_err(|e| EventStoreError::StorageEngineError(Box::new(e)))?;
+                    report.inserted += 1;
+                }
+            }
+            let sync = self.query_builder.sync_type_ids(table.name());
+            self.timed(&sync, sqlx::query(&sync)
+                .execute(&mut tx))
+                .await
+                .map_err(|e| EventStoreError::StorageEngineError(Box::new(e)))?;
+
+            for step in steps {
+                match step {
+                    Step::Restore(_) => {}
+                    Step::Insert(mapping) => {
+                        let destination_id = self.insert_type(&mut tx, table, &mapping.name).await?;
+                        report.inserted += 1;
+                        report.remapped.push(RemappedType { table: table.name(), name: mapping.name, source_id: mapping.id, destination_id });
+                    }
+                    Step::Remap(remapped) => report.remapped.push(remapped),
+                }
+            }
+        }
+        tx.commit()
+            .await
+            .map_err(|e| EventStoreError::StorageEngineError(Box::new(e)))?;
+        Ok(report)
+    }
+
+    /// A type table read from the database, bypassing the id caches.
+    async fn type_table(&self, table: TypeTable) -> Result<Vec<TypeMapping>, EventStoreError> {
+        let query = match table {
+            TypeTable::Aggregate => self.query_builder.list_aggregate_types(false),
+            TypeTable::Event => self.query_builder.list_event_types(false),
+        };
+        let mut mappings: Vec<TypeMapping> = self.list_types(query, false).await?
+            .into_iter()
+            .map(|info| TypeMapping { id: info.id.unwrap_or_default(), name: info.name })
+            .collect();
+        mappings.sort_by_key(|mapping| mapping.id);
+        Ok(mappings)
+    }
+
+    async fn insert_type(&self, tx: &mut Transaction<'_, sqlx::Any>, table: TypeTable, name: &str) -> Result<i64, EventStoreError> {
+        match table {
+            TypeTable::Aggregate => self.insert_aggregate_type(tx, name).await,
+            TypeTable::Event => self.insert_event_type(tx, name).await,
+        }
+    }
+}
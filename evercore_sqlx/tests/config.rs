@@ -0,0 +1,106 @@
+#![cfg(feature = "sqlite")]
+
+use evercore::{EventStore, EventStoreError, event::Event};
+use evercore::aggregate::{CanRequest, Composable, ComposedAggregate};
+use evercore_sqlx::config::{EventStoreConfig, FromConfig};
+use serde::{Serialize, Deserialize};
+
+#[derive(Default, Clone, Serialize, Deserialize)]
+struct Counter {
+    count: i64,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Increment;
+
+impl Composable for Counter {
+    fn get_type(&self) -> &str {
+        "counter"
+    }
+
+    fn apply_event(&mut self, _event: &Event) -> Result<(), EventStoreError> {
+        self.count += 1;
+        Ok(())
+    }
+}
+
+impl CanRequest<Increment, Increment> for Counter {
+    fn request(&self, request: Increment) -> Result<(String, Increment), EventStoreError> {
+        Ok(("incremented".to_string(), request))
+    }
+}
+
+fn config(extra: &str) -> String {
+    // Integer and uuid id schemas cannot share a database.
+    let name = if cfg!(feature = "uuid-ids") { "config_uuid_ids.db" } else { "config.db" };
+    let path = std::path::Path::new(env!("CARGO_TARGET_TMPDIR")).join(name);
+    format!(r#"{{
+        "database": {{ "type": "sqlite", "url": "sqlite://{}?mode=rwc", "max_connections": 2, "build_tables": true }},
+        "retention": {{ "interval_secs": 60, "snapshots_to_keep": 2 }},
+        "snapshot_policies": {{ "counter": {{ "every_n_events": 2 }} }}
+        {}
+    }}"#, path.display(), extra)
+}
+
+#[tokio::test]
+async fn ensure_store_is_built_from_config() {
+    let config = EventStoreConfig::from_json(&config("")).unwrap();
+    let event_store = EventStore::from_config(&config).await.unwrap();
+    assert!(event_store.retention_policy().is_some());
+
+    // The configured policy overrides the aggregate's default snapshot frequency.
+    let context = event_store.get_context();
+    let mut counter = ComposedAggregate::<Counter>::new(&context, None).await.unwrap();
+    counter.request(Increment).unwrap();
+    counter.request(Increment).unwrap();
+    assert_eq!(context.captured_snapshots().unwrap().len(), 1);
+    context.commit().await.unwrap();
+}
+
+#[tokio::test]
+async fn ensure_invalid_config_is_rejected() {
+    let unknown_key = EventStoreConfig::from_json(&config(r#", "tenant_mod": true"#));
+    assert!(matches!(unknown_key, Err(EventStoreError::ConfigurationError(_))));
+
+    let tenant_mode = EventStoreConfig::from_json(&config(r#", "tenant_mode": true"#)).unwrap();
+    assert!(EventStore::from_config(&tenant_mode).await.is_ok());
+
+    let mut pool = EventStoreConfig::from_json(&config("")).unwrap();
+    pool.database.min_connections = Some(3);
+    let Err(EventStoreError::ConfigurationError(problems)) = EventStore::from_config(&pool).await else {
+        panic!("min_connections above max_connections was accepted");
+    };
+    assert_eq!(problems, vec!["min_connections 3 exceeds max_connections 2".to_string()]);
+}
+
+#[tokio::test]
+async fn ensure_invalid_durations_are_rejected() {
+    let mut durations = EventStoreConfig::from_json(&config("")).unwrap();
+    let counter = durations.snapshot_policies.get_mut("counter").unwrap();
+    counter.older_than_secs = Some(i64::MAX);
+    counter.after_duration_secs = Some(60);
+    durations.default_snapshot_policy = Some(serde_json::from_str(r#"{ "after_duration_secs": -1 }"#).unwrap());
+    durations.retention.as_mut().unwrap().dedup_key_max_age_secs = Some(-60);
+
+    let Err(EventStoreError::ConfigurationError(problems)) = EventStore::from_config(&durations).await else {
+        panic!("invalid durations were accepted");
+    };
+    assert_eq!(problems, vec![
+        "retention dedup_key_max_age_secs must not be negative, got -60".to_string(),
+        "default snapshot policy: after_duration_secs must not be negative, got -1".to_string(),
+        format!("snapshot policy of 'counter': older_than_secs {} is out of range", i64::MAX),
+    ]);
+}
+
+#[test]
+fn ensure_store_can_be_built_from_config_in_a_task() {
+    fn assert_send<T: Send>(_: T) {}
+    let _ = |config: &EventStoreConfig| assert_send(EventStore::from_config(config));
+}
+
+#[test]
+fn ensure_example_config_parses() {
+    let example = EventStoreConfig::from_json(&EventStoreConfig::example()).unwrap();
+    assert_eq!(example.snapshot_policies["account"].every_n_events, Some(50));
+    assert_eq!(example.default_snapshot_policy.unwrap().every_n_events, Some(100));
+}
@@ -1,4 +1,6 @@
-use std::sync::Mutex;
+#![cfg(feature = "sqlite")]
+
+use tokio::sync::Mutex;
 mod common;
 use evercore_sqlx::{SqlxStorageEngine, DbType};
 use sqlx::AnyPool;
@@ -10,32 +12,30 @@ struct Initialization {
     pool: sqlx::AnyPool
 }
 
-static mut INITIALIZATION: Mutex<Option<Initialization>> = Mutex::new(None);
+static INITIALIZATION: Mutex<Option<Initialization>> = Mutex::const_new(None);
 
 
 async fn get_initialized_pool() -> sqlx::AnyPool {
 
-    unsafe {
-        let mut initialization = INITIALIZATION.lock().unwrap();
-        let pool = match &*initialization {
-            Some(init) => init.pool.clone(),
-            None => {
-                let pool = AnyPool::connect(DATABASE_URL).await.unwrap();
-                
-                let storage = SqlxStorageEngine::new(DATABASE_TYPE, pool.clone());
-                storage.drop_tables().await.unwrap();
-                storage.build_tables().await.unwrap();
-
-
-                let result_pool = pool.clone();
-                *initialization = Some(Initialization {
-                    pool,
-                });
-                result_pool
-            }
-        };
-        pool
-    }
+    let mut initialization = INITIALIZATION.lock().await;
+    let pool = match &*initialization {
+        Some(init) => init.pool.clone(),
+        None => {
+            let pool = AnyPool::connect(DATABASE_URL).await.unwrap();
+            
+            let storage = SqlxStorageEngine::new(DATABASE_TYPE, pool.clone());
+            storage.drop_tables().await.unwrap();
+            storage.build_tables().await.unwrap();
+
+
+            let result_pool = pool.clone();
+            *initialization = Some(Initialization {
+                pool,
+            });
+            result_pool
+        }
+    };
+    pool
 }
 
 #[tokio::test]
@@ -74,3 +74,327 @@ async fn ensure_can_write_updates() {
     let pool = get_initialized_pool().await;
     common::can_write_updates(DATABASE_TYPE, pool).await;
 }
+
+#[tokio::test]
+async fn ensure_can_get_aggregate_version() {
+    let pool = get_initialized_pool().await;
+    common::can_get_aggregate_version(DATABASE_TYPE, pool).await;
+}
+
+#[tokio::test]
+async fn ensure_description_masks_password() {
+    let pool = get_initialized_pool().await;
+    common::description_masks_password(DATABASE_TYPE, pool).await;
+}
+
+#[tokio::test]
+async fn ensure_schema_verifies_current_database() {
+    let pool = get_initialized_pool().await;
+    common::ensure_schema_verifies_current_database(DATABASE_TYPE, pool).await;
+}
+
+#[tokio::test]
+async fn ensure_schema_creates_missing_tables() {
+    let pool = AnyPool::connect("sqlite://test_schema_empty.db?mode=rwc").await.unwrap();
+    common::ensure_schema_creates_missing_tables(DATABASE_TYPE, pool).await;
+}
+
+#[tokio::test]
+async fn ensure_schema_rejects_incompatible_columns() {
+    let pool = AnyPool::connect("sqlite://test_schema_altered.db?mode=rwc").await.unwrap();
+    let storage = SqlxStorageEngine::new(DATABASE_TYPE, pool.clone());
+    storage.drop_tables().await.unwrap();
+    storage.build_tables().await.unwrap();
+
+    sqlx::query("DROP TABLE snapshots;").execute(&pool).await.unwrap();
+    let aggregate_id_type = if cfg!(feature = "uuid-ids") { "CHAR(36)" } else { "INTEGER" };
+    sqlx::query(&format!("CREATE TABLE snapshots (
+        id INTEGER PRIMARY KEY,
+        aggregate_id {} NOT NULL,
+        aggregate_type_id INTEGER NOT NULL,
+        version INTEGER NOT NULL,
+        data INTEGER NOT NULL
+    );", aggregate_id_type)).execute(&pool).await.unwrap();
+
+    common::ensure_schema_rejects_incompatible_columns(DATABASE_TYPE, pool).await;
+}
+
+#[tokio::test]
+async fn ensure_can_find_aggregates_by_lookup_key() {
+    let pool = get_initialized_pool().await;
+    common::can_find_aggregates_by_lookup_key(DATABASE_TYPE, pool).await;
+}
+
+#[tokio::test]
+async fn ensure_skewed_created_at_is_clamped() {
+    let pool = get_initialized_pool().await;
+    common::clamps_skewed_created_at(DATABASE_TYPE, pool).await;
+}
+
+#[tokio::test]
+async fn ensure_skewed_created_at_fails_when_configured() {
+    let pool = get_initialized_pool().await;
+    common::rejects_skewed_created_at_when_configured(DATABASE_TYPE, pool).await;
+}
+
+#[tokio::test]
+async fn ensure_can_read_global_feed() {
+    let pool = get_initialized_pool().await;
+    common::can_read_global_feed(DATABASE_TYPE, pool).await;
+}
+
+#[tokio::test]
+async fn ensure_satisfies_storage_contract() {
+    let pool = get_initialized_pool().await;
+    common::satisfies_storage_contract(DATABASE_TYPE, pool).await;
+}
+
+#[tokio::test]
+async fn ensure_can_get_multiple_aggregate_type_ids() {
+    let pool = get_initialized_pool().await;
+    common::can_get_multiple_aggregate_type_ids(DATABASE_TYPE, pool).await;
+}
+
+#[tokio::test]
+async fn ensure_validates_required_capabilities() {
+    let pool = get_initialized_pool().await;
+    common::validates_required_capabilities(DATABASE_TYPE, pool).await;
+}
+
+#[tokio::test]
+async fn ensure_isolates_tenants() {
+    let pool = get_initialized_pool().await;
+    common::isolates_tenants(DATABASE_TYPE, pool).await;
+}
+
+#[tokio::test]
+async fn ensure_counts_types_per_tenant() {
+    let pool = get_initialized_pool().await;
+    common::counts_types_per_tenant(DATABASE_TYPE, pool).await;
+}
+
+#[tokio::test]
+async fn ensure_skips_replayed_dedup_keys() {
+    let pool = get_initialized_pool().await;
+    common::skips_replayed_dedup_keys(DATABASE_TYPE, pool).await;
+}
+
+#[tokio::test]
+async fn ensure_rejects_dedup_keys_racing_the_lookup() {
+    let pool = get_initialized_pool().await;
+    common::rejects_dedup_keys_racing_the_lookup(DATABASE_TYPE, pool).await;
+}
+
+#[tokio::test]
+async fn ensure_migrates_aggregate_type() {
+    let pool = get_initialized_pool().await;
+    common::migrates_aggregate_type(DATABASE_TYPE, pool).await;
+}
+
+#[tokio::test]
+async fn ensure_purges_aggregates() {
+    let pool = get_initialized_pool().await;
+    common::purges_aggregates(DATABASE_TYPE, pool).await;
+}
+
+#[tokio::test]
+async fn ensure_rewrites_streams() {
+    let pool = get_initialized_pool().await;
+    common::rewrites_streams(DATABASE_TYPE, pool).await;
+}
+
+#[tokio::test]
+async fn ensure_can_batch_read_snapshots() {
+    let pool = get_initialized_pool().await;
+    common::can_batch_read_snapshots(DATABASE_TYPE, pool).await;
+}
+
+#[tokio::test]
+async fn ensure_stamps_saga_id() {
+    let pool = get_initialized_pool().await;
+    common::stamps_saga_id(DATABASE_TYPE, pool).await;
+}
+
+#[tokio::test]
+async fn ensure_logs_slow_queries() {
+    let pool = get_initialized_pool().await;
+    common::logs_slow_queries(DATABASE_TYPE, pool).await;
+}
+
+#[tokio::test]
+async fn ensure_resolves_duplicate_natural_keys() {
+    let pool = get_initialized_pool().await;
+    common::resolves_duplicate_natural_keys(DATABASE_TYPE, pool).await;
+}
+
+#[tokio::test]
+async fn ensure_reads_snapshot_history() {
+    let pool = get_initialized_pool().await;
+    common::reads_snapshot_history(DATABASE_TYPE, pool).await;
+}
+
+#[tokio::test]
+async fn ensure_reads_events_until_position() {
+    let pool = get_initialized_pool().await;
+    common::reads_events_until_position(DATABASE_TYPE, pool).await;
+}
+
+#[tokio::test]
+async fn ensure_rejects_snapshots_of_another_type() {
+    let pool = get_initialized_pool().await;
+    common::rejects_snapshots_of_another_type(DATABASE_TYPE, pool).await;
+}
+
+#[tokio::test]
+async fn ensure_reads_snapshot_head_version() {
+    let pool = get_initialized_pool().await;
+    common::reads_snapshot_head_version(DATABASE_TYPE, pool).await;
+}
+
+#[tokio::test]
+async fn ensure_rejects_conflicting_versions() {
+    let pool = get_initialized_pool().await;
+    common::rejects_conflicting_versions(DATABASE_TYPE, pool).await;
+}
+
+#[tokio::test]
+async fn ensure_soft_deletes_aggregates() {
+    let pool = get_initialized_pool().await;
+    common::soft_deletes_aggregates(DATABASE_TYPE, pool).await;
+}
+
+#[tokio::test]
+async fn ensure_returns_written_events() {
+    let pool = get_initialized_pool().await;
+    common::returns_written_events(DATABASE_TYPE, pool).await;
+}
+
+#[tokio::test]
+async fn ensure_round_trips_timestamps() {
+    let pool = get_initialized_pool().await;
+    common::round_trips_timestamps(DATABASE_TYPE, pool).await;
+}
+
+#[tokio::test]
+async fn ensure_concurrent_builds_converge() {
+    let pool = get_initialized_pool().await;
+    common::converges_concurrent_builds(DATABASE_TYPE, pool).await;
+}
+
+#[tokio::test]
+async fn ensure_lists_type_vocabularies() {
+    let pool = get_initialized_pool().await;
+    common::lists_type_vocabularies(DATABASE_TYPE, pool).await;
+}
+
+#[tokio::test]
+async fn ensure_detects_tampered_events() {
+    let pool = get_initialized_pool().await;
+    common::detects_tampered_events(DATABASE_TYPE, pool).await;
+}
+
+#[tokio::test]
+async fn ensure_reports_failing_event_context() {
+    let pool = get_initialized_pool().await;
+    common::reports_failing_event_context(DATABASE_TYPE, pool).await;
+}
+
+#[tokio::test]
+async fn ensure_resumes_projections_from_checkpoint() {
+    let pool = get_initialized_pool().await;
+    common::resumes_projections_from_checkpoint(DATABASE_TYPE, pool).await;
+}
+
+#[cfg(feature = "archive")]
+#[tokio::test]
+async fn ensure_restores_archived_memory_store() {
+    let pool = AnyPool::connect("sqlite://test_archive.db?mode=rwc").await.unwrap();
+    let storage = SqlxStorageEngine::new(DATABASE_TYPE, pool.clone());
+    storage.drop_tables().await.unwrap();
+    storage.build_tables().await.unwrap();
+    common::restores_archived_memory_store(DATABASE_TYPE, pool).await;
+}
+
+#[cfg(feature = "blobs")]
+#[tokio::test]
+async fn ensure_offloads_large_snapshots() {
+    let pool = AnyPool::connect("sqlite://test_blobs.db?mode=rwc").await.unwrap();
+    let storage = SqlxStorageEngine::new(DATABASE_TYPE, pool.clone());
+    storage.drop_tables().await.unwrap();
+    storage.build_tables().await.unwrap();
+    common::offloads_large_snapshots(DATABASE_TYPE, pool).await;
+}
+
+#[cfg(feature = "blobs")]
+#[tokio::test]
+async fn ensure_releases_blobs_of_failed_writes() {
+    let pool = get_initialized_pool().await;
+    common::releases_blobs_of_failed_writes(DATABASE_TYPE, pool).await;
+}
+
+#[tokio::test]
+async fn ensure_verifies_feed_integrity() {
+    let pool = AnyPool::connect("sqlite://test_feed_integrity.db?mode=rwc").await.unwrap();
+    let storage = SqlxStorageEngine::new(DATABASE_TYPE, pool.clone());
+    storage.drop_tables().await.unwrap();
+    storage.build_tables().await.unwrap();
+    common::verifies_feed_integrity(DATABASE_TYPE, pool).await;
+}
+
+async fn fresh_storage(url: &str) -> SqlxStorageEngine {
+    let pool = AnyPool::connect(url).await.unwrap();
+    let storage = SqlxStorageEngine::new(DATABASE_TYPE, pool);
+    storage.drop_tables().await.unwrap();
+    storage.build_tables().await.unwrap();
+    storage
+}
+
+#[tokio::test]
+async fn ensure_type_ids_survive_export_and_import() {
+    use evercore::EventStoreError;
+    use evercore_sqlx::type_mappings::{ImportMode, RemappedType};
+
+    let source = fresh_storage("sqlite://test_types_source.db?mode=rwc").await;
+    for name in ["order", "invoice", "customer"] {
+        source.get_aggregate_type_id(name).await.unwrap();
+    }
+    for name in ["placed", "paid"] {
+        source.get_event_type_id(name).await.unwrap();
+    }
+    let mappings = source.export_type_mappings().await.unwrap();
+
+    // Types the destination already has with the same id are kept.
+    let destination = fresh_storage("sqlite://test_types_destination.db?mode=rwc").await;
+    destination.get_aggregate_type_id("order").await.unwrap();
+    let report = destination.import_type_mappings(&mappings, ImportMode::StrictIds).await.unwrap();
+    assert_eq!((report.inserted, report.remapped.len()), (4, 0));
+    assert_eq!(destination.export_type_mappings().await.unwrap(), mappings);
+    assert_eq!(destination.get_aggregate_type_id("refund").await.unwrap(), 4);
+
+    // A database that assigned ids in another order cannot match them strictly.
+    let other = fresh_storage("sqlite://test_types_other.db?mode=rwc").await;
+    other.get_aggregate_type_id("invoice").await.unwrap();
+    let Err(EventStoreError::TypeIdConflict(conflicts)) = other.import_type_mappings(&mappings, ImportMode::StrictIds).await else {
+        panic!("conflicting ids were imported");
+    };
+    assert_eq!(conflicts.len(), 2);
+    assert_eq!(other.export_type_mappings().await.unwrap().aggregate_types.len(), 1);
+
+    let report = other.import_type_mappings(&mappings, ImportMode::RemapNames).await.unwrap();
+    assert_eq!(report.inserted, 4);
+    assert_eq!(report.remapped, vec![
+        RemappedType { table: "aggregate_types", name: "order".to_string(), source_id: 1, destination_id: 4 },
+        RemappedType { table: "aggregate_types", name: "invoice".to_string(), source_id: 2, destination_id: 1 },
+    ]);
+    assert_eq!(other.export_type_mappings().await.unwrap().event_types, mappings.event_types);
+}
+
+#[cfg(feature = "msgpack")]
+#[tokio::test]
+async fn ensure_stores_binary_payloads() {
+    let pool = AnyPool::connect("sqlite://test_binary.db?mode=rwc").await.unwrap();
+    let storage = SqlxStorageEngine::new(DATABASE_TYPE, pool.clone()).with_binary_payloads();
+    storage.drop_tables().await.unwrap();
+    storage.build_tables().await.unwrap();
+    common::stores_binary_payloads(DATABASE_TYPE, pool).await;
+}